@@ -1,12 +1,16 @@
 use nostdio::NoStdIoError;
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq)]
 pub enum FileIoError {
     IoError(NoStdIoError),
     FilenameTooLong,
     BufferTooSmall,
     FileAlreadyExists,
     OutOfDiskSpaceError,
+    NotFound,
+    NotADirectory,
+    IsADirectory,
+    DirectoryNotEmpty,
     Other(&'static str),
 }
 
@@ -16,4 +20,15 @@ impl From<NoStdIoError> for FileIoError {
     }
 }
 
+impl From<FileIoError> for NoStdIoError {
+    fn from(err: FileIoError) -> Self {
+        match err {
+            FileIoError::IoError(err) => err,
+            FileIoError::OutOfDiskSpaceError => NoStdIoError::StorageFull,
+            FileIoError::FilenameTooLong => NoStdIoError::InvalidInput,
+            _ => NoStdIoError::Other,
+        }
+    }
+}
+
 pub type Result<T> = core::result::Result<T, FileIoError>;
@@ -56,12 +56,30 @@ pub struct SignedFilePos(pub i64);
 pub struct Mode(pub u16);
 
 impl Mode {
+    /// Mask of the file type bits.
+    pub fn file_type() -> Self {
+        Mode(0o170000)
+    }
+
     pub fn directory() -> Self {
         Mode(0o40000)
     }
 
+    pub fn regular_file() -> Self {
+        Mode(0o100000)
+    }
+
+    /// Permission bits, including setuid, setgid and sticky.
+    pub fn permissions(&self) -> Self {
+        Mode(self.0 & 0o7777)
+    }
+
     pub fn is_directory(&self) -> bool {
-        (*self & Mode::directory()) == Mode::directory()
+        (*self & Mode::file_type()) == Mode::directory()
+    }
+
+    pub fn is_regular_file(&self) -> bool {
+        (*self & Mode::file_type()) == Mode::regular_file()
     }
 }
 
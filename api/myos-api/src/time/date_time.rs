@@ -0,0 +1,220 @@
+use core::fmt::Display;
+
+use crate::time::{NANOS_PER_SECOND, Result, SECONDS_PER_DAY, TimeError};
+
+/// A calendar date and time in UTC, as read from an RTC or shown to a user.
+///
+/// Uses the proleptic Gregorian calendar. `month` and `day` start at 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: i64,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    pub nanosecond: u32,
+}
+
+impl DateTime {
+    /// Creates a date time, validating each field.
+    pub fn new(year: i64, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> Result<Self> {
+        let date_time = Self {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+            nanosecond: 0,
+        };
+        date_time.validate()?;
+        Ok(date_time)
+    }
+
+    pub fn with_nanosecond(self, nanosecond: u32) -> Result<Self> {
+        if nanosecond >= NANOS_PER_SECOND {
+            return Err(TimeError::InvalidNanoseconds);
+        }
+        Ok(Self { nanosecond, ..self })
+    }
+
+    pub(crate) fn validate(&self) -> Result<()> {
+        if self.month == 0 || self.month > 12 {
+            return Err(TimeError::InvalidDateTime);
+        }
+        if self.day == 0 || self.day > days_in_month(self.year, self.month) {
+            return Err(TimeError::InvalidDateTime);
+        }
+        if self.hour > 23 || self.minute > 59 || self.second > 59 {
+            return Err(TimeError::InvalidDateTime);
+        }
+        if self.nanosecond >= NANOS_PER_SECOND {
+            return Err(TimeError::InvalidNanoseconds);
+        }
+        Ok(())
+    }
+
+    /// Number of seconds since 1970-01-01T00:00:00Z, ignoring `nanosecond`.
+    pub(crate) fn to_unix_seconds(self) -> Result<i64> {
+        self.validate()?;
+        let days = days_from_civil(self.year, self.month, self.day)?;
+        let time_of_day = self.hour as i64 * 3600 + self.minute as i64 * 60 + self.second as i64;
+        days.checked_mul(SECONDS_PER_DAY)
+            .and_then(|s| s.checked_add(time_of_day))
+            .ok_or(TimeError::OutOfRange)
+    }
+
+    pub(crate) fn from_unix_seconds(seconds: i64, nanosecond: u32) -> Result<Self> {
+        let days = seconds.div_euclid(SECONDS_PER_DAY);
+        let time_of_day = seconds.rem_euclid(SECONDS_PER_DAY);
+        let (year, month, day) = civil_from_days(days)?;
+        Ok(Self {
+            year,
+            month,
+            day,
+            hour: to_u8(time_of_day / 3600)?,
+            minute: to_u8(time_of_day % 3600 / 60)?,
+            second: to_u8(time_of_day % 60)?,
+            nanosecond,
+        })
+    }
+}
+
+/// Formats as ISO-8601 in UTC, e.g. `2025-07-04T12:30:00Z`. Fractional seconds
+/// are only printed when non-zero. Years outside `0000..=9999` use the expanded
+/// `+YYYYY`/`-YYYY` representation.
+impl Display for DateTime {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        if (0..=9999).contains(&self.year) {
+            write!(f, "{:04}", self.year)?;
+        } else {
+            write!(f, "{:+05}", self.year)?;
+        }
+        write!(
+            f,
+            "-{:02}-{:02}T{:02}:{:02}:{:02}",
+            self.month, self.day, self.hour, self.minute, self.second
+        )?;
+        if self.nanosecond != 0 {
+            write!(f, ".{:09}", self.nanosecond)?;
+        }
+        write!(f, "Z")
+    }
+}
+
+pub(crate) fn is_leap_year(year: i64) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+pub(crate) fn days_in_month(year: i64, month: u8) -> u8 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+fn to_u8(v: i64) -> Result<u8> {
+    u8::try_from(v).map_err(|_| TimeError::OutOfRange)
+}
+
+// see http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+fn days_from_civil(year: i64, month: u8, day: u8) -> Result<i64> {
+    let year = if month <= 2 {
+        year.checked_sub(1).ok_or(TimeError::OutOfRange)?
+    } else {
+        year
+    };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month_from_march = (month as i64 + 9) % 12;
+    let day_of_year = (153 * month_from_march + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era.checked_mul(146_097)
+        .and_then(|d| d.checked_add(day_of_era - 719_468))
+        .ok_or(TimeError::OutOfRange)
+}
+
+// see http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> Result<(i64, u8, u8)> {
+    let days = days.checked_add(719_468).ok_or(TimeError::OutOfRange)?;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    };
+    let year = year_of_era + era * 400;
+    let year = if month <= 2 { year + 1 } else { year };
+    Ok((year, to_u8(month)?, to_u8(day)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        assert!(DateTime::new(2024, 2, 29, 0, 0, 0).is_ok());
+        assert!(DateTime::new(2023, 2, 29, 0, 0, 0).is_err());
+        assert!(DateTime::new(1900, 2, 29, 0, 0, 0).is_err());
+        assert!(DateTime::new(2000, 2, 29, 0, 0, 0).is_ok());
+        assert!(DateTime::new(2024, 13, 1, 0, 0, 0).is_err());
+        assert!(DateTime::new(2024, 4, 31, 0, 0, 0).is_err());
+        assert!(DateTime::new(2024, 1, 1, 24, 0, 0).is_err());
+        assert!(
+            DateTime::new(2024, 1, 1, 0, 0, 0)
+                .unwrap()
+                .with_nanosecond(NANOS_PER_SECOND)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_unix_seconds_round_trip() {
+        for seconds in [
+            0,
+            -1,
+            951_782_400, // 2000-02-29
+            1_720_096_200,
+            -2_208_988_800,  // 1900-01-01
+            253_402_300_799, // 9999-12-31T23:59:59
+        ] {
+            let date_time = DateTime::from_unix_seconds(seconds, 0).unwrap();
+            assert_eq!(seconds, date_time.to_unix_seconds().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_extreme_years() {
+        for (year, month) in [(i64::MIN, 1), (i64::MIN, 3), (i64::MAX, 12)] {
+            let date_time = DateTime::new(year, month, 1, 0, 0, 0).unwrap();
+            assert_eq!(Err(TimeError::OutOfRange), date_time.to_unix_seconds());
+        }
+        assert!(DateTime::from_unix_seconds(i64::MIN, 0).is_ok());
+        assert!(DateTime::from_unix_seconds(i64::MAX, 0).is_ok());
+    }
+
+    #[test]
+    fn test_display() {
+        let date_time = DateTime::new(2024, 7, 4, 12, 30, 5).unwrap();
+        assert_eq!("2024-07-04T12:30:05Z", date_time.to_string());
+
+        let date_time = date_time.with_nanosecond(1_500).unwrap();
+        assert_eq!("2024-07-04T12:30:05.000001500Z", date_time.to_string());
+
+        let date_time = DateTime::new(-1, 1, 1, 0, 0, 0).unwrap();
+        assert_eq!("-0001-01-01T00:00:00Z", date_time.to_string());
+
+        let date_time = DateTime::new(10000, 1, 1, 0, 0, 0).unwrap();
+        assert_eq!("+10000-01-01T00:00:00Z", date_time.to_string());
+    }
+}
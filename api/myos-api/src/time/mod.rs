@@ -1,9 +1,23 @@
+mod date_time;
+mod timestamp;
+
+pub use core::time::Duration;
+pub use date_time::DateTime;
+pub use timestamp::Timestamp;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TimeError {
     ToEpochError,
+    InvalidDateTime,
+    InvalidNanoseconds,
+    OutOfRange,
 }
 
 pub type Result<T> = core::result::Result<T, TimeError>;
 
+pub(crate) const NANOS_PER_SECOND: u32 = 1_000_000_000;
+pub(crate) const SECONDS_PER_DAY: i64 = 86_400;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct TimeSeconds(pub u64);
 
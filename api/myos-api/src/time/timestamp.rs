@@ -0,0 +1,282 @@
+use core::{
+    fmt::Display,
+    ops::{Add, AddAssign, Sub, SubAssign},
+    time::Duration,
+};
+
+use crate::time::{DateTime, NANOS_PER_SECOND, Result, TimeError, TimeSeconds};
+
+const EXT4_EPOCH_BITS: u32 = 2;
+const EXT4_EPOCH_MASK: u32 = (1 << EXT4_EPOCH_BITS) - 1;
+
+/// A point in time, stored as seconds and nanoseconds relative to
+/// 1970-01-01T00:00:00Z. Times before the epoch have negative `seconds`;
+/// `nanoseconds` always counts forward from `seconds`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Timestamp {
+    seconds: i64,
+    nanoseconds: u32,
+}
+
+impl Timestamp {
+    pub const UNIX_EPOCH: Timestamp = Timestamp {
+        seconds: 0,
+        nanoseconds: 0,
+    };
+
+    pub const MIN: Timestamp = Timestamp {
+        seconds: i64::MIN,
+        nanoseconds: 0,
+    };
+
+    pub const MAX: Timestamp = Timestamp {
+        seconds: i64::MAX,
+        nanoseconds: NANOS_PER_SECOND - 1,
+    };
+
+    pub fn new(seconds: i64, nanoseconds: u32) -> Result<Self> {
+        if nanoseconds >= NANOS_PER_SECOND {
+            return Err(TimeError::InvalidNanoseconds);
+        }
+        Ok(Self {
+            seconds,
+            nanoseconds,
+        })
+    }
+
+    pub const fn from_seconds(seconds: i64) -> Self {
+        Self {
+            seconds,
+            nanoseconds: 0,
+        }
+    }
+
+    #[cfg(feature = "std")]
+    pub fn now() -> Result<Self> {
+        let duration = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_err(|_| TimeError::ToEpochError)?;
+        Timestamp::UNIX_EPOCH
+            .checked_add(duration)
+            .ok_or(TimeError::OutOfRange)
+    }
+
+    pub fn seconds(&self) -> i64 {
+        self.seconds
+    }
+
+    pub fn nanoseconds(&self) -> u32 {
+        self.nanoseconds
+    }
+
+    pub fn checked_add(&self, duration: Duration) -> Option<Self> {
+        let seconds = i64::try_from(duration.as_secs()).ok()?;
+        let mut seconds = self.seconds.checked_add(seconds)?;
+        let mut nanoseconds = self.nanoseconds + duration.subsec_nanos();
+        if nanoseconds >= NANOS_PER_SECOND {
+            nanoseconds -= NANOS_PER_SECOND;
+            seconds = seconds.checked_add(1)?;
+        }
+        Some(Self {
+            seconds,
+            nanoseconds,
+        })
+    }
+
+    pub fn checked_sub(&self, duration: Duration) -> Option<Self> {
+        let seconds = i64::try_from(duration.as_secs()).ok()?;
+        let mut seconds = self.seconds.checked_sub(seconds)?;
+        let nanoseconds = if self.nanoseconds >= duration.subsec_nanos() {
+            self.nanoseconds - duration.subsec_nanos()
+        } else {
+            seconds = seconds.checked_sub(1)?;
+            self.nanoseconds + NANOS_PER_SECOND - duration.subsec_nanos()
+        };
+        Some(Self {
+            seconds,
+            nanoseconds,
+        })
+    }
+
+    /// Returns the amount of time elapsed from `earlier` to `self`, or an error if
+    /// `earlier` is later than `self`.
+    pub fn duration_since(&self, earlier: Timestamp) -> Result<Duration> {
+        if *self < earlier {
+            return Err(TimeError::OutOfRange);
+        }
+        let (seconds, nanoseconds) = if self.nanoseconds >= earlier.nanoseconds {
+            (
+                self.seconds.abs_diff(earlier.seconds),
+                self.nanoseconds - earlier.nanoseconds,
+            )
+        } else {
+            (
+                self.seconds.abs_diff(earlier.seconds) - 1,
+                self.nanoseconds + NANOS_PER_SECOND - earlier.nanoseconds,
+            )
+        };
+        Ok(Duration::new(seconds, nanoseconds))
+    }
+
+    /// Decodes an ext4 timestamp from its 32-bit seconds field and the matching
+    /// `*_extra` field (`nsec << 2 | epoch`).
+    ///
+    /// see https://docs.kernel.org/filesystems/ext4/inodes.html#inode-timestamps
+    pub fn from_ext4(seconds: u32, extra: u32) -> Result<Self> {
+        // the low 32 bits are signed, the epoch bits extend the range past 2038
+        let seconds = seconds as i32 as i64 + (((extra & EXT4_EPOCH_MASK) as i64) << 32);
+        Self::new(seconds, extra >> EXT4_EPOCH_BITS)
+    }
+
+    /// Encodes as ext4's 32-bit seconds field and the matching `*_extra` field.
+    ///
+    /// Returns an error for times outside the range ext4 can represent
+    /// (1901-12-13 to 2446-05-10).
+    pub fn to_ext4(&self) -> Result<(u32, u32)> {
+        let min = i32::MIN as i64;
+        let max = min + (1 << (32 + EXT4_EPOCH_BITS)) - 1;
+        if self.seconds < min || self.seconds > max {
+            return Err(TimeError::OutOfRange);
+        }
+        let lo = u32::try_from(self.seconds & 0xffff_ffff).map_err(|_| TimeError::OutOfRange)?;
+        let epoch = u32::try_from((self.seconds - lo as i32 as i64) >> 32)
+            .map_err(|_| TimeError::OutOfRange)?;
+        Ok((lo, (self.nanoseconds << EXT4_EPOCH_BITS) | epoch))
+    }
+
+    /// Converts a calendar reading (e.g. from the CMOS RTC) in UTC.
+    pub fn from_date_time(date_time: &DateTime) -> Result<Self> {
+        Self::new(date_time.to_unix_seconds()?, date_time.nanosecond)
+    }
+
+    pub fn to_date_time(&self) -> Result<DateTime> {
+        DateTime::from_unix_seconds(self.seconds, self.nanoseconds)
+    }
+}
+
+impl From<TimeSeconds> for Timestamp {
+    fn from(value: TimeSeconds) -> Self {
+        Timestamp::from_seconds(i64::try_from(value.0).unwrap_or(i64::MAX))
+    }
+}
+
+impl TryFrom<DateTime> for Timestamp {
+    type Error = TimeError;
+
+    fn try_from(value: DateTime) -> Result<Self> {
+        Timestamp::from_date_time(&value)
+    }
+}
+
+/// Saturates at [`Timestamp::MAX`].
+impl Add<Duration> for Timestamp {
+    type Output = Timestamp;
+
+    fn add(self, rhs: Duration) -> Self::Output {
+        self.checked_add(rhs).unwrap_or(Timestamp::MAX)
+    }
+}
+
+impl AddAssign<Duration> for Timestamp {
+    fn add_assign(&mut self, rhs: Duration) {
+        *self = *self + rhs
+    }
+}
+
+/// Saturates at [`Timestamp::MIN`].
+impl Sub<Duration> for Timestamp {
+    type Output = Timestamp;
+
+    fn sub(self, rhs: Duration) -> Self::Output {
+        self.checked_sub(rhs).unwrap_or(Timestamp::MIN)
+    }
+}
+
+impl SubAssign<Duration> for Timestamp {
+    fn sub_assign(&mut self, rhs: Duration) {
+        *self = *self - rhs
+    }
+}
+
+/// Formats as ISO-8601 in UTC, see [`DateTime`].
+impl Display for Timestamp {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let date_time = self.to_date_time().map_err(|_| core::fmt::Error)?;
+        date_time.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_arithmetic() {
+        let t = Timestamp::new(10, 900_000_000).unwrap();
+        let t2 = t + Duration::from_millis(200);
+        assert_eq!(Timestamp::new(11, 100_000_000).unwrap(), t2);
+        assert_eq!(Duration::from_millis(200), t2.duration_since(t).unwrap());
+        assert!(t.duration_since(t2).is_err());
+        assert_eq!(t, t2 - Duration::from_millis(200));
+
+        let t = Timestamp::new(-1, 500_000_000).unwrap();
+        assert_eq!(
+            Timestamp::new(-2, 750_000_000).unwrap(),
+            t - Duration::from_millis(750)
+        );
+        assert_eq!(
+            Duration::from_millis(1500),
+            Timestamp::new(1, 0).unwrap().duration_since(t).unwrap()
+        );
+
+        assert_eq!(Timestamp::MAX, Timestamp::MAX + Duration::from_nanos(1));
+        assert_eq!(Timestamp::MIN, Timestamp::MIN - Duration::from_nanos(1));
+        assert!(Timestamp::new(0, NANOS_PER_SECOND).is_err());
+    }
+
+    #[test]
+    fn test_ext4_round_trip() {
+        for (seconds, nanoseconds) in [
+            (0, 0),
+            (-1, 999_999_999),
+            (i32::MIN as i64, 0),
+            (i32::MAX as i64 + 1, 5),     // 2038-01-19T03:14:08Z
+            (0x3_7fff_ffff, 999_999_999), // 2446-05-10T22:38:55Z
+        ] {
+            let t = Timestamp::new(seconds, nanoseconds).unwrap();
+            let (lo, extra) = t.to_ext4().unwrap();
+            assert_eq!(t, Timestamp::from_ext4(lo, extra).unwrap());
+        }
+
+        assert_eq!(
+            (0x8000_0000, 1),
+            Timestamp::from_seconds(i32::MAX as i64 + 1)
+                .to_ext4()
+                .unwrap()
+        );
+        assert!(
+            Timestamp::from_seconds(i32::MIN as i64 - 1)
+                .to_ext4()
+                .is_err()
+        );
+        assert!(Timestamp::from_seconds(0x3_8000_0000).to_ext4().is_err());
+    }
+
+    #[test]
+    fn test_date_time() {
+        let date_time = DateTime::new(2024, 7, 4, 12, 30, 5)
+            .unwrap()
+            .with_nanosecond(250)
+            .unwrap();
+        let t = Timestamp::from_date_time(&date_time).unwrap();
+        assert_eq!(1_720_096_205, t.seconds());
+        assert_eq!(250, t.nanoseconds());
+        assert_eq!(date_time, t.to_date_time().unwrap());
+        assert_eq!("2024-07-04T12:30:05.000000250Z", t.to_string());
+
+        assert_eq!(
+            "1969-12-31T23:59:59Z",
+            Timestamp::from_seconds(-1).to_string()
+        );
+    }
+}
@@ -1,11 +1,13 @@
 use anyhow::Context;
 use bootloader::DiskImageBuilder;
+use myos_api::{Uid, filesystem::Mode, time::Timestamp};
+use nostdio::OffsetBlockDevice;
 use std::{
     env,
     fs::{self},
     path::{Path, PathBuf},
 };
-use vsfs::{CreateFileOptions, ROOT_UID};
+use vsfs::CreateFileOptions;
 
 fn main() {
     // set by cargo for the kernel artifact dependency
@@ -29,7 +31,7 @@ fn main() {
 
 fn create_ram_disk(out_dir: &Path) -> anyhow::Result<PathBuf> {
     let ram_disk_path = out_dir.join("myos-ram-disk.img");
-    let mut ram_disk_file = fs::OpenOptions::new()
+    let ram_disk_file = fs::OpenOptions::new()
         .read(true)
         .write(true)
         .create(true)
@@ -40,18 +42,28 @@ fn create_ram_disk(out_dir: &Path) -> anyhow::Result<PathBuf> {
     let inode_count = 100;
     let data_block_count = 100;
     let format_options = vsfs::FormatVolumeOptions::new(inode_count, data_block_count);
-    vsfs::format_volume(&mut ram_disk_file, format_options).expect("failed to format volume");
+    ram_disk_file
+        .set_len(format_options.volume_size())
+        .context("Failed to size ram disk file")?;
+    let device = OffsetBlockDevice::from_file(ram_disk_file, 512)
+        .map_err(|err| anyhow::anyhow!("failed to open ram disk: {err:?}"))?;
+    let mut fs = vsfs::format_volume(device, format_options)
+        .map_err(|err| anyhow::anyhow!("failed to format volume: {err:?}"))?;
 
-    let fs = vsfs::Vsfs::new(&mut ram_disk_file, vsfs::FsOptions::new()).unwrap();
-    let mut root_dir = fs.root_dir();
+    let mut root_dir = fs.root_dir().unwrap();
     let mut file = root_dir
-        .create_file(CreateFileOptions {
-            file_name: "hello.txt",
-            uid: ROOT_UID,
-            gid: ROOT_UID,
-            mode: 0o755,
-        })
+        .create_file(
+            &mut fs,
+            CreateFileOptions {
+                file_name: "hello.txt",
+                uid: Uid::root(),
+                gid: Uid::root(),
+                mode: Mode(0o644),
+                time: Timestamp::now().unwrap(),
+            },
+        )
         .unwrap();
     file.write_all(b"Hello World!").unwrap();
+    file.flush().unwrap();
     Ok(ram_disk_path)
 }
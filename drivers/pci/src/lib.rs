@@ -3,7 +3,7 @@
 
 use core::fmt::Debug;
 
-use spin::Mutex;
+use irq_sync::IrqSpinLock;
 
 use crate::{
     types::{DeviceId, PciAddress, PciCommonHeader, PciConfigPort, VendorId},
@@ -16,11 +16,11 @@ mod x86;
 pub static PCI_DRIVER: PciDriver<X86PciConfigPort> = PciDriver::new(&PCI_CONFIG_PORT);
 
 pub struct PciDriver<'a, T: PciConfigPort> {
-    config_port: &'a Mutex<T>,
+    config_port: &'a IrqSpinLock<T>,
 }
 
 impl<'a, T: PciConfigPort> PciDriver<'a, T> {
-    pub const fn new(config_port: &'a Mutex<T>) -> Self {
+    pub const fn new(config_port: &'a IrqSpinLock<T>) -> Self {
         Self { config_port }
     }
 
@@ -30,7 +30,7 @@ impl<'a, T: PciConfigPort> PciDriver<'a, T> {
 }
 
 pub struct PciDeviceIterator<'a, T: PciConfigPort> {
-    config_port: &'a Mutex<T>,
+    config_port: &'a IrqSpinLock<T>,
     has_next: bool,
     next_bus: u8,
     next_device: u8,
@@ -38,7 +38,7 @@ pub struct PciDeviceIterator<'a, T: PciConfigPort> {
 }
 
 impl<'a, T: PciConfigPort> PciDeviceIterator<'a, T> {
-    fn new(config_port: &'a Mutex<T>) -> Self {
+    fn new(config_port: &'a IrqSpinLock<T>) -> Self {
         Self {
             config_port,
             has_next: true,
@@ -96,7 +96,7 @@ impl<'a, T: PciConfigPort> Iterator for PciDeviceIterator<'a, T> {
 }
 
 pub struct PciDevice<'a, T: PciConfigPort> {
-    _config_port: &'a Mutex<T>,
+    _config_port: &'a IrqSpinLock<T>,
     pub addr: PciAddress,
     pub vendor_id: VendorId,
     pub device_id: DeviceId,
@@ -105,7 +105,7 @@ pub struct PciDevice<'a, T: PciConfigPort> {
 
 impl<'a, T: PciConfigPort> PciDevice<'a, T> {
     fn new(
-        config_port: &'a Mutex<T>,
+        config_port: &'a IrqSpinLock<T>,
         addr: PciAddress,
         vendor_id: VendorId,
         device_id: DeviceId,
@@ -1,21 +1,22 @@
-use spin::Mutex;
+use irq_sync::IrqSpinLock;
 use x86_64::instructions::port::{PortGeneric, ReadWriteAccess};
 
 use crate::types::{PciAddress, PciConfigPort};
 
-pub static PCI_CONFIG_PORT: Mutex<X86PciConfigPort> = Mutex::new(X86PciConfigPort::new());
+pub static PCI_CONFIG_PORT: IrqSpinLock<X86PciConfigPort> =
+    IrqSpinLock::new(X86PciConfigPort::new());
 
 const PCI_CONFIG_ADDRESS: u16 = 0xcf8;
 const PCI_CONFIG_DATA: u16 = 0xcfc;
 
 pub struct X86PciConfigPort {
-    inner: Mutex<X86PciConfigPortInner>,
+    inner: IrqSpinLock<X86PciConfigPortInner>,
 }
 
 impl X86PciConfigPort {
     pub const fn new() -> Self {
         Self {
-            inner: Mutex::new(X86PciConfigPortInner::new()),
+            inner: IrqSpinLock::new(X86PciConfigPortInner::new()),
         }
     }
 }
@@ -2,17 +2,20 @@
 
 // see https://github.com/rust-osdev/uart_16550/blob/master/src/port.rs
 
-use conquer_once::{spin::OnceCell, TryInitError};
-use spin::Mutex;
-use port::SerialPort;
 use bitflags::bitflags;
+use conquer_once::{TryInitError, spin::OnceCell};
+use irq_sync::IrqSpinLock;
+
+use error::WouldBlockError;
+use port::SerialPort;
 
 pub mod error;
 pub mod port;
 
 pub const SERIAL1_ADDR: u16 = 0x03f8;
 
-static SERIAL1: OnceCell<Mutex<SerialPort>> = OnceCell::uninit();
+/// Printed to from interrupt handlers, so it disables interrupts while locked.
+static SERIAL1: OnceCell<IrqSpinLock<SerialPort>> = OnceCell::uninit();
 
 /// Initialize serial 1
 /// 
@@ -21,7 +24,7 @@ static SERIAL1: OnceCell<Mutex<SerialPort>> = OnceCell::uninit();
 pub unsafe fn serial1_init() -> Result<(), TryInitError> {
     SERIAL1.try_init_once(|| {
         let serial_port = unsafe { SerialPort::new(SERIAL1_ADDR) };
-        spin::Mutex::new(serial_port)
+        IrqSpinLock::new(serial_port)
     })
 }
 
@@ -37,6 +40,17 @@ bitflags! {
     }
 }
 
+bitflags! {
+    /// Interrupt enable flags
+    #[repr(transparent)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    struct IntEnFlags: u8 {
+        const RECEIVED = 1;
+        const SENT = 1 << 1;
+        // 2 to 7 unknown
+    }
+}
+
 #[macro_export]
 macro_rules! retry_until_ok {
     ($cond:expr) => {
@@ -49,6 +63,25 @@ macro_rules! retry_until_ok {
     };
 }
 
+/// Enables the serial 1 receive interrupt, see [`serial1_try_receive`].
+pub fn serial1_enable_receive_interrupt() {
+    if let Ok(serial1) = SERIAL1.try_get() {
+        serial1.lock().enable_receive_interrupt();
+    }
+}
+
+/// Tries to receive a byte from serial 1.
+///
+/// Doesn't lock serial 1, so it can be called from the receive interrupt
+/// while the interrupted code is printing.
+pub fn serial1_try_receive() -> Result<u8, WouldBlockError> {
+    if SERIAL1.is_initialized() {
+        unsafe { SerialPort::new(SERIAL1_ADDR) }.try_receive()
+    } else {
+        Err(WouldBlockError)
+    }
+}
+
 pub fn serial_print_args(args: ::core::fmt::Arguments) -> core::fmt::Result {
     use core::fmt::Write;
     if let Ok(serial1) = SERIAL1.try_get() {
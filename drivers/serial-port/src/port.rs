@@ -1,4 +1,6 @@
-use crate::{error::WouldBlockError, retry_until_ok, LineStsFlags};
+use crate::{error::WouldBlockError, retry_until_ok, IntEnFlags, LineStsFlags};
+
+const MODEM_CTRL_OUT2: u8 = 1 << 3;
 
 pub struct SerialPort(u16);
 
@@ -15,7 +17,6 @@ impl SerialPort {
         self.port_base()
     }
 
-    #[allow(dead_code)]
     /// Interrupt enable port.
     ///
     /// Write only.
@@ -39,10 +40,9 @@ impl SerialPort {
         self.port_base() + 3
     }
 
-    #[allow(dead_code)]
     /// Modem control port.
     ///
-    /// Write only.
+    /// Read and write.
     fn port_modem_ctrl(&self) -> u16 {
         self.port_base() + 4
     }
@@ -95,6 +95,25 @@ impl SerialPort {
         }
     }
 
+    /// Tries to receive a byte from the serial port.
+    pub fn try_receive(&mut self) -> Result<u8, WouldBlockError> {
+        if self.line_sts().contains(LineStsFlags::INPUT_FULL) {
+            Ok(unsafe { x86::io::inb(self.port_data()) })
+        } else {
+            Err(WouldBlockError)
+        }
+    }
+
+    /// Raises an interrupt whenever a byte is received.
+    pub fn enable_receive_interrupt(&mut self) {
+        unsafe {
+            x86::io::outb(self.port_int_en(), IntEnFlags::RECEIVED.bits());
+            // OUT2 connects the UART interrupt line on PC compatibles
+            let modem_ctrl = x86::io::inb(self.port_modem_ctrl());
+            x86::io::outb(self.port_modem_ctrl(), modem_ctrl | MODEM_CTRL_OUT2);
+        }
+    }
+
     fn line_sts(&mut self) -> LineStsFlags {
         unsafe { LineStsFlags::from_bits_truncate(x86::io::inb(self.port_line_sts())) }
     }
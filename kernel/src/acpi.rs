@@ -0,0 +1,720 @@
+// see https://wiki.osdev.org/RSDP and https://uefi.org/specs/ACPI/6.5/05_ACPI_Software_Programming_Model.html
+
+use alloc::vec::Vec;
+use core::convert::Infallible;
+
+use conquer_once::spin::OnceCell;
+use x86_64::{PhysAddr, VirtAddr, instructions::port::Port};
+use zerocopy::{FromBytes, Immutable, KnownLayout};
+
+use crate::apic::{ApicConfig, InterruptOverride, IoApicInfo, Polarity, TriggerMode};
+
+const RSDP_SIGNATURE: [u8; 8] = *b"RSD PTR ";
+const MADT_SIGNATURE: [u8; 4] = *b"APIC";
+const HPET_SIGNATURE: [u8; 4] = *b"HPET";
+const MCFG_SIGNATURE: [u8; 4] = *b"MCFG";
+const FADT_SIGNATURE: [u8; 4] = *b"FACP";
+const DSDT_SIGNATURE: [u8; 4] = *b"DSDT";
+
+/// Areas searched for the RSDP when the bootloader didn't find it.
+const EBDA_POINTER_ADDRESS: u64 = 0x40e;
+const BIOS_AREA_START: u64 = 0xe_0000;
+const BIOS_AREA_END: u64 = 0x10_0000;
+
+const ADDRESS_SPACE_SYSTEM_MEMORY: u8 = 0;
+const ADDRESS_SPACE_SYSTEM_IO: u8 = 1;
+
+const FADT_FLAG_RESET_REG_SUPPORTED: u32 = 1 << 10;
+const PM1_CNT_SCI_EN: u16 = 1 << 0;
+const PM1_CNT_SLP_EN: u16 = 1 << 13;
+const PM1_CNT_SLP_TYP_SHIFT: u16 = 10;
+
+const MADT_FLAG_PCAT_COMPAT: u32 = 1 << 0;
+const MADT_PROCESSOR_ENABLED: u32 = 1 << 0;
+const MADT_PROCESSOR_ONLINE_CAPABLE: u32 = 1 << 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcpiError {
+    AlreadyInitialized,
+    NotInitialized,
+    RsdpNotFound,
+    InvalidRsdp,
+    /// The table with the given signature failed validation
+    InvalidTable([u8; 4]),
+    /// A required table is missing
+    MissingTable([u8; 4]),
+    /// The `\_S5` sleep package wasn't found in the DSDT
+    NoS5,
+    Unsupported,
+}
+
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy, FromBytes, Immutable, KnownLayout)]
+struct Rsdp {
+    signature: [u8; 8],
+    checksum: u8,
+    oem_id: [u8; 6],
+    revision: u8,
+    rsdt_address: u32,
+    // ACPI 2.0+
+    length: u32,
+    xsdt_address: u64,
+    extended_checksum: u8,
+    reserved: [u8; 3],
+}
+
+const RSDP_V1_SIZE: usize = 20;
+
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy, FromBytes, Immutable, KnownLayout)]
+struct SdtHeader {
+    signature: [u8; 4],
+    length: u32,
+    revision: u8,
+    checksum: u8,
+    oem_id: [u8; 6],
+    oem_table_id: [u8; 8],
+    oem_revision: u32,
+    creator_id: u32,
+    creator_revision: u32,
+}
+
+const SDT_HEADER_SIZE: usize = core::mem::size_of::<SdtHeader>();
+
+/// Register location used by the FADT and HPET tables.
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy, Default, FromBytes, Immutable, KnownLayout)]
+pub struct GenericAddress {
+    pub address_space: u8,
+    pub bit_width: u8,
+    pub bit_offset: u8,
+    pub access_size: u8,
+    pub address: u64,
+}
+
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy, FromBytes, Immutable, KnownLayout)]
+struct PhysicalMadt {
+    header: SdtHeader,
+    local_apic_address: u32,
+    flags: u32,
+    // followed by variable length entries
+}
+
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy, FromBytes, Immutable, KnownLayout)]
+struct PhysicalHpet {
+    header: SdtHeader,
+    event_timer_block_id: u32,
+    address: GenericAddress,
+    hpet_number: u8,
+    minimum_tick: u16,
+    page_protection: u8,
+}
+
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy, FromBytes, Immutable, KnownLayout)]
+struct PhysicalMcfgEntry {
+    base_address: u64,
+    segment_group: u16,
+    start_bus: u8,
+    end_bus: u8,
+    reserved: u32,
+}
+
+const MCFG_ENTRY_SIZE: usize = core::mem::size_of::<PhysicalMcfgEntry>();
+/// Offset of the first MCFG entry, the header is followed by 8 reserved bytes.
+const MCFG_ENTRIES_OFFSET: usize = SDT_HEADER_SIZE + 8;
+
+/// Fixed ACPI Description Table up to the extended PM1 control blocks, older
+/// revisions are shorter and the missing fields read as zero.
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy, FromBytes, Immutable, KnownLayout)]
+struct PhysicalFadt {
+    header: SdtHeader,
+    firmware_ctrl: u32,
+    dsdt: u32,
+    reserved0: u8,
+    preferred_pm_profile: u8,
+    sci_interrupt: u16,
+    smi_command: u32,
+    acpi_enable: u8,
+    acpi_disable: u8,
+    s4bios_req: u8,
+    pstate_control: u8,
+    pm1a_event_block: u32,
+    pm1b_event_block: u32,
+    pm1a_control_block: u32,
+    pm1b_control_block: u32,
+    pm2_control_block: u32,
+    pm_timer_block: u32,
+    gpe0_block: u32,
+    gpe1_block: u32,
+    pm1_event_length: u8,
+    pm1_control_length: u8,
+    pm2_control_length: u8,
+    pm_timer_length: u8,
+    gpe0_block_length: u8,
+    gpe1_block_length: u8,
+    gpe1_base: u8,
+    cstate_control: u8,
+    worst_c2_latency: u16,
+    worst_c3_latency: u16,
+    flush_size: u16,
+    flush_stride: u16,
+    duty_offset: u8,
+    duty_width: u8,
+    day_alarm: u8,
+    month_alarm: u8,
+    century: u8,
+    boot_architecture_flags: u16,
+    reserved1: u8,
+    flags: u32,
+    reset_register: GenericAddress,
+    reset_value: u8,
+    arm_boot_architecture_flags: u16,
+    minor_version: u8,
+    x_firmware_ctrl: u64,
+    x_dsdt: u64,
+    x_pm1a_event_block: GenericAddress,
+    x_pm1b_event_block: GenericAddress,
+    x_pm1a_control_block: GenericAddress,
+    x_pm1b_control_block: GenericAddress,
+}
+
+#[derive(Debug, Clone, Copy)]
+#[allow(dead_code)]
+pub struct Processor {
+    pub processor_uid: u32,
+    pub apic_id: u32,
+    /// Disabled processors may still be brought online if online capable
+    pub enabled: bool,
+    pub online_capable: bool,
+}
+
+#[derive(Debug, Clone)]
+#[allow(dead_code)]
+pub struct Madt {
+    pub local_apic_address: PhysAddr,
+    /// The system also has the legacy 8259 PICs which must be disabled
+    pub pc_at_compatible: bool,
+    pub processors: Vec<Processor>,
+    pub io_apics: Vec<IoApicInfo>,
+    pub overrides: Vec<InterruptOverride>,
+}
+
+impl Madt {
+    pub fn apic_config(&self) -> ApicConfig {
+        ApicConfig {
+            io_apics: self.io_apics.clone(),
+            overrides: self.overrides.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+#[allow(dead_code)]
+pub struct Hpet {
+    pub address: PhysAddr,
+    pub hpet_number: u8,
+    /// Minimum clock ticks for periodic mode without losing interrupts
+    pub minimum_tick: u16,
+}
+
+/// Memory mapped PCI express configuration space of a segment group.
+#[derive(Debug, Clone, Copy)]
+#[allow(dead_code)]
+pub struct McfgEntry {
+    pub base_address: PhysAddr,
+    pub segment_group: u16,
+    pub start_bus: u8,
+    pub end_bus: u8,
+}
+
+#[derive(Debug, Clone, Copy)]
+#[allow(dead_code)]
+pub struct Fadt {
+    pub sci_interrupt: u16,
+    pub smi_command: u32,
+    pub acpi_enable: u8,
+    pub pm1a_control_block: u32,
+    pub pm1b_control_block: u32,
+    pub pm_timer_block: u32,
+    /// CMOS RTC index of the century register, 0 if not supported
+    pub century: u8,
+    pub boot_architecture_flags: u16,
+    pub flags: u32,
+    pub reset_register: Option<GenericAddress>,
+    pub reset_value: u8,
+    dsdt: PhysAddr,
+}
+
+#[derive(Debug, Clone)]
+pub struct AcpiTables {
+    pub revision: u8,
+    pub madt: Option<Madt>,
+    pub hpet: Option<Hpet>,
+    pub mcfg: Vec<McfgEntry>,
+    pub fadt: Option<Fadt>,
+    /// Sleep type values written to PM1a/PM1b for soft off
+    s5_sleep_types: Option<(u8, u8)>,
+}
+
+struct AcpiState {
+    physical_memory_offset: VirtAddr,
+    tables: AcpiTables,
+}
+
+static ACPI: OnceCell<AcpiState> = OnceCell::uninit();
+
+/// Locates the RSDP, using the address from the bootloader when available,
+/// and parses the tables the kernel uses.
+///
+/// # Safety
+/// Physical memory must be mapped at `physical_memory_offset`.
+pub unsafe fn init(
+    physical_memory_offset: VirtAddr,
+    rsdp_addr: Option<u64>,
+) -> Result<&'static AcpiTables, AcpiError> {
+    let memory = PhysicalMemory(physical_memory_offset);
+    let rsdp_addr = match rsdp_addr {
+        Some(addr) => PhysAddr::new(addr),
+        None => memory.find_rsdp().ok_or(AcpiError::RsdpNotFound)?,
+    };
+    let tables = memory.parse_tables(rsdp_addr)?;
+    ACPI.try_init_once(|| AcpiState {
+        physical_memory_offset,
+        tables,
+    })
+    .map_err(|_| AcpiError::AlreadyInitialized)?;
+    tables_ref()
+}
+
+/// Tables parsed by [`init`].
+pub fn tables() -> Option<&'static AcpiTables> {
+    tables_ref().ok()
+}
+
+fn tables_ref() -> Result<&'static AcpiTables, AcpiError> {
+    ACPI.try_get()
+        .map(|state| &state.tables)
+        .map_err(|_| AcpiError::NotInitialized)
+}
+
+/// Powers off the machine by entering the S5 sleep state.
+pub fn shutdown() -> Result<Infallible, AcpiError> {
+    let state = ACPI.try_get().map_err(|_| AcpiError::NotInitialized)?;
+    let fadt = state
+        .tables
+        .fadt
+        .ok_or(AcpiError::MissingTable(FADT_SIGNATURE))?;
+    let (slp_typa, slp_typb) = state.tables.s5_sleep_types.ok_or(AcpiError::NoS5)?;
+    let pm1a_control_block =
+        u16::try_from(fadt.pm1a_control_block).map_err(|_| AcpiError::Unsupported)?;
+    if pm1a_control_block == 0 {
+        return Err(AcpiError::Unsupported);
+    }
+
+    x86_64::instructions::interrupts::disable();
+    unsafe {
+        enable_acpi_mode(&fadt, pm1a_control_block);
+        write_sleep_type(pm1a_control_block, slp_typa);
+        if let Ok(pm1b_control_block) = u16::try_from(fadt.pm1b_control_block)
+            && pm1b_control_block != 0
+        {
+            write_sleep_type(pm1b_control_block, slp_typb);
+        }
+    }
+
+    // the write takes effect asynchronously
+    loop {
+        x86_64::instructions::hlt();
+    }
+}
+
+/// Resets the machine using the FADT reset register, falling back to the
+/// keyboard controller and finally a triple fault.
+pub fn reboot() -> ! {
+    x86_64::instructions::interrupts::disable();
+
+    if let Ok(state) = ACPI.try_get()
+        && let Some(fadt) = state.tables.fadt
+        && let Some(reset_register) = fadt.reset_register
+    {
+        unsafe {
+            write_generic_address(
+                state.physical_memory_offset,
+                &reset_register,
+                fadt.reset_value,
+            )
+        };
+    }
+
+    // pulse the CPU reset line through the 8042
+    unsafe { Port::<u8>::new(0x64).write(0xfe) };
+
+    // load an empty IDT, the next interrupt triple faults
+    unsafe {
+        let idt = x86_64::structures::DescriptorTablePointer {
+            limit: 0,
+            base: VirtAddr::zero(),
+        };
+        x86_64::instructions::tables::lidt(&idt);
+        core::arch::asm!("int3");
+    }
+    loop {
+        x86_64::instructions::hlt();
+    }
+}
+
+unsafe fn enable_acpi_mode(fadt: &Fadt, pm1a_control_block: u16) {
+    let mut pm1a_control = Port::<u16>::new(pm1a_control_block);
+    if unsafe { pm1a_control.read() } & PM1_CNT_SCI_EN != 0 {
+        return;
+    }
+    let Ok(smi_command) = u16::try_from(fadt.smi_command) else {
+        return;
+    };
+    if smi_command == 0 || fadt.acpi_enable == 0 {
+        return;
+    }
+    unsafe {
+        Port::<u8>::new(smi_command).write(fadt.acpi_enable);
+        for _ in 0..1_000_000 {
+            if pm1a_control.read() & PM1_CNT_SCI_EN != 0 {
+                break;
+            }
+            core::hint::spin_loop();
+        }
+    }
+}
+
+unsafe fn write_sleep_type(control_block: u16, sleep_type: u8) {
+    let mut port = Port::<u16>::new(control_block);
+    unsafe {
+        let value = port.read() & !(0b111 << PM1_CNT_SLP_TYP_SHIFT);
+        port.write(value | ((sleep_type as u16 & 0b111) << PM1_CNT_SLP_TYP_SHIFT) | PM1_CNT_SLP_EN);
+    }
+}
+
+unsafe fn write_generic_address(
+    physical_memory_offset: VirtAddr,
+    register: &GenericAddress,
+    value: u8,
+) {
+    let address = register.address;
+    match register.address_space {
+        ADDRESS_SPACE_SYSTEM_IO => {
+            if let Ok(port) = u16::try_from(address) {
+                unsafe { Port::<u8>::new(port).write(value) };
+            }
+        }
+        ADDRESS_SPACE_SYSTEM_MEMORY => unsafe {
+            core::ptr::write_volatile((physical_memory_offset + address).as_mut_ptr::<u8>(), value)
+        },
+        // PCI configuration space resets are not supported
+        _ => {}
+    }
+}
+
+struct PhysicalMemory(VirtAddr);
+
+impl PhysicalMemory {
+    /// # Safety
+    /// `addr..addr + len` must be mapped physical memory.
+    unsafe fn slice(&self, addr: PhysAddr, len: usize) -> &'static [u8] {
+        unsafe { core::slice::from_raw_parts((self.0 + addr.as_u64()).as_ptr::<u8>(), len) }
+    }
+
+    fn find_rsdp(&self) -> Option<PhysAddr> {
+        let ebda_segment = unsafe { self.slice(PhysAddr::new(EBDA_POINTER_ADDRESS), 2) };
+        let ebda = u16::from_le_bytes([ebda_segment[0], ebda_segment[1]]) as u64 * 16;
+        let ebda_area = (ebda != 0).then_some(ebda..ebda + 1024);
+        ebda_area
+            .into_iter()
+            .chain(core::iter::once(BIOS_AREA_START..BIOS_AREA_END))
+            .flat_map(|area| area.step_by(16))
+            .map(PhysAddr::new)
+            .find(|addr| {
+                let bytes = unsafe { self.slice(*addr, RSDP_V1_SIZE) };
+                bytes.starts_with(&RSDP_SIGNATURE) && checksum_ok(bytes)
+            })
+    }
+
+    fn parse_tables(&self, rsdp_addr: PhysAddr) -> Result<AcpiTables, AcpiError> {
+        let rsdp_bytes = unsafe { self.slice(rsdp_addr, core::mem::size_of::<Rsdp>()) };
+        let rsdp = Rsdp::read_from_bytes(rsdp_bytes).map_err(|_| AcpiError::InvalidRsdp)?;
+        if rsdp.signature != RSDP_SIGNATURE || !checksum_ok(&rsdp_bytes[..RSDP_V1_SIZE]) {
+            return Err(AcpiError::InvalidRsdp);
+        }
+
+        // ACPI 2.0+ uses the XSDT with 64-bit table pointers
+        let (root, entry_size) = if rsdp.revision >= 2 && rsdp.xsdt_address != 0 {
+            if !checksum_ok(rsdp_bytes) {
+                return Err(AcpiError::InvalidRsdp);
+            }
+            (self.table(PhysAddr::new(rsdp.xsdt_address), *b"XSDT")?, 8)
+        } else {
+            (
+                self.table(PhysAddr::new(rsdp.rsdt_address as u64), *b"RSDT")?,
+                4,
+            )
+        };
+
+        let mut tables = AcpiTables {
+            revision: rsdp.revision,
+            madt: None,
+            hpet: None,
+            mcfg: Vec::new(),
+            fadt: None,
+            s5_sleep_types: None,
+        };
+
+        for entry in root[SDT_HEADER_SIZE..].chunks_exact(entry_size) {
+            let addr = match entry_size {
+                8 => u64::from_le_bytes(entry.try_into().unwrap_or_default()),
+                _ => u32::from_le_bytes(entry.try_into().unwrap_or_default()) as u64,
+            };
+            let addr = PhysAddr::new(addr);
+            let Some(signature) = self.signature(addr) else {
+                continue;
+            };
+            match signature {
+                MADT_SIGNATURE => tables.madt = Some(parse_madt(self.table(addr, signature)?)?),
+                HPET_SIGNATURE => tables.hpet = Some(parse_hpet(self.table(addr, signature)?)?),
+                MCFG_SIGNATURE => tables.mcfg = parse_mcfg(self.table(addr, signature)?),
+                FADT_SIGNATURE => {
+                    let fadt = parse_fadt(self.table(addr, signature)?)?;
+                    tables.s5_sleep_types = self
+                        .table(fadt.dsdt, DSDT_SIGNATURE)
+                        .ok()
+                        .and_then(find_s5_sleep_types);
+                    tables.fadt = Some(fadt);
+                }
+                _ => {}
+            }
+        }
+
+        Ok(tables)
+    }
+
+    fn signature(&self, addr: PhysAddr) -> Option<[u8; 4]> {
+        if addr.is_null() {
+            return None;
+        }
+        let bytes = unsafe { self.slice(addr, 4) };
+        bytes.try_into().ok()
+    }
+
+    /// Returns the bytes of the table at `addr` after checking its signature
+    /// and checksum.
+    fn table(&self, addr: PhysAddr, signature: [u8; 4]) -> Result<&'static [u8], AcpiError> {
+        if addr.is_null() {
+            return Err(AcpiError::MissingTable(signature));
+        }
+        let header = unsafe { self.slice(addr, SDT_HEADER_SIZE) };
+        let header =
+            SdtHeader::read_from_bytes(header).map_err(|_| AcpiError::InvalidTable(signature))?;
+        let length = header.length as usize;
+        if header.signature != signature || length < SDT_HEADER_SIZE {
+            return Err(AcpiError::InvalidTable(signature));
+        }
+        let bytes = unsafe { self.slice(addr, length) };
+        if !checksum_ok(bytes) {
+            return Err(AcpiError::InvalidTable(signature));
+        }
+        Ok(bytes)
+    }
+}
+
+fn checksum_ok(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) == 0
+}
+
+fn parse_madt(bytes: &[u8]) -> Result<Madt, AcpiError> {
+    let (madt, mut entries) = PhysicalMadt::read_from_prefix(bytes)
+        .map_err(|_| AcpiError::InvalidTable(MADT_SIGNATURE))?;
+    let mut result = Madt {
+        local_apic_address: PhysAddr::new(madt.local_apic_address as u64),
+        pc_at_compatible: madt.flags & MADT_FLAG_PCAT_COMPAT != 0,
+        processors: Vec::new(),
+        io_apics: Vec::new(),
+        overrides: Vec::new(),
+    };
+
+    while let [entry_type, len, ..] = *entries {
+        let len = len as usize;
+        if len < 2 || len > entries.len() {
+            return Err(AcpiError::InvalidTable(MADT_SIGNATURE));
+        }
+        let (entry, rest) = entries.split_at(len);
+        entries = rest;
+        let data = &entry[2..];
+        let u16_at = |i: usize| u16::from_le_bytes([data[i], data[i + 1]]);
+        let u32_at =
+            |i: usize| u32::from_le_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+        match (entry_type, data.len()) {
+            // processor local APIC
+            (0, 6..) => {
+                let flags = u32_at(2);
+                result.processors.push(Processor {
+                    processor_uid: data[0] as u32,
+                    apic_id: data[1] as u32,
+                    enabled: flags & MADT_PROCESSOR_ENABLED != 0,
+                    online_capable: flags & MADT_PROCESSOR_ONLINE_CAPABLE != 0,
+                });
+            }
+            // I/O APIC
+            (1, 10..) => result.io_apics.push(IoApicInfo {
+                address: PhysAddr::new(u32_at(2) as u64),
+                gsi_base: u32_at(6),
+            }),
+            // interrupt source override, only the ISA bus is defined
+            (2, 8..) => {
+                let flags = u16_at(6);
+                result.overrides.push(InterruptOverride {
+                    irq: data[1],
+                    gsi: u32_at(2),
+                    polarity: match flags & 0b11 {
+                        0b11 => Polarity::ActiveLow,
+                        _ => Polarity::ActiveHigh,
+                    },
+                    trigger: match (flags >> 2) & 0b11 {
+                        0b11 => TriggerMode::Level,
+                        _ => TriggerMode::Edge,
+                    },
+                });
+            }
+            // local APIC address override
+            (5, 10..) => {
+                let addr = u64::from(u32_at(2)) | (u64::from(u32_at(6)) << 32);
+                result.local_apic_address = PhysAddr::new(addr);
+            }
+            // processor local x2APIC
+            (9, 14..) => {
+                let flags = u32_at(6);
+                result.processors.push(Processor {
+                    processor_uid: u32_at(10),
+                    apic_id: u32_at(2),
+                    enabled: flags & MADT_PROCESSOR_ENABLED != 0,
+                    online_capable: flags & MADT_PROCESSOR_ONLINE_CAPABLE != 0,
+                });
+            }
+            _ => {}
+        }
+    }
+
+    Ok(result)
+}
+
+fn parse_hpet(bytes: &[u8]) -> Result<Hpet, AcpiError> {
+    let (hpet, _) = PhysicalHpet::read_from_prefix(bytes)
+        .map_err(|_| AcpiError::InvalidTable(HPET_SIGNATURE))?;
+    Ok(Hpet {
+        address: PhysAddr::new(hpet.address.address),
+        hpet_number: hpet.hpet_number,
+        minimum_tick: hpet.minimum_tick,
+    })
+}
+
+fn parse_mcfg(bytes: &[u8]) -> Vec<McfgEntry> {
+    bytes
+        .get(MCFG_ENTRIES_OFFSET..)
+        .unwrap_or_default()
+        .as_chunks::<MCFG_ENTRY_SIZE>()
+        .0
+        .iter()
+        .filter_map(|entry| PhysicalMcfgEntry::read_from_bytes(entry).ok())
+        .map(|entry| McfgEntry {
+            base_address: PhysAddr::new(entry.base_address),
+            segment_group: entry.segment_group,
+            start_bus: entry.start_bus,
+            end_bus: entry.end_bus,
+        })
+        .collect()
+}
+
+fn parse_fadt(bytes: &[u8]) -> Result<Fadt, AcpiError> {
+    let mut buf = [0; core::mem::size_of::<PhysicalFadt>()];
+    let len = bytes.len().min(buf.len());
+    buf[..len].copy_from_slice(&bytes[..len]);
+    let fadt = PhysicalFadt::read_from_bytes(&buf[..])
+        .map_err(|_| AcpiError::InvalidTable(FADT_SIGNATURE))?;
+
+    let reset_register = fadt.reset_register;
+    let reset_supported =
+        fadt.flags & FADT_FLAG_RESET_REG_SUPPORTED != 0 && reset_register.address != 0;
+    let dsdt = match fadt.x_dsdt {
+        0 => fadt.dsdt as u64,
+        x_dsdt => x_dsdt,
+    };
+    let x_pm1a_control_block = fadt.x_pm1a_control_block;
+    let x_pm1b_control_block = fadt.x_pm1b_control_block;
+    let io_block = |legacy: u32, extended: GenericAddress| {
+        if legacy == 0 && extended.address_space == ADDRESS_SPACE_SYSTEM_IO {
+            u32::try_from(extended.address).unwrap_or(0)
+        } else {
+            legacy
+        }
+    };
+
+    Ok(Fadt {
+        sci_interrupt: fadt.sci_interrupt,
+        smi_command: fadt.smi_command,
+        acpi_enable: fadt.acpi_enable,
+        pm1a_control_block: io_block(fadt.pm1a_control_block, x_pm1a_control_block),
+        pm1b_control_block: io_block(fadt.pm1b_control_block, x_pm1b_control_block),
+        pm_timer_block: fadt.pm_timer_block,
+        century: fadt.century,
+        boot_architecture_flags: fadt.boot_architecture_flags,
+        flags: fadt.flags,
+        reset_register: reset_supported.then_some(reset_register),
+        reset_value: fadt.reset_value,
+        dsdt: PhysAddr::new(dsdt),
+    })
+}
+
+/// Finds the `\_S5` package in the DSDT AML without a full interpreter, the
+/// package is always a `Name(_S5_, Package() { SLP_TYPa, SLP_TYPb, ... })`.
+fn find_s5_sleep_types(dsdt: &[u8]) -> Option<(u8, u8)> {
+    const NAME_OP: u8 = 0x08;
+    const PACKAGE_OP: u8 = 0x12;
+    const ZERO_OP: u8 = 0x00;
+    const ONE_OP: u8 = 0x01;
+    const BYTE_PREFIX: u8 = 0x0a;
+
+    let aml = dsdt.get(SDT_HEADER_SIZE..)?;
+    let pos = aml.windows(4).position(|w| w == b"_S5_")?;
+    // NameOp directly before the name, optionally with a root prefix
+    let name_op_ok = match pos {
+        1.. if aml[pos - 1] == NAME_OP => true,
+        2.. if aml[pos - 1] == b'\\' && aml[pos - 2] == NAME_OP => true,
+        _ => false,
+    };
+    if !name_op_ok {
+        return None;
+    }
+
+    let mut rest = aml.get(pos + 4..)?;
+    if *rest.first()? != PACKAGE_OP {
+        return None;
+    }
+    // the top 2 bits of the lead byte give the number of extra length bytes
+    let pkg_length_bytes = ((*rest.get(1)? >> 6) & 0b11) as usize + 1;
+    // skip PackageOp, PkgLength and NumElements
+    rest = rest.get(1 + pkg_length_bytes + 1..)?;
+
+    let mut read_value = || -> Option<u8> {
+        let (value, len) = match *rest.first()? {
+            ZERO_OP => (0, 1),
+            ONE_OP => (1, 1),
+            BYTE_PREFIX => (*rest.get(1)?, 2),
+            _ => return None,
+        };
+        rest = rest.get(len..)?;
+        Some(value)
+    };
+    let slp_typa = read_value()?;
+    let slp_typb = read_value()?;
+    Some((slp_typa, slp_typb))
+}
@@ -1,44 +1,262 @@
 // see https://os.phil-opp.com/heap-allocation/#creating-a-kernel-heap
 
-use allocator::{LinkedListAllocator, LockedAllocator};
+use core::{
+    alloc::Layout,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+#[cfg(feature = "heap-debug")]
+use core::{ptr::null_mut, sync::atomic::AtomicPtr};
+
+#[cfg(feature = "heap-debug")]
+use allocator::{DebugAllocator, HeapError, LiveAllocation};
+use allocator::{LinkedListAllocator, LockedAllocator, bucket_size};
+#[cfg(feature = "slab-allocator")]
+use allocator::{SIZE_CLASS_COUNT, SlabAllocator};
+
+#[cfg(feature = "heap-debug")]
+use crate::cpu::{self, MAX_CPUS};
+use crate::{memory, println, sync::level};
+
+#[cfg(feature = "slab-allocator")]
+type BaseAllocator = SlabAllocator<SIZE_CLASS_COUNT, LinkedListAllocator>;
+#[cfg(not(feature = "slab-allocator"))]
+type BaseAllocator = LinkedListAllocator;
+
+#[cfg(feature = "heap-debug")]
+type HeapAllocator = DebugAllocator<BaseAllocator>;
+#[cfg(not(feature = "heap-debug"))]
+type HeapAllocator = BaseAllocator;
+
+#[cfg(feature = "slab-allocator")]
+const fn base_allocator() -> BaseAllocator {
+    SlabAllocator::with_size_classes(LinkedListAllocator::new(), PAGE_SIZE)
+}
+
+#[cfg(not(feature = "slab-allocator"))]
+const fn base_allocator() -> BaseAllocator {
+    LinkedListAllocator::new()
+}
+
+#[cfg(feature = "heap-debug")]
+const fn heap_allocator() -> HeapAllocator {
+    DebugAllocator::new(base_allocator(), Some(current_tag), report_heap_error)
+}
+
+#[cfg(not(feature = "heap-debug"))]
+const fn heap_allocator() -> HeapAllocator {
+    base_allocator()
+}
 
 #[global_allocator]
-static ALLOCATOR: LockedAllocator<LinkedListAllocator> =
-    LockedAllocator::new(LinkedListAllocator::new());
+static ALLOCATOR: LockedAllocator<HeapAllocator> =
+    LockedAllocator::growable(heap_allocator(), level::ALLOCATOR, grow_heap, out_of_memory);
+
+pub const HEAP_START: usize = 0xffff_c000_0000_0000;
+pub const HEAP_INITIAL_SIZE: usize = 1024 * 1024; // 1 MiB
+/// The heap never grows beyond this
+pub const HEAP_MAX_SIZE: usize = 256 * 1024 * 1024; // 256 MiB
+/// Smallest step the heap grows by, so small allocations don't grow it page
+/// by page
+const HEAP_GROW_SIZE: usize = 64 * 1024; // 64 KiB
+const PAGE_SIZE: usize = 4096;
 
-pub const HEAP_START: usize = 0x_4444_4444_0000;
-pub const HEAP_SIZE: usize = 100 * 1024; // 100 KiB
+/// Bytes mapped at `HEAP_START`, only changed with the allocator locked.
+static HEAP_SIZE: AtomicUsize = AtomicUsize::new(0);
 
 use x86_64::{
     VirtAddr,
     structures::paging::{
-        FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB, mapper::MapToError,
+        FrameAllocator, FrameDeallocator, Mapper, Page, PageTableFlags, Size4KiB,
+        mapper::MapToError,
     },
 };
 
-pub fn init_heap(
-    mapper: &mut impl Mapper<Size4KiB>,
-    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
-) -> Result<(), MapToError<Size4KiB>> {
-    let page_range = {
-        let heap_start = VirtAddr::new(HEAP_START as u64);
-        let heap_end = heap_start + HEAP_SIZE as u64 - 1;
-        let heap_start_page = Page::containing_address(heap_start);
-        let heap_end_page = Page::containing_address(heap_end);
-        Page::range_inclusive(heap_start_page, heap_end_page)
-    };
-
-    for page in page_range {
-        let frame = frame_allocator
-            .allocate_frame()
-            .ok_or(MapToError::FrameAllocationFailed)?;
-        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
-        unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
+pub fn init_heap() -> Result<(), MapToError<Size4KiB>> {
+    for offset in (0..HEAP_INITIAL_SIZE).step_by(PAGE_SIZE) {
+        map_heap_page(HEAP_START + offset)?;
     }
+    HEAP_SIZE.store(HEAP_INITIAL_SIZE, Ordering::Relaxed);
 
     unsafe {
-        ALLOCATOR.init(HEAP_START as *mut u8, HEAP_SIZE);
+        ALLOCATOR.init(HEAP_START as *mut u8, HEAP_INITIAL_SIZE);
     }
 
     Ok(())
 }
+
+fn map_heap_page(address: usize) -> Result<(), MapToError<Size4KiB>> {
+    let page = Page::<Size4KiB>::containing_address(VirtAddr::new(address as u64));
+    let mut frame_allocator = memory::GlobalFrameAllocator;
+    let frame = frame_allocator
+        .allocate_frame()
+        .ok_or(MapToError::FrameAllocationFailed)?;
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    let result = memory::with_kernel_mapper(|mapper| unsafe {
+        mapper
+            .map_to(page, frame, flags, &mut frame_allocator)
+            .map(|flush| flush.flush())
+    })
+    .unwrap_or(Err(MapToError::FrameAllocationFailed));
+    if result.is_err() {
+        unsafe { frame_allocator.deallocate_frame(frame) };
+    }
+    result
+}
+
+/// Maps more pages at the end of the heap, runs with the allocator locked.
+fn grow_heap(min_size: usize) -> usize {
+    let heap_size = HEAP_SIZE.load(Ordering::Relaxed);
+    let size = min_size
+        .max(HEAP_GROW_SIZE)
+        .next_multiple_of(PAGE_SIZE)
+        .min(HEAP_MAX_SIZE - heap_size);
+    let mut grown = 0;
+    while grown < size && map_heap_page(HEAP_START + heap_size + grown).is_ok() {
+        grown += PAGE_SIZE;
+    }
+    HEAP_SIZE.store(heap_size + grown, Ordering::Relaxed);
+    grown
+}
+
+fn out_of_memory(layout: Layout, used: usize, free: usize) {
+    println!(
+        "out of memory allocating {} bytes (align {}): {} KiB used, {} KiB free, heap {} of {} KiB",
+        layout.size(),
+        layout.align(),
+        used / 1024,
+        free / 1024,
+        HEAP_SIZE.load(Ordering::Relaxed) / 1024,
+        HEAP_MAX_SIZE / 1024
+    );
+    print_memory_report();
+    #[cfg(feature = "heap-debug")]
+    dump_heap();
+}
+
+/// Prints heap statistics and physical memory usage.
+pub fn print_memory_report() {
+    let stats = ALLOCATOR.stats();
+    println!(
+        "heap: {} KiB used (peak {} KiB), {} KiB free, largest free block {} KiB, {}% fragmented",
+        stats.used / 1024,
+        stats.peak_used / 1024,
+        stats.free / 1024,
+        stats.largest_free / 1024,
+        stats.fragmentation_percent()
+    );
+    println!(
+        "heap: {} allocations, {} frees, {} live, {} failed",
+        stats.allocations,
+        stats.deallocations,
+        stats.live_allocations(),
+        stats.failed_allocations
+    );
+    for (bucket, &count) in stats.size_histogram.iter().enumerate() {
+        if count == 0 {
+            continue;
+        }
+        match bucket_size(bucket) {
+            Some(size) => println!("  <= {size:>5} bytes: {count}"),
+            None => println!(
+                "   > {:>5} bytes: {count}",
+                bucket_size(bucket - 1).unwrap_or(0)
+            ),
+        }
+    }
+    if let Some(stats) = memory::frame_stats() {
+        println!(
+            "physical memory: {} KiB used, {} KiB free",
+            stats.used / 1024,
+            stats.free / 1024
+        );
+    }
+}
+
+/// Tag of new allocations on each CPU, set by [`HeapTag`]
+#[cfg(feature = "heap-debug")]
+static TAGS: [AtomicPtr<&'static str>; MAX_CPUS] = [const { AtomicPtr::new(null_mut()) }; MAX_CPUS];
+/// Live allocations printed by [`dump_heap`]
+#[cfg(feature = "heap-debug")]
+const DUMP_LIMIT: usize = 32;
+
+/// Tags allocations made on the current CPU until dropped, see
+/// [`heap_tag!`](crate::heap_tag). Does nothing without the `heap-debug`
+/// feature. The tag follows the CPU, not the thread, so a thread preempted
+/// inside the scope lends it to whatever runs next on that CPU.
+pub struct HeapTag {
+    #[cfg(feature = "heap-debug")]
+    previous: *mut &'static str,
+}
+
+impl HeapTag {
+    pub fn new(tag: &'static &'static str) -> Self {
+        #[cfg(feature = "heap-debug")]
+        {
+            let tag = tag as *const &'static str as *mut &'static str;
+            Self {
+                previous: TAGS[cpu::current_index()].swap(tag, Ordering::Relaxed),
+            }
+        }
+        #[cfg(not(feature = "heap-debug"))]
+        {
+            let _ = tag;
+            Self {}
+        }
+    }
+}
+
+impl Drop for HeapTag {
+    fn drop(&mut self) {
+        #[cfg(feature = "heap-debug")]
+        TAGS[cpu::current_index()].store(self.previous, Ordering::Relaxed);
+    }
+}
+
+/// Tags heap allocations made in the rest of the scope with the caller's
+/// file and line, they show up in heap errors and [`dump_heap`].
+#[macro_export]
+macro_rules! heap_tag {
+    () => {
+        let _heap_tag = $crate::allocator::HeapTag::new(&concat!(file!(), ":", line!()));
+    };
+}
+
+#[cfg(feature = "heap-debug")]
+fn current_tag() -> &'static str {
+    let tag = TAGS[cpu::current_index()].load(Ordering::Relaxed);
+    if tag.is_null() {
+        "untagged"
+    } else {
+        unsafe { *tag }
+    }
+}
+
+/// Runs with the allocator locked, the heap is corrupt so nothing can
+/// continue.
+#[cfg(feature = "heap-debug")]
+fn report_heap_error(error: HeapError) {
+    // the panic handler's output must not wait for the allocator
+    unsafe { ALLOCATOR.force_unlock() };
+    panic!("heap corruption: {error:?}");
+}
+
+/// Checks every live allocation's redzones and prints the most recent ones.
+#[cfg(feature = "heap-debug")]
+pub fn dump_heap() {
+    let (count, allocations, result) = ALLOCATOR.inspect(|allocator| {
+        let allocations: heapless::Vec<LiveAllocation, DUMP_LIMIT> =
+            allocator.live_allocations().take(DUMP_LIMIT).collect();
+        (allocator.live_count(), allocations, allocator.check())
+    });
+    println!("{count} live heap allocations, most recent first:");
+    for allocation in &allocations {
+        println!(
+            "  #{} {:#x} {} bytes (align {}) {}",
+            allocation.id, allocation.ptr, allocation.size, allocation.align, allocation.tag
+        );
+    }
+    if let Err(error) = result {
+        println!("heap corruption: {error:?}");
+    }
+}
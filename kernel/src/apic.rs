@@ -0,0 +1,518 @@
+// see https://wiki.osdev.org/APIC and https://wiki.osdev.org/IOAPIC
+
+use alloc::vec::Vec;
+use conquer_once::spin::OnceCell;
+use irq_sync::IrqSpinLock;
+use x86_64::{PhysAddr, instructions::port::Port, registers::model_specific::Msr};
+
+use crate::{
+    interrupts,
+    mmio::{CacheMode, Mmio},
+    println,
+    sync::level,
+};
+
+/// First vector used for I/O APIC interrupts, everything below is reserved for CPU exceptions.
+pub const IRQ_BASE_VECTOR: u8 = 0x20;
+/// Vectors from here on are reserved for interrupts raised by the local APIC itself.
+pub const LOCAL_VECTOR_BASE: u8 = 0xe0;
+pub const TIMER_VECTOR: u8 = LOCAL_VECTOR_BASE;
+pub const TLB_SHOOTDOWN_VECTOR: u8 = LOCAL_VECTOR_BASE + 1;
+pub const ERROR_VECTOR: u8 = 0xfe;
+/// The low 4 bits must be set on older CPUs, 0xff works everywhere.
+pub const SPURIOUS_VECTOR: u8 = 0xff;
+
+const IA32_APIC_BASE_MSR: u32 = 0x1b;
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+const APIC_BASE_X2APIC_ENABLE: u64 = 1 << 10;
+const APIC_BASE_ADDRESS_MASK: u64 = 0x000f_ffff_ffff_f000;
+const X2APIC_MSR_BASE: u32 = 0x800;
+const LOCAL_APIC_SIZE: usize = 0x400;
+const IO_APIC_SIZE: usize = 0x20;
+
+// local APIC register offsets, x2APIC uses MSR 0x800 + (offset >> 4)
+const REG_ID: u32 = 0x20;
+const REG_TPR: u32 = 0x80;
+const REG_EOI: u32 = 0xb0;
+const REG_SPURIOUS: u32 = 0xf0;
+const REG_ESR: u32 = 0x280;
+const REG_ICR_LOW: u32 = 0x300;
+const REG_ICR_HIGH: u32 = 0x310;
+const REG_LVT_TIMER: u32 = 0x320;
+const REG_LVT_LINT0: u32 = 0x350;
+const REG_LVT_LINT1: u32 = 0x360;
+const REG_LVT_ERROR: u32 = 0x370;
+const REG_TIMER_INITIAL_COUNT: u32 = 0x380;
+const REG_TIMER_CURRENT_COUNT: u32 = 0x390;
+const REG_TIMER_DIVIDE: u32 = 0x3e0;
+
+const SPURIOUS_APIC_ENABLE: u32 = 1 << 8;
+const LVT_MASKED: u32 = 1 << 16;
+const LVT_DELIVERY_NMI: u32 = 0b100 << 8;
+const LVT_TIMER_PERIODIC: u32 = 1 << 17;
+const ICR_DELIVERY_INIT: u32 = 0b101 << 8;
+const ICR_DELIVERY_STARTUP: u32 = 0b110 << 8;
+const ICR_DELIVERY_PENDING: u32 = 1 << 12;
+const ICR_LEVEL_ASSERT: u32 = 1 << 14;
+/// The timer counts down at the bus clock divided by 16
+const TIMER_DIVIDE_BY_16: u32 = 0b0011;
+
+const PIC1_COMMAND: u16 = 0x20;
+const PIC1_DATA: u16 = 0x21;
+const PIC2_COMMAND: u16 = 0xa0;
+const PIC2_DATA: u16 = 0xa1;
+
+const IOAPIC_REG_VERSION: u32 = 0x01;
+const IOAPIC_REG_REDIRECTION_BASE: u32 = 0x10;
+const REDIRECTION_ACTIVE_LOW: u64 = 1 << 13;
+const REDIRECTION_LEVEL_TRIGGERED: u64 = 1 << 15;
+const REDIRECTION_MASKED: u64 = 1 << 16;
+
+/// Standard I/O APIC location used when no ACPI tables are available.
+const DEFAULT_IO_APIC_ADDRESS: u64 = 0xfec0_0000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Polarity {
+    ActiveHigh,
+    ActiveLow,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriggerMode {
+    Edge,
+    Level,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct IoApicInfo {
+    pub address: PhysAddr,
+    /// First global system interrupt handled by this I/O APIC
+    pub gsi_base: u32,
+}
+
+/// ISA IRQ which is wired to a different global system interrupt, or with
+/// different signaling, than the identity mapping.
+#[derive(Debug, Clone, Copy)]
+pub struct InterruptOverride {
+    pub irq: u8,
+    pub gsi: u32,
+    pub polarity: Polarity,
+    pub trigger: TriggerMode,
+}
+
+/// Where the interrupt controllers are and how the ISA IRQs are wired to them.
+#[derive(Debug, Clone)]
+pub struct ApicConfig {
+    pub io_apics: Vec<IoApicInfo>,
+    pub overrides: Vec<InterruptOverride>,
+}
+
+impl Default for ApicConfig {
+    /// Single I/O APIC at the standard address with the PIT wired to GSI 2,
+    /// which is how every PC chipset (and QEMU) is set up.
+    fn default() -> Self {
+        Self {
+            io_apics: alloc::vec![IoApicInfo {
+                address: PhysAddr::new(DEFAULT_IO_APIC_ADDRESS),
+                gsi_base: 0,
+            }],
+            overrides: alloc::vec![InterruptOverride {
+                irq: 0,
+                gsi: 2,
+                polarity: Polarity::ActiveHigh,
+                trigger: TriggerMode::Edge,
+            }],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimerMode {
+    OneShot,
+    Periodic,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApicError {
+    AlreadyInitialized,
+    NotInitialized,
+    /// No I/O APIC handles the global system interrupt
+    NoIoApic(u32),
+    /// The global system interrupt doesn't fit in the I/O APIC vector range
+    NoVector(u32),
+    /// The registers couldn't be mapped
+    MapFailed,
+}
+
+enum LocalApic {
+    XApic(Mmio),
+    X2Apic,
+}
+
+impl LocalApic {
+    unsafe fn read(&self, reg: u32) -> u32 {
+        match self {
+            LocalApic::XApic(registers) => registers.read(reg as usize),
+            LocalApic::X2Apic => unsafe { Msr::new(X2APIC_MSR_BASE + (reg >> 4)).read() as u32 },
+        }
+    }
+
+    unsafe fn write(&self, reg: u32, value: u32) {
+        match self {
+            LocalApic::XApic(registers) => registers.write(reg as usize, value),
+            LocalApic::X2Apic => unsafe {
+                Msr::new(X2APIC_MSR_BASE + (reg >> 4)).write(value as u64)
+            },
+        }
+    }
+
+    /// Sends an inter-processor interrupt to the CPU with APIC id `destination`.
+    unsafe fn send_ipi(&self, destination: u32, command: u32) {
+        match self {
+            LocalApic::XApic(_) => unsafe {
+                self.write(REG_ICR_HIGH, destination << 24);
+                self.write(REG_ICR_LOW, command);
+                while self.read(REG_ICR_LOW) & ICR_DELIVERY_PENDING != 0 {
+                    core::hint::spin_loop();
+                }
+            },
+            // x2APIC writes the whole ICR at once and doesn't report delivery
+            LocalApic::X2Apic => unsafe {
+                Msr::new(X2APIC_MSR_BASE + (REG_ICR_LOW >> 4))
+                    .write(((destination as u64) << 32) | command as u64)
+            },
+        }
+    }
+
+    fn id(&self) -> u32 {
+        let id = unsafe { self.read(REG_ID) };
+        match self {
+            LocalApic::XApic(_) => id >> 24,
+            LocalApic::X2Apic => id,
+        }
+    }
+}
+
+struct IoApic {
+    registers: Mmio,
+    gsi_base: u32,
+    redirection_count: u32,
+}
+
+impl IoApic {
+    /// # Safety
+    /// `registers` must map the I/O APIC registers.
+    unsafe fn new(registers: Mmio, gsi_base: u32) -> Self {
+        let mut io_apic = Self {
+            registers,
+            gsi_base,
+            redirection_count: 0,
+        };
+        io_apic.redirection_count = ((io_apic.read(IOAPIC_REG_VERSION) >> 16) & 0xff) + 1;
+        io_apic
+    }
+
+    fn read(&mut self, reg: u32) -> u32 {
+        self.registers.write(0, reg);
+        self.registers.read(0x10)
+    }
+
+    fn write(&mut self, reg: u32, value: u32) {
+        self.registers.write(0, reg);
+        self.registers.write(0x10, value);
+    }
+
+    fn handles(&self, gsi: u32) -> bool {
+        gsi >= self.gsi_base && gsi < self.gsi_base + self.redirection_count
+    }
+
+    fn set_redirection(&mut self, gsi: u32, entry: u64) {
+        let reg = IOAPIC_REG_REDIRECTION_BASE + (gsi - self.gsi_base) * 2;
+        // mask first so the entry never fires half written
+        self.write(reg, REDIRECTION_MASKED as u32);
+        self.write(reg + 1, (entry >> 32) as u32);
+        self.write(reg, entry as u32);
+    }
+
+    fn mask_all(&mut self) {
+        for i in 0..self.redirection_count {
+            self.set_redirection(self.gsi_base + i, REDIRECTION_MASKED);
+        }
+    }
+}
+
+static LOCAL_APIC: OnceCell<LocalApic> = OnceCell::uninit();
+static IO_APICS: IrqSpinLock<Vec<IoApic>> = IrqSpinLock::with_level(Vec::new(), level::IO_APICS);
+static OVERRIDES: IrqSpinLock<Vec<InterruptOverride>> =
+    IrqSpinLock::with_level(Vec::new(), level::IO_APICS);
+
+/// Disables the 8259 PIC and brings up the local APIC of this CPU and the I/O APICs.
+///
+/// # Safety
+/// The IDT must be loaded and the MMIO window set up, interrupts must still
+/// be disabled.
+pub unsafe fn init(config: &ApicConfig) -> Result<(), ApicError> {
+    unsafe { disable_pic() };
+
+    let apic_base = unsafe { Msr::new(IA32_APIC_BASE_MSR).read() };
+    let local_apic = if x2apic_supported() {
+        LocalApic::X2Apic
+    } else {
+        LocalApic::XApic(map_registers(
+            PhysAddr::new(apic_base & APIC_BASE_ADDRESS_MASK),
+            LOCAL_APIC_SIZE,
+        )?)
+    };
+    unsafe { enable_local_apic(&local_apic) };
+
+    LOCAL_APIC
+        .try_init_once(|| local_apic)
+        .map_err(|_| ApicError::AlreadyInitialized)?;
+    init_local_apic()?;
+
+    // mapping may sleep, so it happens before the lock is taken
+    let mut io_apics = Vec::with_capacity(config.io_apics.len());
+    for info in &config.io_apics {
+        let registers = map_registers(info.address, IO_APIC_SIZE)?;
+        let mut io_apic = unsafe { IoApic::new(registers, info.gsi_base) };
+        io_apic.mask_all();
+        io_apics.push(io_apic);
+    }
+    IO_APICS.lock().extend(io_apics);
+    OVERRIDES.lock().extend_from_slice(&config.overrides);
+
+    Ok(())
+}
+
+fn map_registers(address: PhysAddr, size: usize) -> Result<Mmio, ApicError> {
+    Mmio::map(address, size, CacheMode::Uncached).map_err(|_| ApicError::MapFailed)
+}
+
+/// Enables the local APIC of an application processor in the mode the
+/// bootstrap processor uses and programs it.
+///
+/// # Safety
+/// Must be called once on each application processor after [`init`].
+pub unsafe fn init_ap() -> Result<(), ApicError> {
+    let local_apic = LOCAL_APIC
+        .try_get()
+        .map_err(|_| ApicError::NotInitialized)?;
+    unsafe { enable_local_apic(local_apic) };
+    init_local_apic()
+}
+
+/// Sets the enable bits of the local APIC of the current CPU, every local
+/// APIC keeps the address the firmware gave it.
+unsafe fn enable_local_apic(local_apic: &LocalApic) {
+    let mut apic_base_msr = Msr::new(IA32_APIC_BASE_MSR);
+    let mut apic_base = unsafe { apic_base_msr.read() } | APIC_BASE_ENABLE;
+    if let LocalApic::X2Apic = local_apic {
+        apic_base |= APIC_BASE_X2APIC_ENABLE;
+    }
+    unsafe { apic_base_msr.write(apic_base) };
+}
+
+/// Sends an INIT IPI, which resets the CPU into waiting for a startup IPI.
+pub fn send_init(apic_id: u32) -> Result<(), ApicError> {
+    let local_apic = LOCAL_APIC
+        .try_get()
+        .map_err(|_| ApicError::NotInitialized)?;
+    clear_error_status(local_apic);
+    unsafe { local_apic.send_ipi(apic_id, ICR_DELIVERY_INIT | ICR_LEVEL_ASSERT) };
+    Ok(())
+}
+
+/// Sends a startup IPI, the CPU starts in real mode at `page << 12`.
+pub fn send_startup(apic_id: u32, page: u8) -> Result<(), ApicError> {
+    let local_apic = LOCAL_APIC
+        .try_get()
+        .map_err(|_| ApicError::NotInitialized)?;
+    clear_error_status(local_apic);
+    unsafe { local_apic.send_ipi(apic_id, ICR_DELIVERY_STARTUP | page as u32) };
+    Ok(())
+}
+
+/// Raises `vector` on the CPU with `apic_id`.
+pub fn send_fixed(apic_id: u32, vector: u8) -> Result<(), ApicError> {
+    let local_apic = LOCAL_APIC
+        .try_get()
+        .map_err(|_| ApicError::NotInitialized)?;
+    unsafe { local_apic.send_ipi(apic_id, vector as u32 | ICR_LEVEL_ASSERT) };
+    Ok(())
+}
+
+/// Programs the local APIC of the current CPU, also used when bringing up other CPUs.
+pub fn init_local_apic() -> Result<(), ApicError> {
+    let local_apic = LOCAL_APIC
+        .try_get()
+        .map_err(|_| ApicError::NotInitialized)?;
+    unsafe {
+        local_apic.write(REG_TPR, 0);
+        local_apic.write(REG_LVT_TIMER, LVT_MASKED);
+        // the PIC is gone so nothing useful arrives on LINT0, LINT1 is wired to NMI
+        local_apic.write(REG_LVT_LINT0, LVT_MASKED);
+        local_apic.write(REG_LVT_LINT1, LVT_DELIVERY_NMI);
+        local_apic.write(REG_LVT_ERROR, ERROR_VECTOR as u32);
+        clear_error_status(local_apic);
+        local_apic.write(REG_SPURIOUS, SPURIOUS_APIC_ENABLE | SPURIOUS_VECTOR as u32);
+    }
+    let _ = interrupts::register_vector(ERROR_VECTOR, error_handler);
+    Ok(())
+}
+
+/// Remaps the PIC away from the exception vectors, in case it raises a
+/// spurious interrupt, and masks every line.
+unsafe fn disable_pic() {
+    let mut pic1_command = Port::<u8>::new(PIC1_COMMAND);
+    let mut pic1_data = Port::<u8>::new(PIC1_DATA);
+    let mut pic2_command = Port::<u8>::new(PIC2_COMMAND);
+    let mut pic2_data = Port::<u8>::new(PIC2_DATA);
+    unsafe {
+        // ICW1: initialize, expect ICW4
+        pic1_command.write(0x11);
+        pic2_command.write(0x11);
+        // ICW2: vector offsets
+        pic1_data.write(IRQ_BASE_VECTOR);
+        pic2_data.write(IRQ_BASE_VECTOR + 8);
+        // ICW3: slave on IRQ2
+        pic1_data.write(1 << 2);
+        pic2_data.write(2);
+        // ICW4: 8086 mode
+        pic1_data.write(0x01);
+        pic2_data.write(0x01);
+        // mask everything
+        pic1_data.write(0xff);
+        pic2_data.write(0xff);
+    }
+}
+
+fn x2apic_supported() -> bool {
+    let cpuid = core::arch::x86_64::__cpuid(1);
+    cpuid.ecx & (1 << 21) != 0
+}
+
+fn clear_error_status(local_apic: &LocalApic) {
+    // xAPIC latches the errors on write, so write twice to clear them
+    unsafe {
+        local_apic.write(REG_ESR, 0);
+        local_apic.write(REG_ESR, 0);
+    }
+}
+
+fn error_handler(_vector: u8) {
+    if let Ok(local_apic) = LOCAL_APIC.try_get() {
+        unsafe { local_apic.write(REG_ESR, 0) };
+        let esr = unsafe { local_apic.read(REG_ESR) };
+        println!("APIC error: 0x{esr:02x}");
+    }
+}
+
+/// Starts the local APIC timer of the current CPU, counting down from
+/// `initial_count` and raising [`TIMER_VECTOR`] when it reaches zero unless
+/// `masked`. Writing an initial count of 0 stops the timer.
+pub fn start_timer(mode: TimerMode, initial_count: u32, masked: bool) -> Result<(), ApicError> {
+    let local_apic = LOCAL_APIC
+        .try_get()
+        .map_err(|_| ApicError::NotInitialized)?;
+    let mut lvt = TIMER_VECTOR as u32;
+    if mode == TimerMode::Periodic {
+        lvt |= LVT_TIMER_PERIODIC;
+    }
+    if masked {
+        lvt |= LVT_MASKED;
+    }
+    unsafe {
+        local_apic.write(REG_TIMER_DIVIDE, TIMER_DIVIDE_BY_16);
+        local_apic.write(REG_LVT_TIMER, lvt);
+        local_apic.write(REG_TIMER_INITIAL_COUNT, initial_count);
+    }
+    Ok(())
+}
+
+/// Remaining count of the local APIC timer of the current CPU.
+pub fn timer_current_count() -> Result<u32, ApicError> {
+    let local_apic = LOCAL_APIC
+        .try_get()
+        .map_err(|_| ApicError::NotInitialized)?;
+    Ok(unsafe { local_apic.read(REG_TIMER_CURRENT_COUNT) })
+}
+
+/// Signals the end of the interrupt being handled to the local APIC.
+pub fn end_of_interrupt() {
+    if let Ok(local_apic) = LOCAL_APIC.try_get() {
+        unsafe { local_apic.write(REG_EOI, 0) };
+    }
+}
+
+/// APIC id of the current CPU.
+pub fn local_apic_id() -> Result<u32, ApicError> {
+    LOCAL_APIC
+        .try_get()
+        .map(LocalApic::id)
+        .map_err(|_| ApicError::NotInitialized)
+}
+
+/// Vector a global system interrupt is delivered on.
+pub fn gsi_vector(gsi: u32) -> Result<u8, ApicError> {
+    u8::try_from(gsi)
+        .ok()
+        .and_then(|gsi| gsi.checked_add(IRQ_BASE_VECTOR))
+        .filter(|vector| *vector < LOCAL_VECTOR_BASE)
+        .ok_or(ApicError::NoVector(gsi))
+}
+
+/// Routes an ISA IRQ to the current CPU, following the interrupt overrides,
+/// and unmasks it. Returns the vector it is delivered on.
+pub fn route_isa_irq(irq: u8) -> Result<u8, ApicError> {
+    let (gsi, polarity, trigger) = isa_irq_routing(irq);
+    route_gsi(gsi, polarity, trigger)
+}
+
+/// Global system interrupt an ISA IRQ is wired to.
+pub fn isa_irq_gsi(irq: u8) -> u32 {
+    isa_irq_routing(irq).0
+}
+
+fn isa_irq_routing(irq: u8) -> (u32, Polarity, TriggerMode) {
+    OVERRIDES
+        .lock()
+        .iter()
+        .find(|o| o.irq == irq)
+        .map(|o| (o.gsi, o.polarity, o.trigger))
+        .unwrap_or((irq as u32, Polarity::ActiveHigh, TriggerMode::Edge))
+}
+
+/// Routes a global system interrupt to the current CPU and unmasks it.
+/// Returns the vector it is delivered on.
+pub fn route_gsi(gsi: u32, polarity: Polarity, trigger: TriggerMode) -> Result<u8, ApicError> {
+    let vector = gsi_vector(gsi)?;
+    let destination = local_apic_id()?;
+
+    let mut entry = vector as u64 | ((destination as u64 & 0xff) << 56);
+    if polarity == Polarity::ActiveLow {
+        entry |= REDIRECTION_ACTIVE_LOW;
+    }
+    if trigger == TriggerMode::Level {
+        entry |= REDIRECTION_LEVEL_TRIGGERED;
+    }
+
+    let mut io_apics = IO_APICS.lock();
+    let io_apic = io_apics
+        .iter_mut()
+        .find(|io_apic| io_apic.handles(gsi))
+        .ok_or(ApicError::NoIoApic(gsi))?;
+    io_apic.set_redirection(gsi, entry);
+    Ok(vector)
+}
+
+/// Masks a global system interrupt.
+#[allow(dead_code)]
+pub fn mask_gsi(gsi: u32) -> Result<(), ApicError> {
+    let mut io_apics = IO_APICS.lock();
+    let io_apic = io_apics
+        .iter_mut()
+        .find(|io_apic| io_apic.handles(gsi))
+        .ok_or(ApicError::NoIoApic(gsi))?;
+    io_apic.set_redirection(gsi, REDIRECTION_MASKED);
+    Ok(())
+}
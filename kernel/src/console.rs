@@ -1,8 +1,10 @@
 use conquer_once::{TryInitError, spin::OnceCell};
 use framebuffer::{FrameBuffer, FrameBufferDriver, console::Console};
+use irq_sync::IrqSpinLock;
 use pc_screen_font::Font;
 use serial_port::serial_print_args;
-use spin::Mutex;
+
+use crate::sync::level;
 
 struct MyFrameBuffer {
     framebuffer: bootloader_api::info::FrameBuffer,
@@ -40,7 +42,7 @@ impl FrameBuffer for MyFrameBuffer {
     }
 }
 
-static CONSOLE: OnceCell<Mutex<Console<MyFrameBuffer>>> = OnceCell::uninit();
+static CONSOLE: OnceCell<IrqSpinLock<Console<MyFrameBuffer>>> = OnceCell::uninit();
 
 const DEFAULT_8X16: &[u8] = include_bytes!("./resources/Tamsyn8x16r.psf");
 const DEFAULT_8X16_BOLD: &[u8] = include_bytes!("./resources/Tamsyn8x16b.psf");
@@ -63,7 +65,7 @@ pub fn console_init(
         .try_init_once(|| {
             let mut console = Console::new(framebuffer, font, bold_font);
             console.clear();
-            Mutex::new(console)
+            IrqSpinLock::with_level(console, level::CONSOLE)
         })
         .map_err(ConsoleInitError::TryInitError)
 }
@@ -0,0 +1,119 @@
+// see https://wiki.osdev.org/SWAPGS and https://wiki.osdev.org/Thread_Local_Storage
+
+use alloc::boxed::Box;
+use core::{
+    arch::asm,
+    sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering},
+};
+
+use x86_64::{VirtAddr, registers::model_specific::GsBase};
+
+/// Most CPUs brought online, the rest are left halted.
+pub const MAX_CPUS: usize = 16;
+
+/// Data private to one CPU, found through the GS base.
+#[repr(C)]
+pub struct Cpu {
+    /// Points to the struct itself so it can be read from `gs:0`
+    this: *const Cpu,
+    /// 0 for the bootstrap processor, application processors count up from 1
+    index: usize,
+    /// Lock levels held on this CPU, see [`irq_sync::LockLevel`]
+    held_lock_levels: AtomicU64,
+}
+
+// only the owning CPU uses it, or others before it is online
+unsafe impl Sync for Cpu {}
+
+impl Cpu {
+    pub fn index(&self) -> usize {
+        self.index
+    }
+}
+
+static BSP: Cpu = Cpu {
+    this: &raw const BSP,
+    index: 0,
+    held_lock_levels: AtomicU64::new(0),
+};
+static CPU_COUNT: AtomicUsize = AtomicUsize::new(1);
+/// APIC ids of the online CPUs by index, [`OFFLINE`] until [`set_online`]
+static APIC_IDS: [AtomicU32; MAX_CPUS] = [const { AtomicU32::new(OFFLINE) }; MAX_CPUS];
+const OFFLINE: u32 = u32::MAX;
+
+/// Points the GS base of the bootstrap processor at its [`Cpu`], must run
+/// before any lock is taken.
+pub fn init_bsp() {
+    GsBase::write(VirtAddr::from_ptr(&raw const BSP));
+    irq_sync::set_held_levels(held_lock_levels);
+}
+
+/// Creates the [`Cpu`] of the next application processor. Returns `None`
+/// when [`MAX_CPUS`] are already in use.
+pub fn allocate_ap() -> Option<&'static Cpu> {
+    let index = CPU_COUNT
+        .try_update(Ordering::Relaxed, Ordering::Relaxed, |count| {
+            (count < MAX_CPUS).then_some(count + 1)
+        })
+        .ok()?;
+    let cpu = Box::leak(Box::new(Cpu {
+        this: core::ptr::null(),
+        index,
+        held_lock_levels: AtomicU64::new(0),
+    }));
+    cpu.this = cpu;
+    Some(cpu)
+}
+
+/// Gives the index of an application processor back when it didn't start.
+/// Only the most recently allocated index can be returned, the [`Cpu`] is
+/// leaked.
+pub fn release_ap(cpu: &'static Cpu) {
+    let _ = CPU_COUNT.compare_exchange(
+        cpu.index + 1,
+        cpu.index,
+        Ordering::Relaxed,
+        Ordering::Relaxed,
+    );
+}
+
+/// Points the GS base of the calling application processor at `cpu`, must
+/// run before any lock is taken.
+pub fn init_ap(cpu: &'static Cpu) {
+    GsBase::write(VirtAddr::from_ptr(cpu));
+}
+
+/// The [`Cpu`] of the CPU running the caller, threads can move to another
+/// CPU unless interrupts are disabled.
+pub fn current() -> &'static Cpu {
+    let this: *const Cpu;
+    unsafe {
+        asm!("mov {}, gs:[0]", out(reg) this, options(nostack, readonly, preserves_flags));
+        &*this
+    }
+}
+
+pub fn current_index() -> usize {
+    current().index
+}
+
+/// Marks the calling CPU as online, from then on it takes part in IPIs sent
+/// to every CPU, e.g. TLB shootdowns.
+pub fn set_online(apic_id: u32) {
+    APIC_IDS[current_index()].store(apic_id, Ordering::Release);
+}
+
+/// APIC ids of the online CPUs other than the caller's.
+pub fn other_online_apic_ids() -> impl Iterator<Item = u32> {
+    let current = current_index();
+    APIC_IDS
+        .iter()
+        .enumerate()
+        .filter(move |(index, _)| *index != current)
+        .map(|(_, apic_id)| apic_id.load(Ordering::Acquire))
+        .filter(|apic_id| *apic_id != OFFLINE)
+}
+
+fn held_lock_levels() -> &'static AtomicU64 {
+    &current().held_lock_levels
+}
@@ -0,0 +1,144 @@
+// Loads executables parsed by the elf-loader crate into user address spaces.
+
+use alloc::vec;
+use core::arch::x86_64::_rdtsc;
+
+use elf_loader::{ElfError, PAGE_SIZE, ProgramHeader, RANDOM_SIZE};
+use nostdio::{NoStdIoError, Read, Seek, SeekFrom};
+use x86_64::{VirtAddr, instructions::random::RdRand};
+use zerocopy::IntoBytes;
+
+use crate::{
+    heap_tag,
+    vmm::{AddressSpace, Protection, USER_END, USER_START, VmmError},
+};
+
+/// The stack ends a page below the end of the lower half, with a guard page
+/// below it
+const USER_STACK_TOP: u64 = USER_END - PAGE_SIZE;
+const USER_STACK_SIZE: u64 = 8 * 1024 * 1024; // 8 MiB, mapped on demand
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadError {
+    #[allow(dead_code)]
+    Elf(ElfError),
+    #[allow(dead_code)]
+    Vmm(VmmError),
+}
+
+impl From<ElfError> for LoadError {
+    fn from(value: ElfError) -> Self {
+        LoadError::Elf(value)
+    }
+}
+
+impl From<NoStdIoError> for LoadError {
+    fn from(value: NoStdIoError) -> Self {
+        LoadError::Elf(ElfError::Io(value))
+    }
+}
+
+impl From<VmmError> for LoadError {
+    fn from(value: VmmError) -> Self {
+        LoadError::Vmm(value)
+    }
+}
+
+/// A program loaded into its own address space, ready to enter user mode.
+#[allow(dead_code)] // nothing enters user mode yet
+pub struct Program {
+    pub space: AddressSpace,
+    pub entry: VirtAddr,
+    /// Points at `argc`, followed by argv, envp and the auxiliary vector
+    pub stack_pointer: VirtAddr,
+}
+
+/// Loads the static x86_64 executable `file` into a new user address space
+/// and prepares its stack with `args` and `env`, like `execve`.
+pub fn load<R: Read + Seek>(
+    file: &mut R,
+    args: &[&str],
+    env: &[&str],
+) -> Result<Program, LoadError> {
+    heap_tag!();
+    // segments stay below the stack and its guard page
+    let segment_range = USER_START..USER_STACK_TOP - USER_STACK_SIZE - PAGE_SIZE;
+    let executable = elf_loader::parse(file, segment_range)?;
+
+    let mut space = AddressSpace::new_user()?;
+    for segment in executable.segments() {
+        load_segment(&mut space, file, segment)?;
+    }
+
+    let stack_start = VirtAddr::new(USER_STACK_TOP - USER_STACK_SIZE);
+    space.reserve(stack_start - PAGE_SIZE, PAGE_SIZE)?;
+    space.map(
+        stack_start,
+        USER_STACK_SIZE,
+        Protection::USER | Protection::WRITE,
+    )?;
+
+    let stack =
+        elf_loader::initial_stack(USER_STACK_TOP, args, env, executable.auxv(), random_bytes())?;
+    space.write(VirtAddr::new(stack.strings_start), &stack.strings)?;
+    space.write(
+        VirtAddr::new(stack.stack_pointer),
+        stack.pointers.as_bytes(),
+    )?;
+
+    Ok(Program {
+        space,
+        entry: VirtAddr::new(executable.entry),
+        stack_pointer: VirtAddr::new(stack.stack_pointer),
+    })
+}
+
+/// Maps the segment's pages and copies its file contents, the rest stays
+/// zero. Pages are copied rather than mapped from the file, so the file
+/// doesn't need to outlive the address space.
+fn load_segment<R: Read + Seek>(
+    space: &mut AddressSpace,
+    file: &mut R,
+    segment: &ProgramHeader,
+) -> Result<(), LoadError> {
+    let (start, end) = segment.pages();
+    space.map(VirtAddr::new(start), end - start, protection(segment))?;
+
+    file.seek(SeekFrom::Start(segment.offset))?;
+    let mut buffer = vec![0; PAGE_SIZE as usize];
+    let mut copied = 0;
+    while copied < segment.file_size {
+        let size = (segment.file_size - copied).min(PAGE_SIZE) as usize;
+        file.read_exact(&mut buffer[..size])?;
+        space.write(
+            VirtAddr::new(segment.virtual_address + copied),
+            &buffer[..size],
+        )?;
+        copied += size as u64;
+    }
+    Ok(())
+}
+
+fn protection(segment: &ProgramHeader) -> Protection {
+    let mut protection = Protection::USER;
+    if segment.is_writable() {
+        protection |= Protection::WRITE;
+    }
+    if segment.is_executable() {
+        protection |= Protection::EXECUTE;
+    }
+    protection
+}
+
+fn random_bytes() -> [u8; RANDOM_SIZE] {
+    let random = || {
+        RdRand::new()
+            .and_then(|rdrand| rdrand.get_u64())
+            // without RDRAND only the boot time varies
+            .unwrap_or_else(|| unsafe { _rdtsc() }.wrapping_mul(0x9e37_79b9_7f4a_7c15))
+    };
+    let mut bytes = [0; RANDOM_SIZE];
+    bytes[..8].copy_from_slice(&random().to_le_bytes());
+    bytes[8..].copy_from_slice(&random().to_le_bytes());
+    bytes
+}
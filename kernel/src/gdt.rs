@@ -0,0 +1,129 @@
+// see https://os.phil-opp.com/double-fault-exceptions/
+
+use alloc::{boxed::Box, vec};
+
+use spin::Lazy;
+use x86_64::{
+    VirtAddr,
+    instructions::{
+        segmentation::{CS, DS, ES, SS, Segment},
+        tables::load_tss,
+    },
+    structures::{
+        gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector},
+        tss::TaskStateSegment,
+    },
+};
+
+/// IST slot used by the double fault handler, so a kernel stack overflow
+/// is reported instead of triple faulting.
+pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+pub const NMI_IST_INDEX: u16 = 1;
+pub const MACHINE_CHECK_IST_INDEX: u16 = 2;
+
+const IST_STACK_SIZE: usize = 5 * 4096;
+/// Stack used when an interrupt arrives while running in ring 3.
+const PRIVILEGE_STACK_SIZE: usize = 5 * 4096;
+
+#[repr(C, align(16))]
+struct Stack<const N: usize>([u8; N]);
+
+impl<const N: usize> Stack<N> {
+    const fn new() -> Self {
+        Self([0; N])
+    }
+
+    /// Stacks grow down, returns the address just past the end of the stack.
+    fn top(stack: *const Self) -> VirtAddr {
+        VirtAddr::from_ptr(stack) + N as u64
+    }
+}
+
+static mut DOUBLE_FAULT_STACK: Stack<IST_STACK_SIZE> = Stack::new();
+static mut NMI_STACK: Stack<IST_STACK_SIZE> = Stack::new();
+static mut MACHINE_CHECK_STACK: Stack<IST_STACK_SIZE> = Stack::new();
+static mut PRIVILEGE_STACK: Stack<PRIVILEGE_STACK_SIZE> = Stack::new();
+
+static TSS: Lazy<TaskStateSegment> = Lazy::new(|| {
+    let mut tss = TaskStateSegment::new();
+    // only the addresses of the stacks are taken, they are never accessed from Rust
+    tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] =
+        Stack::top(&raw const DOUBLE_FAULT_STACK);
+    tss.interrupt_stack_table[NMI_IST_INDEX as usize] = Stack::top(&raw const NMI_STACK);
+    tss.interrupt_stack_table[MACHINE_CHECK_IST_INDEX as usize] =
+        Stack::top(&raw const MACHINE_CHECK_STACK);
+    tss.privilege_stack_table[0] = Stack::top(&raw const PRIVILEGE_STACK);
+    tss
+});
+
+#[derive(Debug, Clone, Copy)]
+#[allow(dead_code)] // user segments are unused until there is a user mode
+pub struct Selectors {
+    pub kernel_code: SegmentSelector,
+    pub kernel_data: SegmentSelector,
+    pub user_data: SegmentSelector,
+    pub user_code: SegmentSelector,
+    pub tss: SegmentSelector,
+}
+
+static GDT: Lazy<(GlobalDescriptorTable, Selectors)> = Lazy::new(|| build_gdt(&TSS));
+
+/// Every CPU has its own GDT with the same layout, only the TSS differs.
+fn build_gdt(tss: &'static TaskStateSegment) -> (GlobalDescriptorTable, Selectors) {
+    let mut gdt = GlobalDescriptorTable::new();
+    let kernel_code = gdt.append(Descriptor::kernel_code_segment());
+    let kernel_data = gdt.append(Descriptor::kernel_data_segment());
+    // sysret expects user data to come right before user code
+    let user_data = gdt.append(Descriptor::user_data_segment());
+    let user_code = gdt.append(Descriptor::user_code_segment());
+    let tss = gdt.append(Descriptor::tss_segment(tss));
+    (
+        gdt,
+        Selectors {
+            kernel_code,
+            kernel_data,
+            user_data,
+            user_code,
+            tss,
+        },
+    )
+}
+
+/// Loads the kernel GDT, reloads the segment registers and loads the TSS.
+pub fn init() {
+    load(&GDT.0, &GDT.1);
+}
+
+/// Loads a GDT and TSS of its own on an application processor, with stacks
+/// allocated from the heap. They are never freed.
+pub fn init_ap() {
+    let leak_stack = |size: usize| {
+        let stack = Box::leak(vec![0u8; size].into_boxed_slice());
+        VirtAddr::from_ptr(stack.as_ptr_range().end).align_down(16u64)
+    };
+    let mut tss = TaskStateSegment::new();
+    tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = leak_stack(IST_STACK_SIZE);
+    tss.interrupt_stack_table[NMI_IST_INDEX as usize] = leak_stack(IST_STACK_SIZE);
+    tss.interrupt_stack_table[MACHINE_CHECK_IST_INDEX as usize] = leak_stack(IST_STACK_SIZE);
+    tss.privilege_stack_table[0] = leak_stack(PRIVILEGE_STACK_SIZE);
+    let tss = Box::leak(Box::new(tss));
+    let (gdt, selectors) = build_gdt(tss);
+    let gdt = Box::leak(Box::new(gdt));
+    load(gdt, &selectors);
+}
+
+fn load(gdt: &'static GlobalDescriptorTable, selectors: &Selectors) {
+    gdt.load();
+    unsafe {
+        CS::set_reg(selectors.kernel_code);
+        SS::set_reg(selectors.kernel_data);
+        DS::set_reg(selectors.kernel_data);
+        ES::set_reg(selectors.kernel_data);
+        load_tss(selectors.tss);
+    }
+}
+
+#[allow(dead_code)]
+pub fn selectors() -> &'static Selectors {
+    &GDT.1
+}
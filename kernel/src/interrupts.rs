@@ -0,0 +1,439 @@
+// see https://os.phil-opp.com/cpu-exceptions/
+
+use irq_sync::IrqSpinLock;
+use spin::Lazy;
+use x86_64::{
+    VirtAddr,
+    registers::{
+        control::{Cr0, Cr2, Cr3, Cr4},
+        rflags::RFlags,
+    },
+    structures::{
+        idt::{
+            ExceptionVector, InterruptDescriptorTable, InterruptStackFrame,
+            InterruptStackFrameValue, PageFaultErrorCode,
+        },
+        paging::{Page, Size4KiB},
+    },
+};
+
+use crate::{
+    apic::{self, ApicError},
+    gdt, println, scheduler,
+    sync::level,
+    vmm,
+};
+
+/// Number of instruction bytes printed, the longest x86 instruction is 15 bytes.
+const INSTRUCTION_BYTES: usize = 16;
+
+static IDT: Lazy<InterruptDescriptorTable> = Lazy::new(|| {
+    let mut idt = InterruptDescriptorTable::new();
+    // SAFETY: the entry stubs take the frame the CPU pushes for their vector
+    unsafe {
+        idt.divide_error
+            .set_handler_addr(entry_addr(divide_error_entry));
+        idt.debug.set_handler_addr(entry_addr(debug_entry));
+        idt.breakpoint
+            .set_handler_addr(entry_addr(breakpoint_entry));
+        idt.overflow.set_handler_addr(entry_addr(overflow_entry));
+        idt.bound_range_exceeded
+            .set_handler_addr(entry_addr(bound_range_exceeded_entry));
+        idt.invalid_opcode
+            .set_handler_addr(entry_addr(invalid_opcode_entry));
+        idt.device_not_available
+            .set_handler_addr(entry_addr(device_not_available_entry));
+        idt.invalid_tss
+            .set_handler_addr(entry_addr(invalid_tss_entry));
+        idt.segment_not_present
+            .set_handler_addr(entry_addr(segment_not_present_entry));
+        idt.stack_segment_fault
+            .set_handler_addr(entry_addr(stack_segment_fault_entry));
+        idt.general_protection_fault
+            .set_handler_addr(entry_addr(general_protection_fault_entry));
+        idt.page_fault
+            .set_handler_addr(entry_addr(page_fault_entry));
+        idt.x87_floating_point
+            .set_handler_addr(entry_addr(x87_floating_point_entry));
+        idt.alignment_check
+            .set_handler_addr(entry_addr(alignment_check_entry));
+        idt.simd_floating_point
+            .set_handler_addr(entry_addr(simd_floating_point_entry));
+        idt.virtualization
+            .set_handler_addr(entry_addr(virtualization_entry));
+        idt.cp_protection_exception
+            .set_handler_addr(entry_addr(cp_protection_entry));
+        idt.hv_injection_exception
+            .set_handler_addr(entry_addr(hv_injection_entry));
+        idt.vmm_communication_exception
+            .set_handler_addr(entry_addr(vmm_communication_entry));
+        idt.security_exception
+            .set_handler_addr(entry_addr(security_entry));
+
+        // these can happen with a broken stack, run them on their own stacks
+        idt.double_fault
+            .set_handler_addr(entry_addr(double_fault_entry))
+            .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+        idt.non_maskable_interrupt
+            .set_handler_addr(entry_addr(nmi_entry))
+            .set_stack_index(gdt::NMI_IST_INDEX);
+        idt.machine_check
+            .set_handler_addr(entry_addr(machine_check_entry))
+            .set_stack_index(gdt::MACHINE_CHECK_IST_INDEX);
+    }
+
+    x86_64::set_general_handler!(&mut idt, irq_handler, apic::IRQ_BASE_VECTOR..=255);
+    idt
+});
+
+/// Called with the vector the interrupt arrived on, the end of interrupt is
+/// signaled after it returns.
+pub type IrqHandler = fn(vector: u8);
+
+static IRQ_HANDLERS: IrqSpinLock<[Option<IrqHandler>; 256]> =
+    IrqSpinLock::with_level([None; 256], level::IRQ_HANDLERS);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqError {
+    /// Vectors below 32 are CPU exceptions
+    ReservedVector(u8),
+    AlreadyRegistered(u8),
+    #[allow(dead_code)]
+    Apic(ApicError),
+}
+
+impl From<ApicError> for IrqError {
+    fn from(value: ApicError) -> Self {
+        IrqError::Apic(value)
+    }
+}
+
+pub fn init_idt() {
+    IDT.load();
+}
+
+/// Installs a handler for an interrupt vector without touching the interrupt
+/// controllers, used for interrupts raised by the local APIC.
+pub fn register_vector(vector: u8, handler: IrqHandler) -> Result<(), IrqError> {
+    if vector < apic::IRQ_BASE_VECTOR || vector == apic::SPURIOUS_VECTOR {
+        return Err(IrqError::ReservedVector(vector));
+    }
+    let mut handlers = IRQ_HANDLERS.lock();
+    let slot = &mut handlers[vector as usize];
+    if slot.is_some() {
+        return Err(IrqError::AlreadyRegistered(vector));
+    }
+    *slot = Some(handler);
+    Ok(())
+}
+
+/// Installs a handler for an ISA IRQ (e.g. 1 for the keyboard, 4 for COM1)
+/// and unmasks it in the I/O APIC. Returns the vector it is delivered on.
+pub fn register_irq(irq: u8, handler: IrqHandler) -> Result<u8, IrqError> {
+    let vector = apic::gsi_vector(apic::isa_irq_gsi(irq))?;
+    register_vector(vector, handler)?;
+    apic::route_isa_irq(irq)?;
+    Ok(vector)
+}
+
+fn irq_handler(_stack_frame: InterruptStackFrame, vector: u8, _error_code: Option<u64>) {
+    // spurious interrupts are not in service and must not be acknowledged
+    if vector == apic::SPURIOUS_VECTOR {
+        return;
+    }
+    let handler = IRQ_HANDLERS.lock()[vector as usize];
+    match handler {
+        Some(handler) => handler(vector),
+        None => println!("unhandled interrupt vector 0x{vector:02x}"),
+    }
+    apic::end_of_interrupt();
+    // switching threads before the end of interrupt would block this vector
+    scheduler::preempt();
+}
+
+/// General purpose registers of the interrupted code, in the reverse order
+/// the entry stubs push them.
+#[repr(C)]
+struct Registers {
+    r15: u64,
+    r14: u64,
+    r13: u64,
+    r12: u64,
+    r11: u64,
+    r10: u64,
+    r9: u64,
+    r8: u64,
+    rbp: u64,
+    rdi: u64,
+    rsi: u64,
+    rdx: u64,
+    rcx: u64,
+    rbx: u64,
+    rax: u64,
+}
+
+/// The stack of an exception entry stub when it calls [`exception_handler`].
+#[repr(C)]
+struct ExceptionFrame {
+    registers: Registers,
+    vector: u64,
+    /// Zero for exceptions without an error code
+    error_code: u64,
+    stack_frame: InterruptStackFrameValue,
+}
+
+/// Exceptions for which the CPU pushes an error code.
+const fn has_error_code(vector: ExceptionVector) -> bool {
+    matches!(
+        vector,
+        ExceptionVector::Double
+            | ExceptionVector::InvalidTss
+            | ExceptionVector::SegmentNotPresent
+            | ExceptionVector::Stack
+            | ExceptionVector::GeneralProtection
+            | ExceptionVector::Page
+            | ExceptionVector::AlignmentCheck
+            | ExceptionVector::ControlProtection
+            | ExceptionVector::VmmCommunication
+            | ExceptionVector::Security
+    )
+}
+
+/// Defines the entry stub of an exception. It pushes a zero if the CPU
+/// doesn't push an error code, the vector and the general purpose registers,
+/// calls [`exception_handler`] with the resulting [`ExceptionFrame`] and
+/// returns from the interrupt if the handler does.
+macro_rules! exception_entry {
+    ($name:ident, $vector:ident) => {
+        #[unsafe(naked)]
+        unsafe extern "C" fn $name() {
+            core::arch::naked_asm!(
+                ".if {has_error_code} == 0",
+                "push 0",
+                ".endif",
+                "push {vector}",
+                "push rax",
+                "push rbx",
+                "push rcx",
+                "push rdx",
+                "push rsi",
+                "push rdi",
+                "push rbp",
+                "push r8",
+                "push r9",
+                "push r10",
+                "push r11",
+                "push r12",
+                "push r13",
+                "push r14",
+                "push r15",
+                // the CPU aligned the stack to 16 bytes before pushing its 5
+                // words, after 17 more it is aligned again for the call
+                "mov rdi, rsp",
+                "cld",
+                "call {handler}",
+                "pop r15",
+                "pop r14",
+                "pop r13",
+                "pop r12",
+                "pop r11",
+                "pop r10",
+                "pop r9",
+                "pop r8",
+                "pop rbp",
+                "pop rdi",
+                "pop rsi",
+                "pop rdx",
+                "pop rcx",
+                "pop rbx",
+                "pop rax",
+                // the vector and error code
+                "add rsp, 16",
+                "iretq",
+                has_error_code = const has_error_code(ExceptionVector::$vector) as u8,
+                vector = const ExceptionVector::$vector as u8,
+                handler = sym exception_handler,
+            )
+        }
+    };
+}
+
+exception_entry!(divide_error_entry, Division);
+exception_entry!(debug_entry, Debug);
+exception_entry!(nmi_entry, NonMaskableInterrupt);
+exception_entry!(breakpoint_entry, Breakpoint);
+exception_entry!(overflow_entry, Overflow);
+exception_entry!(bound_range_exceeded_entry, BoundRange);
+exception_entry!(invalid_opcode_entry, InvalidOpcode);
+exception_entry!(device_not_available_entry, DeviceNotAvailable);
+exception_entry!(double_fault_entry, Double);
+exception_entry!(invalid_tss_entry, InvalidTss);
+exception_entry!(segment_not_present_entry, SegmentNotPresent);
+exception_entry!(stack_segment_fault_entry, Stack);
+exception_entry!(general_protection_fault_entry, GeneralProtection);
+exception_entry!(page_fault_entry, Page);
+exception_entry!(x87_floating_point_entry, X87FloatingPoint);
+exception_entry!(alignment_check_entry, AlignmentCheck);
+exception_entry!(machine_check_entry, MachineCheck);
+exception_entry!(simd_floating_point_entry, SimdFloatingPoint);
+exception_entry!(virtualization_entry, Virtualization);
+exception_entry!(cp_protection_entry, ControlProtection);
+exception_entry!(hv_injection_entry, HypervisorInjection);
+exception_entry!(vmm_communication_entry, VmmCommunication);
+exception_entry!(security_entry, Security);
+
+fn entry_addr(entry: unsafe extern "C" fn()) -> VirtAddr {
+    VirtAddr::new(entry as usize as u64)
+}
+
+/// Dumps the exception and halts, except for traps and resolved page faults
+/// which return to the interrupted code.
+extern "C" fn exception_handler(frame: &ExceptionFrame) {
+    match ExceptionVector::try_from(frame.vector as u8) {
+        // traps, the stack frame already points past the instruction so
+        // returning resumes execution
+        Ok(ExceptionVector::Breakpoint | ExceptionVector::Debug) => {
+            dump_exception(frame, false);
+        }
+        Ok(ExceptionVector::Page) => page_fault_handler(frame),
+        // the fault may have been caused by the instruction fetch, don't touch it
+        Ok(ExceptionVector::Double | ExceptionVector::MachineCheck) | Err(_) => {
+            dump_exception(frame, false);
+            halt();
+        }
+        Ok(_) => {
+            dump_exception(frame, true);
+            halt();
+        }
+    }
+}
+
+fn page_fault_handler(frame: &ExceptionFrame) {
+    let error_code = PageFaultErrorCode::from_bits_truncate(frame.error_code);
+    // only faults with interrupts enabled may sleep while they are resolved
+    if let Ok(address) = Cr2::read()
+        && frame.stack_frame.cpu_flags.contains(RFlags::INTERRUPT_FLAG)
+    {
+        x86_64::instructions::interrupts::enable();
+        let resolved = vmm::handle_page_fault(address, error_code);
+        x86_64::instructions::interrupts::disable();
+        if resolved {
+            return;
+        }
+    }
+    // the faulting address is the instruction itself, reading it would fault again
+    dump_exception(
+        frame,
+        !error_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH),
+    );
+    halt();
+}
+
+fn description(vector: u64) -> &'static str {
+    let Ok(vector) = ExceptionVector::try_from(vector as u8) else {
+        return "UNKNOWN";
+    };
+    match vector {
+        ExceptionVector::Division => "DIVIDE ERROR",
+        ExceptionVector::Debug => "DEBUG",
+        ExceptionVector::NonMaskableInterrupt => "NON MASKABLE INTERRUPT",
+        ExceptionVector::Breakpoint => "BREAKPOINT",
+        ExceptionVector::Overflow => "OVERFLOW",
+        ExceptionVector::BoundRange => "BOUND RANGE EXCEEDED",
+        ExceptionVector::InvalidOpcode => "INVALID OPCODE",
+        ExceptionVector::DeviceNotAvailable => "DEVICE NOT AVAILABLE",
+        ExceptionVector::Double => "DOUBLE FAULT",
+        ExceptionVector::InvalidTss => "INVALID TSS",
+        ExceptionVector::SegmentNotPresent => "SEGMENT NOT PRESENT",
+        ExceptionVector::Stack => "STACK SEGMENT FAULT",
+        ExceptionVector::GeneralProtection => "GENERAL PROTECTION FAULT",
+        ExceptionVector::Page => "PAGE FAULT",
+        ExceptionVector::X87FloatingPoint => "x87 FLOATING POINT",
+        ExceptionVector::AlignmentCheck => "ALIGNMENT CHECK",
+        ExceptionVector::MachineCheck => "MACHINE CHECK",
+        ExceptionVector::SimdFloatingPoint => "SIMD FLOATING POINT",
+        ExceptionVector::Virtualization => "VIRTUALIZATION",
+        ExceptionVector::ControlProtection => "CONTROL PROTECTION",
+        ExceptionVector::HypervisorInjection => "HYPERVISOR INJECTION",
+        ExceptionVector::VmmCommunication => "VMM COMMUNICATION",
+        ExceptionVector::Security => "SECURITY",
+        _ => "UNKNOWN",
+    }
+}
+
+fn dump_exception(frame: &ExceptionFrame, dump_instruction: bool) {
+    println!("EXCEPTION: {}", description(frame.vector));
+    if let Ok(vector) = ExceptionVector::try_from(frame.vector as u8)
+        && has_error_code(vector)
+    {
+        println!("Error Code: 0x{:x}", frame.error_code);
+    }
+    let stack_frame = &frame.stack_frame;
+    println!(
+        "RIP: {:#018x}  CS: {:#06x}  RFLAGS: {:#018x}",
+        stack_frame.instruction_pointer.as_u64(),
+        stack_frame.code_segment.0,
+        stack_frame.cpu_flags.bits()
+    );
+    println!(
+        "RSP: {:#018x}  SS: {:#06x}",
+        stack_frame.stack_pointer.as_u64(),
+        stack_frame.stack_segment.0
+    );
+    let r = &frame.registers;
+    println!(
+        "RAX: {:#018x}  RBX: {:#018x}  RCX: {:#018x}",
+        r.rax, r.rbx, r.rcx
+    );
+    println!(
+        "RDX: {:#018x}  RSI: {:#018x}  RDI: {:#018x}",
+        r.rdx, r.rsi, r.rdi
+    );
+    println!(
+        "RBP: {:#018x}  R8:  {:#018x}  R9:  {:#018x}",
+        r.rbp, r.r8, r.r9
+    );
+    println!(
+        "R10: {:#018x}  R11: {:#018x}  R12: {:#018x}",
+        r.r10, r.r11, r.r12
+    );
+    println!(
+        "R13: {:#018x}  R14: {:#018x}  R15: {:#018x}",
+        r.r13, r.r14, r.r15
+    );
+    let (cr3_frame, cr3_flags) = Cr3::read();
+    println!(
+        "CR0: {:#018x}  CR3: {:#018x} ({:?})",
+        Cr0::read_raw(),
+        cr3_frame.start_address().as_u64(),
+        cr3_flags
+    );
+    println!(
+        "CR2: {:#018x}  CR4: {:#018x}",
+        Cr2::read_raw(),
+        Cr4::read_raw()
+    );
+    if dump_instruction {
+        dump_instruction_bytes(stack_frame.instruction_pointer);
+    }
+}
+
+/// Prints the bytes at `rip`, stopping at the end of its page since the next
+/// page may not be mapped.
+fn dump_instruction_bytes(rip: VirtAddr) {
+    let page_end = Page::<Size4KiB>::containing_address(rip).start_address() + 4096u64;
+    let len = (page_end - rip).min(INSTRUCTION_BYTES as u64) as usize;
+    let bytes = unsafe { core::slice::from_raw_parts(rip.as_ptr::<u8>(), len) };
+    crate::print!("Instruction:");
+    for b in bytes {
+        crate::print!(" {b:02x}");
+    }
+    println!();
+}
+
+fn halt() -> ! {
+    loop {
+        x86_64::instructions::interrupts::disable();
+        x86_64::instructions::hlt();
+    }
+}
@@ -1,36 +1,63 @@
 #![no_std]
 #![no_main]
+#![feature(abi_x86_interrupt)]
 
 extern crate alloc;
 
-use core::slice;
+use alloc::vec::Vec;
+use core::{slice, time::Duration};
 
 use ansi_escape::{Ansi, Color};
 use bootloader_api::{BootInfo, BootloaderConfig, config::Mapping, info::Optional};
 
 use console::console_init;
+use nostdio::{Cursor, OffsetBlockDevice};
 use pci::PCI_DRIVER;
 use serial_port::serial1_init;
+use sync::{CondVar, Mutex, Semaphore};
 use x86_64::VirtAddr;
 
-use crate::memory::BootInfoFrameAllocator;
-
+mod acpi;
 mod allocator;
+mod apic;
 mod console;
+mod cpu;
+mod elf;
+mod gdt;
+mod interrupts;
 mod memory;
+mod mmio;
+mod rtc;
+mod scheduler;
+mod smp;
+mod sync;
+mod task;
+mod timer;
+mod tlb;
+mod vmm;
+
+const MIB: u64 = 1024 * 1024;
 
 const BOOTLOADER_CONFIG: BootloaderConfig = {
     let mut config = BootloaderConfig::new_default();
-    config.mappings.physical_memory = Some(Mapping::FixedAddress(0x0000_6000_0000_0000));
+    config.mappings.physical_memory = Some(Mapping::FixedAddress(0xffff_a000_0000_0000));
+    // the lower half is left to user address spaces
+    config.mappings.dynamic_range_start = Some(0xffff_8000_0000_0000);
     config
 };
 
 bootloader_api::entry_point!(kernel_main, config = &BOOTLOADER_CONFIG);
 
 fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
+    cpu::init_bsp();
     unsafe { serial1_init() }.expect("serial1 failed to init");
     println!("after serial init");
 
+    gdt::init();
+    println_status!("OK", "GDT initialized.");
+    interrupts::init_idt();
+    println_status!("OK", "IDT initialized.");
+
     let framebuffer = boot_info.framebuffer.take();
 
     if let Some(framebuffer) = framebuffer {
@@ -41,14 +68,83 @@ fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
     if let Optional::Some(physical_memory_offset) = boot_info.physical_memory_offset {
         let phys_mem_offset = VirtAddr::new(physical_memory_offset);
         let mut mapper = unsafe { memory::init(phys_mem_offset) };
-        let mut frame_allocator =
-            unsafe { BootInfoFrameAllocator::init(&boot_info.memory_regions) };
-        allocator::init_heap(&mut mapper, &mut frame_allocator)
-            .expect("heap initialization failed");
+        unsafe { memory::init_frame_allocator(&boot_info.memory_regions, phys_mem_offset) }
+            .expect("frame allocator initialization failed");
+        if let Some(stats) = memory::frame_stats() {
+            println_status!(
+                "OK",
+                "Frame allocator initialized ({} MiB free of {} MiB).",
+                stats.free / MIB,
+                stats.total / MIB
+            );
+        }
+        let mut frame_allocator = memory::GlobalFrameAllocator;
+        let trampoline = smp::allocate_trampoline(&mut mapper, &mut frame_allocator);
+        memory::init_kernel_mapper(mapper);
+        allocator::init_heap().expect("heap initialization failed");
         println_status!("OK", "Allocator initialized.");
+        vmm::init().expect("virtual memory manager initialization failed");
+        println_status!("OK", "Virtual memory manager initialized.");
+        mmio::init().expect("MMIO initialization failed");
+
+        let rsdp_addr = boot_info.rsdp_addr.into_option();
+        let apic_config = match unsafe { acpi::init(phys_mem_offset, rsdp_addr) } {
+            Ok(tables) => {
+                println_status!("OK", "ACPI {} initialized.", tables.revision);
+                tables.madt.as_ref().map(acpi::Madt::apic_config)
+            }
+            Err(err) => {
+                println!("ACPI initialization failed: {err:?}");
+                None
+            }
+        };
+        unsafe { apic::init(&apic_config.unwrap_or_default()) }
+            .expect("APIC initialization failed");
+        println_status!("OK", "APIC initialized.");
+
+        let hpet_address = acpi::tables()
+            .and_then(|tables| tables.hpet)
+            .map(|hpet| hpet.address);
+        unsafe { timer::init(hpet_address) }.expect("timer initialization failed");
+        x86_64::instructions::interrupts::enable();
+        println_status!("OK", "Timer initialized ({}).", timer::clock_source());
+
+        let century_register = acpi::tables()
+            .and_then(|tables| tables.fadt)
+            .map_or(0, |fadt| fadt.century);
+        match rtc::init(century_register) {
+            Ok(timestamp) => match timestamp.to_date_time() {
+                Ok(date_time) => println_status!("OK", "RTC initialized ({date_time})."),
+                Err(_) => println_status!("OK", "RTC initialized."),
+            },
+            Err(err) => println!("RTC initialization failed: {err:?}"),
+        }
+
+        scheduler::init();
+        println_status!("OK", "Scheduler initialized.");
+        match vmm::self_test() {
+            Ok(()) => println_status!("OK", "User address space mapped, faulted in and unmapped."),
+            Err(err) => println!("user address space self-test failed: {err:?}"),
+        }
+
+        task::keyboard::init().expect("keyboard initialization failed");
+        task::serial::init().expect("serial input initialization failed");
+        println_status!("OK", "Keyboard and serial input initialized.");
+
+        let processors = acpi::tables()
+            .and_then(|tables| tables.madt.as_ref())
+            .map_or(&[][..], |madt| &madt.processors[..]);
+        match trampoline
+            .and_then(|trampoline| unsafe { smp::init(phys_mem_offset, &trampoline, processors) })
+        {
+            Ok(online) => println_status!("OK", "SMP initialized ({} CPUs online).", online + 1),
+            Err(err) => println!("SMP initialization failed: {err:?}"),
+        }
     }
 
     if let Optional::Some(ramdisk_addr) = boot_info.ramdisk_addr {
+        // the file system's allocations
+        heap_tag!();
         println!(
             "ram disk 0x{ramdisk_addr:08x} (size: {})",
             boot_info.ramdisk_len
@@ -56,29 +152,124 @@ fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
         let data = unsafe {
             slice::from_raw_parts_mut(ramdisk_addr as *mut u8, boot_info.ramdisk_len as usize)
         };
-        let mut disk = vsfs::io::Cursor::new(data);
-        let mut vsfs = vsfs::Vsfs::new(&mut disk, vsfs::FsOptions::new()).unwrap();
+        let disk = OffsetBlockDevice::from_cursor(Cursor::new(data), 512).unwrap();
+        let mut vsfs = vsfs::Vsfs::new(disk, vsfs::FsOptions::new()).unwrap();
 
-        let root_dir = vsfs.root_dir();
-        for entry in root_dir.iter(&mut vsfs).unwrap() {
+        let root_dir = vsfs.root_dir().unwrap();
+        for entry in root_dir.iter(&vsfs).unwrap() {
             let entry = entry.unwrap();
             println!("{entry:?}");
         }
+
+        if let Ok(mut file) = root_dir.open_file(&mut vsfs, "init") {
+            match elf::load(&mut file, &["init"], &[]) {
+                Ok(program) => println_status!("OK", "Loaded init (entry {:#x}).", program.entry),
+                Err(err) => println!("failed to load init: {err:?}"),
+            }
+        }
     } else {
         println!("ram disk not found");
     }
 
-    for pci_device in PCI_DRIVER.iterate_devices() {
-        println!("{pci_device:?}");
-    }
+    let pci = scheduler::spawn("pci", || {
+        for pci_device in PCI_DRIVER.iterate_devices() {
+            println!("{pci_device:?}");
+        }
+    })
+    .expect("failed to spawn pci thread");
+    pci.join();
+    test_scheduler();
+    test_sync();
 
-    loop {
-        core::hint::spin_loop();
+    match rtc::now().map(|now| now.to_date_time()) {
+        Some(Ok(date_time)) => println!("boot took {:?}, it is {date_time}", timer::now()),
+        _ => println!("boot took {:?}", timer::now()),
     }
+
+    task::spawn(task::keyboard::print_scancodes()).expect("failed to spawn keyboard task");
+    task::spawn(task::serial::echo()).expect("failed to spawn serial task");
+    task::executor::Executor::new().run();
+
+    println!("shutting down");
+    power_off()
 }
 
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo) -> ! {
     println!("{}", info);
-    loop {}
+    power_off()
+}
+
+/// Runs a thread which sleeps while the caller yields until it should be done.
+fn test_scheduler() {
+    const NAP: Duration = Duration::from_millis(10);
+    let start = timer::now();
+    let sleeper = scheduler::spawn("sleeper", || {
+        scheduler::sleep(NAP);
+        scheduler::current_name()
+    })
+    .expect("failed to spawn sleeper thread");
+    let id = sleeper.id();
+    while timer::now() < start + NAP {
+        scheduler::yield_now();
+    }
+    match sleeper.join() {
+        Some(Some("sleeper")) if timer::now() >= start + NAP => {
+            println_status!("OK", "Thread {id:?} slept and was joined.")
+        }
+        result => println!("scheduler self-test failed: {result:?}"),
+    }
+}
+
+/// Threads block on a semaphore until the caller releases it, count
+/// themselves under a mutex and wait until the caller has seen all of them.
+fn test_sync() {
+    const WAITERS: usize = 2;
+    static PERMITS: Semaphore = Semaphore::new(0);
+    /// Started waiters and whether they may finish
+    static STATE: Mutex<(usize, bool)> = Mutex::new((0, false));
+    static STARTED: CondVar = CondVar::new();
+    static FINISH: CondVar = CondVar::new();
+
+    let waiters: Vec<_> = (0..WAITERS)
+        .map(|_| {
+            scheduler::spawn("waiter", || {
+                PERMITS.acquire();
+                let mut state = STATE.lock();
+                state.0 += 1;
+                // only the caller waits for STARTED
+                STARTED.notify_one();
+                drop(FINISH.wait_while(state, |(_, finish)| !*finish));
+            })
+            .expect("failed to spawn waiter thread")
+        })
+        .collect();
+    for _ in 0..WAITERS {
+        PERMITS.release();
+    }
+    let mut state = STARTED.wait_while(STATE.lock(), |(started, _)| *started < WAITERS);
+    state.1 = true;
+    let started = state.0;
+    drop(state);
+    FINISH.notify_all();
+
+    let joined = waiters.into_iter().all(|waiter| waiter.join().is_some());
+    if started == WAITERS && joined && !PERMITS.try_acquire() {
+        println_status!(
+            "OK",
+            "{WAITERS} threads passed a semaphore and a condition variable."
+        );
+    } else {
+        println!("sync self-test failed: {started} started, joined {joined}");
+    }
+}
+
+/// Shuts the machine down, e.g. so QEMU exits, or halts if ACPI can't.
+fn power_off() -> ! {
+    let Err(err) = acpi::shutdown();
+    println!("shutdown failed: {err:?}");
+    x86_64::instructions::interrupts::disable();
+    loop {
+        x86_64::instructions::hlt();
+    }
 }
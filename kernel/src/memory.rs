@@ -1,11 +1,22 @@
 // see https://github.com/phil-opp/blog_os/blob/post-10/src/memory.rs
 
+use alloc::collections::BTreeMap;
 use bootloader_api::info::{MemoryRegionKind, MemoryRegions};
+use conquer_once::spin::OnceCell;
+use irq_sync::IrqSpinLock;
 use x86_64::{
     PhysAddr, VirtAddr,
-    structures::paging::{FrameAllocator, OffsetPageTable, PageTable, PhysFrame, Size4KiB},
+    structures::paging::{
+        FrameAllocator, FrameDeallocator, OffsetPageTable, PageTable, PhysFrame, Size2MiB,
+        Size4KiB, frame::PhysFrameRange,
+    },
 };
 
+use crate::sync::level;
+
+const FRAME_SIZE: u64 = 4096;
+const FRAMES_PER_HUGE_FRAME: usize = 512;
+
 /// Initialize a new OffsetPageTable.
 ///
 /// This function is unsafe because the caller must guarantee that the
@@ -13,12 +24,37 @@ use x86_64::{
 /// `physical_memory_offset`. Also, this function must be only called once
 /// to avoid aliasing `&mut` references (which is undefined behavior).
 pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
+    PHYSICAL_MEMORY_OFFSET.init_once(|| physical_memory_offset);
     unsafe {
         let level_4_table = active_level_4_table(physical_memory_offset);
         OffsetPageTable::new(level_4_table, physical_memory_offset)
     }
 }
 
+static PHYSICAL_MEMORY_OFFSET: OnceCell<VirtAddr> = OnceCell::uninit();
+
+/// Where the complete physical memory is mapped, `None` before [`init`].
+pub fn physical_memory_offset() -> Option<VirtAddr> {
+    PHYSICAL_MEMORY_OFFSET.try_get().ok().copied()
+}
+
+/// The kernel's page tables once boot is done with them, see
+/// [`init_kernel_mapper`].
+static KERNEL_MAPPER: OnceCell<IrqSpinLock<OffsetPageTable<'static>>> = OnceCell::uninit();
+
+/// Hands the page tables from [`init`] over to the rest of the kernel, e.g.
+/// for growing the heap.
+pub fn init_kernel_mapper(mapper: OffsetPageTable<'static>) {
+    KERNEL_MAPPER.init_once(|| IrqSpinLock::with_level(mapper, level::PAGE_TABLES));
+}
+
+/// Runs `f` with the kernel's page tables locked, `None` before
+/// [`init_kernel_mapper`].
+pub fn with_kernel_mapper<R>(f: impl FnOnce(&mut OffsetPageTable<'static>) -> R) -> Option<R> {
+    let mapper = KERNEL_MAPPER.try_get().ok()?;
+    Some(f(&mut mapper.lock()))
+}
+
 /// Returns a mutable reference to the active level 4 table.
 ///
 /// This function is unsafe because the caller must guarantee that the
@@ -37,43 +73,360 @@ unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static mut
     unsafe { &mut *page_table_ptr }
 }
 
-/// A FrameAllocator that returns usable frames from the bootloader's memory map.
-pub struct BootInfoFrameAllocator {
-    memory_map: &'static MemoryRegions,
+/// Physical memory in bytes, see [`frame_stats`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameStats {
+    pub total: u64,
+    pub used: u64,
+    pub free: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameAllocatorError {
+    AlreadyInitialized,
+    /// No usable region is large enough to hold the bitmap
+    NoBitmapSpace,
+}
+
+/// Tracks every 4 KiB frame of physical memory with two bits, whether it is
+/// in use and whether it is memory the allocator hands out.
+pub struct BitmapFrameAllocator {
+    /// Set while the frame is in use or isn't usable memory
+    bitmap: &'static mut [u64],
+    /// Set for usable frames, except those reserved for the allocator itself
+    /// and frame 0, so freeing any other frame is ignored
+    allocatable: &'static mut [u64],
+    usable_frames: usize,
+    free_frames: usize,
+    /// Every frame below this index is in use
     next: usize,
 }
 
-impl BootInfoFrameAllocator {
-    /// Create a FrameAllocator from the passed memory map.
+impl BitmapFrameAllocator {
+    /// Builds the bitmaps from the bootloader's memory map, storing them in
+    /// the first usable region above 1 MiB which is large enough.
     ///
-    /// This function is unsafe because the caller must guarantee that the passed
-    /// memory map is valid. The main requirement is that all frames that are marked
-    /// as `USABLE` in it are really unused.
-    pub unsafe fn init(memory_map: &'static MemoryRegions) -> Self {
-        BootInfoFrameAllocator {
-            memory_map,
+    /// # Safety
+    /// The complete physical memory must be mapped at `physical_memory_offset`
+    /// and all frames marked as `USABLE` in the memory map must be unused.
+    pub unsafe fn init(
+        memory_regions: &MemoryRegions,
+        physical_memory_offset: VirtAddr,
+    ) -> Result<Self, FrameAllocatorError> {
+        let usable = || {
+            memory_regions
+                .iter()
+                .filter(|r| r.kind == MemoryRegionKind::Usable)
+                .map(|r| {
+                    (
+                        r.start.next_multiple_of(FRAME_SIZE),
+                        r.end / FRAME_SIZE * FRAME_SIZE,
+                    )
+                })
+                .filter(|(start, end)| start < end)
+        };
+        let frame_count = usable().map(|(_, end)| end / FRAME_SIZE).max().unwrap_or(0) as usize;
+        let words = frame_count.div_ceil(u64::BITS as usize);
+        let bitmap_size = 2 * (words * size_of::<u64>()) as u64;
+        // low memory is kept for things which need it, e.g. the SMP trampoline
+        let bitmap_start = usable()
+            .map(|(start, end)| (start.max(0x10_0000), end))
+            .chain(usable())
+            .find(|(start, end)| start + bitmap_size <= *end)
+            .map(|(start, _)| start)
+            .ok_or(FrameAllocatorError::NoBitmapSpace)?;
+
+        let bitmaps = unsafe {
+            core::slice::from_raw_parts_mut(
+                (physical_memory_offset + bitmap_start).as_mut_ptr::<u64>(),
+                2 * words,
+            )
+        };
+        let (bitmap, allocatable) = bitmaps.split_at_mut(words);
+        bitmap.fill(u64::MAX);
+        allocatable.fill(0);
+        let mut allocator = Self {
+            bitmap,
+            allocatable,
+            usable_frames: 0,
+            free_frames: 0,
             next: 0,
+        };
+        for (start, end) in usable() {
+            for index in (start / FRAME_SIZE) as usize..(end / FRAME_SIZE) as usize {
+                allocator.set_used(index, false);
+                set_bit(allocator.allocatable, index, true);
+                allocator.usable_frames += 1;
+                allocator.free_frames += 1;
+            }
+        }
+        let bitmap_frames = bitmap_size.div_ceil(FRAME_SIZE) as usize;
+        let bitmap_index = (bitmap_start / FRAME_SIZE) as usize;
+        // physical address 0 is too easily mistaken for a null pointer
+        for range in [bitmap_index..bitmap_index + bitmap_frames, 0..1] {
+            allocator.reserve(range.clone());
+            for index in range {
+                set_bit(allocator.allocatable, index, false);
+            }
+        }
+        Ok(allocator)
+    }
+
+    fn frame_count(&self) -> usize {
+        self.bitmap.len() * u64::BITS as usize
+    }
+
+    fn is_used(&self, index: usize) -> bool {
+        bit(self.bitmap, index).unwrap_or(true)
+    }
+
+    fn set_used(&mut self, index: usize, used: bool) {
+        set_bit(self.bitmap, index, used);
+    }
+
+    /// Marks free frames in `range` as used without handing them out.
+    fn reserve(&mut self, range: core::ops::Range<usize>) {
+        for index in range {
+            if !self.is_used(index) {
+                self.set_used(index, true);
+                self.free_frames -= 1;
+            }
+        }
+    }
+
+    fn frame(index: usize) -> PhysFrame {
+        PhysFrame::containing_address(PhysAddr::new(index as u64 * FRAME_SIZE))
+    }
+
+    fn index(frame: PhysFrame) -> usize {
+        (frame.start_address().as_u64() / FRAME_SIZE) as usize
+    }
+
+    /// Allocates `count` physically contiguous frames, e.g. for DMA, starting
+    /// at a multiple of `align` frames and ending below `limit` when given.
+    /// `align` must be a power of two.
+    pub fn allocate_contiguous(
+        &mut self,
+        count: usize,
+        align: usize,
+        limit: Option<PhysAddr>,
+    ) -> Option<PhysFrameRange> {
+        let end = limit.map_or(self.frame_count(), |limit| {
+            self.frame_count()
+                .min((limit.as_u64() / FRAME_SIZE) as usize)
+        });
+        let mut start = self.next.next_multiple_of(align);
+        while start + count <= end {
+            // continue after the last used frame in the window
+            match (start..start + count).rev().find(|i| self.is_used(*i)) {
+                Some(used) => start = (used + 1).next_multiple_of(align),
+                None => {
+                    self.reserve(start..start + count);
+                    return Some(PhysFrame::range(
+                        Self::frame(start),
+                        Self::frame(start + count),
+                    ));
+                }
+            }
+        }
+        None
+    }
+
+    /// Frees frames from [`allocate_contiguous`](Self::allocate_contiguous).
+    ///
+    /// # Safety
+    /// The frames must have been allocated from this allocator and be unused.
+    pub unsafe fn deallocate_contiguous(&mut self, range: PhysFrameRange) {
+        for frame in range {
+            unsafe { self.deallocate_frame(frame) };
+        }
+    }
+
+    pub fn stats(&self) -> FrameStats {
+        let free = self.free_frames as u64 * FRAME_SIZE;
+        let total = self.usable_frames as u64 * FRAME_SIZE;
+        FrameStats {
+            total,
+            used: total - free,
+            free,
+        }
+    }
+}
+
+fn bit(words: &[u64], index: usize) -> Option<bool> {
+    words
+        .get(index / 64)
+        .map(|word| word & (1 << (index % 64)) != 0)
+}
+
+fn set_bit(words: &mut [u64], index: usize, value: bool) {
+    if let Some(word) = words.get_mut(index / 64) {
+        if value {
+            *word |= 1 << (index % 64);
+        } else {
+            *word &= !(1 << (index % 64));
         }
     }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BitmapFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        // skip whole words of used frames
+        let first_word = self.next / 64;
+        let (word_index, word) = self
+            .bitmap
+            .iter()
+            .enumerate()
+            .skip(first_word)
+            .find(|(_, word)| **word != u64::MAX)?;
+        let index = word_index * 64 + (!*word).trailing_zeros() as usize;
+        self.set_used(index, true);
+        self.free_frames -= 1;
+        self.next = index + 1;
+        Some(Self::frame(index))
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BitmapFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        let index = Self::index(frame);
+        // freeing twice or freeing memory which was never handed out is ignored
+        if !bit(self.allocatable, index).unwrap_or(false) || !self.is_used(index) {
+            return;
+        }
+        self.set_used(index, false);
+        self.free_frames += 1;
+        self.next = self.next.min(index);
+    }
+}
+
+unsafe impl FrameAllocator<Size2MiB> for BitmapFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size2MiB>> {
+        let range = self.allocate_contiguous(FRAMES_PER_HUGE_FRAME, FRAMES_PER_HUGE_FRAME, None)?;
+        PhysFrame::from_start_address(range.start.start_address()).ok()
+    }
+}
 
-    /// Returns an iterator over the usable frames specified in the memory map.
-    fn usable_frames(&self) -> impl Iterator<Item = PhysFrame> {
-        // get usable regions from memory map
-        let regions = self.memory_map.iter();
-        let usable_regions = regions.filter(|r| r.kind == MemoryRegionKind::Usable);
-        // map each region to its address range
-        let addr_ranges = usable_regions.map(|r| r.start..r.end);
-        // transform to an iterator of frame start addresses
-        let frame_addresses = addr_ranges.flat_map(|r| r.step_by(4096));
-        // create `PhysFrame` types from the start addresses
-        frame_addresses.map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
+impl FrameDeallocator<Size2MiB> for BitmapFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size2MiB>) {
+        let start = PhysFrame::containing_address(frame.start_address());
+        unsafe {
+            self.deallocate_contiguous(PhysFrame::range(
+                start,
+                start + FRAMES_PER_HUGE_FRAME as u64,
+            ))
+        };
     }
 }
 
-unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
+/// Also used while mapping pages from interrupt handlers, e.g. page faults.
+static FRAME_ALLOCATOR: OnceCell<IrqSpinLock<BitmapFrameAllocator>> = OnceCell::uninit();
+
+/// Initializes the global frame allocator, see [`BitmapFrameAllocator::init`].
+///
+/// # Safety
+/// See [`BitmapFrameAllocator::init`].
+pub unsafe fn init_frame_allocator(
+    memory_regions: &MemoryRegions,
+    physical_memory_offset: VirtAddr,
+) -> Result<(), FrameAllocatorError> {
+    let allocator = unsafe { BitmapFrameAllocator::init(memory_regions, physical_memory_offset) }?;
+    FRAME_ALLOCATOR
+        .try_init_once(|| IrqSpinLock::with_level(allocator, level::FRAMES))
+        .map_err(|_| FrameAllocatorError::AlreadyInitialized)
+}
+
+/// Handle to the global frame allocator, for the page table functions which
+/// take a [`FrameAllocator`]. Allocations fail before [`init_frame_allocator`].
+pub struct GlobalFrameAllocator;
+
+unsafe impl FrameAllocator<Size4KiB> for GlobalFrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame> {
-        let frame = self.usable_frames().nth(self.next);
-        self.next += 1;
-        frame
+        FRAME_ALLOCATOR.try_get().ok()?.lock().allocate_frame()
     }
 }
+
+impl FrameDeallocator<Size4KiB> for GlobalFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        if let Ok(allocator) = FRAME_ALLOCATOR.try_get() {
+            unsafe { allocator.lock().deallocate_frame(frame) };
+        }
+    }
+}
+
+unsafe impl FrameAllocator<Size2MiB> for GlobalFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size2MiB>> {
+        FRAME_ALLOCATOR.try_get().ok()?.lock().allocate_frame()
+    }
+}
+
+impl FrameDeallocator<Size2MiB> for GlobalFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size2MiB>) {
+        if let Ok(allocator) = FRAME_ALLOCATOR.try_get() {
+            unsafe { allocator.lock().deallocate_frame(frame) };
+        }
+    }
+}
+
+/// See [`BitmapFrameAllocator::allocate_contiguous`].
+pub fn allocate_contiguous(
+    count: usize,
+    align: usize,
+    limit: Option<PhysAddr>,
+) -> Option<PhysFrameRange> {
+    FRAME_ALLOCATOR
+        .try_get()
+        .ok()?
+        .lock()
+        .allocate_contiguous(count, align, limit)
+}
+
+/// See [`BitmapFrameAllocator::deallocate_contiguous`].
+///
+/// # Safety
+/// The frames must have been allocated from the global frame allocator and be
+/// unused.
+#[allow(dead_code)]
+pub unsafe fn deallocate_contiguous(range: PhysFrameRange) {
+    if let Ok(allocator) = FRAME_ALLOCATOR.try_get() {
+        unsafe { allocator.lock().deallocate_contiguous(range) };
+    }
+}
+
+/// Physical memory usage, `None` before [`init_frame_allocator`].
+pub fn frame_stats() -> Option<FrameStats> {
+    FRAME_ALLOCATOR
+        .try_get()
+        .ok()
+        .map(|allocator| allocator.lock().stats())
+}
+
+/// Reference counts of frames mapped more than once, e.g. shared
+/// copy-on-write. Frames which aren't in it have a single reference.
+static SHARED_FRAMES: IrqSpinLock<BTreeMap<PhysFrame, usize>> =
+    IrqSpinLock::with_level(BTreeMap::new(), level::FRAME_REFS);
+
+/// Adds a reference to `frame`.
+pub fn share_frame(frame: PhysFrame) {
+    *SHARED_FRAMES.lock().entry(frame).or_insert(1) += 1;
+}
+
+/// Drops a reference to `frame`, returns whether it was the last one and the
+/// frame can be freed.
+pub fn release_frame(frame: PhysFrame) -> bool {
+    let mut shared = SHARED_FRAMES.lock();
+    match shared.get_mut(&frame) {
+        None => true,
+        Some(references) => {
+            *references -= 1;
+            if *references == 1 {
+                shared.remove(&frame);
+            }
+            false
+        }
+    }
+}
+
+pub fn is_frame_shared(frame: PhysFrame) -> bool {
+    SHARED_FRAMES.lock().contains_key(&frame)
+}
@@ -0,0 +1,176 @@
+// see https://wiki.osdev.org/Paging#PAT and https://en.wikipedia.org/wiki/Page_attribute_table
+
+use core::{
+    arch::x86_64::__cpuid,
+    mem::{align_of, size_of},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use conquer_once::spin::OnceCell;
+use x86_64::{
+    PhysAddr, VirtAddr, instructions::tlb, registers::model_specific::Msr,
+    structures::paging::PageTableFlags,
+};
+
+use crate::{
+    sync::Mutex,
+    vmm::{AddressSpace, Protection, VmmError},
+};
+
+const PAGE_SIZE: u64 = 4096;
+/// Where device memory is mapped, apart from all other kernel mappings
+const MMIO_START: u64 = 0xffff_e800_0000_0000;
+const MMIO_END: u64 = 0xffff_f000_0000_0000;
+
+const IA32_PAT_MSR: u32 = 0x277;
+const CPUID_PAT: u32 = 1 << 16;
+const PAT_UNCACHED: u64 = 0x00;
+const PAT_WRITE_COMBINING: u64 = 0x01;
+const PAT_UNCACHED_MINUS: u64 = 0x07;
+const PAT_WRITE_BACK: u64 = 0x06;
+/// The power on layout with write through replaced by write combining. The
+/// upper 4 entries repeat the lower ones, so the PAT bit, which is the huge
+/// page bit in higher level tables, is never needed.
+const PAT: u64 = {
+    let low = PAT_WRITE_BACK
+        | (PAT_WRITE_COMBINING << 8)
+        | (PAT_UNCACHED_MINUS << 16)
+        | (PAT_UNCACHED << 24);
+    low | (low << 32)
+};
+
+/// How the CPU caches a physical mapping.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheMode {
+    /// Normal memory
+    WriteBack,
+    /// Writes are buffered and combined, for frame buffers. Uncached without
+    /// PAT support.
+    WriteCombining,
+    /// Every access goes to the device, for registers
+    Uncached,
+}
+
+impl CacheMode {
+    /// Bits selecting the PAT entry programmed by [`init_pat`].
+    pub fn page_table_flags(self) -> PageTableFlags {
+        match self {
+            CacheMode::WriteBack => PageTableFlags::empty(),
+            CacheMode::WriteCombining if PAT_ENABLED.load(Ordering::Relaxed) => {
+                PageTableFlags::WRITE_THROUGH
+            }
+            CacheMode::WriteCombining | CacheMode::Uncached => {
+                PageTableFlags::NO_CACHE | PageTableFlags::WRITE_THROUGH
+            }
+        }
+    }
+}
+
+static PAT_ENABLED: AtomicBool = AtomicBool::new(false);
+static MMIO_SPACE: OnceCell<Mutex<AddressSpace>> = OnceCell::uninit();
+
+/// Sets up the MMIO window and the PAT of the bootstrap processor, needs the
+/// virtual memory manager.
+pub fn init() -> Result<(), VmmError> {
+    let space = AddressSpace::new_kernel(MMIO_START..MMIO_END)?;
+    MMIO_SPACE
+        .try_init_once(|| Mutex::new(space))
+        .map_err(|_| VmmError::AlreadyInitialized)?;
+    init_pat();
+    Ok(())
+}
+
+/// Programs the PAT of the calling CPU, every CPU must use the same layout.
+pub fn init_pat() {
+    let features = __cpuid(1);
+    if features.edx & CPUID_PAT == 0 {
+        return;
+    }
+    unsafe {
+        Msr::new(IA32_PAT_MSR).write(PAT);
+        // cached lines and translations may still use the old memory types
+        core::arch::asm!("wbinvd", options(nostack, preserves_flags));
+    }
+    tlb::flush_all();
+    PAT_ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Values which can be read and written in device memory with a single
+/// access.
+pub trait MmioValue: Copy {}
+
+impl MmioValue for u8 {}
+impl MmioValue for u16 {}
+impl MmioValue for u32 {}
+impl MmioValue for u64 {}
+
+/// Device memory mapped into the MMIO window, e.g. a PCI BAR or APIC
+/// registers, unmapped when dropped.
+#[derive(Debug)]
+pub struct Mmio {
+    /// Virtual address of the physical address it was mapped from
+    base: VirtAddr,
+    size: usize,
+}
+
+impl Mmio {
+    /// Maps `size` bytes of device memory at `address`, which doesn't need to
+    /// be page aligned.
+    pub fn map(address: PhysAddr, size: usize, cache: CacheMode) -> Result<Self, VmmError> {
+        let space = MMIO_SPACE.try_get().map_err(|_| VmmError::NotInitialized)?;
+        let offset = address.as_u64() % PAGE_SIZE;
+        let mapped_size = (offset + size as u64).next_multiple_of(PAGE_SIZE);
+        let start = space.lock().allocate_physical(
+            address.align_down(PAGE_SIZE),
+            mapped_size,
+            Protection::WRITE,
+            cache,
+        )?;
+        Ok(Self {
+            base: start + offset,
+            size,
+        })
+    }
+
+    #[allow(dead_code)]
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Reads the value at `offset`, which must be aligned and inside the
+    /// mapping.
+    pub fn read<T: MmioValue>(&self, offset: usize) -> T {
+        unsafe { core::ptr::read_volatile(self.ptr(offset)) }
+    }
+
+    /// Writes the value at `offset`, which must be aligned and inside the
+    /// mapping.
+    pub fn write<T: MmioValue>(&self, offset: usize, value: T) {
+        unsafe { core::ptr::write_volatile(self.ptr(offset), value) }
+    }
+
+    fn ptr<T>(&self, offset: usize) -> *mut T {
+        assert!(
+            offset.is_multiple_of(align_of::<T>())
+                && offset
+                    .checked_add(size_of::<T>())
+                    .is_some_and(|end| end <= self.size),
+            "MMIO access at {offset:#x} outside of {:#x} bytes",
+            self.size
+        );
+        (self.base + offset as u64).as_mut_ptr()
+    }
+}
+
+impl Drop for Mmio {
+    /// Needs interrupts enabled with other CPUs online, see
+    /// [`crate::tlb::shootdown`].
+    fn drop(&mut self) {
+        let start = self.base.align_down(PAGE_SIZE);
+        let size = (self.base - start + self.size as u64).next_multiple_of(PAGE_SIZE);
+        if let Ok(space) = MMIO_SPACE.try_get() {
+            let _ = space.lock().unmap(start, size);
+        }
+    }
+}
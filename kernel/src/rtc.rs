@@ -0,0 +1,244 @@
+// see https://wiki.osdev.org/CMOS and https://wiki.osdev.org/RTC
+
+use core::time::Duration;
+
+use conquer_once::spin::OnceCell;
+use irq_sync::IrqSpinLock;
+use myos_api::time::{DateTime, TimeError, Timestamp};
+use x86_64::instructions::port::Port;
+
+use crate::{
+    interrupts::{self, IrqError, IrqHandler},
+    sync::level,
+    timer,
+};
+
+const CMOS_INDEX: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+/// Set in the index register to keep NMIs disabled while it is selected
+const CMOS_NMI_DISABLE: u8 = 0x80;
+
+const REG_SECONDS: u8 = 0x00;
+const REG_MINUTES: u8 = 0x02;
+const REG_HOURS: u8 = 0x04;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+const REG_STATUS_A: u8 = 0x0a;
+const REG_STATUS_B: u8 = 0x0b;
+const REG_STATUS_C: u8 = 0x0c;
+const REG_STATUS_D: u8 = 0x0d;
+
+const STATUS_A_UPDATE_IN_PROGRESS: u8 = 1 << 7;
+const STATUS_A_RATE_MASK: u8 = 0x0f;
+const STATUS_B_24_HOUR: u8 = 1 << 1;
+const STATUS_B_BINARY: u8 = 1 << 2;
+const STATUS_B_PERIODIC_INTERRUPT: u8 = 1 << 6;
+const HOURS_PM: u8 = 0x80;
+
+const RTC_IRQ: u8 = 8;
+/// The periodic interrupt runs at `32768 >> (rate - 1)` Hz, rates 1 and 2 are unusable
+const MIN_PERIODIC_RATE: u8 = 3;
+const MAX_PERIODIC_RATE: u8 = 15;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RtcError {
+    AlreadyInitialized,
+    #[allow(dead_code)]
+    InvalidTime(TimeError),
+    /// The clock never left the update in progress state
+    Timeout,
+    #[allow(dead_code)]
+    Irq(IrqError),
+}
+
+impl From<TimeError> for RtcError {
+    fn from(value: TimeError) -> Self {
+        RtcError::InvalidTime(value)
+    }
+}
+
+struct Cmos {
+    index: Port<u8>,
+    data: Port<u8>,
+}
+
+impl Cmos {
+    const fn new() -> Self {
+        Self {
+            index: Port::new(CMOS_INDEX),
+            data: Port::new(CMOS_DATA),
+        }
+    }
+
+    fn read(&mut self, reg: u8) -> u8 {
+        unsafe {
+            self.index.write(CMOS_NMI_DISABLE | reg);
+            let value = self.data.read();
+            self.deselect();
+            value
+        }
+    }
+
+    fn write(&mut self, reg: u8, value: u8) {
+        unsafe {
+            self.index.write(CMOS_NMI_DISABLE | reg);
+            self.data.write(value);
+            self.deselect();
+        }
+    }
+
+    /// Enables NMIs again, leaving the read only status register D selected
+    /// like the firmware does.
+    unsafe fn deselect(&mut self) {
+        unsafe { self.index.write(REG_STATUS_D) }
+    }
+
+    fn update_in_progress(&mut self) -> bool {
+        self.read(REG_STATUS_A) & STATUS_A_UPDATE_IN_PROGRESS != 0
+    }
+}
+
+/// Raw register values, compared to detect a read racing an update.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct RawTime {
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    day: u8,
+    month: u8,
+    year: u8,
+    century: u8,
+}
+
+/// Also used by the periodic interrupt handler.
+static CMOS: IrqSpinLock<Cmos> = IrqSpinLock::with_level(Cmos::new(), level::CMOS);
+/// Wall clock time at a point of the monotonic clock, see [`now`].
+static BOOT_TIME: OnceCell<(Timestamp, Duration)> = OnceCell::uninit();
+static PERIODIC_HANDLER: OnceCell<IrqHandler> = OnceCell::uninit();
+
+/// Reads the RTC and anchors the wall clock to the monotonic clock.
+///
+/// `century_register` is the CMOS index of the century from the ACPI FADT,
+/// 0 when the RTC doesn't have one.
+pub fn init(century_register: u8) -> Result<Timestamp, RtcError> {
+    let date_time = read_date_time(century_register)?;
+    let timestamp = Timestamp::from_date_time(&date_time)?;
+    let monotonic = timer::now();
+    BOOT_TIME
+        .try_init_once(|| (timestamp, monotonic))
+        .map_err(|_| RtcError::AlreadyInitialized)?;
+    Ok(timestamp)
+}
+
+/// Current wall clock time, `None` before [`init`].
+pub fn now() -> Option<Timestamp> {
+    let (timestamp, monotonic) = BOOT_TIME.try_get().ok()?;
+    timestamp.checked_add(timer::now().saturating_sub(*monotonic))
+}
+
+/// Reads the date and time from the RTC, which keeps UTC on this system.
+pub fn read_date_time(century_register: u8) -> Result<DateTime, RtcError> {
+    let (raw, status_b) = {
+        let mut cmos = CMOS.lock();
+        // read until two reads agree, an update may happen in between
+        let mut last = read_raw(&mut cmos, century_register)?;
+        loop {
+            let raw = read_raw(&mut cmos, century_register)?;
+            if raw == last {
+                break (raw, cmos.read(REG_STATUS_B));
+            }
+            last = raw;
+        }
+    };
+    decode(raw, status_b)
+}
+
+fn read_raw(cmos: &mut Cmos, century_register: u8) -> Result<RawTime, RtcError> {
+    // an update takes under 2ms, give up well after that
+    let mut retries = 1_000_000;
+    while cmos.update_in_progress() {
+        retries -= 1;
+        if retries == 0 {
+            return Err(RtcError::Timeout);
+        }
+        core::hint::spin_loop();
+    }
+    Ok(RawTime {
+        seconds: cmos.read(REG_SECONDS),
+        minutes: cmos.read(REG_MINUTES),
+        hours: cmos.read(REG_HOURS),
+        day: cmos.read(REG_DAY),
+        month: cmos.read(REG_MONTH),
+        year: cmos.read(REG_YEAR),
+        century: match century_register {
+            0 => 0,
+            reg => cmos.read(reg),
+        },
+    })
+}
+
+fn decode(raw: RawTime, status_b: u8) -> Result<DateTime, RtcError> {
+    let binary = status_b & STATUS_B_BINARY != 0;
+    let value = |v: u8| if binary { v } else { from_bcd(v) };
+
+    let pm = raw.hours & HOURS_PM != 0;
+    let mut hour = value(raw.hours & !HOURS_PM);
+    if status_b & STATUS_B_24_HOUR == 0 {
+        // 12 hour clocks count 12, 1, ..., 11
+        hour %= 12;
+        if pm {
+            hour += 12;
+        }
+    }
+
+    let year = value(raw.year) as i64;
+    let year = match raw.century {
+        0 => 2000 + year,
+        century => value(century) as i64 * 100 + year,
+    };
+
+    Ok(DateTime::new(
+        year,
+        value(raw.month),
+        value(raw.day),
+        hour,
+        value(raw.minutes),
+        value(raw.seconds),
+    )?)
+}
+
+fn from_bcd(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0x0f)
+}
+
+/// Starts the RTC periodic interrupt at roughly `frequency_hz`, rounded down
+/// to a power of two between 2 and 8192 Hz. Returns the actual frequency.
+pub fn start_periodic_interrupt(frequency_hz: u32, handler: IrqHandler) -> Result<u32, RtcError> {
+    // the fastest rate which isn't faster than requested
+    let rate = (MIN_PERIODIC_RATE..=MAX_PERIODIC_RATE)
+        .find(|rate| 32768 >> (rate - 1) <= frequency_hz)
+        .unwrap_or(MAX_PERIODIC_RATE);
+    PERIODIC_HANDLER
+        .try_init_once(|| handler)
+        .map_err(|_| RtcError::AlreadyInitialized)?;
+
+    {
+        let mut cmos = CMOS.lock();
+        let status_a = cmos.read(REG_STATUS_A);
+        cmos.write(REG_STATUS_A, (status_a & !STATUS_A_RATE_MASK) | rate);
+        let status_b = cmos.read(REG_STATUS_B);
+        cmos.write(REG_STATUS_B, status_b | STATUS_B_PERIODIC_INTERRUPT);
+        // the interrupt isn't raised again until status C is read
+        cmos.read(REG_STATUS_C);
+    }
+    interrupts::register_irq(RTC_IRQ, periodic_handler).map_err(RtcError::Irq)?;
+    Ok(32768 >> (rate - 1))
+}
+
+fn periodic_handler(vector: u8) {
+    CMOS.lock().read(REG_STATUS_C);
+    if let Ok(handler) = PERIODIC_HANDLER.try_get() {
+        handler(vector);
+    }
+}
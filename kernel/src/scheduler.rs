@@ -0,0 +1,651 @@
+// see https://wiki.osdev.org/Kernel_Multitasking and https://wiki.osdev.org/Context_Switching
+
+use alloc::{boxed::Box, sync::Arc};
+use core::{
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::Duration,
+};
+
+use heapless::Deque;
+use irq_sync::IrqSpinLock;
+use x86_64::{
+    VirtAddr,
+    instructions::{self, interrupts::without_interrupts},
+};
+
+use crate::{
+    cpu::{self, MAX_CPUS},
+    heap_tag,
+    sync::{Mutex, level},
+    timer,
+    vmm::KernelStack,
+};
+
+pub const MAX_THREADS: usize = 64;
+const STACK_SIZE: usize = 16 * 1024;
+/// Timer ticks a thread runs before it is preempted.
+const TIME_SLICE_TICKS: u32 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpawnError {
+    NotInitialized,
+    TooManyThreads,
+    /// No kernel stack could be mapped
+    NoStack,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ThreadId(u64);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Ready,
+    Running,
+    /// Waiting until the monotonic clock reaches the deadline
+    Sleeping(Duration),
+    /// Waiting for the thread to finish
+    Joining(ThreadId),
+    /// Waiting for [`unpark`]
+    Parked,
+    /// Its stack is freed by the next thread calling [`reap`]
+    Finished,
+}
+
+struct Thread {
+    id: ThreadId,
+    name: &'static str,
+    state: State,
+    /// Stack pointer saved by [`switch_context`] while not running
+    rsp: u64,
+    /// `None` for the boot thread, which keeps the bootloader stack
+    _stack: Option<KernelStack>,
+    entry: Option<Box<dyn FnOnce() + Send>>,
+    /// Set by [`unpark`] while the thread wasn't parked, the next [`park`]
+    /// returns at once
+    unparked: bool,
+    /// Set from the moment the thread is switched away from until
+    /// [`switch_context`] saved its stack pointer, another CPU may only
+    /// resume it or free its stack afterwards
+    saving: AtomicBool,
+}
+
+impl Thread {
+    fn new(name: &'static str, entry: Box<dyn FnOnce() + Send>) -> Result<Box<Self>, SpawnError> {
+        let stack = KernelStack::new(STACK_SIZE).map_err(|_| SpawnError::NoStack)?;
+        let rsp = unsafe { init_stack(stack.top()) };
+        Ok(Box::new(Self {
+            id: ThreadId(NEXT_THREAD_ID.fetch_add(1, Ordering::Relaxed)),
+            name,
+            state: State::Ready,
+            rsp,
+            _stack: Some(stack),
+            entry: Some(entry),
+            unparked: false,
+            saving: AtomicBool::new(false),
+        }))
+    }
+
+    /// The thread already running on a CPU, which keeps its current stack.
+    fn running(name: &'static str) -> Box<Self> {
+        Box::new(Self {
+            id: ThreadId(NEXT_THREAD_ID.fetch_add(1, Ordering::Relaxed)),
+            name,
+            state: State::Running,
+            rsp: 0,
+            _stack: None,
+            entry: None,
+            unparked: false,
+            saving: AtomicBool::new(false),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct CpuState {
+    current: ThreadId,
+    /// Runs when nothing else is ready, never queued and never moves to
+    /// another CPU
+    idle: ThreadId,
+    slice_remaining: u32,
+}
+
+/// Stack pointers [`schedule`] switches between.
+struct Switch {
+    old_rsp: *mut u64,
+    old_saving: *const AtomicBool,
+    new_rsp: *const u64,
+    new_saving: *const AtomicBool,
+}
+
+/// All state is fixed size so the scheduler never allocates while its lock
+/// is held with interrupts disabled. Ready threads run on whichever CPU
+/// picks them first.
+struct Scheduler {
+    threads: [Option<Box<Thread>>; MAX_THREADS],
+    ready: Deque<ThreadId, MAX_THREADS>,
+    /// Indexed by [`cpu::current_index`], `None` until the CPU is online
+    cpus: [Option<CpuState>; MAX_CPUS],
+}
+
+impl Scheduler {
+    /// State of the CPU holding the lock.
+    fn cpu(&self) -> Option<&CpuState> {
+        self.cpus.get(cpu::current_index())?.as_ref()
+    }
+
+    fn cpu_mut(&mut self) -> Option<&mut CpuState> {
+        self.cpus.get_mut(cpu::current_index())?.as_mut()
+    }
+
+    /// Thread running on the CPU holding the lock.
+    fn current(&self) -> Option<ThreadId> {
+        self.cpu().map(|cpu| cpu.current)
+    }
+
+    fn is_idle(&self) -> bool {
+        self.cpu().is_none_or(|cpu| cpu.current == cpu.idle)
+    }
+
+    fn running_anywhere(&self, id: ThreadId) -> bool {
+        self.cpus.iter().flatten().any(|cpu| cpu.current == id)
+    }
+
+    fn add_thread(&mut self, thread: Box<Thread>) -> Result<(), Box<Thread>> {
+        match self.threads.iter_mut().find(|slot| slot.is_none()) {
+            Some(slot) => {
+                *slot = Some(thread);
+                Ok(())
+            }
+            None => Err(thread),
+        }
+    }
+
+    fn thread(&self, id: ThreadId) -> Option<&Thread> {
+        self.threads
+            .iter()
+            .flatten()
+            .find(|t| t.id == id)
+            .map(|t| &**t)
+    }
+
+    fn thread_mut(&mut self, id: ThreadId) -> Option<&mut Thread> {
+        self.threads
+            .iter_mut()
+            .flatten()
+            .find(|t| t.id == id)
+            .map(|t| &mut **t)
+    }
+
+    fn set_state(&mut self, id: ThreadId, state: State) {
+        if let Some(thread) = self.thread_mut(id) {
+            thread.state = state;
+        }
+    }
+
+    fn make_ready(&mut self, id: ThreadId) {
+        self.set_state(id, State::Ready);
+        // every thread is queued at most once, so the queue can't be full
+        let _ = self.ready.push_back(id);
+    }
+
+    /// Picks the next thread for the CPU holding the lock and returns the
+    /// stack pointers to switch between. A thread which blocks sets its state
+    /// and calls this without releasing the lock in between, otherwise another
+    /// CPU could wake and resume it before it is marked as saving.
+    fn switch_next(&mut self) -> Option<Switch> {
+        let CpuState { current, idle, .. } = *self.cpu()?;
+        let current_running = self
+            .thread(current)
+            .is_some_and(|t| t.state == State::Running);
+        let next = match self.ready.pop_front() {
+            Some(next) => next,
+            None if current_running => {
+                self.cpu_mut()?.slice_remaining = TIME_SLICE_TICKS;
+                return None;
+            }
+            None => idle,
+        };
+
+        if current_running {
+            if current == idle {
+                self.set_state(idle, State::Ready);
+            } else {
+                self.make_ready(current);
+            }
+        }
+        self.set_state(next, State::Running);
+        let cpu = self.cpu_mut()?;
+        cpu.slice_remaining = TIME_SLICE_TICKS;
+        cpu.current = next;
+        if current == next {
+            return None;
+        }
+
+        let previous = self.thread_mut(current)?;
+        previous.saving.store(true, Ordering::Relaxed);
+        let old_rsp = &raw mut previous.rsp;
+        let old_saving = &raw const previous.saving;
+        let next = self.thread(next)?;
+        Some(Switch {
+            old_rsp,
+            old_saving,
+            new_rsp: &raw const next.rsp,
+            new_saving: &raw const next.saving,
+        })
+    }
+}
+
+/// Also taken by the timer interrupt, switches happen with interrupts
+/// disabled and the lock released.
+static SCHEDULER: IrqSpinLock<Option<Scheduler>> = IrqSpinLock::with_level(None, level::SCHEDULER);
+static NEXT_THREAD_ID: AtomicU64 = AtomicU64::new(0);
+/// Indexed by [`cpu::current_index`]
+static NEED_RESCHEDULE: [AtomicBool; MAX_CPUS] = [const { AtomicBool::new(false) }; MAX_CPUS];
+
+/// Turns the caller into the `main` thread and starts scheduling on the
+/// bootstrap processor.
+pub fn init() {
+    let idle = Thread::new(
+        "idle",
+        Box::new(|| {
+            idle_loop();
+        }),
+    )
+    .expect("idle thread stack allocation failed");
+    let main = Thread::running("main");
+
+    let mut scheduler = Scheduler {
+        threads: [const { None }; MAX_THREADS],
+        ready: Deque::new(),
+        cpus: [None; MAX_CPUS],
+    };
+    scheduler.cpus[0] = Some(CpuState {
+        current: main.id,
+        idle: idle.id,
+        slice_remaining: TIME_SLICE_TICKS,
+    });
+    scheduler.threads[0] = Some(main);
+    scheduler.threads[1] = Some(idle);
+    *SCHEDULER.lock() = Some(scheduler);
+}
+
+/// Turns the caller into the idle thread of an application processor, which
+/// then runs ready threads on every tick.
+pub fn init_ap() -> ! {
+    let idle = Thread::running("idle");
+    let id = idle.id;
+    let rejected = {
+        let mut guard = SCHEDULER.lock();
+        match guard.as_mut() {
+            None => Some(idle),
+            Some(scheduler) => match scheduler.add_thread(idle) {
+                Err(idle) => Some(idle),
+                Ok(()) => {
+                    if let Some(slot) = scheduler.cpus.get_mut(cpu::current_index()) {
+                        *slot = Some(CpuState {
+                            current: id,
+                            idle: id,
+                            slice_remaining: TIME_SLICE_TICKS,
+                        });
+                    }
+                    None
+                }
+            },
+        }
+    };
+    // without a thread slot the CPU never runs threads, it only halts
+    drop(rejected);
+    instructions::interrupts::enable();
+    idle_loop();
+}
+
+/// Starts a kernel thread running `f`.
+pub fn spawn<F, T>(name: &'static str, f: F) -> Result<JoinHandle<T>, SpawnError>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    reap();
+
+    heap_tag!();
+    let result = Arc::new(Mutex::new(None));
+    let thread_result = result.clone();
+    let thread = Thread::new(
+        name,
+        Box::new(move || {
+            let value = f();
+            *thread_result.lock() = Some(value);
+        }),
+    )?;
+    let id = thread.id;
+
+    let rejected = {
+        let mut guard = SCHEDULER.lock();
+        match guard.as_mut() {
+            None => Some((thread, SpawnError::NotInitialized)),
+            Some(scheduler) => match scheduler.add_thread(thread) {
+                Err(thread) => Some((thread, SpawnError::TooManyThreads)),
+                Ok(()) => {
+                    scheduler.make_ready(id);
+                    None
+                }
+            },
+        }
+    };
+    // dropped with interrupts enabled, the allocator may be locked by a preempted thread
+    if let Some((thread, err)) = rejected {
+        drop(thread);
+        return Err(err);
+    }
+
+    Ok(JoinHandle { id, result })
+}
+
+pub struct JoinHandle<T> {
+    id: ThreadId,
+    result: Arc<Mutex<Option<T>>>,
+}
+
+impl<T> JoinHandle<T> {
+    pub fn id(&self) -> ThreadId {
+        self.id
+    }
+
+    /// Blocks until the thread finishes and returns its result.
+    pub fn join(self) -> Option<T> {
+        loop {
+            let finished = without_interrupts(|| {
+                let switch = {
+                    let mut guard = SCHEDULER.lock();
+                    let Some(scheduler) = guard.as_mut() else {
+                        return true;
+                    };
+                    if matches!(
+                        scheduler.thread(self.id).map(|t| t.state),
+                        None | Some(State::Finished)
+                    ) {
+                        return true;
+                    }
+                    let Some(current) = scheduler.current() else {
+                        return true;
+                    };
+                    scheduler.set_state(current, State::Joining(self.id));
+                    scheduler.switch_next()
+                };
+                if let Some(switch) = switch {
+                    switch_to(switch);
+                }
+                false
+            });
+            if finished {
+                break;
+            }
+        }
+        reap();
+        self.result.lock().take()
+    }
+}
+
+/// Gives up the rest of the time slice.
+pub fn yield_now() {
+    without_interrupts(schedule);
+}
+
+/// Blocks the current thread for at least `duration`, busy waits before the
+/// scheduler is running.
+pub fn sleep(duration: Duration) {
+    let deadline = timer::now() + duration;
+    let scheduled = without_interrupts(|| {
+        let switch = {
+            let mut guard = SCHEDULER.lock();
+            let Some(scheduler) = guard.as_mut() else {
+                return false;
+            };
+            let Some(current) = scheduler.current() else {
+                return false;
+            };
+            if scheduler.is_idle() {
+                return false;
+            }
+            scheduler.set_state(current, State::Sleeping(deadline));
+            scheduler.switch_next()
+        };
+        if let Some(switch) = switch {
+            switch_to(switch);
+        }
+        true
+    });
+    if !scheduled {
+        timer::sleep(duration);
+    }
+}
+
+/// Blocks the current thread until [`unpark`] is called for it. Returns at
+/// once if it was unparked since the last call, or when the scheduler isn't
+/// running, so callers must check their condition in a loop.
+pub fn park() {
+    without_interrupts(|| {
+        let switch = {
+            let mut guard = SCHEDULER.lock();
+            let Some(scheduler) = guard.as_mut() else {
+                return;
+            };
+            let Some(current) = scheduler.current() else {
+                return;
+            };
+            if scheduler.is_idle() {
+                return;
+            }
+            let Some(thread) = scheduler.thread_mut(current) else {
+                return;
+            };
+            if core::mem::take(&mut thread.unparked) {
+                return;
+            }
+            thread.state = State::Parked;
+            scheduler.switch_next()
+        };
+        if let Some(switch) = switch {
+            switch_to(switch);
+        }
+    });
+}
+
+/// Wakes a thread blocked in [`park`], or makes its next [`park`] return at
+/// once. Can be called from interrupt handlers.
+pub fn unpark(id: ThreadId) {
+    let mut guard = SCHEDULER.lock();
+    let Some(scheduler) = guard.as_mut() else {
+        return;
+    };
+    let Some(thread) = scheduler.thread_mut(id) else {
+        return;
+    };
+    match thread.state {
+        State::Parked => scheduler.make_ready(id),
+        State::Finished => {}
+        _ => thread.unparked = true,
+    }
+}
+
+/// Id of the running thread, `None` before [`init`].
+pub fn current() -> Option<ThreadId> {
+    SCHEDULER.lock().as_ref()?.current()
+}
+
+/// Name of the running thread.
+pub fn current_name() -> Option<&'static str> {
+    let guard = SCHEDULER.lock();
+    let scheduler = guard.as_ref()?;
+    scheduler.thread(scheduler.current()?).map(|t| t.name)
+}
+
+/// Called from the timer interrupt of every CPU, wakes sleeping threads and
+/// requests a reschedule when the time slice is used up.
+pub fn tick() {
+    let index = cpu::current_index();
+    let mut guard = SCHEDULER.lock();
+    let Some(scheduler) = guard.as_mut() else {
+        return;
+    };
+
+    // one CPU is enough to wake the sleepers
+    if index == 0 {
+        let now = timer::now();
+        for slot in 0..MAX_THREADS {
+            let Some(thread) = &scheduler.threads[slot] else {
+                continue;
+            };
+            if let State::Sleeping(deadline) = thread.state
+                && deadline <= now
+            {
+                let id = thread.id;
+                scheduler.make_ready(id);
+            }
+        }
+    }
+
+    let idle = scheduler.is_idle();
+    let has_ready = !scheduler.ready.is_empty();
+    let Some(cpu) = scheduler.cpu_mut() else {
+        return;
+    };
+    cpu.slice_remaining = cpu.slice_remaining.saturating_sub(1);
+    if cpu.slice_remaining == 0 || (idle && has_ready) {
+        NEED_RESCHEDULE[index].store(true, Ordering::Relaxed);
+    }
+}
+
+/// Called at the end of an interrupt, after the end of interrupt was signaled,
+/// to switch threads when [`tick`] asked for it.
+pub fn preempt() {
+    if NEED_RESCHEDULE[cpu::current_index()].swap(false, Ordering::Relaxed) {
+        schedule();
+    }
+}
+
+/// Switches to the next ready thread, interrupts must be disabled.
+fn schedule() {
+    let switch = SCHEDULER.lock().as_mut().and_then(Scheduler::switch_next);
+    if let Some(switch) = switch {
+        switch_to(switch);
+    }
+}
+
+/// Performs a switch returned by [`Scheduler::switch_next`], interrupts must
+/// be disabled and the scheduler unlocked.
+fn switch_to(switch: Switch) {
+    unsafe {
+        // the next thread may have been switched away from on another
+        // CPU a moment ago
+        while (*switch.new_saving).load(Ordering::Acquire) {
+            core::hint::spin_loop();
+        }
+        switch_context(switch.old_rsp, *switch.new_rsp, switch.old_saving);
+    }
+}
+
+/// Ends the current thread.
+fn exit() -> ! {
+    instructions::interrupts::disable();
+    let switch = SCHEDULER.lock().as_mut().and_then(|scheduler| {
+        let current = scheduler.current()?;
+        scheduler.set_state(current, State::Finished);
+        for slot in 0..MAX_THREADS {
+            if let Some(thread) = &scheduler.threads[slot]
+                && thread.state == State::Joining(current)
+            {
+                let id = thread.id;
+                scheduler.make_ready(id);
+            }
+        }
+        scheduler.switch_next()
+    });
+    if let Some(switch) = switch {
+        switch_to(switch);
+    }
+    // finished threads are never scheduled again
+    loop {
+        instructions::hlt();
+    }
+}
+
+/// Frees finished threads, the stacks are dropped with interrupts enabled.
+fn reap() {
+    let mut finished: heapless::Vec<Box<Thread>, MAX_THREADS> = heapless::Vec::new();
+    if let Some(scheduler) = SCHEDULER.lock().as_mut() {
+        for slot in 0..MAX_THREADS {
+            let reapable = scheduler.threads[slot].as_ref().is_some_and(|t| {
+                t.state == State::Finished
+                    && !t.saving.load(Ordering::Acquire)
+                    && !scheduler.running_anywhere(t.id)
+            });
+            if reapable && let Some(thread) = scheduler.threads[slot].take() {
+                let _ = finished.push(thread);
+            }
+        }
+    }
+    drop(finished);
+}
+
+fn idle_loop() -> ! {
+    loop {
+        reap();
+        instructions::hlt();
+    }
+}
+
+/// First code run by a new thread, [`switch_context`] returns here.
+extern "C" fn thread_entry() -> ! {
+    // the switch happened with interrupts disabled and the scheduler unlocked
+    let entry = SCHEDULER.lock().as_mut().and_then(|scheduler| {
+        let current = scheduler.current()?;
+        scheduler.thread_mut(current)?.entry.take()
+    });
+    instructions::interrupts::enable();
+    if let Some(entry) = entry {
+        entry();
+    }
+    exit();
+}
+
+/// Builds the frame [`switch_context`] pops for a thread which hasn't run yet.
+unsafe fn init_stack(top: VirtAddr) -> u64 {
+    let top = top.align_down(16u64).as_u64();
+    // callee saved registers, the return address into thread_entry and a fake
+    // return address for thread_entry so it starts with the ABI stack alignment
+    let frame = [0, 0, 0, 0, 0, 0, thread_entry as *const () as u64, 0];
+    let rsp = top - core::mem::size_of_val(&frame) as u64;
+    unsafe { core::ptr::write(rsp as *mut [u64; 8], frame) };
+    rsp
+}
+
+/// Saves the callee saved registers on the current stack, stores the stack
+/// pointer in `old_rsp`, clears `old_saving` and resumes the thread whose
+/// stack pointer is `new_rsp`.
+#[unsafe(naked)]
+unsafe extern "C" fn switch_context(
+    old_rsp: *mut u64,
+    new_rsp: u64,
+    old_saving: *const AtomicBool,
+) {
+    core::arch::naked_asm!(
+        "push rbp",
+        "push rbx",
+        "push r12",
+        "push r13",
+        "push r14",
+        "push r15",
+        "mov [rdi], rsp",
+        // the old stack isn't touched after this, another CPU may resume it
+        "mov byte ptr [rdx], 0",
+        "mov rsp, rsi",
+        "pop r15",
+        "pop r14",
+        "pop r13",
+        "pop r12",
+        "pop rbx",
+        "pop rbp",
+        "ret",
+    )
+}
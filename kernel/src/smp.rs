@@ -0,0 +1,347 @@
+// see https://wiki.osdev.org/SMP and https://wiki.osdev.org/Symmetric_Multiprocessing
+
+use core::{
+    arch::global_asm,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::Duration,
+};
+
+use x86_64::{
+    PhysAddr, VirtAddr,
+    registers::{
+        control::{Cr0, Cr3, Cr4, Cr4Flags},
+        model_specific::{Efer, EferFlags},
+    },
+    structures::paging::{
+        FrameAllocator, Mapper, PageTableFlags, PhysFrame, Size4KiB, mapper::MapToError,
+    },
+};
+
+use crate::{
+    acpi::Processor,
+    apic::{self, ApicError},
+    cpu::{self, Cpu},
+    gdt, interrupts, memory, mmio, println, scheduler, timer,
+    vmm::{KernelStack, VmmError},
+};
+
+/// Startup IPIs can only start CPUs in the first megabyte.
+const TRAMPOLINE_LIMIT: u64 = 0x10_0000;
+const PAGE_SIZE: u64 = 4096;
+const AP_STACK_SIZE: usize = 16 * 1024;
+/// The INIT IPI needs 10ms before the CPU accepts a startup IPI
+const INIT_DELAY: Duration = Duration::from_millis(10);
+/// How long the first startup IPI gets before it is sent again
+const FIRST_STARTUP_TIMEOUT: Duration = Duration::from_millis(1);
+const STARTUP_TIMEOUT: Duration = Duration::from_millis(100);
+
+// Real mode entry of the application processors, copied to the trampoline
+// page. It switches straight to long mode with the page tables and control
+// registers of the bootstrap processor, claims the stack from the data area
+// and calls the entry with the argument found on top of the stack in rdi.
+// The data area is laid out as `TrampolineData`.
+global_asm!(
+    ".pushsection .text.ap_trampoline, \"ax\", @progbits",
+    ".code16",
+    ".global ap_trampoline_start",
+    "ap_trampoline_start:",
+    ".Lstart:",
+    "cli",
+    "cld",
+    // the startup IPI sets CS to the trampoline page
+    "mov %cs, %ax",
+    "mov %ax, %ds",
+    "lgdtl (.Ldata - .Lstart)",
+    "mov (.Ldata + 32 - .Lstart), %eax",
+    "mov %eax, %cr4",
+    "mov (.Ldata + 24 - .Lstart), %eax",
+    "mov %eax, %cr3",
+    "mov $0xc0000080, %ecx",
+    "mov (.Ldata + 40 - .Lstart), %eax",
+    "mov (.Ldata + 44 - .Lstart), %edx",
+    "wrmsr",
+    "mov (.Ldata + 16 - .Lstart), %eax",
+    "mov %eax, %cr0",
+    "ljmpl *(.Ldata + 8 - .Lstart)",
+    ".code64",
+    ".global ap_long_mode",
+    "ap_long_mode:",
+    "mov $0x10, %ax",
+    "mov %ax, %ds",
+    "mov %ax, %es",
+    "mov %ax, %ss",
+    "xor %eax, %eax",
+    "mov %ax, %fs",
+    "mov %ax, %gs",
+    // a CPU which comes up too late may find the stack taken back, rax is
+    // still zero
+    "xchg %rax, .Ldata + 48(%rip)",
+    "test %rax, %rax",
+    "jz .Lrevoked",
+    "mov %rax, %rsp",
+    "mov (%rsp), %rdi",
+    "mov .Ldata + 56(%rip), %rax",
+    "call *%rax",
+    "ud2",
+    ".Lrevoked:",
+    "hlt",
+    "jmp .Lrevoked",
+    ".balign 8",
+    ".global ap_gdt",
+    "ap_gdt:",
+    ".quad 0",
+    // 64-bit code
+    ".quad 0x00af9a000000ffff",
+    // data
+    ".quad 0x00cf92000000ffff",
+    ".balign 8",
+    ".global ap_trampoline_data",
+    "ap_trampoline_data:",
+    ".Ldata:",
+    ".skip 64",
+    ".global ap_trampoline_end",
+    "ap_trampoline_end:",
+    ".popsection",
+    options(att_syntax)
+);
+
+unsafe extern "C" {
+    static ap_trampoline_start: u8;
+    static ap_long_mode: u8;
+    static ap_gdt: u8;
+    static ap_trampoline_data: u8;
+    static ap_trampoline_end: u8;
+}
+
+const TRAMPOLINE_GDT_LIMIT: u16 = 3 * 8 - 1;
+const TRAMPOLINE_CODE_SELECTOR: u16 = 0x08;
+
+/// Data area at the end of the trampoline.
+#[repr(C, packed)]
+struct TrampolineData {
+    gdt_limit: u16,
+    gdt_base: u32,
+    _pad0: u16,
+    long_mode_offset: u32,
+    long_mode_selector: u16,
+    _pad1: u16,
+    cr0: u64,
+    cr3: u64,
+    cr4: u64,
+    efer: u64,
+    /// Points at the argument of `entry`, taken by the CPU which uses it
+    stack_pointer: u64,
+    entry: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmpError {
+    /// No free frame below 1 MiB for the trampoline
+    NoTrampolineFrame,
+    MapFailed,
+    /// The trampoline loads CR3 in real mode
+    PageTableAboveFourGiB,
+    #[allow(dead_code)]
+    Apic(ApicError),
+    #[allow(dead_code)]
+    Vmm(VmmError),
+}
+
+impl From<ApicError> for SmpError {
+    fn from(value: ApicError) -> Self {
+        SmpError::Apic(value)
+    }
+}
+
+impl From<VmmError> for SmpError {
+    fn from(value: VmmError) -> Self {
+        SmpError::Vmm(value)
+    }
+}
+
+/// Page below 1 MiB, identity mapped, where the application processors start.
+pub struct Trampoline {
+    frame: PhysFrame,
+}
+
+/// Set by an application processor once it is done with the trampoline.
+static AP_STARTED: AtomicBool = AtomicBool::new(false);
+
+/// Allocates and identity maps the trampoline page, `frame_allocator` is
+/// used for the page tables.
+pub fn allocate_trampoline(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<Trampoline, SmpError> {
+    let frame = memory::allocate_contiguous(1, 1, Some(PhysAddr::new(TRAMPOLINE_LIMIT)))
+        .ok_or(SmpError::NoTrampolineFrame)?
+        .start;
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    match unsafe { mapper.identity_map(frame, flags, frame_allocator) } {
+        Ok(flush) => flush.flush(),
+        Err(MapToError::PageAlreadyMapped(mapped)) if mapped == frame => {}
+        Err(_) => return Err(SmpError::MapFailed),
+    }
+    Ok(Trampoline { frame })
+}
+
+/// Starts the enabled processors from the MADT one after the other, returns
+/// how many came online.
+///
+/// # Safety
+/// Physical memory must be mapped at `physical_memory_offset`. Must be called
+/// once on the bootstrap processor after the APIC, timer and scheduler are
+/// initialized.
+pub unsafe fn init(
+    physical_memory_offset: VirtAddr,
+    trampoline: &Trampoline,
+    processors: &[Processor],
+) -> Result<usize, SmpError> {
+    let (cr3_frame, _) = Cr3::read();
+    let cr3 = cr3_frame.start_address().as_u64();
+    if cr3 > u32::MAX as u64 {
+        return Err(SmpError::PageTableAboveFourGiB);
+    }
+
+    let start = &raw const ap_trampoline_start as u64;
+    let code_len = &raw const ap_trampoline_end as u64 - start;
+    let offset = |symbol: *const u8| symbol as u64 - start;
+    let base = trampoline.frame.start_address().as_u64();
+    let page = (base / PAGE_SIZE) as u8;
+    let virt = physical_memory_offset + base;
+    unsafe {
+        core::ptr::copy_nonoverlapping(
+            start as *const u8,
+            virt.as_mut_ptr::<u8>(),
+            code_len as usize,
+        )
+    };
+    let data = (virt + offset(&raw const ap_trampoline_data)).as_mut_ptr::<TrampolineData>();
+
+    let bsp_apic_id = apic::local_apic_id()?;
+    cpu::set_online(bsp_apic_id);
+    let mut online = 0;
+    for processor in processors
+        .iter()
+        .filter(|p| p.enabled && p.apic_id != bsp_apic_id)
+    {
+        let Some(cpu) = cpu::allocate_ap() else {
+            println!(
+                "CPU with APIC id {} not started, at most {} CPUs are supported",
+                processor.apic_id,
+                cpu::MAX_CPUS
+            );
+            break;
+        };
+        let stack = match KernelStack::new(AP_STACK_SIZE) {
+            Ok(stack) => stack,
+            Err(err) => {
+                cpu::release_ap(cpu);
+                return Err(err.into());
+            }
+        };
+        // the argument keeps the stack pointer 16 byte aligned for the call
+        let stack_pointer = stack.top() - 16u64;
+        unsafe {
+            stack_pointer
+                .as_mut_ptr::<u64>()
+                .write(cpu as *const Cpu as u64)
+        };
+        unsafe {
+            data.write_unaligned(TrampolineData {
+                gdt_limit: TRAMPOLINE_GDT_LIMIT,
+                gdt_base: (base + offset(&raw const ap_gdt)) as u32,
+                _pad0: 0,
+                long_mode_offset: (base + offset(&raw const ap_long_mode)) as u32,
+                long_mode_selector: TRAMPOLINE_CODE_SELECTOR,
+                _pad1: 0,
+                cr0: Cr0::read_raw(),
+                cr3,
+                // PCIDs can only be enabled in long mode
+                cr4: Cr4::read_raw() & !Cr4Flags::PCID.bits(),
+                efer: Efer::read_raw() & !EferFlags::LONG_MODE_ACTIVE.bits(),
+                stack_pointer: 0,
+                entry: ap_entry as *const () as u64,
+            })
+        };
+        // the data area is 8 byte aligned, so is the field at offset 48
+        let claim = unsafe { &*(&raw mut (*data).stack_pointer).cast::<AtomicU64>() };
+        claim.store(stack_pointer.as_u64(), Ordering::Release);
+
+        let started = start_ap(processor.apic_id, page);
+        // a CPU which took the stack keeps running on it and its Cpu even if
+        // it came up too late, neither may be used for the next CPU
+        let claimed = claim.swap(0, Ordering::AcqRel) == 0;
+        if claimed {
+            core::mem::forget(stack);
+        } else {
+            cpu::release_ap(cpu);
+        }
+        match (started?, claimed) {
+            (true, true) => {
+                online += 1;
+                println!("CPU {} online (APIC id {})", cpu.index(), processor.apic_id);
+            }
+            (false, true) => println!(
+                "CPU {} with APIC id {} started too late",
+                cpu.index(),
+                processor.apic_id
+            ),
+            (_, false) => println!("CPU with APIC id {} didn't start", processor.apic_id),
+        }
+    }
+    Ok(online)
+}
+
+/// Sends INIT and up to two startup IPIs, returns whether the CPU came up.
+fn start_ap(apic_id: u32, page: u8) -> Result<bool, SmpError> {
+    AP_STARTED.store(false, Ordering::Release);
+    apic::send_init(apic_id)?;
+    timer::sleep(INIT_DELAY);
+    for timeout in [FIRST_STARTUP_TIMEOUT, STARTUP_TIMEOUT] {
+        apic::send_startup(apic_id, page)?;
+        if wait_started(timeout) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+fn wait_started(timeout: Duration) -> bool {
+    let deadline = timer::now() + timeout;
+    while timer::now() < deadline {
+        if AP_STARTED.load(Ordering::Acquire) {
+            return true;
+        }
+        core::hint::spin_loop();
+    }
+    AP_STARTED.load(Ordering::Acquire)
+}
+
+/// Called by the trampoline on the stack allocated for the CPU.
+extern "C" fn ap_entry(cpu: &'static Cpu) -> ! {
+    cpu::init_ap(cpu);
+    gdt::init_ap();
+    mmio::init_pat();
+    interrupts::init_idt();
+    if let Err(err) = unsafe { apic::init_ap() } {
+        println!("CPU {} APIC initialization failed: {err:?}", cpu.index());
+        halt();
+    }
+    if let Err(err) = timer::init_ap() {
+        println!("CPU {} timer initialization failed: {err:?}", cpu.index());
+    }
+    match apic::local_apic_id() {
+        Ok(apic_id) => cpu::set_online(apic_id),
+        Err(err) => println!("CPU {} has no APIC id: {err:?}", cpu.index()),
+    }
+    AP_STARTED.store(true, Ordering::Release);
+    scheduler::init_ap()
+}
+
+fn halt() -> ! {
+    x86_64::instructions::interrupts::disable();
+    loop {
+        x86_64::instructions::hlt();
+    }
+}
@@ -0,0 +1,264 @@
+// see https://wiki.osdev.org/Synchronization_Primitives
+
+use core::{
+    cell::UnsafeCell,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+use heapless::Deque;
+use irq_sync::IrqSpinLock;
+
+use crate::scheduler::{self, MAX_THREADS, ThreadId};
+
+/// Lock order of the kernel's interrupt safe locks, a lock may only be taken
+/// while holding locks of lower levels.
+pub mod level {
+    use irq_sync::LockLevel;
+
+    pub const IRQ_HANDLERS: LockLevel = LockLevel::new(8);
+    pub const WAIT_QUEUE: LockLevel = LockLevel::new(10);
+    pub const SCHEDULER: LockLevel = LockLevel::new(12);
+    pub const TIMERS: LockLevel = LockLevel::new(16);
+    pub const CMOS: LockLevel = LockLevel::new(20);
+    pub const ADDRESS_SPACES: LockLevel = LockLevel::new(24);
+    pub const WAKER: LockLevel = LockLevel::new(32);
+    pub const CONSOLE: LockLevel = LockLevel::new(40);
+    /// The I/O APICs and the ISA interrupt overrides
+    pub const IO_APICS: LockLevel = LockLevel::new(44);
+    /// Reference counts of shared frames, allocates
+    pub const FRAME_REFS: LockLevel = LockLevel::new(50);
+    /// Anything may allocate, so the allocator comes last
+    pub const ALLOCATOR: LockLevel = LockLevel::new(60);
+    /// The kernel page table mapper, taken by the allocator to map new heap
+    /// pages
+    pub const PAGE_TABLES: LockLevel = LockLevel::new(61);
+    /// Taken by the allocator to grow the heap
+    pub const FRAMES: LockLevel = LockLevel::new(62);
+}
+
+/// Threads blocked until they are notified.
+pub struct WaitQueue {
+    waiters: IrqSpinLock<Deque<ThreadId, MAX_THREADS>>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        Self {
+            waiters: IrqSpinLock::with_level(Deque::new(), level::WAIT_QUEUE),
+        }
+    }
+
+    /// Blocks the current thread while `condition` holds. Busy waits before
+    /// the scheduler is running.
+    pub fn wait_while(&self, mut condition: impl FnMut() -> bool) {
+        let Some(current) = scheduler::current() else {
+            while condition() {
+                core::hint::spin_loop();
+            }
+            return;
+        };
+        loop {
+            // queued before checking, a notify after the check unparks us
+            self.enqueue(current);
+            if !condition() {
+                self.remove(current);
+                return;
+            }
+            scheduler::park();
+        }
+    }
+
+    /// Queues `id` to be unparked by the next notify.
+    fn enqueue(&self, id: ThreadId) {
+        let mut waiters = self.waiters.lock();
+        if !waiters.iter().any(|waiter| *waiter == id) {
+            // every thread is queued at most once, so the queue can't be full
+            let _ = waiters.push_back(id);
+        }
+    }
+
+    fn remove(&self, id: ThreadId) {
+        let mut waiters = self.waiters.lock();
+        for _ in 0..waiters.len() {
+            if let Some(waiter) = waiters.pop_front()
+                && waiter != id
+            {
+                let _ = waiters.push_back(waiter);
+            }
+        }
+    }
+
+    /// Wakes the longest waiting thread, returns false if there was none.
+    /// Can be called from interrupt handlers.
+    pub fn notify_one(&self) -> bool {
+        let waiter = self.waiters.lock().pop_front();
+        match waiter {
+            Some(waiter) => {
+                scheduler::unpark(waiter);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Wakes all waiting threads. Can be called from interrupt handlers.
+    pub fn notify_all(&self) {
+        while self.notify_one() {}
+    }
+}
+
+/// Mutex which blocks the thread instead of spinning, for long critical
+/// sections in thread context. Must not be used from interrupt handlers.
+pub struct Mutex<T> {
+    locked: AtomicBool,
+    waiters: WaitQueue,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Send for Mutex<T> {}
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            waiters: WaitQueue::new(),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        loop {
+            if let Some(guard) = self.try_lock() {
+                return guard;
+            }
+            self.waiters
+                .wait_while(|| self.locked.load(Ordering::Relaxed));
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        self.locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| MutexGuard { mutex: self })
+    }
+
+    fn unlock(&self) {
+        self.locked.store(false, Ordering::Release);
+        self.waiters.notify_one();
+    }
+}
+
+pub struct MutexGuard<'a, T> {
+    mutex: &'a Mutex<T>,
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.value.get() }
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.value.get() }
+    }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.unlock();
+    }
+}
+
+/// Counting semaphore, [`acquire`](Self::acquire) blocks the thread while no
+/// permits are left. [`release`](Self::release) can be called from interrupt
+/// handlers.
+pub struct Semaphore {
+    permits: AtomicUsize,
+    waiters: WaitQueue,
+}
+
+impl Semaphore {
+    pub const fn new(permits: usize) -> Self {
+        Self {
+            permits: AtomicUsize::new(permits),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    pub fn acquire(&self) {
+        loop {
+            if self.try_acquire() {
+                return;
+            }
+            self.waiters
+                .wait_while(|| self.permits.load(Ordering::Relaxed) == 0);
+        }
+    }
+
+    pub fn try_acquire(&self) -> bool {
+        self.permits
+            .try_update(Ordering::Acquire, Ordering::Relaxed, |permits| {
+                permits.checked_sub(1)
+            })
+            .is_ok()
+    }
+
+    pub fn release(&self) {
+        self.permits.fetch_add(1, Ordering::Release);
+        self.waiters.notify_one();
+    }
+}
+
+/// Condition variable used with [`Mutex`].
+pub struct CondVar {
+    waiters: WaitQueue,
+}
+
+impl CondVar {
+    pub const fn new() -> Self {
+        Self {
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Unlocks the mutex, blocks until notified and locks it again. May wake
+    /// up spuriously, so the condition must be checked in a loop.
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let mutex = guard.mutex;
+        let Some(current) = scheduler::current() else {
+            return guard;
+        };
+        // queued before unlocking, a notify after the unlock unparks us
+        self.waiters.enqueue(current);
+        drop(guard);
+        scheduler::park();
+        self.waiters.remove(current);
+        mutex.lock()
+    }
+
+    /// Blocks while `condition` holds, see [`wait`](Self::wait).
+    pub fn wait_while<'a, T>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut condition: impl FnMut(&mut T) -> bool,
+    ) -> MutexGuard<'a, T> {
+        while condition(&mut guard) {
+            guard = self.wait(guard);
+        }
+        guard
+    }
+
+    pub fn notify_one(&self) -> bool {
+        self.waiters.notify_one()
+    }
+
+    pub fn notify_all(&self) {
+        self.waiters.notify_all();
+    }
+}
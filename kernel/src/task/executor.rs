@@ -0,0 +1,127 @@
+// see https://os.phil-opp.com/async-await/#executor-with-waker-support
+
+use alloc::{collections::BTreeMap, sync::Arc, task::Wake};
+use core::{
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll, Waker},
+};
+
+use irq_sync::ArrayQueue;
+use x86_64::instructions::interrupts;
+
+use super::{MAX_TASKS, SPAWN_QUEUE, TASK_COUNT, Task, TaskId};
+
+/// Ids of woken tasks, each task is queued at most once.
+static WAKE_QUEUE: ArrayQueue<TaskId, MAX_TASKS> = ArrayQueue::new();
+/// Set by [`stop`]
+static STOPPED: AtomicBool = AtomicBool::new(false);
+
+/// Makes [`Executor::run`] return once the tasks woken so far were polled,
+/// for a task to end the kernel.
+pub fn stop() {
+    STOPPED.store(true, Ordering::Release);
+}
+
+/// Polls the tasks passed to [`super::spawn`] whenever they are woken.
+pub struct Executor {
+    tasks: BTreeMap<TaskId, Task>,
+    waker_cache: BTreeMap<TaskId, Arc<TaskWaker>>,
+}
+
+impl Executor {
+    pub fn new() -> Self {
+        Self {
+            tasks: BTreeMap::new(),
+            waker_cache: BTreeMap::new(),
+        }
+    }
+
+    /// Runs the tasks until [`stop`] is called, halting while none of them
+    /// are woken.
+    pub fn run(&mut self) {
+        loop {
+            self.spawn_tasks();
+            self.run_ready_tasks();
+            if STOPPED.load(Ordering::Acquire) {
+                return;
+            }
+            self.sleep_if_idle();
+        }
+    }
+
+    fn spawn_tasks(&mut self) {
+        while let Some(task) = SPAWN_QUEUE.pop() {
+            let task_id = task.id;
+            if self.tasks.insert(task_id, task).is_some() {
+                panic!("task with same ID already in tasks");
+            }
+            let waker = Arc::new(TaskWaker::new(task_id));
+            waker.wake_task();
+            self.waker_cache.insert(task_id, waker);
+        }
+    }
+
+    fn run_ready_tasks(&mut self) {
+        while let Some(task_id) = WAKE_QUEUE.pop() {
+            let Some(task) = self.tasks.get_mut(&task_id) else {
+                // task already completed
+                continue;
+            };
+            let Some(task_waker) = self.waker_cache.get(&task_id) else {
+                continue;
+            };
+            // wakeups from here on queue the task again
+            task_waker.queued.store(false, Ordering::Release);
+            let waker = Waker::from(task_waker.clone());
+            let mut context = Context::from_waker(&waker);
+            if let Poll::Ready(()) = task.poll(&mut context) {
+                self.tasks.remove(&task_id);
+                self.waker_cache.remove(&task_id);
+                TASK_COUNT.fetch_sub(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn sleep_if_idle(&self) {
+        // checked with interrupts disabled so a wakeup can't slip in before
+        // the hlt
+        interrupts::disable();
+        if WAKE_QUEUE.is_empty() && SPAWN_QUEUE.is_empty() {
+            interrupts::enable_and_hlt();
+        } else {
+            interrupts::enable();
+        }
+    }
+}
+
+struct TaskWaker {
+    task_id: TaskId,
+    /// Set while the task is in [`WAKE_QUEUE`]
+    queued: AtomicBool,
+}
+
+impl TaskWaker {
+    fn new(task_id: TaskId) -> Self {
+        Self {
+            task_id,
+            queued: AtomicBool::new(false),
+        }
+    }
+
+    fn wake_task(&self) {
+        if !self.queued.swap(true, Ordering::AcqRel) {
+            // can't be full, there are at most MAX_TASKS tasks
+            let _ = WAKE_QUEUE.push(self.task_id);
+        }
+    }
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_task();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.wake_task();
+    }
+}
@@ -0,0 +1,90 @@
+// see https://os.phil-opp.com/async-await/#scancode-queue and
+// https://wiki.osdev.org/PS/2_Keyboard
+
+use core::{
+    future::poll_fn,
+    task::{Context, Poll},
+};
+
+use irq_sync::ArrayQueue;
+use x86_64::instructions::port::Port;
+
+use crate::{
+    acpi,
+    interrupts::{self, IrqError},
+    println,
+};
+
+use super::waker::AtomicWaker;
+
+const KEYBOARD_IRQ: u8 = 1;
+const PS2_DATA_PORT: u16 = 0x60;
+/// Scan code set 1 presses, the release is the press with the top bit set
+const SCANCODE_CTRL: u8 = 0x1d;
+const SCANCODE_ALT: u8 = 0x38;
+const SCANCODE_DELETE: u8 = 0x53;
+const SCANCODE_RELEASE: u8 = 0x80;
+
+static SCANCODES: ArrayQueue<u8, 128> = ArrayQueue::new();
+static WAKER: AtomicWaker = AtomicWaker::new();
+
+/// Routes the PS/2 keyboard interrupt to [`ScancodeStream`].
+pub fn init() -> Result<u8, IrqError> {
+    interrupts::register_irq(KEYBOARD_IRQ, keyboard_handler)
+}
+
+fn keyboard_handler(_vector: u8) {
+    let scancode = unsafe { Port::<u8>::new(PS2_DATA_PORT).read() };
+    if SCANCODES.push(scancode).is_err() {
+        // nobody is reading, drop the scancode
+        return;
+    }
+    WAKER.wake();
+}
+
+/// Scancodes received from the PS/2 keyboard, there should only be one
+/// reader.
+pub struct ScancodeStream {
+    _private: (),
+}
+
+impl ScancodeStream {
+    pub fn new() -> Self {
+        Self { _private: () }
+    }
+
+    pub fn poll_next(&mut self, context: &mut Context) -> Poll<u8> {
+        if let Some(scancode) = SCANCODES.pop() {
+            return Poll::Ready(scancode);
+        }
+        WAKER.register(context.waker());
+        // a scancode may have arrived before the waker was registered
+        match SCANCODES.pop() {
+            Some(scancode) => Poll::Ready(scancode),
+            None => Poll::Pending,
+        }
+    }
+
+    /// Waits for the next scancode.
+    pub async fn next(&mut self) -> u8 {
+        poll_fn(|context| self.poll_next(context)).await
+    }
+}
+
+/// Prints the scancodes of key presses and releases, Ctrl-Alt-Delete
+/// reboots.
+pub async fn print_scancodes() {
+    let mut scancodes = ScancodeStream::new();
+    let (mut ctrl, mut alt) = (false, false);
+    loop {
+        let scancode = scancodes.next().await;
+        println!("scancode 0x{scancode:02x}");
+        let pressed = scancode & SCANCODE_RELEASE == 0;
+        match scancode & !SCANCODE_RELEASE {
+            SCANCODE_CTRL => ctrl = pressed,
+            SCANCODE_ALT => alt = pressed,
+            SCANCODE_DELETE if pressed && ctrl && alt => acpi::reboot(),
+            _ => {}
+        }
+    }
+}
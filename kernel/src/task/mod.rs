@@ -0,0 +1,121 @@
+// see https://os.phil-opp.com/async-await/
+
+use alloc::{boxed::Box, sync::Arc};
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use irq_sync::ArrayQueue;
+
+use crate::timer::{self, TimerId};
+
+use self::waker::AtomicWaker;
+
+pub mod executor;
+pub mod keyboard;
+pub mod serial;
+pub mod waker;
+
+/// Maximum number of tasks, spawned or queued to be spawned
+const MAX_TASKS: usize = 256;
+
+/// Tasks waiting to be picked up by the [`executor::Executor`].
+static SPAWN_QUEUE: ArrayQueue<Task, 64> = ArrayQueue::new();
+static TASK_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TaskId(u64);
+
+impl TaskId {
+    fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        TaskId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpawnError {
+    TooManyTasks,
+}
+
+pub struct Task {
+    id: TaskId,
+    future: Pin<Box<dyn Future<Output = ()> + Send>>,
+}
+
+impl Task {
+    fn new(future: impl Future<Output = ()> + Send + 'static) -> Task {
+        Task {
+            id: TaskId::new(),
+            future: Box::pin(future),
+        }
+    }
+
+    fn poll(&mut self, context: &mut Context) -> Poll<()> {
+        self.future.as_mut().poll(context)
+    }
+}
+
+/// Spawns a task onto the executor. Allocates, so it must not be called from
+/// interrupt handlers.
+pub fn spawn(future: impl Future<Output = ()> + Send + 'static) -> Result<TaskId, SpawnError> {
+    TASK_COUNT
+        .try_update(Ordering::Relaxed, Ordering::Relaxed, |count| {
+            (count < MAX_TASKS).then_some(count + 1)
+        })
+        .map_err(|_| SpawnError::TooManyTasks)?;
+    let task = Task::new(future);
+    let id = task.id;
+    if SPAWN_QUEUE.push(task).is_err() {
+        TASK_COUNT.fetch_sub(1, Ordering::Relaxed);
+        return Err(SpawnError::TooManyTasks);
+    }
+    Ok(id)
+}
+
+/// Completes after `duration`, with timer tick resolution.
+#[allow(dead_code)]
+pub fn sleep(duration: Duration) -> Sleep {
+    Sleep {
+        deadline: timer::now() + duration,
+        waker: Arc::new(AtomicWaker::new()),
+        timer: None,
+    }
+}
+
+/// Future returned by [`sleep`].
+#[allow(dead_code)]
+pub struct Sleep {
+    deadline: Duration,
+    waker: Arc<AtomicWaker>,
+    timer: Option<TimerId>,
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<()> {
+        let now = timer::now();
+        if now >= self.deadline {
+            return Poll::Ready(());
+        }
+        self.waker.register(context.waker());
+        if self.timer.is_none() {
+            let waker = self.waker.clone();
+            self.timer = Some(timer::one_shot(self.deadline - now, move || waker.wake()));
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if let Some(timer) = self.timer.take() {
+            timer::cancel(timer);
+        }
+    }
+}
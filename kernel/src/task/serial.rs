@@ -0,0 +1,92 @@
+use core::{
+    future::poll_fn,
+    task::{Context, Poll},
+};
+
+use irq_sync::ArrayQueue;
+use serial_port::{serial1_enable_receive_interrupt, serial1_try_receive};
+
+use crate::{
+    allocator,
+    interrupts::{self, IrqError},
+    print,
+};
+
+use super::{executor, waker::AtomicWaker};
+
+const SERIAL1_IRQ: u8 = 4;
+/// Ends the kernel, like end of input on a terminal
+const CTRL_D: u8 = 0x04;
+/// Prints a memory report and dumps the heap, like the status request of BSD terminals
+const CTRL_T: u8 = 0x14;
+
+static RECEIVED: ArrayQueue<u8, 256> = ArrayQueue::new();
+static WAKER: AtomicWaker = AtomicWaker::new();
+
+/// Routes the serial 1 receive interrupt to [`SerialStream`].
+pub fn init() -> Result<u8, IrqError> {
+    let vector = interrupts::register_irq(SERIAL1_IRQ, serial_handler)?;
+    serial1_enable_receive_interrupt();
+    Ok(vector)
+}
+
+fn serial_handler(_vector: u8) {
+    // drain the FIFO, the interrupt isn't raised again while bytes are left
+    while let Ok(byte) = serial1_try_receive() {
+        // nobody is reading when the queue is full, drop the byte
+        let _ = RECEIVED.push(byte);
+    }
+    WAKER.wake();
+}
+
+/// Bytes received on serial 1, there should only be one reader.
+pub struct SerialStream {
+    _private: (),
+}
+
+impl SerialStream {
+    pub fn new() -> Self {
+        Self { _private: () }
+    }
+
+    pub fn poll_next(&mut self, context: &mut Context) -> Poll<u8> {
+        if let Some(byte) = RECEIVED.pop() {
+            return Poll::Ready(byte);
+        }
+        WAKER.register(context.waker());
+        // a byte may have arrived before the waker was registered
+        match RECEIVED.pop() {
+            Some(byte) => Poll::Ready(byte),
+            None => Poll::Pending,
+        }
+    }
+
+    /// Waits for the next received byte.
+    pub async fn next(&mut self) -> u8 {
+        poll_fn(|context| self.poll_next(context)).await
+    }
+}
+
+/// Echoes bytes received on serial 1 to the console, Ctrl-T prints a memory
+/// report, with the `heap-debug` feature also the live allocations, and
+/// Ctrl-D stops the executor, which shuts the machine down.
+pub async fn echo() {
+    let mut received = SerialStream::new();
+    loop {
+        let byte = received.next().await;
+        match byte {
+            b'\r' => print!("\n"),
+            CTRL_T => {
+                allocator::print_memory_report();
+                #[cfg(feature = "heap-debug")]
+                allocator::dump_heap();
+            }
+            CTRL_D => {
+                executor::stop();
+                return;
+            }
+            byte if byte.is_ascii() => print!("{}", byte as char),
+            _ => {}
+        }
+    }
+}
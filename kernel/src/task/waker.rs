@@ -0,0 +1,42 @@
+use core::task::Waker;
+
+use irq_sync::IrqSpinLock;
+
+use crate::sync::level;
+
+/// Holds the waker of a future waiting on an interrupt.
+///
+/// The future registers itself before checking for data, the interrupt
+/// handler makes the data available before calling [`wake`](Self::wake), so
+/// a wakeup is never lost.
+pub struct AtomicWaker {
+    waker: IrqSpinLock<Option<Waker>>,
+}
+
+impl AtomicWaker {
+    pub const fn new() -> Self {
+        Self {
+            waker: IrqSpinLock::with_level(None, level::WAKER),
+        }
+    }
+
+    /// Registers the waker to be woken by the next [`wake`](Self::wake).
+    pub fn register(&self, waker: &Waker) {
+        let mut slot = self.waker.lock();
+        if !slot
+            .as_ref()
+            .is_some_and(|current| current.will_wake(waker))
+        {
+            *slot = Some(waker.clone());
+        }
+    }
+
+    /// Wakes the registered waker, if any. Safe to call from interrupt
+    /// handlers.
+    pub fn wake(&self) {
+        let waker = self.waker.lock().take();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
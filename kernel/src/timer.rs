@@ -0,0 +1,391 @@
+// see https://wiki.osdev.org/Programmable_Interval_Timer, https://wiki.osdev.org/HPET
+// and https://wiki.osdev.org/APIC_Timer
+
+use alloc::{boxed::Box, vec::Vec};
+use core::{
+    arch::x86_64::{__cpuid, _rdtsc},
+    sync::atomic::{AtomicU32, AtomicU64, Ordering},
+    time::Duration,
+};
+
+use conquer_once::spin::OnceCell;
+use irq_sync::IrqSpinLock;
+use x86_64::{
+    PhysAddr,
+    instructions::{self, port::Port},
+};
+
+use crate::{
+    apic::{self, ApicError, TimerMode},
+    cpu,
+    interrupts::{self, IrqError},
+    mmio::{CacheMode, Mmio},
+    rtc::{self, RtcError},
+    scheduler,
+    sync::level,
+    vmm::VmmError,
+};
+
+/// Frequency of the local APIC timer interrupt.
+pub const TICK_HZ: u64 = 1000;
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+const FEMTOS_PER_NANO: u64 = 1_000_000;
+
+/// Length of the calibration measurements.
+const CALIBRATION_MS: u64 = 10;
+
+const PIT_FREQUENCY_HZ: u64 = 1_193_182;
+const PIT_CHANNEL2_DATA: u16 = 0x42;
+const PIT_COMMAND: u16 = 0x43;
+/// Bit 0 gates PIT channel 2, bit 1 connects it to the speaker, bit 5 is its output
+const PIT_CHANNEL2_CONTROL: u16 = 0x61;
+const PIT_CHANNEL2_GATE: u8 = 1 << 0;
+const PIT_SPEAKER_ENABLE: u8 = 1 << 1;
+const PIT_CHANNEL2_OUTPUT: u8 = 1 << 5;
+/// Channel 2, lobyte/hibyte access, mode 0 (interrupt on terminal count)
+const PIT_CHANNEL2_ONE_SHOT: u8 = 0b1011_0000;
+
+const HPET_SIZE: usize = 0x400;
+const HPET_REG_CAPABILITIES: u64 = 0x00;
+const HPET_REG_CONFIG: u64 = 0x10;
+const HPET_REG_MAIN_COUNTER: u64 = 0xf0;
+const HPET_CAPABILITY_64BIT_COUNTER: u64 = 1 << 13;
+const HPET_CONFIG_ENABLE: u64 = 1 << 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimerError {
+    AlreadyInitialized,
+    #[allow(dead_code)]
+    Apic(ApicError),
+    #[allow(dead_code)]
+    Irq(IrqError),
+    #[allow(dead_code)]
+    Rtc(RtcError),
+    #[allow(dead_code)]
+    Vmm(VmmError),
+}
+
+impl From<VmmError> for TimerError {
+    fn from(value: VmmError) -> Self {
+        TimerError::Vmm(value)
+    }
+}
+
+impl From<ApicError> for TimerError {
+    fn from(value: ApicError) -> Self {
+        TimerError::Apic(value)
+    }
+}
+
+struct Hpet {
+    registers: Mmio,
+    period_fs: u64,
+    counter_64bit: bool,
+}
+
+impl Hpet {
+    /// # Safety
+    /// `registers` must map the HPET registers.
+    unsafe fn new(registers: Mmio) -> Self {
+        let mut hpet = Self {
+            registers,
+            period_fs: 0,
+            counter_64bit: false,
+        };
+        let capabilities = hpet.read(HPET_REG_CAPABILITIES);
+        hpet.period_fs = capabilities >> 32;
+        hpet.counter_64bit = capabilities & HPET_CAPABILITY_64BIT_COUNTER != 0;
+        let config = hpet.read(HPET_REG_CONFIG);
+        hpet.write(HPET_REG_CONFIG, config | HPET_CONFIG_ENABLE);
+        hpet
+    }
+
+    fn read(&self, reg: u64) -> u64 {
+        self.registers.read(reg as usize)
+    }
+
+    fn write(&mut self, reg: u64, value: u64) {
+        self.registers.write(reg as usize, value)
+    }
+
+    fn counter(&self) -> u64 {
+        self.read(HPET_REG_MAIN_COUNTER)
+    }
+
+    fn counter_to_nanos(&self, count: u64) -> u64 {
+        (count as u128 * self.period_fs as u128 / FEMTOS_PER_NANO as u128) as u64
+    }
+
+    /// Busy waits, the counter must already be running.
+    fn wait(&self, duration: Duration) {
+        let start = self.counter();
+        while self.counter_to_nanos(self.counter().wrapping_sub(start)) < duration.as_nanos() as u64
+        {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/// What [`now`] is read from, in order of preference.
+enum ClockSource {
+    /// Invariant TSC, runs at a constant rate in every power state
+    Tsc {
+        start: u64,
+        frequency_hz: u64,
+    },
+    Hpet {
+        start: u64,
+        hpet: Hpet,
+    },
+    /// Counted timer interrupts, only has tick resolution
+    Ticks,
+}
+
+static CLOCK: OnceCell<ClockSource> = OnceCell::uninit();
+static TICKS: AtomicU64 = AtomicU64::new(0);
+/// Length of a tick, differs from [`TICK_HZ`] when the RTC provides the tick
+static NANOS_PER_TICK: AtomicU64 = AtomicU64::new(NANOS_PER_SECOND / TICK_HZ);
+
+/// Identifies a timer added with [`one_shot`] or [`periodic`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimerId(u64);
+
+struct Timer {
+    id: TimerId,
+    deadline: Duration,
+    interval: Option<Duration>,
+    callback: Box<dyn FnMut() + Send>,
+}
+
+/// Also taken by the tick interrupt.
+static TIMERS: IrqSpinLock<Vec<Timer>> = IrqSpinLock::with_level(Vec::new(), level::TIMERS);
+static NEXT_TIMER_ID: AtomicU64 = AtomicU64::new(1);
+/// Local APIC timer count of one tick, 0 when the RTC provides the tick
+static APIC_INITIAL_COUNT: AtomicU32 = AtomicU32::new(0);
+
+/// Calibrates the TSC and local APIC timer against the HPET, or the PIT when
+/// there is no HPET, picks the clock source for [`now`] and starts the tick.
+///
+/// # Safety
+/// `hpet_address` must be the HPET from the ACPI tables.
+pub unsafe fn init(hpet_address: Option<PhysAddr>) -> Result<(), TimerError> {
+    let hpet = match hpet_address {
+        Some(address) => {
+            let registers = Mmio::map(address, HPET_SIZE, CacheMode::Uncached)?;
+            Some(unsafe { Hpet::new(registers) })
+        }
+        None => None,
+    };
+    let calibration = Duration::from_millis(CALIBRATION_MS);
+
+    apic::start_timer(TimerMode::OneShot, u32::MAX, true)?;
+    let tsc_start = unsafe { _rdtsc() };
+    match &hpet {
+        Some(hpet) => hpet.wait(calibration),
+        None => unsafe { pit_wait(calibration) },
+    }
+    let tsc_end = unsafe { _rdtsc() };
+    let apic_elapsed = u32::MAX - apic::timer_current_count()?;
+
+    let per_second = NANOS_PER_SECOND / calibration.as_nanos() as u64;
+    let tsc_frequency_hz = tsc_end.wrapping_sub(tsc_start) * per_second;
+    let apic_frequency_hz = apic_elapsed as u64 * per_second;
+
+    let clock = if invariant_tsc_supported() {
+        ClockSource::Tsc {
+            start: unsafe { _rdtsc() },
+            frequency_hz: tsc_frequency_hz,
+        }
+    } else {
+        match hpet {
+            Some(hpet) if hpet.counter_64bit => ClockSource::Hpet {
+                start: hpet.counter(),
+                hpet,
+            },
+            _ => ClockSource::Ticks,
+        }
+    };
+    CLOCK
+        .try_init_once(|| clock)
+        .map_err(|_| TimerError::AlreadyInitialized)?;
+
+    if apic_frequency_hz >= TICK_HZ {
+        interrupts::register_vector(apic::TIMER_VECTOR, tick_handler).map_err(TimerError::Irq)?;
+        let initial_count = u32::try_from(apic_frequency_hz / TICK_HZ).unwrap_or(u32::MAX);
+        APIC_INITIAL_COUNT.store(initial_count, Ordering::Relaxed);
+        apic::start_timer(TimerMode::Periodic, initial_count, false)?;
+    } else {
+        // the local APIC timer isn't counting, fall back to the RTC
+        apic::start_timer(TimerMode::OneShot, 0, true)?;
+        let frequency_hz =
+            rtc::start_periodic_interrupt(TICK_HZ as u32, tick_handler).map_err(TimerError::Rtc)?;
+        NANOS_PER_TICK.store(NANOS_PER_SECOND / frequency_hz as u64, Ordering::Relaxed);
+    }
+    Ok(())
+}
+
+/// Starts the tick on an application processor, assuming all local APIC
+/// timers run at the calibrated frequency. Without a local APIC timer the
+/// CPU gets no tick and only runs its idle thread.
+pub fn init_ap() -> Result<(), TimerError> {
+    match APIC_INITIAL_COUNT.load(Ordering::Relaxed) {
+        0 => Ok(()),
+        initial_count => Ok(apic::start_timer(
+            TimerMode::Periodic,
+            initial_count,
+            false,
+        )?),
+    }
+}
+
+/// Name of the clock source backing [`now`], for the boot log.
+pub fn clock_source() -> &'static str {
+    match CLOCK.try_get() {
+        Ok(ClockSource::Tsc { .. }) => "TSC",
+        Ok(ClockSource::Hpet { .. }) => "HPET",
+        Ok(ClockSource::Ticks) => "timer ticks",
+        Err(_) => "none",
+    }
+}
+
+/// Monotonic time since the timers were initialized.
+pub fn now() -> Duration {
+    let nanos = match CLOCK.try_get() {
+        Ok(ClockSource::Tsc {
+            start,
+            frequency_hz,
+        }) => {
+            let elapsed = unsafe { _rdtsc() }.wrapping_sub(*start);
+            (elapsed as u128 * NANOS_PER_SECOND as u128 / *frequency_hz as u128) as u64
+        }
+        Ok(ClockSource::Hpet { start, hpet }) => {
+            hpet.counter_to_nanos(hpet.counter().wrapping_sub(*start))
+        }
+        Ok(ClockSource::Ticks) | Err(_) => {
+            TICKS.load(Ordering::Relaxed) * NANOS_PER_TICK.load(Ordering::Relaxed)
+        }
+    };
+    Duration::from_nanos(nanos)
+}
+
+/// Number of timer interrupts since the timers were initialized.
+#[allow(dead_code)]
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Waits at least `duration`, halting between ticks when interrupts are enabled.
+/// Threads should use [`scheduler::sleep`] which lets other threads run.
+#[allow(dead_code)]
+pub fn sleep(duration: Duration) {
+    let deadline = now() + duration;
+    while now() < deadline {
+        if instructions::interrupts::are_enabled() {
+            x86_64::instructions::hlt();
+        } else {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/// Calls `callback` once after `delay`. Callbacks run in interrupt context
+/// with tick resolution and must not block.
+#[allow(dead_code)]
+pub fn one_shot(delay: Duration, callback: impl FnMut() + Send + 'static) -> TimerId {
+    add_timer(delay, None, Box::new(callback))
+}
+
+/// Calls `callback` every `interval` until cancelled. Callbacks run in
+/// interrupt context with tick resolution and must not block.
+#[allow(dead_code)]
+pub fn periodic(interval: Duration, callback: impl FnMut() + Send + 'static) -> TimerId {
+    add_timer(interval, Some(interval), Box::new(callback))
+}
+
+/// Removes a timer, returns false if it already fired or was cancelled.
+#[allow(dead_code)]
+pub fn cancel(id: TimerId) -> bool {
+    let mut timers = TIMERS.lock();
+    let len = timers.len();
+    timers.retain(|timer| timer.id != id);
+    timers.len() != len
+}
+
+fn add_timer(
+    delay: Duration,
+    interval: Option<Duration>,
+    callback: Box<dyn FnMut() + Send>,
+) -> TimerId {
+    let id = TimerId(NEXT_TIMER_ID.fetch_add(1, Ordering::Relaxed));
+    let timer = Timer {
+        id,
+        deadline: now() + delay,
+        interval,
+        callback,
+    };
+    TIMERS.lock().push(timer);
+    id
+}
+
+fn tick_handler(_vector: u8) {
+    // every CPU ticks, the clock and timers advance on the bootstrap processor
+    let bsp = cpu::current_index() == 0;
+    if bsp {
+        TICKS.fetch_add(1, Ordering::Relaxed);
+    }
+    scheduler::tick();
+    if !bsp {
+        return;
+    }
+
+    let current = now();
+    let expired: Vec<Timer> = TIMERS
+        .lock()
+        .extract_if(.., |timer| timer.deadline <= current)
+        .collect();
+    if expired.is_empty() {
+        return;
+    }
+
+    // run the callbacks without the lock so they can add timers
+    let mut rearm = Vec::new();
+    for mut timer in expired {
+        (timer.callback)();
+        if let Some(interval) = timer.interval {
+            // skip missed periods instead of firing them back to back
+            while timer.deadline <= current {
+                timer.deadline += interval;
+            }
+            rearm.push(timer);
+        }
+    }
+    TIMERS.lock().extend(rearm);
+}
+
+fn invariant_tsc_supported() -> bool {
+    let max_extended_leaf = __cpuid(0x8000_0000).eax;
+    max_extended_leaf >= 0x8000_0007 && __cpuid(0x8000_0007).edx & (1 << 8) != 0
+}
+
+/// Busy waits using PIT channel 2, which isn't wired to an interrupt.
+unsafe fn pit_wait(duration: Duration) {
+    let count = PIT_FREQUENCY_HZ * duration.as_micros() as u64 / 1_000_000;
+    let count = u16::try_from(count).unwrap_or(u16::MAX);
+    let mut control = Port::<u8>::new(PIT_CHANNEL2_CONTROL);
+    let mut command = Port::<u8>::new(PIT_COMMAND);
+    let mut data = Port::<u8>::new(PIT_CHANNEL2_DATA);
+    unsafe {
+        // gate off while programming, keep the speaker quiet
+        let value = control.read() & !(PIT_CHANNEL2_GATE | PIT_SPEAKER_ENABLE);
+        control.write(value);
+        command.write(PIT_CHANNEL2_ONE_SHOT);
+        data.write(count as u8);
+        data.write((count >> 8) as u8);
+        // raising the gate starts the count, the output goes high at zero
+        control.write(value | PIT_CHANNEL2_GATE);
+        while control.read() & PIT_CHANNEL2_OUTPUT == 0 {
+            core::hint::spin_loop();
+        }
+        control.write(value);
+    }
+}
@@ -0,0 +1,90 @@
+// see https://wiki.osdev.org/TLB
+
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+use x86_64::{
+    VirtAddr,
+    instructions::{
+        interrupts::{self, without_interrupts},
+        tlb,
+    },
+    structures::paging::page::PageRange,
+};
+
+use crate::{
+    apic,
+    cpu::{self, MAX_CPUS},
+    interrupts::{IrqError, register_vector},
+};
+
+const PAGE_SIZE: u64 = 4096;
+/// Larger ranges flush the whole TLB instead of page by page
+const MAX_FLUSH_PAGES: u64 = 32;
+
+/// Only one shootdown is in flight at a time, the other CPUs read its range
+/// from [`START`] and [`PAGE_COUNT`].
+static IN_PROGRESS: AtomicBool = AtomicBool::new(false);
+static START: AtomicU64 = AtomicU64::new(0);
+static PAGE_COUNT: AtomicU64 = AtomicU64::new(0);
+/// CPUs which haven't flushed the current shootdown's range yet
+static PENDING: AtomicUsize = AtomicUsize::new(0);
+
+pub fn init() -> Result<(), IrqError> {
+    register_vector(apic::TLB_SHOOTDOWN_VECTOR, shootdown_handler)
+}
+
+/// Invalidates `pages` on every online CPU after their mappings changed.
+/// With other CPUs online it must be called with interrupts enabled and no
+/// spin lock held, another CPU may be waiting for this one to flush.
+pub fn shootdown(pages: PageRange) {
+    let start = pages.start.start_address().as_u64();
+    let page_count = pages.end - pages.start;
+    if cpu::other_online_apic_ids().next().is_none() {
+        flush(start, page_count);
+        return;
+    }
+
+    debug_assert!(interrupts::are_enabled());
+    // spin with interrupts enabled so a concurrent shootdown still reaches
+    // this CPU
+    while IN_PROGRESS
+        .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+        .is_err()
+    {
+        core::hint::spin_loop();
+    }
+    without_interrupts(|| {
+        flush(start, page_count);
+        START.store(start, Ordering::Relaxed);
+        PAGE_COUNT.store(page_count, Ordering::Relaxed);
+        let targets: heapless::Vec<u32, MAX_CPUS> = cpu::other_online_apic_ids().collect();
+        PENDING.store(targets.len(), Ordering::Release);
+        for apic_id in targets {
+            if apic::send_fixed(apic_id, apic::TLB_SHOOTDOWN_VECTOR).is_err() {
+                PENDING.fetch_sub(1, Ordering::AcqRel);
+            }
+        }
+        while PENDING.load(Ordering::Acquire) != 0 {
+            core::hint::spin_loop();
+        }
+    });
+    IN_PROGRESS.store(false, Ordering::Release);
+}
+
+fn shootdown_handler(_vector: u8) {
+    flush(
+        START.load(Ordering::Relaxed),
+        PAGE_COUNT.load(Ordering::Relaxed),
+    );
+    PENDING.fetch_sub(1, Ordering::AcqRel);
+}
+
+fn flush(start: u64, page_count: u64) {
+    if page_count > MAX_FLUSH_PAGES {
+        tlb::flush_all();
+    } else {
+        for page in 0..page_count {
+            tlb::flush(VirtAddr::new(start + page * PAGE_SIZE));
+        }
+    }
+}
@@ -0,0 +1,1099 @@
+// see https://wiki.osdev.org/Paging and https://wiki.osdev.org/Page_Tables
+
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+use core::{
+    fmt,
+    ops::Range,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use bitflags::bitflags;
+use conquer_once::spin::OnceCell;
+use irq_sync::IrqSpinLock;
+use nostdio::{Read, Seek, SeekFrom};
+use x86_64::{
+    PhysAddr, VirtAddr,
+    instructions::interrupts::without_interrupts,
+    registers::control::{Cr3, Cr3Flags},
+    structures::{
+        idt::PageFaultErrorCode,
+        paging::{
+            FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageTable,
+            PageTableFlags, PhysFrame, Size4KiB, Translate,
+            mapper::{MapToError, MappedFrame, TranslateResult},
+            page::PageRange,
+        },
+    },
+};
+
+use crate::{
+    cpu::{self, MAX_CPUS},
+    heap_tag,
+    interrupts::IrqError,
+    memory::{self, GlobalFrameAllocator},
+    mmio::CacheMode,
+    sync::{Mutex, MutexGuard, level},
+    tlb,
+};
+
+const PAGE_SIZE: u64 = 4096;
+/// The lowest 4 MiB stay unmapped so null pointer accesses fault
+pub const USER_START: u64 = 0x40_0000;
+/// End of the lower half, the upper half is the kernel's and shared by all
+/// address spaces
+pub const USER_END: u64 = 0x0000_8000_0000_0000;
+const KERNEL_HALF_START: u64 = 0xffff_8000_0000_0000;
+/// First level 4 entry of the upper half
+const KERNEL_LEVEL_4_INDEX: usize = 256;
+/// Where the kernel address space places its regions
+const KERNEL_DYNAMIC_START: u64 = 0xffff_d000_0000_0000;
+const KERNEL_DYNAMIC_END: u64 = 0xffff_e000_0000_0000;
+/// Kernel stacks live in slots of this size, the first page of every slot
+/// stays unmapped
+const KERNEL_STACKS_START: u64 = 0xffff_e000_0000_0000;
+const KERNEL_STACK_SLOT_SIZE: u64 = 64 * 1024;
+const KERNEL_STACK_SLOTS: usize = 256;
+/// Marks read only entries of writable regions whose frame is shared, the
+/// first write copies it
+const COPY_ON_WRITE: PageTableFlags = PageTableFlags::BIT_9;
+
+bitflags! {
+    /// Access allowed to the pages of a region, mapped pages are always
+    /// readable.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Protection: u8 {
+        const WRITE = 1 << 0;
+        const EXECUTE = 1 << 1;
+        const USER = 1 << 2;
+    }
+}
+
+impl Protection {
+    fn page_table_flags(self) -> PageTableFlags {
+        let mut flags = PageTableFlags::PRESENT;
+        if self.contains(Protection::WRITE) {
+            flags |= PageTableFlags::WRITABLE;
+        }
+        if !self.contains(Protection::EXECUTE) {
+            flags |= PageTableFlags::NO_EXECUTE;
+        }
+        if self.contains(Protection::USER) {
+            flags |= PageTableFlags::USER_ACCESSIBLE;
+        }
+        flags
+    }
+
+    /// Flags of the tables leading to a page, the page's own entry restricts
+    /// it further.
+    fn parent_flags(self) -> PageTableFlags {
+        let mut flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        if self.contains(Protection::USER) {
+            flags |= PageTableFlags::USER_ACCESSIBLE;
+        }
+        flags
+    }
+}
+
+/// Contents of a file mapping, read a page at a time when first accessed.
+pub trait FileBacking: Send + Sync {
+    /// Reads the file from `offset` into `page`, which is zeroed, bytes past
+    /// the end of the file stay zero.
+    fn read_page(&self, offset: u64, page: &mut [u8]) -> nostdio::Result<()>;
+}
+
+/// Anything which can be read and seeked, e.g. an open `vsfs::File`.
+impl<R: Read + Seek + Send> FileBacking for Mutex<R> {
+    fn read_page(&self, offset: u64, page: &mut [u8]) -> nostdio::Result<()> {
+        let mut file = self.lock();
+        file.seek(SeekFrom::Start(offset))?;
+        let mut filled = 0;
+        while filled < page.len() {
+            match file.read(&mut page[filled..])? {
+                0 => break,
+                read => filled += read,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub struct FileMapping {
+    pub file: Arc<dyn FileBacking>,
+    /// Offset in the file of the region's first page
+    pub offset: u64,
+}
+
+impl fmt::Debug for FileMapping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FileMapping")
+            .field("offset", &self.offset)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum RegionKind {
+    /// Zeroed frames owned by the address space, user address spaces map
+    /// them on the first access
+    Anonymous,
+    /// Physical memory starting at the address, e.g. device registers
+    Physical(PhysAddr, CacheMode),
+    /// Private copy of a file, pages are read on the first access
+    File(FileMapping),
+    /// Never mapped, accesses fault
+    Guard,
+}
+
+/// Pages of an address space mapped the same way.
+#[derive(Debug, Clone)]
+pub struct Region {
+    pub start: VirtAddr,
+    pub size: u64,
+    pub protection: Protection,
+    pub kind: RegionKind,
+}
+
+impl Region {
+    pub fn end(&self) -> VirtAddr {
+        self.start + self.size
+    }
+
+    fn pages(&self) -> PageRange {
+        Page::range(
+            Page::containing_address(self.start),
+            Page::containing_address(self.end()),
+        )
+    }
+
+    /// Flags of the region's mapped pages, before copy-on-write.
+    fn page_table_flags(&self) -> PageTableFlags {
+        let mut flags = self.protection.page_table_flags();
+        if let RegionKind::Physical(_, cache) = self.kind {
+            flags |= cache.page_table_flags();
+        }
+        flags
+    }
+
+    /// The part of the region from `start` to `end`, which must lie inside it.
+    fn slice(&self, start: VirtAddr, end: VirtAddr) -> Region {
+        let kind = match &self.kind {
+            RegionKind::Physical(address, cache) => {
+                RegionKind::Physical(*address + (start - self.start), *cache)
+            }
+            RegionKind::File(mapping) => RegionKind::File(FileMapping {
+                file: mapping.file.clone(),
+                offset: mapping.offset + (start - self.start),
+            }),
+            kind => kind.clone(),
+        };
+        Region {
+            start,
+            size: end - start,
+            protection: self.protection,
+            kind,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmmError {
+    NotInitialized,
+    AlreadyInitialized,
+    /// Addresses and sizes must be multiples of the page size
+    NotAligned,
+    /// The range is empty or outside of the address space
+    OutOfRange,
+    /// The range overlaps an existing region
+    Overlaps,
+    /// The range isn't part of a single mapped region
+    NotMapped,
+    NoVirtualSpace,
+    FrameAllocationFailed,
+    /// The page tables map a page outside of any region
+    AlreadyMapped,
+    FileReadFailed,
+    #[allow(dead_code)]
+    Irq(IrqError),
+    /// A check of [`self_test`] failed
+    #[allow(dead_code)]
+    SelfTestFailed(&'static str),
+}
+
+impl From<MapToError<Size4KiB>> for VmmError {
+    fn from(value: MapToError<Size4KiB>) -> Self {
+        match value {
+            MapToError::FrameAllocationFailed => VmmError::FrameAllocationFailed,
+            MapToError::ParentEntryHugePage | MapToError::PageAlreadyMapped(_) => {
+                VmmError::AlreadyMapped
+            }
+        }
+    }
+}
+
+impl From<IrqError> for VmmError {
+    fn from(value: IrqError) -> Self {
+        VmmError::Irq(value)
+    }
+}
+
+/// Page tables and the regions mapped by them. The kernel's half of the
+/// level 4 table is shared, so kernel mappings are visible everywhere.
+pub struct AddressSpace {
+    level_4_frame: PhysFrame,
+    /// Keyed by start address
+    regions: BTreeMap<u64, Region>,
+    /// Where regions may be placed
+    range: Range<u64>,
+    /// The kernel address space uses [`memory::with_kernel_mapper`] and owns
+    /// none of its page tables
+    kernel: bool,
+}
+
+impl AddressSpace {
+    /// Creates an empty user address space sharing the kernel's half.
+    pub fn new_user() -> Result<Self, VmmError> {
+        let kernel_frame = *KERNEL_LEVEL_4_FRAME
+            .try_get()
+            .map_err(|_| VmmError::NotInitialized)?;
+        let frame = allocate_zeroed_frame()?;
+        let table = unsafe { &mut *page_table(frame)? };
+        let kernel_table = unsafe { &*page_table(kernel_frame)? };
+        for index in KERNEL_LEVEL_4_INDEX..512 {
+            table[index] = kernel_table[index].clone();
+        }
+        Ok(Self {
+            level_4_frame: frame,
+            regions: BTreeMap::new(),
+            range: USER_START..USER_END,
+            kernel: false,
+        })
+    }
+
+    /// Creates an address space managing `range` of the kernel's half, which
+    /// no other kernel address space may overlap.
+    pub fn new_kernel(range: Range<u64>) -> Result<Self, VmmError> {
+        let frame = *KERNEL_LEVEL_4_FRAME
+            .try_get()
+            .map_err(|_| VmmError::NotInitialized)?;
+        if range.start < KERNEL_HALF_START || range.is_empty() {
+            return Err(VmmError::OutOfRange);
+        }
+        Ok(Self {
+            level_4_frame: frame,
+            regions: BTreeMap::new(),
+            range,
+            kernel: true,
+        })
+    }
+
+    #[allow(dead_code)]
+    pub fn level_4_frame(&self) -> PhysFrame {
+        self.level_4_frame
+    }
+
+    /// The region containing `address`.
+    pub fn region(&self, address: VirtAddr) -> Option<&Region> {
+        self.regions
+            .range(..=address.as_u64())
+            .next_back()
+            .map(|(_, region)| region)
+            .filter(|region| address < region.end())
+    }
+
+    pub fn regions(&self) -> impl Iterator<Item = &Region> {
+        self.regions.values()
+    }
+
+    /// Maps zeroed memory at `start`.
+    pub fn map(
+        &mut self,
+        start: VirtAddr,
+        size: u64,
+        protection: Protection,
+    ) -> Result<(), VmmError> {
+        self.insert(Region {
+            start,
+            size,
+            protection,
+            kind: RegionKind::Anonymous,
+        })
+    }
+
+    /// Maps the physical memory at `address` to `start`.
+    #[allow(dead_code)]
+    pub fn map_physical(
+        &mut self,
+        start: VirtAddr,
+        address: PhysAddr,
+        size: u64,
+        protection: Protection,
+        cache: CacheMode,
+    ) -> Result<(), VmmError> {
+        if !address.is_aligned(PAGE_SIZE) {
+            return Err(VmmError::NotAligned);
+        }
+        self.insert(Region {
+            start,
+            size,
+            protection,
+            kind: RegionKind::Physical(address, cache),
+        })
+    }
+
+    /// Maps the physical memory at `address` to a free address.
+    pub fn allocate_physical(
+        &mut self,
+        address: PhysAddr,
+        size: u64,
+        protection: Protection,
+        cache: CacheMode,
+    ) -> Result<VirtAddr, VmmError> {
+        let start = self.find_free(size)?;
+        self.map_physical(start, address, size, protection, cache)?;
+        Ok(start)
+    }
+
+    /// Maps `file` from `offset` on to `start`, writes stay private.
+    #[allow(dead_code)]
+    pub fn map_file(
+        &mut self,
+        start: VirtAddr,
+        size: u64,
+        protection: Protection,
+        file: Arc<dyn FileBacking>,
+        offset: u64,
+    ) -> Result<(), VmmError> {
+        if !offset.is_multiple_of(PAGE_SIZE) {
+            return Err(VmmError::NotAligned);
+        }
+        self.insert(Region {
+            start,
+            size,
+            protection,
+            kind: RegionKind::File(FileMapping { file, offset }),
+        })
+    }
+
+    /// Keeps `start..start + size` unmapped, e.g. as a guard page.
+    pub fn reserve(&mut self, start: VirtAddr, size: u64) -> Result<(), VmmError> {
+        self.insert(Region {
+            start,
+            size,
+            protection: Protection::empty(),
+            kind: RegionKind::Guard,
+        })
+    }
+
+    /// Maps zeroed memory at a free address.
+    pub fn allocate(&mut self, size: u64, protection: Protection) -> Result<VirtAddr, VmmError> {
+        let start = self.find_free(size)?;
+        self.map(start, size, protection)?;
+        Ok(start)
+    }
+
+    /// Unmaps part of a region, freeing the frames no other address space
+    /// shares.
+    pub fn unmap(&mut self, start: VirtAddr, size: u64) -> Result<(), VmmError> {
+        let region = self.split(start, size)?;
+        self.regions.remove(&start.as_u64());
+        self.unmap_region(&region);
+        Ok(())
+    }
+
+    /// Changes the protection of part of a region.
+    #[allow(dead_code)]
+    pub fn protect(
+        &mut self,
+        start: VirtAddr,
+        size: u64,
+        protection: Protection,
+    ) -> Result<(), VmmError> {
+        if self
+            .region(start)
+            .is_some_and(|region| matches!(region.kind, RegionKind::Guard))
+        {
+            return Err(VmmError::NotMapped);
+        }
+        let mut region = self.split(start, size)?;
+        region.protection = protection;
+        let flags = region.page_table_flags();
+        for page in region.pages() {
+            // pages which weren't accessed yet get the flags once mapped
+            let Some((frame, _)) = self.translate(page)? else {
+                continue;
+            };
+            let flags =
+                if flags.contains(PageTableFlags::WRITABLE) && memory::is_frame_shared(frame) {
+                    (flags - PageTableFlags::WRITABLE) | COPY_ON_WRITE
+                } else {
+                    flags
+                };
+            self.update_flags(page, flags)?;
+        }
+        if let Some(region) = self.regions.get_mut(&start.as_u64()) {
+            region.protection = protection;
+        }
+        tlb::shootdown(region.pages());
+        Ok(())
+    }
+
+    /// Copies `data` to `address` whatever the protection of its regions,
+    /// e.g. to load a program into an address space which isn't active.
+    pub fn write(&mut self, address: VirtAddr, data: &[u8]) -> Result<(), VmmError> {
+        let mut written = 0;
+        while written < data.len() {
+            let address = address + written as u64;
+            let region = self.region(address).cloned().ok_or(VmmError::NotMapped)?;
+            if matches!(region.kind, RegionKind::Guard) {
+                return Err(VmmError::NotMapped);
+            }
+            let page = Page::containing_address(address);
+            match self.translate(page)? {
+                None => self.map_on_demand(&region, page)?,
+                Some((frame, flags)) if flags.contains(COPY_ON_WRITE) => {
+                    self.copy_on_write(&region, page, frame)?
+                }
+                Some(_) => {}
+            }
+            let (frame, _) = self.translate(page)?.ok_or(VmmError::NotMapped)?;
+            let offset = (address - page.start_address()) as usize;
+            let size = (PAGE_SIZE as usize - offset).min(data.len() - written);
+            unsafe {
+                let to = frame_ptr(frame)?.add(offset);
+                core::ptr::copy_nonoverlapping(data[written..].as_ptr(), to, size);
+            }
+            written += size;
+        }
+        Ok(())
+    }
+
+    fn check_range(&self, start: VirtAddr, size: u64) -> Result<VirtAddr, VmmError> {
+        if !start.is_aligned(PAGE_SIZE) || !size.is_multiple_of(PAGE_SIZE) {
+            return Err(VmmError::NotAligned);
+        }
+        let end = start
+            .as_u64()
+            .checked_add(size)
+            .filter(|end| size > 0 && start.as_u64() >= self.range.start && *end <= self.range.end)
+            .ok_or(VmmError::OutOfRange)?;
+        Ok(VirtAddr::new(end))
+    }
+
+    fn find_free(&self, size: u64) -> Result<VirtAddr, VmmError> {
+        let mut start = self.range.start;
+        for region in self.regions.values() {
+            if start
+                .checked_add(size)
+                .is_some_and(|end| end <= region.start.as_u64())
+            {
+                break;
+            }
+            start = start.max(region.end().as_u64());
+        }
+        self.check_range(VirtAddr::new(start), size)
+            .map(|_| VirtAddr::new(start))
+            .map_err(|_| VmmError::NoVirtualSpace)
+    }
+
+    fn insert(&mut self, region: Region) -> Result<(), VmmError> {
+        heap_tag!();
+        let end = self.check_range(region.start, region.size)?;
+        if self
+            .regions
+            .range(..end.as_u64())
+            .next_back()
+            .is_some_and(|(_, other)| other.end() > region.start)
+        {
+            return Err(VmmError::Overlaps);
+        }
+        self.map_region(&region)?;
+        self.regions.insert(region.start.as_u64(), region);
+        Ok(())
+    }
+
+    /// Splits the region containing `start..start + size` so the range is a
+    /// region of its own and returns it.
+    fn split(&mut self, start: VirtAddr, size: u64) -> Result<Region, VmmError> {
+        heap_tag!();
+        let end = self.check_range(start, size)?;
+        let region = self.region(start).cloned().ok_or(VmmError::NotMapped)?;
+        if end > region.end() {
+            return Err(VmmError::NotMapped);
+        }
+        self.regions.remove(&region.start.as_u64());
+        for part in [
+            region.slice(region.start, start),
+            region.slice(start, end),
+            region.slice(end, region.end()),
+        ] {
+            if part.size > 0 {
+                self.regions.insert(part.start.as_u64(), part);
+            }
+        }
+        Ok(region.slice(start, end))
+    }
+
+    /// Creates a user address space sharing all pages of this one
+    /// copy-on-write, like `fork`.
+    #[allow(dead_code)]
+    pub fn clone_cow(&mut self) -> Result<AddressSpace, VmmError> {
+        if self.kernel {
+            return Err(VmmError::OutOfRange);
+        }
+        heap_tag!();
+        let mut child = AddressSpace::new_user()?;
+        for region in self.regions.values() {
+            // inserted first, so dropping the child on errors releases the
+            // pages shared so far
+            child.regions.insert(region.start.as_u64(), region.clone());
+            match region.kind {
+                RegionKind::Guard => {}
+                RegionKind::Physical(..) => child.map_region(region)?,
+                RegionKind::Anonymous | RegionKind::File(_) => self.share_pages(&child, region)?,
+            }
+        }
+        Ok(child)
+    }
+
+    /// Maps the mapped pages of `region` into `child` as well, write
+    /// protecting them in both.
+    fn share_pages(&self, child: &AddressSpace, region: &Region) -> Result<(), VmmError> {
+        let mut write_protected = false;
+        for page in region.pages() {
+            let Some((frame, mut flags)) = self.translate(page)? else {
+                continue;
+            };
+            if flags.contains(PageTableFlags::WRITABLE) {
+                flags = (flags - PageTableFlags::WRITABLE) | COPY_ON_WRITE;
+                self.update_flags(page, flags)?;
+                write_protected = true;
+            }
+            memory::share_frame(frame);
+            if let Err(err) = child.map_page(page, frame, flags, region.protection) {
+                memory::release_frame(frame);
+                return Err(err);
+            }
+        }
+        if write_protected {
+            tlb::shootdown(region.pages());
+        }
+        Ok(())
+    }
+
+    /// Resolves a fault on `address` by mapping the page on demand or copying
+    /// a copy-on-write page, returns whether the access can be retried.
+    fn handle_fault(&self, address: VirtAddr, error_code: PageFaultErrorCode) -> bool {
+        let Some(region) = self.region(address) else {
+            return false;
+        };
+        let write = error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE);
+        let allowed = [
+            (write, Protection::WRITE),
+            (
+                error_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH),
+                Protection::EXECUTE,
+            ),
+            (
+                error_code.contains(PageFaultErrorCode::USER_MODE),
+                Protection::USER,
+            ),
+        ]
+        .into_iter()
+        .all(|(needed, protection)| !needed || region.protection.contains(protection));
+        if !allowed || matches!(region.kind, RegionKind::Guard) {
+            return false;
+        }
+        let page = Page::containing_address(address);
+        match self.translate(page) {
+            Err(_) => false,
+            Ok(None) => self.map_on_demand(region, page).is_ok(),
+            Ok(Some((frame, flags))) if write && flags.contains(COPY_ON_WRITE) => {
+                self.copy_on_write(region, page, frame).is_ok()
+            }
+            // another CPU resolved it first, this one may still have the old
+            // entry cached
+            Ok(Some((_, flags))) => {
+                x86_64::instructions::tlb::flush(page.start_address());
+                !write || flags.contains(PageTableFlags::WRITABLE)
+            }
+        }
+    }
+
+    fn map_on_demand(&self, region: &Region, page: Page) -> Result<(), VmmError> {
+        let frame = allocate_zeroed_frame()?;
+        let result = self.fill_page(region, page, frame).and_then(|()| {
+            self.map_page(
+                page,
+                frame,
+                region.protection.page_table_flags(),
+                region.protection,
+            )
+        });
+        if result.is_err() {
+            unsafe { GlobalFrameAllocator.deallocate_frame(frame) };
+        }
+        result
+    }
+
+    /// Reads the contents of a file backed page into its zeroed frame.
+    fn fill_page(&self, region: &Region, page: Page, frame: PhysFrame) -> Result<(), VmmError> {
+        let RegionKind::File(mapping) = &region.kind else {
+            return Ok(());
+        };
+        let offset = mapping.offset + (page.start_address() - region.start);
+        let data =
+            unsafe { core::slice::from_raw_parts_mut(frame_ptr(frame)?, PAGE_SIZE as usize) };
+        mapping
+            .file
+            .read_page(offset, data)
+            .map_err(|_| VmmError::FileReadFailed)
+    }
+
+    fn copy_on_write(&self, region: &Region, page: Page, frame: PhysFrame) -> Result<(), VmmError> {
+        let flags = region.protection.page_table_flags();
+        if !memory::is_frame_shared(frame) {
+            // every other address space dropped the frame already
+            return self.update_flags(page, flags);
+        }
+        let copy = GlobalFrameAllocator
+            .allocate_frame()
+            .ok_or(VmmError::FrameAllocationFailed)?;
+        let copied = frame_ptr(frame).and_then(|from| {
+            frame_ptr(copy)
+                .map(|to| unsafe { core::ptr::copy_nonoverlapping(from, to, PAGE_SIZE as usize) })
+        });
+        let result = copied
+            .and_then(|()| {
+                self.with_mapper(|mapper| mapper.unmap(page).map(|(_, flush)| flush.ignore()))?
+                    .map_err(|_| VmmError::NotMapped)
+            })
+            .and_then(|()| self.map_page(page, copy, flags, region.protection));
+        if let Err(err) = result {
+            unsafe { GlobalFrameAllocator.deallocate_frame(copy) };
+            return Err(err);
+        }
+        // other CPUs running this address space may still read the old frame
+        tlb::shootdown(Page::range(page, page + 1));
+        if memory::release_frame(frame) {
+            unsafe { GlobalFrameAllocator.deallocate_frame(frame) };
+        }
+        Ok(())
+    }
+
+    /// Whether pages of `kind` are only mapped once they are accessed. The
+    /// kernel maps anonymous memory up front, so it never faults on it while
+    /// holding a lock.
+    fn demand_paged(&self, kind: &RegionKind) -> bool {
+        match kind {
+            RegionKind::File(_) => true,
+            RegionKind::Anonymous => !self.kernel,
+            RegionKind::Physical(..) | RegionKind::Guard => false,
+        }
+    }
+
+    fn map_region(&self, region: &Region) -> Result<(), VmmError> {
+        if self.demand_paged(&region.kind) {
+            return Ok(());
+        }
+        let flags = region.page_table_flags();
+        for (index, page) in region.pages().enumerate() {
+            let frame = match region.kind {
+                RegionKind::Guard | RegionKind::File(_) => return Ok(()),
+                RegionKind::Anonymous => allocate_zeroed_frame(),
+                RegionKind::Physical(address, _) => Ok(PhysFrame::containing_address(
+                    address + index as u64 * PAGE_SIZE,
+                )),
+            };
+            let result = frame.and_then(|frame| {
+                let result = self.map_page(page, frame, flags, region.protection);
+                if result.is_err() && matches!(region.kind, RegionKind::Anonymous) {
+                    unsafe { GlobalFrameAllocator.deallocate_frame(frame) };
+                }
+                result
+            });
+            if let Err(err) = result {
+                let mapped = page.start_address();
+                self.unmap_region(&region.slice(region.start, mapped));
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    fn unmap_region(&self, region: &Region) {
+        if matches!(region.kind, RegionKind::Guard) || region.size == 0 {
+            return;
+        }
+        heap_tag!();
+        let mut frames = Vec::new();
+        for page in region.pages() {
+            if let Ok(Ok((frame, flush))) = self.with_mapper(|mapper| mapper.unmap(page)) {
+                flush.ignore();
+                frames.push(frame);
+            }
+        }
+        // other CPUs may still use the frames until they flushed
+        tlb::shootdown(region.pages());
+        if !matches!(region.kind, RegionKind::Physical(..)) {
+            for frame in frames {
+                if memory::release_frame(frame) {
+                    unsafe { GlobalFrameAllocator.deallocate_frame(frame) };
+                }
+            }
+        }
+    }
+
+    fn map_page(
+        &self,
+        page: Page,
+        frame: PhysFrame,
+        flags: PageTableFlags,
+        protection: Protection,
+    ) -> Result<(), VmmError> {
+        self.with_mapper(|mapper| unsafe {
+            mapper
+                .map_to_with_table_flags(
+                    page,
+                    frame,
+                    flags,
+                    protection.parent_flags(),
+                    &mut GlobalFrameAllocator,
+                )
+                .map(|flush| flush.flush())
+        })?
+        .map_err(VmmError::from)
+    }
+
+    /// The frame and flags `page` is mapped with, `None` if it isn't.
+    fn translate(&self, page: Page) -> Result<Option<(PhysFrame, PageTableFlags)>, VmmError> {
+        self.with_mapper(|mapper| match mapper.translate(page.start_address()) {
+            TranslateResult::Mapped {
+                frame: MappedFrame::Size4KiB(frame),
+                flags,
+                ..
+            } => Some((frame, flags)),
+            _ => None,
+        })
+    }
+
+    /// Changes the flags of a mapped page, only flushed on this CPU.
+    fn update_flags(&self, page: Page, flags: PageTableFlags) -> Result<(), VmmError> {
+        self.with_mapper(|mapper| unsafe { mapper.update_flags(page, flags) })?
+            .map(|flush| flush.flush())
+            .map_err(|_| VmmError::NotMapped)
+    }
+
+    fn with_mapper<R>(
+        &self,
+        f: impl FnOnce(&mut OffsetPageTable<'static>) -> R,
+    ) -> Result<R, VmmError> {
+        if self.kernel {
+            return memory::with_kernel_mapper(f).ok_or(VmmError::NotInitialized);
+        }
+        let offset = memory::physical_memory_offset().ok_or(VmmError::NotInitialized)?;
+        let table = unsafe { &mut *page_table(self.level_4_frame)? };
+        let mut mapper = unsafe { OffsetPageTable::new(table, offset) };
+        Ok(f(&mut mapper))
+    }
+}
+
+impl Drop for AddressSpace {
+    /// Frees the regions and page tables, the address space must not be
+    /// active on any CPU.
+    fn drop(&mut self) {
+        if self.kernel {
+            return;
+        }
+        for region in core::mem::take(&mut self.regions).values() {
+            self.unmap_region(region);
+        }
+        unsafe { free_table(self.level_4_frame, 4, KERNEL_LEVEL_4_INDEX) };
+    }
+}
+
+/// Frees a page table and the tables below it, only the first `entries`
+/// entries are looked at.
+unsafe fn free_table(frame: PhysFrame, level: u8, entries: usize) {
+    if let Ok(table) = page_table(frame) {
+        if level > 1 {
+            let table = unsafe { &*table };
+            for entry in table.iter().take(entries) {
+                if entry.flags().contains(PageTableFlags::PRESENT)
+                    && !entry.flags().contains(PageTableFlags::HUGE_PAGE)
+                    && let Ok(frame) = entry.frame()
+                {
+                    unsafe { free_table(frame, level - 1, 512) };
+                }
+            }
+        }
+        unsafe { GlobalFrameAllocator.deallocate_frame(frame) };
+    }
+}
+
+fn page_table(frame: PhysFrame) -> Result<*mut PageTable, VmmError> {
+    frame_ptr(frame).map(|ptr| ptr.cast())
+}
+
+/// The frame's contents through the physical memory mapping.
+fn frame_ptr(frame: PhysFrame) -> Result<*mut u8, VmmError> {
+    let offset = memory::physical_memory_offset().ok_or(VmmError::NotInitialized)?;
+    Ok((offset + frame.start_address().as_u64()).as_mut_ptr())
+}
+
+fn allocate_zeroed_frame() -> Result<PhysFrame, VmmError> {
+    let frame = GlobalFrameAllocator
+        .allocate_frame()
+        .ok_or(VmmError::FrameAllocationFailed)?;
+    match frame_ptr(frame) {
+        Ok(ptr) => {
+            unsafe { ptr.write_bytes(0, PAGE_SIZE as usize) };
+            Ok(frame)
+        }
+        Err(err) => {
+            unsafe { GlobalFrameAllocator.deallocate_frame(frame) };
+            Err(err)
+        }
+    }
+}
+
+static KERNEL_LEVEL_4_FRAME: OnceCell<PhysFrame> = OnceCell::uninit();
+static KERNEL_SPACE: OnceCell<Mutex<AddressSpace>> = OnceCell::uninit();
+
+/// Sets up the kernel address space, needs the heap and the kernel mapper.
+pub fn init() -> Result<(), VmmError> {
+    let (frame, _) = Cr3::read();
+    KERNEL_LEVEL_4_FRAME
+        .try_init_once(|| frame)
+        .map_err(|_| VmmError::AlreadyInitialized)?;
+    share_kernel_half()?;
+    tlb::init()?;
+    let space = AddressSpace::new_kernel(KERNEL_DYNAMIC_START..KERNEL_DYNAMIC_END)?;
+    KERNEL_SPACE.init_once(|| Mutex::new(space));
+    Ok(())
+}
+
+/// Fills every empty level 4 entry of the kernel's half, so mappings added
+/// later show up in address spaces copied from it before.
+fn share_kernel_half() -> Result<(), VmmError> {
+    memory::with_kernel_mapper(|mapper| {
+        for entry in mapper
+            .level_4_table_mut()
+            .iter_mut()
+            .skip(KERNEL_LEVEL_4_INDEX)
+            .filter(|entry| entry.is_unused())
+        {
+            entry.set_frame(
+                allocate_zeroed_frame()?,
+                PageTableFlags::PRESENT | PageTableFlags::WRITABLE,
+            );
+        }
+        Ok(())
+    })
+    .ok_or(VmmError::NotInitialized)?
+}
+
+/// The user address space each CPU runs in, `None` while in the kernel's.
+static ACTIVE: [IrqSpinLock<Option<Arc<Mutex<AddressSpace>>>>; MAX_CPUS] =
+    [const { IrqSpinLock::with_level(None, level::ADDRESS_SPACES) }; MAX_CPUS];
+
+/// Switches the calling CPU to `space`, or back to the kernel address space
+/// for `None`. The address space stays alive while it is active.
+pub fn activate(space: Option<Arc<Mutex<AddressSpace>>>) {
+    let frame = match &space {
+        Some(space) => space.lock().level_4_frame,
+        None => match KERNEL_LEVEL_4_FRAME.try_get() {
+            Ok(frame) => *frame,
+            Err(_) => return,
+        },
+    };
+    let previous = without_interrupts(|| {
+        let mut active = ACTIVE[cpu::current_index()].lock();
+        unsafe { Cr3::write(frame, Cr3Flags::empty()) };
+        core::mem::replace(&mut *active, space)
+    });
+    // dropping the last reference frees the address space, not with the lock
+    // held
+    drop(previous);
+}
+
+/// Resolves a page fault on `address` in the active address space, returns
+/// whether the access can be retried. Resolving may sleep, so it must be
+/// called with interrupts enabled.
+pub fn handle_page_fault(address: VirtAddr, error_code: PageFaultErrorCode) -> bool {
+    if (KERNEL_DYNAMIC_START..KERNEL_DYNAMIC_END).contains(&address.as_u64()) {
+        return KERNEL_SPACE
+            .try_get()
+            .is_ok_and(|space| space.lock().handle_fault(address, error_code));
+    }
+    if !(USER_START..USER_END).contains(&address.as_u64()) {
+        return false;
+    }
+    let space = without_interrupts(|| ACTIVE[cpu::current_index()].lock().clone());
+    space.is_some_and(|space| space.lock().handle_fault(address, error_code))
+}
+
+/// Locks the kernel address space, `None` before [`init`].
+#[allow(dead_code)]
+pub fn kernel_space() -> Option<MutexGuard<'static, AddressSpace>> {
+    KERNEL_SPACE.try_get().ok().map(Mutex::lock)
+}
+
+/// Maps a user range with a guard page after it, faults both pages in by
+/// writing to them from the active address space and unmaps them again, then
+/// checks that protecting an uncached physical mapping keeps it uncached. Must
+/// run before other CPUs are online, the thread mustn't move while its
+/// address space is active.
+pub fn self_test() -> Result<(), VmmError> {
+    const SIZE: u64 = 2 * PAGE_SIZE;
+    let space = Arc::new(Mutex::new(AddressSpace::new_user()?));
+    let start = {
+        let mut space = space.lock();
+        let start = space.allocate(SIZE, Protection::WRITE)?;
+        space.reserve(start + SIZE, PAGE_SIZE)?;
+        let mapped = space.translate(Page::containing_address(start))?.is_some();
+        check(!mapped, "user memory was mapped before it was accessed")?;
+        start
+    };
+
+    activate(Some(space.clone()));
+    let pages = [start, start + PAGE_SIZE].map(|address| address.as_mut_ptr::<u64>());
+    for (value, page) in (1..).zip(pages) {
+        unsafe { page.write_volatile(value) };
+    }
+    let read: [u64; 2] = pages.map(|page| unsafe { page.read_volatile() });
+    activate(None);
+    check(read == [1, 2], "user memory read back other values")?;
+
+    let mut space = space.lock();
+    check(space.regions().count() == 2, "wrong number of regions")?;
+    let mapped = space.translate(Page::containing_address(start))?.is_some();
+    check(mapped, "accessed user memory wasn't mapped")?;
+    space.unmap(start, SIZE)?;
+    check(space.region(start).is_none(), "the unmapped region is left")?;
+    let mapped = space.translate(Page::containing_address(start))?.is_some();
+    check(!mapped, "unmapped user memory is still mapped")?;
+
+    let frame = allocate_zeroed_frame()?;
+    let flags = protected_physical_flags(&mut space, frame);
+    unsafe { GlobalFrameAllocator.deallocate_frame(frame) };
+    let flags = flags?.ok_or(VmmError::SelfTestFailed("physical memory isn't mapped"))?;
+    check(
+        flags.contains(CacheMode::Uncached.page_table_flags()),
+        "protect dropped the cache mode of physical memory",
+    )?;
+    check(
+        !flags.contains(PageTableFlags::WRITABLE),
+        "protect left physical memory writable",
+    )
+}
+
+/// Maps `frame` uncached and writable into `space`, protects it read-only and
+/// returns the flags it was left with.
+fn protected_physical_flags(
+    space: &mut AddressSpace,
+    frame: PhysFrame,
+) -> Result<Option<PageTableFlags>, VmmError> {
+    let start = space.allocate_physical(
+        frame.start_address(),
+        PAGE_SIZE,
+        Protection::WRITE,
+        CacheMode::Uncached,
+    )?;
+    let protected = space.protect(start, PAGE_SIZE, Protection::empty());
+    let translated = space.translate(Page::containing_address(start));
+    space.unmap(start, PAGE_SIZE)?;
+    protected?;
+    Ok(translated?.map(|(_, flags)| flags))
+}
+
+fn check(condition: bool, failure: &'static str) -> Result<(), VmmError> {
+    condition
+        .then_some(())
+        .ok_or(VmmError::SelfTestFailed(failure))
+}
+
+/// One bit per kernel stack slot, set while the slot is used.
+static STACK_SLOTS: [AtomicU64; KERNEL_STACK_SLOTS / 64] =
+    [const { AtomicU64::new(0) }; KERNEL_STACK_SLOTS / 64];
+
+/// A kernel stack at the end of its slot. The unmapped start of the slot
+/// below it and the first page of the next slot above it fault on overflows.
+pub struct KernelStack {
+    slot: usize,
+    size: u64,
+}
+
+impl KernelStack {
+    /// Maps a stack of `size` bytes rounded up to pages, at most a slot
+    /// without its guard page.
+    pub fn new(size: usize) -> Result<Self, VmmError> {
+        let size = (size as u64).next_multiple_of(PAGE_SIZE);
+        if size == 0 || size > KERNEL_STACK_SLOT_SIZE - PAGE_SIZE {
+            return Err(VmmError::OutOfRange);
+        }
+        let slot = allocate_stack_slot().ok_or(VmmError::NoVirtualSpace)?;
+        // dropping it unmaps whatever was mapped so far
+        let stack = Self { slot, size };
+        let flags = Protection::WRITE.page_table_flags();
+        for page in stack.pages() {
+            let frame = allocate_zeroed_frame()?;
+            let result = memory::with_kernel_mapper(|mapper| unsafe {
+                mapper
+                    .map_to(page, frame, flags, &mut GlobalFrameAllocator)
+                    .map(|flush| flush.flush())
+            })
+            .unwrap_or(Err(MapToError::FrameAllocationFailed));
+            if let Err(err) = result {
+                unsafe { GlobalFrameAllocator.deallocate_frame(frame) };
+                return Err(err.into());
+            }
+        }
+        Ok(stack)
+    }
+
+    /// Initial stack pointer, 16 byte aligned.
+    pub fn top(&self) -> VirtAddr {
+        VirtAddr::new(KERNEL_STACKS_START + (self.slot as u64 + 1) * KERNEL_STACK_SLOT_SIZE)
+    }
+
+    fn pages(&self) -> PageRange {
+        Page::range(
+            Page::containing_address(self.top() - self.size),
+            Page::containing_address(self.top()),
+        )
+    }
+}
+
+impl Drop for KernelStack {
+    /// Needs interrupts enabled with other CPUs online, see [`tlb::shootdown`].
+    fn drop(&mut self) {
+        let mut frames: heapless::Vec<
+            PhysFrame,
+            { (KERNEL_STACK_SLOT_SIZE / PAGE_SIZE) as usize },
+        > = heapless::Vec::new();
+        for page in self.pages() {
+            if let Some(Ok((frame, flush))) =
+                memory::with_kernel_mapper(|mapper| mapper.unmap(page))
+            {
+                flush.ignore();
+                let _ = frames.push(frame);
+            }
+        }
+        tlb::shootdown(self.pages());
+        for frame in frames {
+            unsafe { GlobalFrameAllocator.deallocate_frame(frame) };
+        }
+        STACK_SLOTS[self.slot / 64].fetch_and(!(1 << (self.slot % 64)), Ordering::Release);
+    }
+}
+
+fn allocate_stack_slot() -> Option<usize> {
+    STACK_SLOTS.iter().enumerate().find_map(|(index, word)| {
+        word.try_update(Ordering::Acquire, Ordering::Relaxed, |bits| {
+            (bits != u64::MAX).then(|| bits | (1 << (!bits).trailing_zeros()))
+        })
+        .ok()
+        .map(|bits| index * 64 + (!bits).trailing_zeros() as usize)
+    })
+}
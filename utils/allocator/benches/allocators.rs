@@ -0,0 +1,83 @@
+//! Compares the heap allocators, run with `cargo bench -p allocator`.
+#![feature(test)]
+
+extern crate test;
+
+use std::{alloc::Layout, ptr::NonNull};
+
+use allocator::{Allocator, BuddyAllocator, LinkedListAllocator, SlabAllocator};
+use test::Bencher;
+
+const HEAP_SIZE: usize = 4 * 1024 * 1024;
+const PAGE_SIZE: usize = 4096;
+/// Typical kernel allocation sizes, mostly small
+const SIZES: [usize; 8] = [8, 24, 32, 48, 100, 256, 1000, 4000];
+const LIVE_ALLOCATIONS: usize = 256;
+
+/// Heap memory for one benchmark, freed when dropped.
+struct Heap {
+    ptr: *mut u8,
+    layout: Layout,
+}
+
+impl Heap {
+    fn new() -> Self {
+        let layout = Layout::from_size_align(HEAP_SIZE, PAGE_SIZE).unwrap();
+        let ptr = unsafe { std::alloc::alloc(layout) };
+        assert!(!ptr.is_null());
+        Self { ptr, layout }
+    }
+
+    fn init<T: Allocator>(&self, mut allocator: T) -> T {
+        unsafe { allocator.init(self.ptr, HEAP_SIZE) };
+        allocator
+    }
+}
+
+impl Drop for Heap {
+    fn drop(&mut self) {
+        unsafe { std::alloc::dealloc(self.ptr, self.layout) }
+    }
+}
+
+/// Keeps a window of live allocations of mixed sizes, freeing the oldest
+/// one for every new one.
+fn churn<T: Allocator>(allocator: &mut T) {
+    let mut live: [Option<(NonNull<u8>, Layout)>; LIVE_ALLOCATIONS] = [None; LIVE_ALLOCATIONS];
+    for i in 0..4 * LIVE_ALLOCATIONS {
+        let slot = &mut live[i % LIVE_ALLOCATIONS];
+        if let Some((ptr, layout)) = slot.take() {
+            allocator.dealloc(ptr, layout);
+        }
+        let layout = Layout::from_size_align(SIZES[i % SIZES.len()], 8).unwrap();
+        let ptr = allocator.alloc(layout).unwrap().cast::<u8>();
+        *slot = Some((test::black_box(ptr), layout));
+    }
+    for (ptr, layout) in live.into_iter().flatten() {
+        allocator.dealloc(ptr, layout);
+    }
+}
+
+#[bench]
+fn linked_list_churn(b: &mut Bencher) {
+    let heap = Heap::new();
+    let mut allocator = heap.init(LinkedListAllocator::new());
+    b.iter(|| churn(&mut allocator));
+}
+
+#[bench]
+fn slab_churn(b: &mut Bencher) {
+    let heap = Heap::new();
+    let mut allocator = heap.init(SlabAllocator::with_size_classes(
+        LinkedListAllocator::new(),
+        PAGE_SIZE,
+    ));
+    b.iter(|| churn(&mut allocator));
+}
+
+#[bench]
+fn buddy_churn(b: &mut Bencher) {
+    let heap = Heap::new();
+    let mut allocator = heap.init(BuddyAllocator::<16>::new(32, 0));
+    b.iter(|| churn(&mut allocator));
+}
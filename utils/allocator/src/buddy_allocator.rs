@@ -0,0 +1,401 @@
+use core::{alloc::Layout, mem::size_of, ptr::NonNull};
+
+use alloc::alloc::AllocError;
+
+use crate::Allocator;
+
+/// Header written into every free block, linking it into the free list of
+/// its order.
+struct FreeBlock {
+    next: *mut FreeBlock,
+    prev: *mut FreeBlock,
+    order: usize,
+}
+
+/// Buddy allocator with `ORDERS` block sizes, from `min_block_size` up to
+/// `min_block_size << (ORDERS - 1)`. Blocks are aligned to their size, so a
+/// block's buddy is found by flipping the bit of its size in its address.
+///
+/// A bitmap at the start of the heap has one bit per minimum block, set when
+/// a free block starts there. With the doubly linked free lists it makes
+/// freeing and coalescing O(log n) without searching the free lists.
+pub struct BuddyAllocator<const ORDERS: usize> {
+    free_lists: [*mut FreeBlock; ORDERS],
+    min_block_size: usize,
+    /// Bytes the heap may be extended to, the bitmap is sized for it
+    capacity: usize,
+    /// Start of the memory covered by the bitmap, where the bitmap lives
+    base: usize,
+    /// End of the memory covered by the bitmap
+    limit: usize,
+    /// End of the memory split into blocks so far
+    top: usize,
+    /// End of the memory handed to the allocator
+    end: usize,
+    used: usize,
+    free: usize,
+}
+
+// the raw pointers only point into the heap owned by the allocator
+unsafe impl<const ORDERS: usize> Send for BuddyAllocator<ORDERS> {}
+
+impl<const ORDERS: usize> BuddyAllocator<ORDERS> {
+    /// Creates an allocator handing out blocks of at least `min_block_size`
+    /// bytes, a power of 2 large enough for the free block header. It can be
+    /// extended up to `capacity` bytes, or the initial heap size if that is
+    /// larger.
+    pub const fn new(min_block_size: usize, capacity: usize) -> Self {
+        assert!(ORDERS > 0 && ORDERS < usize::BITS as usize);
+        assert!(min_block_size.is_power_of_two());
+        assert!(size_of::<FreeBlock>() <= min_block_size);
+        Self {
+            free_lists: [core::ptr::null_mut(); ORDERS],
+            min_block_size,
+            capacity,
+            base: 0,
+            limit: 0,
+            top: 0,
+            end: 0,
+            used: 0,
+            free: 0,
+        }
+    }
+
+    /// Smallest order with blocks fitting `layout`, including its alignment.
+    fn order(&self, layout: &Layout) -> Option<usize> {
+        let size = layout
+            .size()
+            .max(layout.align())
+            .max(self.min_block_size)
+            .checked_next_power_of_two()?;
+        let order = (size / self.min_block_size).trailing_zeros() as usize;
+        (order < ORDERS).then_some(order)
+    }
+
+    fn block_size(&self, order: usize) -> usize {
+        self.min_block_size << order
+    }
+
+    fn bitmap(&self) -> *mut u64 {
+        self.base as *mut u64
+    }
+
+    /// Bitmap word and bit of the block at `address`.
+    fn bit(&self, address: usize) -> (usize, u64) {
+        let index = (address - self.base) / self.min_block_size;
+        (index / 64, 1 << (index % 64))
+    }
+
+    fn is_free(&self, address: usize, order: usize) -> bool {
+        if address < self.base || address >= self.top {
+            return false;
+        }
+        let (word, bit) = self.bit(address);
+        // the header is only valid while the bit is set
+        unsafe {
+            *self.bitmap().add(word) & bit != 0 && (*(address as *const FreeBlock)).order == order
+        }
+    }
+
+    fn set_free(&mut self, address: usize, free: bool) {
+        let (word, bit) = self.bit(address);
+        unsafe {
+            let word = self.bitmap().add(word);
+            if free {
+                *word |= bit;
+            } else {
+                *word &= !bit;
+            }
+        }
+    }
+
+    fn push(&mut self, address: usize, order: usize) {
+        let block = address as *mut FreeBlock;
+        let head = self.free_lists[order];
+        unsafe {
+            block.write(FreeBlock {
+                next: head,
+                prev: core::ptr::null_mut(),
+                order,
+            });
+            if !head.is_null() {
+                (*head).prev = block;
+            }
+        }
+        self.free_lists[order] = block;
+        self.set_free(address, true);
+        self.free += self.block_size(order);
+    }
+
+    fn remove(&mut self, address: usize, order: usize) {
+        let block = address as *mut FreeBlock;
+        unsafe {
+            let FreeBlock { next, prev, .. } = block.read();
+            if prev.is_null() {
+                self.free_lists[order] = next;
+            } else {
+                (*prev).next = next;
+            }
+            if !next.is_null() {
+                (*next).prev = prev;
+            }
+        }
+        self.set_free(address, false);
+        self.free -= self.block_size(order);
+    }
+
+    /// Adds a block to the free lists, merging it with its buddy as long as
+    /// that is free as well.
+    fn free_block(&mut self, mut address: usize, mut order: usize) {
+        while order + 1 < ORDERS {
+            let buddy = address ^ self.block_size(order);
+            if !self.is_free(buddy, order) {
+                break;
+            }
+            self.remove(buddy, order);
+            address = address.min(buddy);
+            order += 1;
+        }
+        self.push(address, order);
+    }
+
+    /// Splits `top..end` into the largest aligned blocks that fit and frees
+    /// them.
+    fn add_memory(&mut self, end: usize) {
+        let end = end.min(self.limit);
+        while self.top + self.min_block_size <= end {
+            let order = (0..ORDERS)
+                .rev()
+                .find(|&order| {
+                    let size = self.block_size(order);
+                    self.top.is_multiple_of(size) && self.top + size <= end
+                })
+                .unwrap_or(0);
+            let address = self.top;
+            self.top += self.block_size(order);
+            self.free_block(address, order);
+        }
+    }
+}
+
+impl<const ORDERS: usize> Allocator for BuddyAllocator<ORDERS> {
+    unsafe fn init(&mut self, data_ptr: *mut u8, heap_size: usize) {
+        let start = data_ptr as usize;
+        self.end = start + heap_size;
+        self.base = start.next_multiple_of(self.min_block_size);
+        self.limit = start + heap_size.max(self.capacity);
+        let blocks = self.limit.saturating_sub(self.base) / self.min_block_size;
+        let bitmap_size = blocks.div_ceil(64) * size_of::<u64>();
+        assert!(
+            self.base + bitmap_size <= self.end,
+            "heap too small for the buddy allocator bitmap"
+        );
+        unsafe { self.bitmap().write_bytes(0, bitmap_size / size_of::<u64>()) };
+        self.top = (self.base + bitmap_size).next_multiple_of(self.min_block_size);
+        self.add_memory(self.end);
+    }
+
+    unsafe fn extend(&mut self, size: usize) -> Result<(), AllocError> {
+        // the bitmap doesn't cover memory past the limit
+        let end = self.end + size;
+        self.end = end.min(self.limit);
+        self.add_memory(self.end);
+        if end > self.limit {
+            return Err(AllocError);
+        }
+        Ok(())
+    }
+
+    fn alloc(&mut self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let order = self.order(&layout).ok_or(AllocError)?;
+        let found = (order..ORDERS)
+            .find(|&order| !self.free_lists[order].is_null())
+            .ok_or(AllocError)?;
+        let address = self.free_lists[found] as usize;
+        self.remove(address, found);
+        // return the upper halves until the block has the requested size
+        for order in (order..found).rev() {
+            self.push(address + self.block_size(order), order);
+        }
+        self.used += self.block_size(order);
+        let ptr = unsafe { NonNull::new_unchecked(address as *mut u8) };
+        Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+    }
+
+    fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        let order = self
+            .order(&layout)
+            .expect("layout was never allocated by this allocator");
+        self.used -= self.block_size(order);
+        self.free_block(ptr.as_ptr() as usize, order);
+    }
+
+    fn used(&self) -> usize {
+        self.used
+    }
+
+    fn free(&self) -> usize {
+        self.free
+    }
+
+    fn largest_free(&mut self) -> usize {
+        (0..ORDERS)
+            .rev()
+            .find(|&order| !self.free_lists[order].is_null())
+            .map_or(0, |order| self.block_size(order))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::alloc::Layout;
+
+    use assert_hex::assert_eq_hex;
+
+    use crate::tests::{Memory, allocate};
+
+    use super::*;
+
+    const MIN_BLOCK_SIZE: usize = 32;
+    const ORDERS: usize = 8;
+    const MAX_BLOCK_SIZE: usize = MIN_BLOCK_SIZE << (ORDERS - 1);
+
+    #[test]
+    pub fn test_split_and_coalesce() {
+        unsafe {
+            const HEAP_SIZE: usize = 2 * MAX_BLOCK_SIZE;
+            let (heap_space_ptr, data_ptr) = Memory::<HEAP_SIZE>::new();
+            let mut allocator = BuddyAllocator::<ORDERS>::new(MIN_BLOCK_SIZE, 0);
+            allocator.init(data_ptr, HEAP_SIZE);
+            // the bitmap takes the first block
+            let available = allocator.free();
+            assert_eq!(HEAP_SIZE - MIN_BLOCK_SIZE, available);
+
+            let first = allocate(&mut allocator, Layout::new::<u32>()).unwrap();
+            let second = allocate(&mut allocator, Layout::new::<u32>()).unwrap();
+            *first.as_mut_u32() = 0xdeadbeef;
+            *second.as_mut_u32() = 0xcafebabe;
+            assert_eq_hex!(0xdeadbeef, *first.as_mut_u32());
+            assert_eq_hex!(0xcafebabe, *second.as_mut_u32());
+            assert_eq!(2 * MIN_BLOCK_SIZE, allocator.used());
+            // only the allocated blocks were taken out of the larger ones
+            assert_eq!(available - 2 * MIN_BLOCK_SIZE, allocator.free());
+
+            first.free(&mut allocator);
+            second.free(&mut allocator);
+            assert_eq!(0, allocator.used());
+            assert_eq!(available, allocator.free());
+
+            // everything merged back, so the largest block is available again
+            let layout = Layout::from_size_align(MAX_BLOCK_SIZE, 8).unwrap();
+            let largest = allocate(&mut allocator, layout).unwrap();
+            assert!(allocate(&mut allocator, layout).is_err());
+            largest.free(&mut allocator);
+
+            Memory::free(heap_space_ptr);
+        }
+    }
+
+    #[test]
+    pub fn test_alignment() {
+        unsafe {
+            const HEAP_SIZE: usize = 2 * MAX_BLOCK_SIZE;
+            let (heap_space_ptr, data_ptr) = Memory::<HEAP_SIZE>::new();
+            let mut allocator = BuddyAllocator::<ORDERS>::new(MIN_BLOCK_SIZE, 0);
+            allocator.init(data_ptr, HEAP_SIZE);
+
+            let small = allocate(&mut allocator, Layout::new::<u8>()).unwrap();
+            let layout = Layout::from_size_align(8, 1024).unwrap();
+            let aligned = allocate(&mut allocator, layout).unwrap();
+            assert_eq!(0, aligned.as_mut_u32() as usize % 1024);
+            assert_eq!(1024 + MIN_BLOCK_SIZE, allocator.used());
+
+            let too_large = Layout::from_size_align(2 * MAX_BLOCK_SIZE, 8).unwrap();
+            assert!(allocate(&mut allocator, too_large).is_err());
+
+            aligned.free(&mut allocator);
+            small.free(&mut allocator);
+            assert_eq!(0, allocator.used());
+
+            Memory::free(heap_space_ptr);
+        }
+    }
+
+    #[test]
+    pub fn test_out_of_memory_and_extend() {
+        unsafe {
+            const HEAP_SIZE: usize = 4 * MAX_BLOCK_SIZE;
+            let (heap_space_ptr, data_ptr) = Memory::<HEAP_SIZE>::new();
+            const BITMAP_SIZE: usize = HEAP_SIZE / MIN_BLOCK_SIZE / 8;
+            let mut allocator = BuddyAllocator::<ORDERS>::new(MIN_BLOCK_SIZE, HEAP_SIZE);
+            allocator.init(data_ptr, MAX_BLOCK_SIZE);
+            assert_eq!(MAX_BLOCK_SIZE - BITMAP_SIZE, allocator.free());
+
+            // the bitmap leaves less than the largest block
+            let layout = Layout::from_size_align(MAX_BLOCK_SIZE / 2, 8).unwrap();
+            let first = allocate(&mut allocator, layout).unwrap();
+            assert!(allocate(&mut allocator, layout).is_err());
+
+            assert!(allocator.extend(3 * MAX_BLOCK_SIZE).is_ok());
+            assert_eq!(HEAP_SIZE - BITMAP_SIZE, allocator.used() + allocator.free());
+            let second = allocate(&mut allocator, layout).unwrap();
+            let largest = Layout::from_size_align(MAX_BLOCK_SIZE, 8).unwrap();
+            let third = allocate(&mut allocator, largest).unwrap();
+
+            first.free(&mut allocator);
+            second.free(&mut allocator);
+            third.free(&mut allocator);
+            assert_eq!(0, allocator.used());
+            assert_eq!(HEAP_SIZE - BITMAP_SIZE, allocator.free());
+
+            Memory::free(heap_space_ptr);
+        }
+    }
+
+    #[test]
+    pub fn test_extend_past_capacity() {
+        unsafe {
+            const HEAP_SIZE: usize = 4 * MAX_BLOCK_SIZE;
+            let (heap_space_ptr, data_ptr) = Memory::<HEAP_SIZE>::new();
+            const CAPACITY: usize = 2 * MAX_BLOCK_SIZE;
+            const BITMAP_SIZE: usize = CAPACITY / MIN_BLOCK_SIZE / 8;
+            let mut allocator = BuddyAllocator::<ORDERS>::new(MIN_BLOCK_SIZE, CAPACITY);
+            allocator.init(data_ptr, MAX_BLOCK_SIZE);
+
+            // only the memory up to the capacity is used
+            assert!(allocator.extend(3 * MAX_BLOCK_SIZE).is_err());
+            assert_eq!(CAPACITY - BITMAP_SIZE, allocator.free());
+            assert!(allocator.extend(MAX_BLOCK_SIZE).is_err());
+            assert_eq!(CAPACITY - BITMAP_SIZE, allocator.free());
+
+            Memory::free(heap_space_ptr);
+        }
+    }
+
+    #[test]
+    pub fn test_pages() {
+        unsafe {
+            const PAGE_SIZE: usize = 4096;
+            const PAGES: usize = 16;
+            // room to align the start to a page
+            const HEAP_SIZE: usize = (PAGES + 1) * PAGE_SIZE;
+            let (heap_space_ptr, data_ptr) = Memory::<HEAP_SIZE>::new();
+            let mut allocator = BuddyAllocator::<5>::new(PAGE_SIZE, 0);
+            allocator.init(data_ptr, HEAP_SIZE);
+
+            let page = Layout::from_size_align(PAGE_SIZE, PAGE_SIZE).unwrap();
+            let contiguous = Layout::from_size_align(4 * PAGE_SIZE, PAGE_SIZE).unwrap();
+            let single = allocate(&mut allocator, page).unwrap();
+            let multiple = allocate(&mut allocator, contiguous).unwrap();
+            assert_eq!(0, single.as_mut_u32() as usize % PAGE_SIZE);
+            assert_eq!(0, multiple.as_mut_u32() as usize % (4 * PAGE_SIZE));
+            assert_eq!(5 * PAGE_SIZE, allocator.used());
+
+            single.free(&mut allocator);
+            multiple.free(&mut allocator);
+            assert_eq!(0, allocator.used());
+
+            Memory::free(heap_space_ptr);
+        }
+    }
+}
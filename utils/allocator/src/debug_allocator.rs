@@ -0,0 +1,473 @@
+use core::{
+    alloc::Layout,
+    mem::{align_of, size_of},
+    ptr::NonNull,
+};
+
+use alloc::alloc::AllocError;
+
+use crate::Allocator;
+
+/// Bytes of canaries before and after every allocation
+const REDZONE_SIZE: usize = 16;
+const CANARY: u8 = 0xfd;
+/// Fresh allocations are filled with this, so reads of uninitialized memory
+/// stand out
+const ALLOC_POISON: u8 = 0xcd;
+/// Freed allocations are filled with this, so use after free stands out
+const FREE_POISON: u8 = 0xdd;
+const MAGIC_ALLOCATED: u64 = 0x4845_4150_4c49_5645;
+const MAGIC_FREED: u64 = 0x4845_4150_4652_4545;
+/// Freed blocks held back from the inner allocator
+const QUARANTINE_SIZE: usize = 16;
+
+/// Returns the tag recorded with a new allocation, e.g. its call site.
+pub type TagFn = fn() -> &'static str;
+
+/// Called with the allocator locked when heap corruption is found, it must
+/// not allocate. The allocator's state can't be trusted afterwards.
+pub type ReportFn = fn(error: HeapError);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeapError {
+    /// The allocation was freed already
+    DoubleFree { ptr: usize },
+    /// The pointer was never returned by the allocator or its header was
+    /// overwritten
+    InvalidPointer { ptr: usize },
+    /// Freed with a layout different from the allocation's
+    LayoutMismatch {
+        ptr: usize,
+        size: usize,
+        align: usize,
+        tag: &'static str,
+    },
+    /// Something wrote before or after the allocation
+    RedzoneOverwritten { ptr: usize, tag: &'static str },
+}
+
+/// An allocation which wasn't freed yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LiveAllocation {
+    pub ptr: usize,
+    pub size: usize,
+    pub align: usize,
+    pub tag: &'static str,
+    /// Counts up with every allocation
+    pub id: u64,
+}
+
+/// Placed before the front redzone. The magic comes last, allocators write
+/// their own bookkeeping at the start of freed blocks.
+#[repr(C)]
+struct Header {
+    prev: *mut Header,
+    next: *mut Header,
+    size: usize,
+    align: usize,
+    tag: &'static str,
+    id: u64,
+    magic: u64,
+}
+
+/// Wraps an allocator to find heap bugs: allocations are surrounded by
+/// redzones with canaries, fresh and freed memory is poisoned, and frees
+/// are checked for double frees and layouts which don't match the
+/// allocation. Live allocations are kept in a list with their tags.
+///
+/// Freed blocks stay in a quarantine for the next [`QUARANTINE_SIZE`] frees
+/// before the inner allocator may reuse them, so a double free within that
+/// window is always found. Later ones are only found while the block wasn't
+/// handed out again, afterwards the block looks like a live allocation.
+/// Quarantined blocks count as used until [`Allocator::reclaim`].
+pub struct DebugAllocator<T: Allocator> {
+    inner: T,
+    tag: Option<TagFn>,
+    report: ReportFn,
+    /// Most recent allocation
+    live: *mut Header,
+    live_count: usize,
+    next_id: u64,
+    /// Freed blocks with their inner layouts, oldest at `quarantine_next`
+    quarantine: [Option<(NonNull<u8>, Layout)>; QUARANTINE_SIZE],
+    quarantine_next: usize,
+}
+
+// the raw pointers only point into the heap owned by the inner allocator
+unsafe impl<T: Allocator + Send> Send for DebugAllocator<T> {}
+
+impl<T: Allocator> DebugAllocator<T> {
+    pub const fn new(inner: T, tag: Option<TagFn>, report: ReportFn) -> Self {
+        Self {
+            inner,
+            tag,
+            report,
+            live: core::ptr::null_mut(),
+            live_count: 0,
+            next_id: 0,
+            quarantine: [None; QUARANTINE_SIZE],
+            quarantine_next: 0,
+        }
+    }
+
+    pub fn live_count(&self) -> usize {
+        self.live_count
+    }
+
+    /// The allocations which weren't freed yet, most recent first.
+    pub fn live_allocations(&self) -> impl Iterator<Item = LiveAllocation> + '_ {
+        let mut header = self.live;
+        core::iter::from_fn(move || {
+            let ptr = user_ptr(header) as usize;
+            let current = unsafe { header.as_ref()? };
+            header = current.next;
+            Some(LiveAllocation {
+                ptr,
+                size: current.size,
+                align: current.align,
+                tag: current.tag,
+                id: current.id,
+            })
+        })
+    }
+
+    /// Checks the redzones of every live allocation.
+    pub fn check(&self) -> Result<(), HeapError> {
+        let mut header = self.live;
+        while let Some(current) = unsafe { header.as_ref() } {
+            check_redzones(header)?;
+            header = current.next;
+        }
+        Ok(())
+    }
+
+    /// Offset of the user data in the inner allocation.
+    fn data_offset(align: usize) -> usize {
+        (size_of::<Header>() + REDZONE_SIZE).next_multiple_of(align)
+    }
+
+    fn inner_layout(layout: &Layout) -> Option<Layout> {
+        let size = Self::data_offset(layout.align())
+            .checked_add(layout.size())?
+            .checked_add(REDZONE_SIZE)?;
+        Layout::from_size_align(size, layout.align().max(align_of::<Header>())).ok()
+    }
+
+    /// Checks `ptr` is a live allocation with `layout`, returns its header.
+    fn validate(&self, ptr: NonNull<u8>, layout: &Layout) -> Result<*mut Header, HeapError> {
+        let address = ptr.as_ptr() as usize;
+        if !address.is_multiple_of(align_of::<Header>()) {
+            return Err(HeapError::InvalidPointer { ptr: address });
+        }
+        let header = header_of(ptr.as_ptr());
+        let current = unsafe { &*header };
+        match current.magic {
+            MAGIC_ALLOCATED => {}
+            MAGIC_FREED => return Err(HeapError::DoubleFree { ptr: address }),
+            _ => return Err(HeapError::InvalidPointer { ptr: address }),
+        }
+        if current.size != layout.size() || current.align != layout.align() {
+            return Err(HeapError::LayoutMismatch {
+                ptr: address,
+                size: current.size,
+                align: current.align,
+                tag: current.tag,
+            });
+        }
+        check_redzones(header)?;
+        Ok(header)
+    }
+
+    fn unlink(&mut self, header: *mut Header) {
+        unsafe {
+            let Header { prev, next, .. } = *header;
+            if prev.is_null() {
+                self.live = next;
+            } else {
+                (*prev).next = next;
+            }
+            if !next.is_null() {
+                (*next).prev = prev;
+            }
+        }
+        self.live_count -= 1;
+    }
+}
+
+impl<T: Allocator> Allocator for DebugAllocator<T> {
+    unsafe fn init(&mut self, data_ptr: *mut u8, heap_size: usize) {
+        unsafe { self.inner.init(data_ptr, heap_size) }
+    }
+
+    unsafe fn extend(&mut self, size: usize) -> Result<(), AllocError> {
+        unsafe { self.inner.extend(size) }
+    }
+
+    fn alloc(&mut self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let inner_layout = Self::inner_layout(&layout).ok_or(AllocError)?;
+        let block = self.inner.alloc(inner_layout)?.as_ptr() as *mut u8;
+        let data = unsafe { block.add(Self::data_offset(layout.align())) };
+        let header = header_of(data);
+        unsafe {
+            header.write(Header {
+                prev: core::ptr::null_mut(),
+                next: self.live,
+                size: layout.size(),
+                align: layout.align(),
+                tag: self.tag.map_or("", |tag| tag()),
+                id: self.next_id,
+                magic: MAGIC_ALLOCATED,
+            });
+            if let Some(next) = self.live.as_mut() {
+                next.prev = header;
+            }
+            data.sub(REDZONE_SIZE).write_bytes(CANARY, REDZONE_SIZE);
+            data.write_bytes(ALLOC_POISON, layout.size());
+            data.add(layout.size()).write_bytes(CANARY, REDZONE_SIZE);
+        }
+        self.live = header;
+        self.live_count += 1;
+        self.next_id += 1;
+        let ptr = unsafe { NonNull::new_unchecked(data) };
+        Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+    }
+
+    fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        let header = match self.validate(ptr, &layout) {
+            Ok(header) => header,
+            Err(err) => {
+                (self.report)(err);
+                // leaking is safer than freeing memory in an unknown state
+                return;
+            }
+        };
+        self.unlink(header);
+        let data = ptr.as_ptr();
+        unsafe {
+            (*header).magic = MAGIC_FREED;
+            data.write_bytes(FREE_POISON, layout.size());
+            let block = data.sub(Self::data_offset(layout.align()));
+            let inner_layout = Self::inner_layout(&layout).unwrap();
+            let slot = &mut self.quarantine[self.quarantine_next];
+            if let Some((oldest, oldest_layout)) =
+                slot.replace((NonNull::new_unchecked(block), inner_layout))
+            {
+                self.inner.dealloc(oldest, oldest_layout);
+            }
+        }
+        self.quarantine_next = (self.quarantine_next + 1) % QUARANTINE_SIZE;
+    }
+
+    fn reclaim(&mut self) -> usize {
+        let mut released = 0;
+        for (block, layout) in self.quarantine.iter_mut().filter_map(Option::take) {
+            self.inner.dealloc(block, layout);
+            released += layout.size();
+        }
+        released + self.inner.reclaim()
+    }
+
+    fn used(&self) -> usize {
+        self.inner.used()
+    }
+
+    fn free(&self) -> usize {
+        self.inner.free()
+    }
+
+    fn largest_free(&mut self) -> usize {
+        self.inner
+            .largest_free()
+            .saturating_sub(Self::data_offset(1) + REDZONE_SIZE)
+    }
+}
+
+fn header_of(data: *mut u8) -> *mut Header {
+    data.wrapping_sub(REDZONE_SIZE + size_of::<Header>()) as *mut Header
+}
+
+fn user_ptr(header: *mut Header) -> *mut u8 {
+    (header as *mut u8).wrapping_add(size_of::<Header>() + REDZONE_SIZE)
+}
+
+fn check_redzones(header: *mut Header) -> Result<(), HeapError> {
+    let data = user_ptr(header);
+    let (size, tag) = unsafe { ((*header).size, (*header).tag) };
+    let intact = |start: *const u8| {
+        unsafe { core::slice::from_raw_parts(start, REDZONE_SIZE) }
+            .iter()
+            .all(|&byte| byte == CANARY)
+    };
+    if intact(data.wrapping_sub(REDZONE_SIZE)) && intact(data.wrapping_add(size)) {
+        Ok(())
+    } else {
+        Err(HeapError::RedzoneOverwritten {
+            ptr: data as usize,
+            tag,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use core::alloc::Layout;
+    use std::sync::Mutex;
+
+    use crate::{
+        LinkedListAllocator,
+        tests::{Memory, allocate},
+    };
+
+    use super::*;
+
+    const HEAP_SIZE: usize = 4096;
+
+    /// Errors reported by the tests, they run in parallel so each one
+    /// filters for its own pointers.
+    static REPORTED: Mutex<std::vec::Vec<HeapError>> = Mutex::new(std::vec::Vec::new());
+
+    fn test_tag() -> &'static str {
+        "test"
+    }
+
+    fn test_report(error: HeapError) {
+        REPORTED.lock().unwrap().push(error);
+    }
+
+    fn reported(ptr: usize) -> std::vec::Vec<HeapError> {
+        REPORTED
+            .lock()
+            .unwrap()
+            .iter()
+            .copied()
+            .filter(|error| match *error {
+                HeapError::DoubleFree { ptr: p }
+                | HeapError::InvalidPointer { ptr: p }
+                | HeapError::LayoutMismatch { ptr: p, .. }
+                | HeapError::RedzoneOverwritten { ptr: p, .. } => p == ptr,
+            })
+            .collect()
+    }
+
+    fn new_allocator() -> DebugAllocator<LinkedListAllocator> {
+        DebugAllocator::new(LinkedListAllocator::new(), Some(test_tag), test_report)
+    }
+
+    #[test]
+    pub fn test_poison_and_live_allocations() {
+        unsafe {
+            let (heap_space_ptr, data_ptr) = Memory::<HEAP_SIZE>::new();
+            let mut allocator = new_allocator();
+            allocator.init(data_ptr, HEAP_SIZE);
+
+            let layout = Layout::from_size_align(24, 8).unwrap();
+            let first = allocate(&mut allocator, layout).unwrap();
+            let aligned = Layout::from_size_align(8, 64).unwrap();
+            let second = allocate(&mut allocator, aligned).unwrap();
+            assert_eq!(0, second.as_mut_u32() as usize % 64);
+            assert_eq!(0xcdcdcdcd, *first.as_mut_u32());
+
+            let live: std::vec::Vec<_> = allocator.live_allocations().collect();
+            assert_eq!(2, live.len());
+            assert_eq!(second.as_mut_u32() as usize, live[0].ptr);
+            assert_eq!(8, live[0].size);
+            assert_eq!(64, live[0].align);
+            assert_eq!("test", live[0].tag);
+            assert_eq!(first.as_mut_u32() as usize, live[1].ptr);
+            assert_eq!(Ok(()), allocator.check());
+
+            let first_ptr = first.as_mut_u32();
+            first.free(&mut allocator);
+            assert_eq!(0xdddddddd, *first_ptr.add(4));
+            assert_eq!(1, allocator.live_count());
+            second.free(&mut allocator);
+            assert_eq!(0, allocator.live_count());
+            assert!(allocator.reclaim() > 0);
+            assert_eq!(0, allocator.used());
+
+            Memory::free(heap_space_ptr);
+        }
+    }
+
+    #[test]
+    pub fn test_detects_errors() {
+        unsafe {
+            let (heap_space_ptr, data_ptr) = Memory::<HEAP_SIZE>::new();
+            let mut allocator = new_allocator();
+            allocator.init(data_ptr, HEAP_SIZE);
+
+            let layout = Layout::from_size_align(16, 8).unwrap();
+            let overflowed = allocate(&mut allocator, layout).unwrap();
+            let ptr = overflowed.0.as_ptr() as *mut u8;
+            ptr.add(16).write(0);
+            assert_eq!(
+                Err(HeapError::RedzoneOverwritten {
+                    ptr: ptr as usize,
+                    tag: "test"
+                }),
+                allocator.check()
+            );
+            allocator.dealloc(NonNull::new_unchecked(ptr), layout);
+            assert_eq!(1, allocator.live_count());
+            assert!(matches!(
+                reported(ptr as usize)[..],
+                [HeapError::RedzoneOverwritten { .. }]
+            ));
+
+            let mismatched = allocate(&mut allocator, layout).unwrap();
+            let ptr = mismatched.0.as_ptr() as *mut u8;
+            let wrong = Layout::from_size_align(32, 8).unwrap();
+            allocator.dealloc(NonNull::new_unchecked(ptr), wrong);
+            allocator.dealloc(NonNull::new_unchecked(ptr), layout);
+            allocator.dealloc(NonNull::new_unchecked(ptr), layout);
+            assert!(matches!(
+                reported(ptr as usize)[..],
+                [
+                    HeapError::LayoutMismatch {
+                        size: 16,
+                        align: 8,
+                        ..
+                    },
+                    HeapError::DoubleFree { .. }
+                ]
+            ));
+
+            Memory::free(heap_space_ptr);
+        }
+    }
+
+    #[test]
+    pub fn test_double_free_after_reuse() {
+        unsafe {
+            let (heap_space_ptr, data_ptr) = Memory::<HEAP_SIZE>::new();
+            let mut allocator = new_allocator();
+            allocator.init(data_ptr, HEAP_SIZE);
+
+            let layout = Layout::from_size_align(16, 8).unwrap();
+            let freed = allocate(&mut allocator, layout).unwrap();
+            let ptr = freed.0.as_ptr() as *mut u8;
+            freed.free(&mut allocator);
+            // the quarantine keeps the block from being handed out again
+            let others: std::vec::Vec<_> = (0..QUARANTINE_SIZE - 1)
+                .map(|_| {
+                    let other = allocate(&mut allocator, layout).unwrap();
+                    assert_ne!(ptr, other.0.as_ptr() as *mut u8);
+                    other
+                })
+                .collect();
+            allocator.dealloc(NonNull::new_unchecked(ptr), layout);
+            // other tests' heaps may have used the address before
+            let double_free = HeapError::DoubleFree { ptr: ptr as usize };
+            assert!(reported(ptr as usize).contains(&double_free));
+
+            for other in others {
+                other.free(&mut allocator);
+            }
+            allocator.reclaim();
+            assert_eq!(0, allocator.used());
+            Memory::free(heap_space_ptr);
+        }
+    }
+}
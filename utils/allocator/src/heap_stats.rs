@@ -0,0 +1,50 @@
+/// Number of buckets in [`HeapStats::size_histogram`].
+pub const SIZE_BUCKETS: usize = 12;
+
+/// Index of the [`HeapStats::size_histogram`] bucket counting allocations
+/// of `size` bytes.
+pub fn size_bucket(size: usize) -> usize {
+    let bucket = size.max(1).div_ceil(8).next_power_of_two().trailing_zeros() as usize;
+    bucket.min(SIZE_BUCKETS - 1)
+}
+
+/// Largest allocation size counted in `bucket`, `None` for the last bucket
+/// which counts everything larger.
+pub fn bucket_size(bucket: usize) -> Option<usize> {
+    (bucket + 1 < SIZE_BUCKETS).then(|| 8 << bucket)
+}
+
+/// Statistics of a [`crate::LockedAllocator`], see
+/// [`crate::LockedAllocator::stats`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeapStats {
+    pub used: usize,
+    pub free: usize,
+    /// Most bytes used at any time
+    pub peak_used: usize,
+    /// Largest allocation which would currently succeed without growing the
+    /// heap
+    pub largest_free: usize,
+    pub allocations: usize,
+    pub deallocations: usize,
+    /// Allocations which failed even after growing the heap
+    pub failed_allocations: usize,
+    /// Allocations made so far by size, bucket `i` counts sizes up to
+    /// `8 << i` bytes, see [`bucket_size`].
+    pub size_histogram: [usize; SIZE_BUCKETS],
+}
+
+impl HeapStats {
+    pub fn live_allocations(&self) -> usize {
+        self.allocations - self.deallocations
+    }
+
+    /// Share of the free bytes which can't be handed out as one allocation,
+    /// 0 when all free memory is one block.
+    pub fn fragmentation_percent(&self) -> usize {
+        match self.free {
+            0 => 0,
+            free => 100 - self.largest_free.min(free) * 100 / free,
+        }
+    }
+}
@@ -4,15 +4,21 @@
 
 extern crate alloc;
 
+mod buddy_allocator;
+mod debug_allocator;
+mod heap_stats;
 mod linked_list_allocator;
 mod locked_allocator;
 mod slab_allocator;
 
 use core::{alloc::Layout, ptr::NonNull};
 
+pub use buddy_allocator::BuddyAllocator;
+pub use debug_allocator::{DebugAllocator, HeapError, LiveAllocation, ReportFn, TagFn};
+pub use heap_stats::{HeapStats, SIZE_BUCKETS, bucket_size, size_bucket};
 pub use linked_list_allocator::LinkedListAllocator;
-pub use locked_allocator::LockedAllocator;
-pub use slab_allocator::SlabAllocator;
+pub use locked_allocator::{GrowFn, LockedAllocator, OutOfMemoryFn};
+pub use slab_allocator::{SIZE_CLASS_COUNT, SIZE_CLASSES, SlabAllocator, SlabStats};
 
 pub trait Allocator {
     /// # Safety
@@ -21,6 +27,14 @@ pub trait Allocator {
     /// to perform the I/O operation.
     unsafe fn init(&mut self, data_ptr: *mut u8, heap_size: usize);
 
+    /// Grows the heap by `size` bytes at its end. Fails if the heap can't
+    /// grow that far, the memory that still fits is used anyway.
+    ///
+    /// # Safety
+    /// The memory directly after the current end of the heap must be valid
+    /// and unused for `size` bytes.
+    unsafe fn extend(&mut self, size: usize) -> Result<(), alloc::alloc::AllocError>;
+
     fn alloc(
         &mut self,
         layout: Layout,
@@ -28,12 +42,33 @@ pub trait Allocator {
 
     fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout);
 
+    /// Returns memory kept for reuse, e.g. free slab blocks, to the heap and
+    /// returns how many bytes that were.
+    fn reclaim(&mut self) -> usize {
+        0
+    }
+
     fn used(&self) -> usize;
     fn free(&self) -> usize;
-}
 
-pub(crate) fn is_power_of_two(n: usize) -> bool {
-    n > 0 && (n & (n - 1)) == 0
+    /// Largest allocation which would currently succeed. The default finds
+    /// it by trying allocations, allocators which know it override this.
+    fn largest_free(&mut self) -> usize {
+        // lo always fits, hi never does
+        let (mut lo, mut hi) = (0, self.free() + 1);
+        while hi - lo > 1 {
+            let size = lo + (hi - lo) / 2;
+            let layout = Layout::from_size_align(size, 1).unwrap();
+            match self.alloc(layout) {
+                Ok(ptr) => {
+                    self.dealloc(ptr.cast(), layout);
+                    lo = size;
+                }
+                Err(_) => hi = size,
+            }
+        }
+        lo
+    }
 }
 
 #[cfg(test)]
@@ -44,7 +79,7 @@ mod tests {
 
     use crate::Allocator;
 
-    #[repr(align(128))]
+    #[repr(align(4096))]
     pub struct Memory<const N: usize> {
         data: MaybeUninit<[u8; N]>,
     }
@@ -22,6 +22,11 @@ impl Allocator for LinkedListAllocator {
         unsafe { self.heap.init(data_ptr, heap_size) }
     }
 
+    unsafe fn extend(&mut self, size: usize) -> Result<(), AllocError> {
+        unsafe { self.heap.extend(size) };
+        Ok(())
+    }
+
     fn alloc(&mut self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
         self.heap
             .allocate_first_fit(layout)
@@ -3,22 +3,92 @@ extern crate alloc;
 use core::{
     alloc::{GlobalAlloc, Layout},
     ptr::NonNull,
+    sync::atomic::{AtomicUsize, Ordering},
 };
 
-use crate::Allocator;
+use crate::{Allocator, HeapStats, SIZE_BUCKETS, size_bucket};
 use alloc::alloc::AllocError;
 use core::ptr::null_mut;
-use spin::Mutex;
+use irq_sync::{IrqSpinLock, LockLevel};
+
+/// Makes at least `min_size` more bytes available directly after the end of
+/// the heap and returns how many bytes were added, 0 if the heap can't grow.
+/// Called with the allocator locked, so it must not allocate.
+pub type GrowFn = fn(min_size: usize) -> usize;
+
+/// Called after the lock is released when an allocation fails, with the
+/// failed layout and the heap's used and free bytes.
+pub type OutOfMemoryFn = fn(layout: Layout, used: usize, free: usize);
 
 pub struct LockedAllocator<T: Allocator> {
-    inner: Mutex<T>,
+    inner: IrqSpinLock<T>,
+    grow: Option<GrowFn>,
+    out_of_memory: Option<OutOfMemoryFn>,
+    counters: Counters,
+}
+
+/// Counts for [`HeapStats`], only changed with the allocator locked.
+struct Counters {
+    peak_used: AtomicUsize,
+    allocations: AtomicUsize,
+    deallocations: AtomicUsize,
+    failed_allocations: AtomicUsize,
+    size_histogram: [AtomicUsize; SIZE_BUCKETS],
+}
+
+impl Counters {
+    const fn new() -> Self {
+        Self {
+            peak_used: AtomicUsize::new(0),
+            allocations: AtomicUsize::new(0),
+            deallocations: AtomicUsize::new(0),
+            failed_allocations: AtomicUsize::new(0),
+            size_histogram: [const { AtomicUsize::new(0) }; SIZE_BUCKETS],
+        }
+    }
+
+    fn allocated(&self, layout: &Layout, used: usize) {
+        self.allocations.fetch_add(1, Ordering::Relaxed);
+        self.size_histogram[size_bucket(layout.size())].fetch_add(1, Ordering::Relaxed);
+        self.peak_used.fetch_max(used, Ordering::Relaxed);
+    }
 }
 
 impl<T: Allocator> LockedAllocator<T> {
     #[allow(clippy::new_without_default)]
     pub const fn new(allocator: T) -> Self {
         Self {
-            inner: Mutex::new(allocator),
+            inner: IrqSpinLock::new(allocator),
+            grow: None,
+            out_of_memory: None,
+            counters: Counters::new(),
+        }
+    }
+
+    /// Creates an allocator whose lock takes part in the lock order checks.
+    pub const fn with_level(allocator: T, level: LockLevel) -> Self {
+        Self {
+            inner: IrqSpinLock::with_level(allocator, level),
+            grow: None,
+            out_of_memory: None,
+            counters: Counters::new(),
+        }
+    }
+
+    /// Creates an allocator which grows the heap with `grow` when an
+    /// allocation doesn't fit and reports allocations which still fail to
+    /// `out_of_memory` before they return null.
+    pub const fn growable(
+        allocator: T,
+        level: LockLevel,
+        grow: GrowFn,
+        out_of_memory: OutOfMemoryFn,
+    ) -> Self {
+        Self {
+            inner: IrqSpinLock::with_level(allocator, level),
+            grow: Some(grow),
+            out_of_memory: Some(out_of_memory),
+            counters: Counters::new(),
         }
     }
 
@@ -29,11 +99,82 @@ impl<T: Allocator> LockedAllocator<T> {
     pub unsafe fn init(&self, data_ptr: *mut u8, heap_size: usize) {
         unsafe { self.inner.lock().init(data_ptr, heap_size) }
     }
+
+    /// Runs `f` with the allocator locked, it must not allocate.
+    pub fn inspect<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        f(&self.inner.lock())
+    }
+
+    /// Counts, usage and fragmentation of the heap. Finding the largest free
+    /// block may try allocations, so this holds the lock for a while.
+    pub fn stats(&self) -> HeapStats {
+        let mut inner = self.inner.lock();
+        let counters = &self.counters;
+        HeapStats {
+            used: inner.used(),
+            free: inner.free(),
+            peak_used: counters.peak_used.load(Ordering::Relaxed),
+            largest_free: inner.largest_free(),
+            allocations: counters.allocations.load(Ordering::Relaxed),
+            deallocations: counters.deallocations.load(Ordering::Relaxed),
+            failed_allocations: counters.failed_allocations.load(Ordering::Relaxed),
+            size_histogram: core::array::from_fn(|bucket| {
+                counters.size_histogram[bucket].load(Ordering::Relaxed)
+            }),
+        }
+    }
+
+    /// Releases the lock, e.g. to report heap corruption found while it was
+    /// held.
+    ///
+    /// # Safety
+    /// The holder must never touch the allocator again.
+    pub unsafe fn force_unlock(&self) {
+        unsafe { self.inner.force_unlock() }
+    }
+
+    fn alloc_or_grow(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let mut inner = self.inner.lock();
+        loop {
+            let err = match inner.alloc(layout) {
+                Ok(p) => {
+                    self.counters.allocated(&layout, inner.used());
+                    return Ok(p);
+                }
+                Err(err) => err,
+            };
+            // empty slab chunks may be enough before the heap has to grow
+            if inner.reclaim() > 0 {
+                continue;
+            }
+            // the padding needed for the alignment has to fit as well
+            let grown = self
+                .grow
+                .map_or(0, |grow| grow(layout.size() + layout.align()));
+            if grown == 0 || unsafe { inner.extend(grown) }.is_err() {
+                let (used, free) = (inner.used(), inner.free());
+                self.counters
+                    .failed_allocations
+                    .fetch_add(1, Ordering::Relaxed);
+                drop(inner);
+                if let Some(out_of_memory) = self.out_of_memory {
+                    out_of_memory(layout, used, free);
+                }
+                return Err(err);
+            }
+        }
+    }
+
+    fn dealloc_counted(&self, ptr: NonNull<u8>, layout: Layout) {
+        let mut inner = self.inner.lock();
+        inner.dealloc(ptr, layout);
+        self.counters.deallocations.fetch_add(1, Ordering::Relaxed);
+    }
 }
 
 unsafe impl<T: Allocator> GlobalAlloc for LockedAllocator<T> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        match self.inner.lock().alloc(layout) {
+        match self.alloc_or_grow(layout) {
             Ok(p) => p.as_ptr() as *mut u8,
             Err(_) => null_mut(),
         }
@@ -41,16 +182,157 @@ unsafe impl<T: Allocator> GlobalAlloc for LockedAllocator<T> {
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
         let ptr = NonNull::new(ptr).unwrap();
-        self.inner.lock().dealloc(ptr, layout)
+        self.dealloc_counted(ptr, layout)
     }
 }
 
 unsafe impl<T: Allocator> core::alloc::Allocator for LockedAllocator<T> {
     fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
-        self.inner.lock().alloc(layout)
+        self.alloc_or_grow(layout)
     }
 
     unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
-        self.inner.lock().dealloc(ptr, layout)
+        self.dealloc_counted(ptr, layout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::{
+        alloc::Layout,
+        sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    };
+
+    use crate::{LinkedListAllocator, SlabAllocator, tests::Memory};
+
+    use super::*;
+
+    const MEMORY_SIZE: usize = 8192;
+    const INITIAL_SIZE: usize = 1024;
+    const GROW_SIZE: usize = 1024;
+
+    static GROWN: AtomicUsize = AtomicUsize::new(0);
+    static OUT_OF_MEMORY: AtomicBool = AtomicBool::new(false);
+
+    fn test_grow(min_size: usize) -> usize {
+        let grown = GROWN.load(Ordering::Relaxed);
+        let size = GROW_SIZE
+            .max(min_size)
+            .min(MEMORY_SIZE - INITIAL_SIZE - grown);
+        GROWN.store(grown + size, Ordering::Relaxed);
+        size
+    }
+
+    fn test_out_of_memory(_layout: Layout, used: usize, free: usize) {
+        assert_eq!(MEMORY_SIZE, used + free);
+        OUT_OF_MEMORY.store(true, Ordering::Relaxed);
+    }
+
+    #[test]
+    pub fn test_grow_until_out_of_memory() {
+        unsafe {
+            let (heap_space_ptr, data_ptr) = Memory::<MEMORY_SIZE>::new();
+            let allocator = LockedAllocator::growable(
+                LinkedListAllocator::new(),
+                LockLevel::new(1),
+                test_grow,
+                test_out_of_memory,
+            );
+            allocator.init(data_ptr, INITIAL_SIZE);
+
+            let layout = Layout::from_size_align(2048, 8).unwrap();
+            let first = allocator.alloc(layout);
+            assert!(!first.is_null());
+            assert!(GROWN.load(Ordering::Relaxed) > 0);
+            assert!(!OUT_OF_MEMORY.load(Ordering::Relaxed));
+
+            let too_large = Layout::from_size_align(MEMORY_SIZE, 8).unwrap();
+            assert!(allocator.alloc(too_large).is_null());
+            assert_eq!(MEMORY_SIZE - INITIAL_SIZE, GROWN.load(Ordering::Relaxed));
+            assert!(OUT_OF_MEMORY.load(Ordering::Relaxed));
+
+            // the grown memory stays usable
+            let second = allocator.alloc(layout);
+            assert!(!second.is_null());
+
+            allocator.dealloc(first, layout);
+            allocator.dealloc(second, layout);
+            Memory::free(heap_space_ptr);
+        }
+    }
+
+    #[test]
+    pub fn test_reclaim_before_out_of_memory() {
+        unsafe {
+            const PAGE_SIZE: usize = 4096;
+            const HEAP_SIZE: usize = 2 * PAGE_SIZE;
+            let (heap_space_ptr, data_ptr) = Memory::<HEAP_SIZE>::new();
+            let allocator = LockedAllocator::new(SlabAllocator::with_size_classes(
+                LinkedListAllocator::new(),
+                PAGE_SIZE,
+            ));
+            allocator.init(data_ptr, HEAP_SIZE);
+
+            // the small block's chunk takes half of the heap until reclaimed
+            let small = Layout::from_size_align(8, 8).unwrap();
+            let ptr = allocator.alloc(small);
+            allocator.dealloc(ptr, small);
+            let large = Layout::from_size_align(HEAP_SIZE, 8).unwrap();
+            let ptr = allocator.alloc(large);
+            assert!(!ptr.is_null());
+            assert_eq!(0, allocator.inspect(|inner| inner.slab_stats()[0].blocks));
+
+            allocator.dealloc(ptr, large);
+            Memory::free(heap_space_ptr);
+        }
+    }
+
+    #[test]
+    pub fn test_stats() {
+        unsafe {
+            const HEAP_SIZE: usize = 4096;
+            let (heap_space_ptr, data_ptr) = Memory::<HEAP_SIZE>::new();
+            let allocator =
+                LockedAllocator::with_level(LinkedListAllocator::new(), LockLevel::new(1));
+            allocator.init(data_ptr, HEAP_SIZE);
+
+            let small = Layout::from_size_align(8, 8).unwrap();
+            let large = Layout::from_size_align(1024, 8).unwrap();
+            let first = allocator.alloc(large);
+            let second = allocator.alloc(small);
+            let third = allocator.alloc(large);
+            assert!(
+                allocator
+                    .alloc(Layout::from_size_align(HEAP_SIZE, 8).unwrap())
+                    .is_null()
+            );
+            let peak = allocator.stats().used;
+            allocator.dealloc(first, large);
+
+            let stats = allocator.stats();
+            assert_eq!(3, stats.allocations);
+            assert_eq!(1, stats.deallocations);
+            assert_eq!(2, stats.live_allocations());
+            assert_eq!(1, stats.failed_allocations);
+            assert_eq!(peak, stats.peak_used);
+            assert_eq!(HEAP_SIZE, stats.used + stats.free);
+            assert_eq!(1, stats.size_histogram[size_bucket(8)]);
+            assert_eq!(2, stats.size_histogram[size_bucket(1024)]);
+            assert_eq!(Some(1024), crate::bucket_size(size_bucket(1024)));
+
+            // the freed block and the end of the heap are apart
+            assert!(stats.largest_free < stats.free);
+            assert!(stats.fragmentation_percent() > 0);
+            let fits = Layout::from_size_align(stats.largest_free, 1).unwrap();
+            let ptr = allocator.alloc(fits);
+            assert!(!ptr.is_null());
+            allocator.dealloc(ptr, fits);
+            let too_large = Layout::from_size_align(stats.largest_free + 1, 1).unwrap();
+            assert!(allocator.alloc(too_large).is_null());
+
+            allocator.dealloc(second, small);
+            allocator.dealloc(third, large);
+            Memory::free(heap_space_ptr);
+        }
     }
 }
@@ -2,50 +2,99 @@ use core::{alloc::Layout, ptr::NonNull};
 
 use alloc::alloc::AllocError;
 
-use crate::{Allocator, is_power_of_two};
+use crate::Allocator;
 
 struct BlockNode {
     next: Option<&'static mut BlockNode>,
 }
 
+impl BlockNode {
+    fn address(&self) -> usize {
+        self as *const Self as usize
+    }
+}
+
 type SlabSelectorFn = fn(&Layout) -> Option<usize>;
-type BlockSizeFn = fn(usize) -> usize;
+
+/// Number of [`SIZE_CLASSES`].
+pub const SIZE_CLASS_COUNT: usize = 10;
+/// Block sizes of [`SlabAllocator::with_size_classes`], larger allocations
+/// go to the fallback allocator.
+pub const SIZE_CLASSES: [usize; SIZE_CLASS_COUNT] =
+    [8, 16, 32, 64, 128, 256, 512, 1024, 2048, 4096];
+
+fn size_class_selector(layout: &Layout) -> Option<usize> {
+    let required_block_size = layout.size().max(layout.align());
+    SIZE_CLASSES.iter().position(|&s| s >= required_block_size)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlabStats {
+    pub block_size: usize,
+    /// Blocks taken from the fallback allocator and not reclaimed since
+    pub blocks: usize,
+    /// Blocks ready to be handed out
+    pub free_blocks: usize,
+}
 
 pub struct SlabAllocator<const SLAB_COUNT: usize, TFallback: Allocator> {
-    block_size_fn: BlockSizeFn,
+    block_sizes: [usize; SLAB_COUNT],
     slab_selector_fn: SlabSelectorFn,
     slabs: [Option<&'static mut BlockNode>; SLAB_COUNT],
+    blocks: [usize; SLAB_COUNT],
+    free_blocks: [usize; SLAB_COUNT],
     fallback_allocator: TFallback,
     page_size: usize,
 }
 
 /// The block sizes must each be power of 2 because they are also used as
-/// the block alignment (alignments must be always powers of 2).
+/// the block alignment (alignments must be always powers of 2). Slabs are
+/// refilled a chunk at a time, chunks are aligned to their size so the chunk
+/// of a block can be found from its address.
 impl<const SLAB_COUNT: usize, TFallback: Allocator> SlabAllocator<SLAB_COUNT, TFallback> {
-    pub fn new(
-        block_size_fn: BlockSizeFn,
+    /// # Panics
+    /// Panics, at compile time when used in a constant, if a block size or
+    /// the page size isn't a power of 2 or a block can't hold a free list
+    /// node.
+    pub const fn new(
+        block_sizes: [usize; SLAB_COUNT],
         slab_selector_fn: SlabSelectorFn,
         fallback_allocator: TFallback,
         page_size: usize,
     ) -> Self {
-        for i in 0..SLAB_COUNT {
-            let block_size = block_size_fn(i);
-            if !is_power_of_two(block_size) {
-                panic!("block size must be power of 2");
-            }
+        assert!(page_size.is_power_of_two(), "page size must be power of 2");
+        let mut i = 0;
+        while i < SLAB_COUNT {
+            let block_size = block_sizes[i];
+            assert!(
+                block_size.is_power_of_two(),
+                "block size must be power of 2"
+            );
             assert!(core::mem::size_of::<BlockNode>() <= block_size);
             assert!(core::mem::align_of::<BlockNode>() <= block_size);
+            i += 1;
         }
         const EMPTY: Option<&'static mut BlockNode> = None;
         Self {
-            block_size_fn,
+            block_sizes,
             slab_selector_fn,
             slabs: [EMPTY; SLAB_COUNT],
+            blocks: [0; SLAB_COUNT],
+            free_blocks: [0; SLAB_COUNT],
             fallback_allocator,
             page_size,
         }
     }
 
+    /// Usage of every slab, in slab order.
+    pub fn slab_stats(&self) -> [SlabStats; SLAB_COUNT] {
+        core::array::from_fn(|i| SlabStats {
+            block_size: self.block_sizes[i],
+            blocks: self.blocks[i],
+            free_blocks: self.free_blocks[i],
+        })
+    }
+
     /// Choose an slab for the given layout.
     ///
     /// Returns an index into the `slabs` array.
@@ -53,13 +102,22 @@ impl<const SLAB_COUNT: usize, TFallback: Allocator> SlabAllocator<SLAB_COUNT, TF
         (self.slab_selector_fn)(layout)
     }
 
+    /// One page, or a single block if blocks are larger than a page.
+    fn chunk_layout(&self, slab_idx: usize) -> Layout {
+        let chunk_size = self.page_size.max(self.block_sizes[slab_idx]);
+        Layout::from_size_align(chunk_size, chunk_size).unwrap()
+    }
+
+    /// Refills a slab with a chunk from the fallback allocator.
     fn allocate_new_blocks_in_slab(&mut self, slab_idx: usize) -> Result<(), AllocError> {
-        let block_size = (self.block_size_fn)(slab_idx);
-        let layout = Layout::from_size_align(block_size, block_size).unwrap();
-        let block_count = self.page_size / block_size;
-        for _ in 0..block_count {
-            let ptr = self.fallback_allocator.alloc(layout)?;
-            let new_node_ptr = ptr.as_ptr() as *mut BlockNode;
+        let block_size = self.block_sizes[slab_idx];
+        let layout = self.chunk_layout(slab_idx);
+        let chunk_size = layout.size();
+        let chunk = self.fallback_allocator.alloc(layout)?.as_ptr() as *mut u8;
+        let block_count = chunk_size / block_size;
+        // pushed from the end, so blocks are handed out in address order
+        for i in (0..block_count).rev() {
+            let new_node_ptr = unsafe { chunk.add(i * block_size) } as *mut BlockNode;
             let new_node = BlockNode {
                 next: self.slabs[slab_idx].take(),
             };
@@ -68,8 +126,108 @@ impl<const SLAB_COUNT: usize, TFallback: Allocator> SlabAllocator<SLAB_COUNT, TF
                 self.slabs[slab_idx] = Some(&mut *new_node_ptr);
             }
         }
+        self.blocks[slab_idx] += block_count;
+        self.free_blocks[slab_idx] += block_count;
         Ok(())
     }
+
+    /// Returns the chunks whose blocks are all free to the fallback
+    /// allocator, returns the bytes released.
+    fn reclaim_slab(&mut self, slab_idx: usize) -> usize {
+        let layout = self.chunk_layout(slab_idx);
+        let blocks_per_chunk = layout.size() / self.block_sizes[slab_idx];
+        let chunk_of = |node: &BlockNode| node.address() & !(layout.size() - 1);
+
+        // the free blocks of a chunk end up next to each other
+        let mut free = sort_by_address(self.slabs[slab_idx].take(), self.free_blocks[slab_idx]);
+        let mut kept = None;
+        let mut released = 0;
+        while let Some(first) = free.as_deref() {
+            let chunk = chunk_of(first);
+            let mut count = 0;
+            let mut cursor = free.as_deref();
+            while let Some(node) = cursor.filter(|node| chunk_of(node) == chunk) {
+                count += 1;
+                cursor = node.next.as_deref();
+            }
+            let rest = split_off(&mut free, count);
+            if count == blocks_per_chunk {
+                let chunk = unsafe { NonNull::new_unchecked(chunk as *mut u8) };
+                self.fallback_allocator.dealloc(chunk, layout);
+                self.blocks[slab_idx] -= count;
+                self.free_blocks[slab_idx] -= count;
+                released += layout.size();
+            } else {
+                while let Some(node) = free {
+                    free = node.next.take();
+                    node.next = kept;
+                    kept = Some(node);
+                }
+            }
+            free = rest;
+        }
+        self.slabs[slab_idx] = kept;
+        released
+    }
+}
+
+/// Splits `list` after `at` nodes and returns the rest.
+fn split_off(
+    list: &mut Option<&'static mut BlockNode>,
+    at: usize,
+) -> Option<&'static mut BlockNode> {
+    let mut cursor = list;
+    for _ in 0..at {
+        cursor = &mut cursor.as_mut()?.next;
+    }
+    cursor.take()
+}
+
+/// Merge sorts a free list of `len` nodes by address, ascending.
+fn sort_by_address(
+    mut list: Option<&'static mut BlockNode>,
+    len: usize,
+) -> Option<&'static mut BlockNode> {
+    if len <= 1 {
+        return list;
+    }
+    let back = split_off(&mut list, len / 2);
+    let mut front = sort_by_address(list, len / 2);
+    let mut back = sort_by_address(back, len - len / 2);
+
+    let mut merged = None;
+    let mut tail = &mut merged;
+    loop {
+        let node = match (front.take(), back.take()) {
+            (Some(a), Some(b)) if a.address() < b.address() => {
+                front = a.next.take();
+                back = Some(b);
+                a
+            }
+            (a, Some(b)) => {
+                front = a;
+                back = b.next.take();
+                b
+            }
+            (rest, None) => {
+                *tail = rest;
+                return merged;
+            }
+        };
+        tail = &mut tail.insert(node).next;
+    }
+}
+
+impl<TFallback: Allocator> SlabAllocator<SIZE_CLASS_COUNT, TFallback> {
+    /// Slabs for the [`SIZE_CLASSES`], refilled `page_size` bytes at a time.
+    pub const fn with_size_classes(fallback_allocator: TFallback, page_size: usize) -> Self {
+        Self::new(
+            SIZE_CLASSES,
+            size_class_selector,
+            fallback_allocator,
+            page_size,
+        )
+    }
 }
 
 impl<const SLAB_COUNT: usize, TFallback: Allocator> Allocator
@@ -81,6 +239,10 @@ impl<const SLAB_COUNT: usize, TFallback: Allocator> Allocator
         }
     }
 
+    unsafe fn extend(&mut self, size: usize) -> Result<(), AllocError> {
+        unsafe { self.fallback_allocator.extend(size) }
+    }
+
     fn alloc(&mut self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
         match self.slab_index(&layout) {
             Some(slab_idx) => {
@@ -92,6 +254,7 @@ impl<const SLAB_COUNT: usize, TFallback: Allocator> Allocator
                 match self.slabs[slab_idx].take() {
                     Some(node) => {
                         self.slabs[slab_idx] = node.next.take();
+                        self.free_blocks[slab_idx] -= 1;
                         let ptr = node as *mut BlockNode as *mut u8;
                         let ptr = unsafe { NonNull::new_unchecked(ptr) };
                         Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
@@ -114,6 +277,7 @@ impl<const SLAB_COUNT: usize, TFallback: Allocator> Allocator
                     new_node_ptr.write(new_node);
                     self.slabs[index] = Some(&mut *new_node_ptr);
                 }
+                self.free_blocks[index] += 1;
             }
             None => {
                 self.fallback_allocator.dealloc(ptr, layout);
@@ -121,6 +285,11 @@ impl<const SLAB_COUNT: usize, TFallback: Allocator> Allocator
         }
     }
 
+    fn reclaim(&mut self) -> usize {
+        let released: usize = (0..SLAB_COUNT).map(|i| self.reclaim_slab(i)).sum();
+        released + self.fallback_allocator.reclaim()
+    }
+
     fn used(&self) -> usize {
         self.fallback_allocator.used()
     }
@@ -128,6 +297,14 @@ impl<const SLAB_COUNT: usize, TFallback: Allocator> Allocator
     fn free(&self) -> usize {
         self.fallback_allocator.free()
     }
+
+    fn largest_free(&mut self) -> usize {
+        let largest_block = (0..SLAB_COUNT)
+            .rev()
+            .find(|&index| self.free_blocks[index] > 0)
+            .map_or(0, |index| self.block_sizes[index]);
+        self.fallback_allocator.largest_free().max(largest_block)
+    }
 }
 
 #[cfg(test)]
@@ -143,12 +320,8 @@ mod tests {
 
     use super::*;
 
-    const BLOCK_SIZES: &[usize] = &[16, 32, 64, 128, 256, 512, 1024, 2048];
-    const SLAB_COUNT: usize = BLOCK_SIZES.len();
-
-    fn test_block_size_fn(idx: usize) -> usize {
-        BLOCK_SIZES[idx]
-    }
+    const SLAB_COUNT: usize = 8;
+    const BLOCK_SIZES: [usize; SLAB_COUNT] = [16, 32, 64, 128, 256, 512, 1024, 2048];
 
     fn test_slab_selector_fn(layout: &Layout) -> Option<usize> {
         let required_block_size = layout.size().max(layout.align());
@@ -163,7 +336,7 @@ mod tests {
             let (heap_space_ptr, data_ptr) = Memory::<HEAP_SIZE>::new();
 
             let mut allocator = SlabAllocator::<SLAB_COUNT, LinkedListAllocator>::new(
-                test_block_size_fn,
+                BLOCK_SIZES,
                 test_slab_selector_fn,
                 LinkedListAllocator::new(),
                 PAGE_SIZE,
@@ -203,4 +376,94 @@ mod tests {
             Memory::free(heap_space_ptr);
         }
     }
+
+    #[test]
+    pub fn test_size_classes() {
+        unsafe {
+            const PAGE_SIZE: usize = 4096;
+            const HEAP_SIZE: usize = 8 * PAGE_SIZE;
+            let (heap_space_ptr, data_ptr) = Memory::<HEAP_SIZE>::new();
+
+            let mut allocator =
+                SlabAllocator::with_size_classes(LinkedListAllocator::new(), PAGE_SIZE);
+            allocator.init(data_ptr, HEAP_SIZE);
+
+            let small = allocate(&mut allocator, Layout::new::<u64>()).unwrap();
+            let medium =
+                allocate(&mut allocator, Layout::from_size_align(100, 4).unwrap()).unwrap();
+            let page = allocate(&mut allocator, Layout::from_size_align(4000, 8).unwrap()).unwrap();
+            let large =
+                allocate(&mut allocator, Layout::from_size_align(5000, 8).unwrap()).unwrap();
+            assert_eq!(0, medium.as_mut_u32() as usize % 128);
+
+            let stats = allocator.slab_stats();
+            assert_eq!(SIZE_CLASSES[0], stats[0].block_size);
+            // one page each, carved into blocks
+            assert_eq!(PAGE_SIZE / 8, stats[0].blocks);
+            assert_eq!(PAGE_SIZE / 8 - 1, stats[0].free_blocks);
+            assert_eq!(PAGE_SIZE / 128 - 1, stats[4].free_blocks);
+            assert_eq!(1, stats[9].blocks);
+            assert_eq!(0, stats[9].free_blocks);
+
+            small.free(&mut allocator);
+            page.free(&mut allocator);
+            let stats = allocator.slab_stats();
+            assert_eq!(stats[0].blocks, stats[0].free_blocks);
+            assert_eq!(1, stats[9].free_blocks);
+
+            medium.free(&mut allocator);
+            large.free(&mut allocator);
+            Memory::free(heap_space_ptr);
+        }
+    }
+
+    #[test]
+    pub fn test_reclaim() {
+        unsafe {
+            const PAGE_SIZE: usize = 4096;
+            const HEAP_SIZE: usize = 4 * PAGE_SIZE;
+            const PER_CHUNK: usize = PAGE_SIZE / 512;
+            let (heap_space_ptr, data_ptr) = Memory::<HEAP_SIZE>::new();
+
+            let mut allocator =
+                SlabAllocator::with_size_classes(LinkedListAllocator::new(), PAGE_SIZE);
+            allocator.init(data_ptr, HEAP_SIZE);
+
+            let layout = Layout::from_size_align(512, 8).unwrap();
+            let mut blocks: alloc::vec::Vec<_> = (0..3 * PER_CHUNK)
+                .map(|_| allocate(&mut allocator, layout).unwrap())
+                .collect();
+            let page = allocate(&mut allocator, Layout::new::<[u8; PAGE_SIZE]>()).unwrap();
+            assert_eq!(HEAP_SIZE, allocator.used());
+            assert_eq!(0, allocator.reclaim());
+
+            // frees the whole second chunk and all but the last block of the
+            // third, out of order
+            let kept = blocks.pop().unwrap();
+            for block in blocks.drain(PER_CHUNK..).rev() {
+                block.free(&mut allocator);
+            }
+            page.free(&mut allocator);
+            assert_eq!(2 * PAGE_SIZE, allocator.reclaim());
+            assert_eq!(2 * PAGE_SIZE, allocator.used());
+            let stats = allocator.slab_stats();
+            assert_eq!(2 * PER_CHUNK, stats[6].blocks);
+            assert_eq!(PER_CHUNK - 1, stats[6].free_blocks);
+            assert_eq!(0, stats[9].blocks);
+
+            // the partly free chunk is still used first
+            let reused = allocate(&mut allocator, layout).unwrap();
+            assert_eq!(2 * PAGE_SIZE, allocator.used());
+            assert_eq!(0, allocator.reclaim());
+
+            reused.free(&mut allocator);
+            kept.free(&mut allocator);
+            for block in blocks {
+                block.free(&mut allocator);
+            }
+            assert_eq!(2 * PAGE_SIZE, allocator.reclaim());
+            assert_eq!(0, allocator.used());
+            Memory::free(heap_space_ptr);
+        }
+    }
 }
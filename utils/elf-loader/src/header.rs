@@ -0,0 +1,409 @@
+// see https://refspecs.linuxfoundation.org/elf/gabi4+/ch4.eheader.html and
+// https://refspecs.linuxfoundation.org/elf/gabi4+/ch5.pheader.html
+
+use alloc::vec::Vec;
+use core::{mem::size_of, ops::Range};
+
+use nostdio::{Read, Seek, SeekFrom};
+use zerocopy::{FromBytes, Immutable, IntoBytes, KnownLayout};
+
+use crate::{ElfError, PAGE_SIZE};
+
+const ELF_MAGIC: [u8; 4] = *b"\x7fELF";
+const ELF_CLASS_64: u8 = 2;
+const ELF_DATA_LITTLE_ENDIAN: u8 = 1;
+const ELF_VERSION_CURRENT: u8 = 1;
+const ELF_OSABI_SYSV: u8 = 0;
+const ELF_OSABI_LINUX: u8 = 3;
+const ET_EXEC: u16 = 2;
+const ET_DYN: u16 = 3;
+const EM_X86_64: u16 = 62;
+/// Programs with more program headers are surely broken
+const MAX_PROGRAM_HEADERS: u16 = 64;
+pub(crate) const PROGRAM_HEADER_SIZE: u64 = size_of::<ProgramHeader>() as u64;
+
+const PT_LOAD: u32 = 1;
+const PT_DYNAMIC: u32 = 2;
+const PT_INTERP: u32 = 3;
+const PF_X: u32 = 1 << 0;
+const PF_W: u32 = 1 << 1;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, FromBytes, IntoBytes, Immutable, KnownLayout)]
+struct FileHeader {
+    magic: [u8; 4],
+    class: u8,
+    data: u8,
+    version: u8,
+    os_abi: u8,
+    abi_version: u8,
+    padding: [u8; 7],
+    kind: u16,
+    machine: u16,
+    version2: u32,
+    entry: u64,
+    program_header_offset: u64,
+    section_header_offset: u64,
+    flags: u32,
+    header_size: u16,
+    program_header_size: u16,
+    program_header_count: u16,
+    section_header_size: u16,
+    section_header_count: u16,
+    section_names_index: u16,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromBytes, IntoBytes, Immutable, KnownLayout)]
+pub struct ProgramHeader {
+    pub kind: u32,
+    pub flags: u32,
+    pub offset: u64,
+    pub virtual_address: u64,
+    pub physical_address: u64,
+    pub file_size: u64,
+    pub memory_size: u64,
+    pub align: u64,
+}
+
+impl ProgramHeader {
+    pub fn is_writable(&self) -> bool {
+        self.flags & PF_W != 0
+    }
+
+    pub fn is_executable(&self) -> bool {
+        self.flags & PF_X != 0
+    }
+
+    /// Page aligned addresses of the segment in memory.
+    pub fn pages(&self) -> (u64, u64) {
+        let start = self.virtual_address & !(PAGE_SIZE - 1);
+        let end = (self.virtual_address + self.memory_size).next_multiple_of(PAGE_SIZE);
+        (start, end)
+    }
+
+    /// Checks the segment fits the file and `range`, so the arithmetic on it
+    /// can't overflow.
+    fn validate(&self, file_size: u64, range: &Range<u64>) -> Result<(), ElfError> {
+        let file_end = self.offset.checked_add(self.file_size);
+        let memory_end = self
+            .virtual_address
+            .checked_add(self.memory_size)
+            .and_then(|end| end.checked_next_multiple_of(PAGE_SIZE));
+        let valid = self.file_size <= self.memory_size
+            && self.memory_size > 0
+            && file_end.is_some_and(|end| end <= file_size)
+            && self.virtual_address >= range.start
+            && memory_end.is_some_and(|end| end <= range.end)
+            && self.virtual_address % PAGE_SIZE == self.offset % PAGE_SIZE;
+        valid.then_some(()).ok_or(ElfError::InvalidSegment)
+    }
+
+    fn contains(&self, address: u64) -> bool {
+        (self.virtual_address..self.virtual_address + self.memory_size).contains(&address)
+    }
+}
+
+/// The checked headers of a static executable.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Executable {
+    pub entry: u64,
+    pub program_headers: Vec<ProgramHeader>,
+    /// Where a loaded segment maps the whole program header table
+    pub program_headers_address: Option<u64>,
+}
+
+impl Executable {
+    /// The segments to map.
+    pub fn segments(&self) -> impl Iterator<Item = &ProgramHeader> {
+        self.program_headers.iter().filter(|p| p.kind == PT_LOAD)
+    }
+}
+
+/// Reads the headers of the static x86_64 executable `file`. Its segments
+/// must lie within `segment_range`.
+pub fn parse<R: Read + Seek>(
+    file: &mut R,
+    segment_range: Range<u64>,
+) -> Result<Executable, ElfError> {
+    let file_size = file.seek(SeekFrom::End(0))?;
+    let header: FileHeader = read_at(file, 0)?;
+    validate(&header, file_size)?;
+
+    let count = header.program_header_count as u64;
+    let mut program_headers = Vec::with_capacity(count as usize);
+    for index in 0..count {
+        // validate checked the table is inside the file
+        let offset = header.program_header_offset + index * PROGRAM_HEADER_SIZE;
+        let program_header: ProgramHeader = read_at(file, offset)?;
+        match program_header.kind {
+            PT_INTERP | PT_DYNAMIC => return Err(ElfError::NotStatic),
+            PT_LOAD => program_header.validate(file_size, &segment_range)?,
+            _ => {}
+        }
+        program_headers.push(program_header);
+    }
+    let mut executable = Executable {
+        entry: header.entry,
+        program_headers,
+        program_headers_address: None,
+    };
+    let entry_is_executable = executable
+        .segments()
+        .any(|segment| segment.contains(header.entry) && segment.is_executable());
+    if !entry_is_executable {
+        return Err(ElfError::InvalidHeader);
+    }
+
+    // the C library finds TLS and other headers through AT_PHDR
+    let table_size = count * PROGRAM_HEADER_SIZE;
+    let program_headers_address = executable.segments().find_map(|segment| {
+        let offset = header.program_header_offset.checked_sub(segment.offset)?;
+        (offset.checked_add(table_size)? <= segment.file_size)
+            .then(|| segment.virtual_address + offset)
+    });
+    executable.program_headers_address = program_headers_address;
+    Ok(executable)
+}
+
+fn validate(header: &FileHeader, file_size: u64) -> Result<(), ElfError> {
+    if header.magic != ELF_MAGIC {
+        return Err(ElfError::NotElf);
+    }
+    let supported = header.class == ELF_CLASS_64
+        && header.data == ELF_DATA_LITTLE_ENDIAN
+        && header.version == ELF_VERSION_CURRENT
+        && header.version2 == ELF_VERSION_CURRENT as u32
+        && matches!(header.os_abi, ELF_OSABI_SYSV | ELF_OSABI_LINUX)
+        && header.machine == EM_X86_64;
+    if !supported {
+        return Err(ElfError::Unsupported);
+    }
+    match header.kind {
+        ET_EXEC => {}
+        // position independent, needs to be relocated
+        ET_DYN => return Err(ElfError::NotStatic),
+        _ => return Err(ElfError::Unsupported),
+    }
+    let table_end = (header.program_header_count as u64)
+        .checked_mul(PROGRAM_HEADER_SIZE)
+        .and_then(|size| header.program_header_offset.checked_add(size));
+    let valid = header.header_size as usize == size_of::<FileHeader>()
+        && header.program_header_size as u64 == PROGRAM_HEADER_SIZE
+        && (1..=MAX_PROGRAM_HEADERS).contains(&header.program_header_count)
+        && table_end.is_some_and(|end| end <= file_size);
+    valid.then_some(()).ok_or(ElfError::InvalidHeader)
+}
+
+fn read_at<R: Read + Seek, T: FromBytes + IntoBytes>(
+    file: &mut R,
+    offset: u64,
+) -> Result<T, ElfError> {
+    let mut value = T::new_zeroed();
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(value.as_mut_bytes())?;
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use nostdio::Cursor;
+
+    use super::*;
+
+    const SEGMENT_RANGE: Range<u64> = 0x40_0000..0x7fff_0000_0000;
+    const ENTRY: u64 = 0x40_1000;
+    const FILE_SIZE: usize = 0x2100;
+
+    fn file_header() -> FileHeader {
+        FileHeader {
+            magic: ELF_MAGIC,
+            class: ELF_CLASS_64,
+            data: ELF_DATA_LITTLE_ENDIAN,
+            version: ELF_VERSION_CURRENT,
+            os_abi: ELF_OSABI_SYSV,
+            abi_version: 0,
+            padding: [0; 7],
+            kind: ET_EXEC,
+            machine: EM_X86_64,
+            version2: ELF_VERSION_CURRENT as u32,
+            entry: ENTRY,
+            program_header_offset: size_of::<FileHeader>() as u64,
+            section_header_offset: 0,
+            flags: 0,
+            header_size: size_of::<FileHeader>() as u16,
+            program_header_size: PROGRAM_HEADER_SIZE as u16,
+            program_header_count: 2,
+            section_header_size: 0,
+            section_header_count: 0,
+            section_names_index: 0,
+        }
+    }
+
+    /// Code mapped from the start of the file, including the headers.
+    fn text() -> ProgramHeader {
+        ProgramHeader {
+            kind: PT_LOAD,
+            flags: PF_X,
+            offset: 0,
+            virtual_address: 0x40_0000,
+            physical_address: 0,
+            file_size: 0x1100,
+            memory_size: 0x1100,
+            align: PAGE_SIZE,
+        }
+    }
+
+    /// Writable data followed by zeroed memory.
+    fn data() -> ProgramHeader {
+        ProgramHeader {
+            kind: PT_LOAD,
+            flags: PF_W,
+            offset: 0x2000,
+            virtual_address: 0x40_2000,
+            physical_address: 0,
+            file_size: 0x100,
+            memory_size: 0x3000,
+            align: PAGE_SIZE,
+        }
+    }
+
+    /// Parses a file with `header` and `program_headers` right after it.
+    fn parse_file(
+        header: FileHeader,
+        program_headers: &[ProgramHeader],
+    ) -> Result<Executable, ElfError> {
+        let mut file = vec![0; FILE_SIZE];
+        file[..size_of::<FileHeader>()].copy_from_slice(header.as_bytes());
+        for (index, program_header) in program_headers.iter().enumerate() {
+            let offset = size_of::<FileHeader>() + index * size_of::<ProgramHeader>();
+            file[offset..offset + size_of::<ProgramHeader>()]
+                .copy_from_slice(program_header.as_bytes());
+        }
+        parse(&mut Cursor::new(&mut file), SEGMENT_RANGE)
+    }
+
+    fn with_header(edit: impl FnOnce(&mut FileHeader)) -> Result<Executable, ElfError> {
+        let mut header = file_header();
+        edit(&mut header);
+        parse_file(header, &[text(), data()])
+    }
+
+    fn with_data(edit: impl FnOnce(&mut ProgramHeader)) -> Result<Executable, ElfError> {
+        let mut data = data();
+        edit(&mut data);
+        parse_file(file_header(), &[text(), data])
+    }
+
+    #[test]
+    fn test_parse() {
+        let executable = parse_file(file_header(), &[text(), data()]).unwrap();
+        assert_eq!(ENTRY, executable.entry);
+        assert_eq!(
+            vec![&text(), &data()],
+            executable.segments().collect::<Vec<_>>()
+        );
+        assert_eq!(Some(0x40_0040), executable.program_headers_address);
+        assert_eq!((0x40_2000, 0x40_5000), data().pages());
+        assert!(data().is_writable() && !data().is_executable());
+    }
+
+    #[test]
+    fn test_reject_header() {
+        assert_eq!(Err(ElfError::NotElf), with_header(|h| h.magic[1] = b'L'));
+        assert_eq!(Err(ElfError::Unsupported), with_header(|h| h.class = 1));
+        assert_eq!(Err(ElfError::Unsupported), with_header(|h| h.machine = 3));
+        assert_eq!(Err(ElfError::NotStatic), with_header(|h| h.kind = ET_DYN));
+        assert_eq!(
+            Err(ElfError::InvalidHeader),
+            with_header(|h| h.program_header_count = 0)
+        );
+        assert_eq!(
+            Err(ElfError::InvalidHeader),
+            with_header(|h| h.program_header_size = 32)
+        );
+    }
+
+    #[test]
+    fn test_reject_program_header_table() {
+        // the table must end inside the file, without overflowing
+        assert_eq!(
+            Err(ElfError::InvalidHeader),
+            with_header(|h| h.program_header_offset = u64::MAX - PROGRAM_HEADER_SIZE)
+        );
+        assert_eq!(
+            Err(ElfError::InvalidHeader),
+            with_header(|h| h.program_header_offset = FILE_SIZE as u64 - PROGRAM_HEADER_SIZE)
+        );
+        assert_eq!(
+            Err(ElfError::InvalidHeader),
+            with_header(|h| h.program_header_count = MAX_PROGRAM_HEADERS + 1)
+        );
+    }
+
+    #[test]
+    fn test_reject_segments() {
+        assert_eq!(Err(ElfError::NotStatic), with_data(|p| p.kind = PT_INTERP));
+        assert_eq!(Err(ElfError::NotStatic), with_data(|p| p.kind = PT_DYNAMIC));
+        assert_eq!(
+            Err(ElfError::InvalidSegment),
+            with_data(|p| p.file_size = p.memory_size + 1)
+        );
+        assert_eq!(
+            Err(ElfError::InvalidSegment),
+            with_data(|p| p.memory_size = 0)
+        );
+        assert_eq!(
+            Err(ElfError::InvalidSegment),
+            with_data(|p| p.file_size = FILE_SIZE as u64)
+        );
+        assert_eq!(
+            Err(ElfError::InvalidSegment),
+            with_data(|p| p.offset = u64::MAX)
+        );
+        assert_eq!(
+            Err(ElfError::InvalidSegment),
+            with_data(|p| p.virtual_address = SEGMENT_RANGE.start - PAGE_SIZE)
+        );
+        assert_eq!(
+            Err(ElfError::InvalidSegment),
+            with_data(|p| p.virtual_address = SEGMENT_RANGE.end - PAGE_SIZE)
+        );
+        assert_eq!(
+            Err(ElfError::InvalidSegment),
+            with_data(|p| p.virtual_address = u64::MAX - PAGE_SIZE + 1)
+        );
+        assert_eq!(
+            Err(ElfError::InvalidSegment),
+            with_data(|p| p.virtual_address += 8)
+        );
+        // other kinds aren't mapped, so aren't checked
+        assert!(
+            with_data(|p| {
+                p.kind = 4;
+                p.offset = u64::MAX;
+            })
+            .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_reject_entry() {
+        // the entry must be in an executable segment
+        assert_eq!(
+            Err(ElfError::InvalidHeader),
+            with_header(|h| h.entry = data().virtual_address)
+        );
+        assert_eq!(Err(ElfError::InvalidHeader), with_header(|h| h.entry = 0));
+    }
+
+    #[test]
+    fn test_program_headers_partly_mapped() {
+        let mut text = text();
+        text.file_size = size_of::<FileHeader>() as u64 + PROGRAM_HEADER_SIZE;
+        let executable = parse_file(file_header(), &[text, data()]).unwrap();
+        assert_eq!(None, executable.program_headers_address);
+    }
+}
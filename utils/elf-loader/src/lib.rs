@@ -0,0 +1,36 @@
+#![no_std]
+
+//! Parsing of static x86_64 ELF executables and the layout of their initial
+//! stack. Mapping the segments and writing the stack is left to the kernel.
+
+extern crate alloc;
+
+mod header;
+mod stack;
+
+use nostdio::NoStdIoError;
+
+pub use header::{Executable, ProgramHeader, parse};
+pub use stack::{InitialStack, RANDOM_SIZE, initial_stack};
+
+pub const PAGE_SIZE: u64 = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfError {
+    NotElf,
+    /// Not a 64 bit little endian x86_64 executable
+    Unsupported,
+    /// Needs an interpreter or relocation, only static executables are
+    /// loaded
+    NotStatic,
+    InvalidHeader,
+    InvalidSegment,
+    ArgumentsTooLarge,
+    Io(NoStdIoError),
+}
+
+impl From<NoStdIoError> for ElfError {
+    fn from(value: NoStdIoError) -> Self {
+        ElfError::Io(value)
+    }
+}
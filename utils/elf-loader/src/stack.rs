@@ -0,0 +1,216 @@
+// see https://gitlab.com/x86-psABIs/x86-64-ABI (3.4 Process Initialization)
+
+use alloc::{vec, vec::Vec};
+
+use crate::{ElfError, Executable, PAGE_SIZE, header::PROGRAM_HEADER_SIZE};
+
+const AT_NULL: u64 = 0;
+const AT_PHDR: u64 = 3;
+const AT_PHENT: u64 = 4;
+const AT_PHNUM: u64 = 5;
+const AT_PAGESZ: u64 = 6;
+const AT_BASE: u64 = 7;
+const AT_FLAGS: u64 = 8;
+const AT_ENTRY: u64 = 9;
+const AT_UID: u64 = 11;
+const AT_EUID: u64 = 12;
+const AT_GID: u64 = 13;
+const AT_EGID: u64 = 14;
+const AT_SECURE: u64 = 23;
+const AT_RANDOM: u64 = 25;
+const AT_EXECFN: u64 = 31;
+
+/// Bytes of the arguments, environment and auxiliary vector
+const MAX_ARGUMENTS_SIZE: usize = 128 * 1024;
+/// Bytes for the C library to seed its stack protector and pointer guard
+pub const RANDOM_SIZE: usize = 16;
+
+impl Executable {
+    /// The auxiliary vector entries describing the executable.
+    pub fn auxv(&self) -> Vec<(u64, u64)> {
+        let mut auxv = vec![
+            (AT_PHENT, PROGRAM_HEADER_SIZE),
+            (AT_PHNUM, self.program_headers.len() as u64),
+            (AT_PAGESZ, PAGE_SIZE),
+            (AT_BASE, 0),
+            (AT_FLAGS, 0),
+            (AT_ENTRY, self.entry),
+            (AT_UID, 0),
+            (AT_EUID, 0),
+            (AT_GID, 0),
+            (AT_EGID, 0),
+            (AT_SECURE, 0),
+        ];
+        if let Some(address) = self.program_headers_address {
+            auxv.push((AT_PHDR, address));
+        }
+        auxv
+    }
+}
+
+/// The top of a new stack: `strings` end at the stack top, `pointers` start
+/// at the stack pointer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InitialStack {
+    pub strings_start: u64,
+    /// The null terminated arguments and environment, then the random bytes
+    pub strings: Vec<u8>,
+    /// 16 byte aligned, points at `argc`
+    pub stack_pointer: u64,
+    /// argc, argv, envp and the auxiliary vector
+    pub pointers: Vec<u64>,
+}
+
+/// Lays out `args`, `env` and `auxv` below `stack_top`, adding `AT_RANDOM`
+/// for `random`, `AT_EXECFN` and the closing `AT_NULL`.
+pub fn initial_stack(
+    stack_top: u64,
+    args: &[&str],
+    env: &[&str],
+    mut auxv: Vec<(u64, u64)>,
+    random: [u8; RANDOM_SIZE],
+) -> Result<InitialStack, ElfError> {
+    // strings and random bytes go to the top, addressed from its start
+    let mut strings = Vec::new();
+    let arg_offsets = push_strings(&mut strings, args);
+    let env_offsets = push_strings(&mut strings, env);
+    let random_offset = strings.len();
+    strings.extend_from_slice(&random);
+
+    let pointers_size = (1 + args.len() + 1 + env.len() + 1 + 2 * (auxv.len() + 3)) * 8;
+    if strings.len() + pointers_size + 16 > MAX_ARGUMENTS_SIZE {
+        return Err(ElfError::ArgumentsTooLarge);
+    }
+    let strings_start = stack_top - strings.len() as u64;
+    let string_address = |offset: usize| strings_start + offset as u64;
+    auxv.push((AT_RANDOM, string_address(random_offset)));
+    if let Some(&offset) = arg_offsets.first() {
+        auxv.push((AT_EXECFN, string_address(offset)));
+    }
+    auxv.push((AT_NULL, 0));
+
+    let mut pointers: Vec<u64> = Vec::with_capacity(pointers_size / 8);
+    pointers.push(args.len() as u64);
+    pointers.extend(arg_offsets.iter().map(|&offset| string_address(offset)));
+    pointers.push(0);
+    pointers.extend(env_offsets.iter().map(|&offset| string_address(offset)));
+    pointers.push(0);
+    for (key, value) in auxv {
+        pointers.extend([key, value]);
+    }
+    let stack_pointer = (strings_start - pointers.len() as u64 * 8) & !0xf;
+
+    Ok(InitialStack {
+        strings_start,
+        strings,
+        stack_pointer,
+        pointers,
+    })
+}
+
+/// Appends the null terminated `values`, returns their offsets.
+fn push_strings(strings: &mut Vec<u8>, values: &[&str]) -> Vec<usize> {
+    values
+        .iter()
+        .map(|value| {
+            let offset = strings.len();
+            strings.extend_from_slice(value.as_bytes());
+            strings.push(0);
+            offset
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::String;
+
+    use super::*;
+
+    const STACK_TOP: u64 = 0x7fff_ffff_f000;
+    const RANDOM: [u8; RANDOM_SIZE] = *b"0123456789abcdef";
+
+    /// The null terminated string at `address`.
+    fn string_at(stack: &InitialStack, address: u64) -> &str {
+        let offset = (address - stack.strings_start) as usize;
+        let length = stack.strings[offset..]
+            .iter()
+            .position(|&b| b == 0)
+            .unwrap();
+        core::str::from_utf8(&stack.strings[offset..offset + length]).unwrap()
+    }
+
+    #[test]
+    fn test_layout() {
+        let auxv = vec![(AT_PAGESZ, PAGE_SIZE)];
+        let stack = initial_stack(STACK_TOP, &["init", "-v"], &["HOME=/"], auxv, RANDOM).unwrap();
+        assert_eq!(STACK_TOP, stack.strings_start + stack.strings.len() as u64);
+        assert_eq!(0, stack.stack_pointer % 16);
+        assert!(stack.stack_pointer + stack.pointers.len() as u64 * 8 <= stack.strings_start);
+
+        let mut pointers = stack.pointers.iter().copied();
+        assert_eq!(Some(2), pointers.next());
+        let args: Vec<_> = pointers.by_ref().take_while(|&p| p != 0).collect();
+        assert_eq!(
+            vec!["init", "-v"],
+            args.iter()
+                .map(|&p| string_at(&stack, p))
+                .collect::<Vec<_>>()
+        );
+        let env: Vec<_> = pointers.by_ref().take_while(|&p| p != 0).collect();
+        assert_eq!(
+            vec!["HOME=/"],
+            env.iter()
+                .map(|&p| string_at(&stack, p))
+                .collect::<Vec<_>>()
+        );
+
+        let auxv: Vec<(u64, u64)> = pointers
+            .collect::<Vec<_>>()
+            .chunks(2)
+            .map(|pair| (pair[0], pair[1]))
+            .collect();
+        assert_eq!(
+            vec![AT_PAGESZ, AT_RANDOM, AT_EXECFN, AT_NULL],
+            auxv.iter().map(|&(key, _)| key).collect::<Vec<_>>()
+        );
+        assert_eq!((AT_PAGESZ, PAGE_SIZE), auxv[0]);
+        let random = (auxv[1].1 - stack.strings_start) as usize;
+        assert_eq!(RANDOM, stack.strings[random..random + RANDOM_SIZE]);
+        assert_eq!("init", string_at(&stack, auxv[2].1));
+        assert_eq!((AT_NULL, 0), auxv[3]);
+    }
+
+    #[test]
+    fn test_no_arguments() {
+        let stack = initial_stack(STACK_TOP, &[], &[], Vec::new(), RANDOM).unwrap();
+        // no AT_EXECFN without a program name
+        assert_eq!(
+            vec![0, 0, 0, AT_RANDOM, stack.strings_start, AT_NULL, 0],
+            stack.pointers
+        );
+        assert_eq!(0, stack.stack_pointer % 16);
+    }
+
+    #[test]
+    fn test_arguments_too_large() {
+        let arg = String::from_utf8(vec![b'a'; MAX_ARGUMENTS_SIZE]).unwrap();
+        assert_eq!(
+            Err(ElfError::ArgumentsTooLarge),
+            initial_stack(STACK_TOP, &["init", &arg], &[], Vec::new(), RANDOM)
+        );
+    }
+
+    #[test]
+    fn test_auxv() {
+        let mut executable = Executable {
+            entry: 0x40_1000,
+            program_headers: Vec::new(),
+            program_headers_address: None,
+        };
+        assert!(!executable.auxv().iter().any(|&(key, _)| key == AT_PHDR));
+        executable.program_headers_address = Some(0x40_0040);
+        assert!(executable.auxv().contains(&(AT_PHDR, 0x40_0040)));
+        assert!(executable.auxv().contains(&(AT_ENTRY, 0x40_1000)));
+    }
+}
@@ -80,7 +80,7 @@ impl<T: Ext4Source> Ext4<T> {
 
     pub(crate) fn read(&self, inode: &INode, offset: FilePos, buf: &mut [u8]) -> Result<()> {
         if offset.0 >= inode.size().0 {
-            return Err(FileIoError::IoError(NoStdIoError::EndOfFile));
+            return Err(FileIoError::IoError(NoStdIoError::UnexpectedEof));
         }
 
         let data_pos = inode.get_data_pos(offset, self.super_block.block_size())?;
@@ -106,13 +106,15 @@ mod tests {
     extern crate std;
     use std::fs::File;
 
-    use crate::source::FileExt4Source;
+    use nostdio::OffsetBlockDevice;
 
     use super::*;
 
     #[test]
     fn test_read() {
-        let source = FileExt4Source::new(File::open("test-data/simple.ext4").unwrap());
+        let source =
+            OffsetBlockDevice::from_file(File::open("test-data/simple.ext4").unwrap(), 512)
+                .unwrap();
         let ext4 = Ext4::new(source).unwrap();
 
         let root = ext4.root_dir().unwrap();
@@ -1,6 +1,5 @@
 use core::fmt::Debug;
 use myos_api::filesystem::{FileIoError, FilePos, Result};
-use nostdio::NoStdIoError;
 use zerocopy::{
     FromBytes, Immutable, IntoBytes, KnownLayout,
     little_endian::{U16, U32},
@@ -66,12 +65,8 @@ impl BlockGroupDescriptor {
     pub(crate) fn read<T: Ext4Source>(source: &T, file_pos: FilePos) -> Result<Self> {
         let mut buf = [0; BLOCK_GROUP_DESCRIPTOR_SIZE];
         source.read(file_pos, &mut buf)?;
-        let bgd = BlockGroupDescriptor::read_from_bytes(&buf).map_err(|err| {
-            FileIoError::IoError(NoStdIoError::from_zerocopy_err(
-                "failed to read block group descriptor from bytes",
-                err,
-            ))
-        })?;
+        let bgd = BlockGroupDescriptor::read_from_bytes(&buf)
+            .map_err(|_| FileIoError::Other("failed to read block group descriptor from bytes"))?;
 
         Ok(bgd)
     }
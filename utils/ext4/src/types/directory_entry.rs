@@ -1,7 +1,6 @@
 use core::fmt::Debug;
 
 use myos_api::filesystem::{FileIoError, FilePos, Result};
-use nostdio::NoStdIoError;
 use zerocopy::{
     FromBytes, Immutable, IntoBytes, KnownLayout, TryFromBytes,
     little_endian::{U16, U32},
@@ -47,11 +46,8 @@ impl DirEntry2 {
 
         let dir_entry_header = match DirEntry2Header::read_from_bytes(&buf) {
             Ok(dir_entry) => dir_entry,
-            Err(err) => {
-                return Err(FileIoError::IoError(NoStdIoError::from_zerocopy_err(
-                    "failed reading dir entry",
-                    err,
-                )));
+            Err(_) => {
+                return Err(FileIoError::Other("failed reading dir entry"));
             }
         };
 
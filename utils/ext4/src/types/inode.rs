@@ -1,9 +1,10 @@
 use core::fmt::Debug;
 
 use bitflags::bitflags;
-use chrono::NaiveDateTime;
-use myos_api::filesystem::{FileIoError, FilePos, Result};
-use nostdio::NoStdIoError;
+use myos_api::{
+    filesystem::{FileIoError, FilePos, Result},
+    time::Timestamp,
+};
 use zerocopy::{
     FromBytes, Immutable, IntoBytes, KnownLayout,
     little_endian::{U16, U32},
@@ -15,7 +16,7 @@ use crate::{
         BlockIndex, INodeIndex,
         extent::{EXTENT_HEADER_MAGIC, EXTENT_SIZE, Extent, ExtentHeader},
     },
-    utils::{hi_low_to_date_time, u32_from_hi_lo, u64_from_hi_lo},
+    utils::{extra_to_timestamp, u32_from_hi_lo, u64_from_hi_lo},
 };
 
 pub(crate) const INODE_SIZE: usize = core::mem::size_of::<INode>();
@@ -168,12 +169,8 @@ impl INode {
             + ((relative_inode_idx.0) as u64 * inode_size as u64);
 
         source.read(file_pos, &mut buf)?;
-        let inode = INode::read_from_bytes(&buf).map_err(|err| {
-            FileIoError::IoError(NoStdIoError::from_zerocopy_err(
-                "failed to read inode from bytes",
-                err,
-            ))
-        })?;
+        let inode = INode::read_from_bytes(&buf)
+            .map_err(|_| FileIoError::Other("failed to read inode from bytes"))?;
 
         Ok(inode)
     }
@@ -183,12 +180,8 @@ impl INode {
             todo!();
         }
 
-        let (extent_header, rest) = ExtentHeader::read_from_prefix(&self.block).map_err(|err| {
-            FileIoError::IoError(NoStdIoError::from_zerocopy_err(
-                "failed reading extent header",
-                err,
-            ))
-        })?;
+        let (extent_header, rest) = ExtentHeader::read_from_prefix(&self.block)
+            .map_err(|_| FileIoError::Other("failed reading extent header"))?;
         if extent_header.magic != EXTENT_HEADER_MAGIC {
             return Err(FileIoError::Other("invalid extent header magic"));
         }
@@ -199,12 +192,8 @@ impl INode {
             let rest = rest
                 .get(inode_block_idx as usize * EXTENT_SIZE..)
                 .ok_or(FileIoError::Other("index out of bounds"))?;
-            let (extent, _) = Extent::read_from_prefix(rest).map_err(|err| {
-                FileIoError::IoError(NoStdIoError::from_zerocopy_err(
-                    "failed reading header block index",
-                    err,
-                ))
-            })?;
+            let (extent, _) = Extent::read_from_prefix(rest)
+                .map_err(|_| FileIoError::Other("failed reading header block index"))?;
 
             let extent_len = extent.len.get() as u64 * block_size as u64;
             if offset.0 < extent_len {
@@ -221,28 +210,24 @@ impl INode {
         todo!("not in header");
     }
 
-    pub fn access_time(&self) -> Result<Option<NaiveDateTime>> {
-        hi_low_to_date_time(0, self.atime.get())
+    pub fn access_time(&self) -> Result<Option<Timestamp>> {
+        extra_to_timestamp(self.atime.get(), self.atime_extra.get())
     }
 
-    pub fn create_time(&self) -> Result<Option<NaiveDateTime>> {
-        // todo ctime_extra
-        hi_low_to_date_time(0, self.ctime.get())
+    pub fn create_time(&self) -> Result<Option<Timestamp>> {
+        extra_to_timestamp(self.ctime.get(), self.ctime_extra.get())
     }
 
-    pub fn modified_time(&self) -> Result<Option<NaiveDateTime>> {
-        // todo mtime_extra
-        hi_low_to_date_time(0, self.mtime.get())
+    pub fn modified_time(&self) -> Result<Option<Timestamp>> {
+        extra_to_timestamp(self.mtime.get(), self.mtime_extra.get())
     }
 
-    pub fn deletion_time(&self) -> Result<Option<NaiveDateTime>> {
-        // todo atime_extra
-        hi_low_to_date_time(0, self.dtime.get())
+    pub fn deletion_time(&self) -> Result<Option<Timestamp>> {
+        extra_to_timestamp(self.dtime.get(), 0)
     }
 
-    pub fn creation_time(&self) -> Result<Option<NaiveDateTime>> {
-        // todo crtime_extra
-        hi_low_to_date_time(0, self.crtime.get())
+    pub fn creation_time(&self) -> Result<Option<Timestamp>> {
+        extra_to_timestamp(self.crtime.get(), self.crtime_extra.get())
     }
 
     pub fn size(&self) -> FilePos {
@@ -1,8 +1,9 @@
 use core::{ffi::CStr, fmt::Debug};
 
-use chrono::NaiveDateTime;
-use myos_api::filesystem::{FileIoError, FilePos, Result};
-use nostdio::NoStdIoError;
+use myos_api::{
+    filesystem::{FileIoError, FilePos, Result},
+    time::Timestamp,
+};
 use uuid::Uuid;
 use zerocopy::{
     FromBytes, Immutable, IntoBytes, KnownLayout,
@@ -12,7 +13,7 @@ use zerocopy::{
 use crate::{
     source::Ext4Source,
     types::{INodeIndex, block_group_descriptor::BLOCK_GROUP_DESCRIPTOR_SIZE},
-    utils::{hi_low_to_date_time, u64_from_hi_lo},
+    utils::{hi_low_to_timestamp, u64_from_hi_lo},
 };
 
 pub(crate) const SUPER_BLOCK_SIZE: usize = core::mem::size_of::<SuperBlock>();
@@ -258,12 +259,8 @@ impl SuperBlock {
     pub(crate) fn read<T: Ext4Source>(source: &T) -> Result<Self> {
         let mut buf = [0; SUPER_BLOCK_SIZE];
         source.read(SUPER_BLOCK_POS, &mut buf)?;
-        let super_block = SuperBlock::read_from_bytes(&buf).map_err(|err| {
-            FileIoError::IoError(NoStdIoError::from_zerocopy_err(
-                "failed to read super block from bytes",
-                err,
-            ))
-        })?;
+        let super_block = SuperBlock::read_from_bytes(&buf)
+            .map_err(|_| FileIoError::Other("failed to read super block from bytes"))?;
 
         if super_block.magic.get() != EXT4_MAGIC {
             return Err(FileIoError::Other("ext4 magic mismatch"));
@@ -287,28 +284,28 @@ impl SuperBlock {
         )
     }
 
-    pub fn mount_time(&self) -> Result<Option<NaiveDateTime>> {
-        hi_low_to_date_time(self.mtime_hi as u32, self.mtime.get())
+    pub fn mount_time(&self) -> Result<Option<Timestamp>> {
+        hi_low_to_timestamp(self.mtime_hi, self.mtime.get())
     }
 
-    pub fn write_time(&self) -> Result<Option<NaiveDateTime>> {
-        hi_low_to_date_time(self.wtime_hi as u32, self.wtime.get())
+    pub fn write_time(&self) -> Result<Option<Timestamp>> {
+        hi_low_to_timestamp(self.wtime_hi, self.wtime.get())
     }
 
-    pub fn create_time(&self) -> Result<Option<NaiveDateTime>> {
-        hi_low_to_date_time(self.mkfs_time_hi as u32, self.mkfs_time.get())
+    pub fn create_time(&self) -> Result<Option<Timestamp>> {
+        hi_low_to_timestamp(self.mkfs_time_hi, self.mkfs_time.get())
     }
 
-    pub fn last_check_time(&self) -> Result<Option<NaiveDateTime>> {
-        hi_low_to_date_time(self.lastcheck_hi as u32, self.lastcheck.get())
+    pub fn last_check_time(&self) -> Result<Option<Timestamp>> {
+        hi_low_to_timestamp(self.lastcheck_hi, self.lastcheck.get())
     }
 
-    pub fn first_error_time(&self) -> Result<Option<NaiveDateTime>> {
-        hi_low_to_date_time(self.first_error_time_hi as u32, self.first_error_time.get())
+    pub fn first_error_time(&self) -> Result<Option<Timestamp>> {
+        hi_low_to_timestamp(self.first_error_time_hi, self.first_error_time.get())
     }
 
-    pub fn last_error_time(&self) -> Result<Option<NaiveDateTime>> {
-        hi_low_to_date_time(self.last_error_time_hi as u32, self.last_error_time.get())
+    pub fn last_error_time(&self) -> Result<Option<Timestamp>> {
+        hi_low_to_timestamp(self.last_error_time_hi, self.last_error_time.get())
     }
 
     pub fn volume_name(&self) -> Result<&CStr> {
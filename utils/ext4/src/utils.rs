@@ -1,5 +1,7 @@
-use chrono::{DateTime, NaiveDateTime};
-use myos_api::filesystem::{FileIoError, Result};
+use myos_api::{
+    filesystem::{FileIoError, Result},
+    time::Timestamp,
+};
 
 pub(crate) fn u64_from_hi_lo(hi: u32, lo: u32) -> u64 {
     ((hi as u64) << 4) | lo as u64
@@ -9,17 +11,24 @@ pub(crate) fn u32_from_hi_lo(hi: u16, lo: u16) -> u32 {
     ((hi as u32) << 2) | lo as u32
 }
 
-pub(crate) fn hi_low_to_date_time(hi: u32, lo: u32) -> Result<Option<NaiveDateTime>> {
-    let ms: i64 = (u64_from_hi_lo(hi, lo) * 1000)
-        .try_into()
-        .map_err(|_| FileIoError::Other("invalid time"))?;
-    if ms == 0 {
+/// Superblock times are stored as 32 low bits plus an 8 bit high byte.
+pub(crate) fn hi_low_to_timestamp(hi: u8, lo: u32) -> Result<Option<Timestamp>> {
+    let seconds = ((hi as i64) << 32) | lo as i64;
+    if seconds == 0 {
+        Ok(None)
+    } else {
+        Ok(Some(Timestamp::from_seconds(seconds)))
+    }
+}
+
+/// Inode times are stored as a signed 32 bit seconds field plus an `*_extra` field
+/// holding nanoseconds and epoch bits.
+pub(crate) fn extra_to_timestamp(seconds: u32, extra: u32) -> Result<Option<Timestamp>> {
+    if seconds == 0 && extra == 0 {
         Ok(None)
     } else {
         Ok(Some(
-            DateTime::from_timestamp_millis(ms)
-                .ok_or(FileIoError::Other("invalid time"))?
-                .naive_utc(),
+            Timestamp::from_ext4(seconds, extra).map_err(|_| FileIoError::Other("invalid time"))?,
         ))
     }
 }
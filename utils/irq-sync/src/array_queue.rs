@@ -0,0 +1,255 @@
+// see https://www.1024cores.net/home/lock-free-algorithms/queues/bounded-mpmc-queue
+
+use core::{
+    cell::UnsafeCell,
+    mem::MaybeUninit,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Bounded lock-free multi-producer multi-consumer queue.
+///
+/// Neither [`push`](Self::push) nor [`pop`](Self::pop) lock or allocate, so
+/// both can be used from interrupt handlers. An interrupted operation is
+/// never waited on, a pop racing it may see the queue as empty until the
+/// interrupted operation completes. `N` must be a power of two.
+pub struct ArrayQueue<T, const N: usize> {
+    slots: [Slot<T>; N],
+    head: AtomicUsize,
+    tail: AtomicUsize,
+}
+
+struct Slot<T> {
+    /// Position this slot is ready for, stored relative to the slot index so
+    /// all slots start at zero. A push to position `p` waits for `p`, a pop
+    /// from position `p` waits for `p + 1`.
+    sequence: AtomicUsize,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+impl<T> Slot<T> {
+    const fn new() -> Self {
+        Self {
+            sequence: AtomicUsize::new(0),
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+}
+
+unsafe impl<T: Send, const N: usize> Send for ArrayQueue<T, N> {}
+unsafe impl<T: Send, const N: usize> Sync for ArrayQueue<T, N> {}
+
+impl<T, const N: usize> ArrayQueue<T, N> {
+    pub const fn new() -> Self {
+        const { assert!(N.is_power_of_two()) };
+        Self {
+            slots: [const { Slot::new() }; N],
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    fn sequence(&self, index: usize) -> usize {
+        self.slots[index]
+            .sequence
+            .load(Ordering::Acquire)
+            .wrapping_add(index)
+    }
+
+    fn set_sequence(&self, index: usize, sequence: usize) {
+        self.slots[index]
+            .sequence
+            .store(sequence.wrapping_sub(index), Ordering::Release);
+    }
+
+    /// Appends `value`, handing it back when the queue is full.
+    pub fn push(&self, value: T) -> Result<(), T> {
+        let mut tail = self.tail.load(Ordering::Relaxed);
+        loop {
+            let index = tail % N;
+            let diff = self.sequence(index).wrapping_sub(tail) as isize;
+            if diff == 0 {
+                match self.tail.compare_exchange_weak(
+                    tail,
+                    tail.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        unsafe { (*self.slots[index].value.get()).write(value) };
+                        self.set_sequence(index, tail.wrapping_add(1));
+                        return Ok(());
+                    }
+                    Err(current) => tail = current,
+                }
+            } else if diff < 0 {
+                // the slot still holds the value pushed one lap ago
+                return Err(value);
+            } else {
+                tail = self.tail.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Removes the oldest value.
+    pub fn pop(&self) -> Option<T> {
+        let mut head = self.head.load(Ordering::Relaxed);
+        loop {
+            let index = head % N;
+            let diff = self.sequence(index).wrapping_sub(head.wrapping_add(1)) as isize;
+            if diff == 0 {
+                match self.head.compare_exchange_weak(
+                    head,
+                    head.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        let value = unsafe { (*self.slots[index].value.get()).assume_init_read() };
+                        self.set_sequence(index, head.wrapping_add(N));
+                        return Some(value);
+                    }
+                    Err(current) => head = current,
+                }
+            } else if diff < 0 {
+                // nothing has been pushed to this position yet
+                return None;
+            } else {
+                head = self.head.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Whether the queue was empty, may be outdated by the time it returns.
+    pub fn is_empty(&self) -> bool {
+        self.head.load(Ordering::Acquire) == self.tail.load(Ordering::Acquire)
+    }
+}
+
+impl<T, const N: usize> Default for ArrayQueue<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> Drop for ArrayQueue<T, N> {
+    fn drop(&mut self) {
+        while self.pop().is_some() {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::{sync::Arc, thread, vec::Vec};
+
+    use super::*;
+
+    #[test]
+    fn test_empty() {
+        let queue = ArrayQueue::<u32, 4>::new();
+        assert!(queue.is_empty());
+        assert_eq!(None, queue.pop());
+        assert_eq!(Ok(()), queue.push(1));
+        assert!(!queue.is_empty());
+        assert_eq!(Some(1), queue.pop());
+        assert_eq!(None, queue.pop());
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn test_full() {
+        let queue = ArrayQueue::<u32, 4>::new();
+        for value in 0..4 {
+            assert_eq!(Ok(()), queue.push(value));
+        }
+        assert_eq!(Err(4), queue.push(4));
+        assert_eq!(Some(0), queue.pop());
+        assert_eq!(Ok(()), queue.push(4));
+        assert_eq!(Err(5), queue.push(5));
+        for value in 1..5 {
+            assert_eq!(Some(value), queue.pop());
+        }
+    }
+
+    #[test]
+    fn test_wrap_around() {
+        let queue = ArrayQueue::<usize, 4>::new();
+        // every slot is reused many times, with the queue neither full nor empty
+        assert_eq!(Ok(()), queue.push(0));
+        for value in 1..100 {
+            assert_eq!(Ok(()), queue.push(value));
+            assert_eq!(Some(value - 1), queue.pop());
+        }
+        assert_eq!(Some(99), queue.pop());
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn test_drop_values() {
+        let value = Arc::new(());
+        let queue = ArrayQueue::<_, 4>::new();
+        assert!(queue.push(value.clone()).is_ok());
+        assert!(queue.push(value.clone()).is_ok());
+        assert_eq!(3, Arc::strong_count(&value));
+        drop(queue);
+        assert_eq!(1, Arc::strong_count(&value));
+    }
+
+    #[test]
+    fn test_concurrent() {
+        const PRODUCERS: usize = 4;
+        const PER_PRODUCER: usize = 10_000;
+        let queue = Arc::new(ArrayQueue::<usize, 8>::new());
+
+        let producers: Vec<_> = (0..PRODUCERS)
+            .map(|producer| {
+                let queue = queue.clone();
+                thread::spawn(move || {
+                    for i in 0..PER_PRODUCER {
+                        let mut value = producer * PER_PRODUCER + i;
+                        while let Err(rejected) = queue.push(value) {
+                            value = rejected;
+                            thread::yield_now();
+                        }
+                    }
+                })
+            })
+            .collect();
+        let consumers: Vec<_> = (0..2)
+            .map(|_| {
+                let queue = queue.clone();
+                thread::spawn(move || {
+                    let mut popped = Vec::new();
+                    while popped.len() < PRODUCERS * PER_PRODUCER / 2 {
+                        match queue.pop() {
+                            Some(value) => popped.push(value),
+                            None => thread::yield_now(),
+                        }
+                    }
+                    popped
+                })
+            })
+            .collect();
+
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        let mut seen = std::vec![false; PRODUCERS * PER_PRODUCER];
+        for consumer in consumers {
+            let popped = consumer.join().unwrap();
+            // values of one producer leave the queue in the order they were pushed
+            for producer in 0..PRODUCERS {
+                let range = producer * PER_PRODUCER..(producer + 1) * PER_PRODUCER;
+                let own: Vec<_> = popped.iter().filter(|v| range.contains(v)).collect();
+                assert!(own.is_sorted());
+            }
+            for value in popped {
+                assert!(!seen[value], "{value} popped twice");
+                seen[value] = true;
+            }
+        }
+        assert!(seen.iter().all(|&seen| seen));
+        assert!(queue.is_empty());
+    }
+}
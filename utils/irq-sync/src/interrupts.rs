@@ -0,0 +1,25 @@
+/// Disables interrupts, returns whether they were enabled.
+#[cfg(all(target_arch = "x86_64", target_os = "none"))]
+pub(crate) fn disable() -> bool {
+    use x86_64::instructions::interrupts;
+    let enabled = interrupts::are_enabled();
+    interrupts::disable();
+    enabled
+}
+
+/// Enables interrupts again if [`disable`] found them enabled.
+#[cfg(all(target_arch = "x86_64", target_os = "none"))]
+pub(crate) fn restore(enabled: bool) {
+    if enabled {
+        x86_64::instructions::interrupts::enable();
+    }
+}
+
+// hosted targets, e.g. tests, can't touch the interrupt flag
+#[cfg(not(all(target_arch = "x86_64", target_os = "none")))]
+pub(crate) fn disable() -> bool {
+    false
+}
+
+#[cfg(not(all(target_arch = "x86_64", target_os = "none")))]
+pub(crate) fn restore(_enabled: bool) {}
@@ -0,0 +1,159 @@
+use core::{
+    fmt,
+    mem::ManuallyDrop,
+    ops::{Deref, DerefMut},
+};
+
+use crate::{interrupts, lock_order, lock_order::LockLevel};
+
+/// Spin lock which keeps interrupts disabled while it is held.
+///
+/// An interrupt handler taking the lock can't interrupt a holder on the same
+/// CPU, which would spin forever.
+pub struct IrqSpinLock<T> {
+    level: Option<LockLevel>,
+    inner: spin::Mutex<T>,
+}
+
+impl<T> IrqSpinLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            level: None,
+            inner: spin::Mutex::new(value),
+        }
+    }
+
+    /// Creates a lock which takes part in the lock order checks, see
+    /// [`LockLevel`].
+    pub const fn with_level(value: T, level: LockLevel) -> Self {
+        Self {
+            level: Some(level),
+            inner: spin::Mutex::new(value),
+        }
+    }
+
+    pub fn lock(&self) -> IrqSpinLockGuard<'_, T> {
+        let interrupts_enabled = interrupts::disable();
+        lock_order::acquire(self.level);
+        IrqSpinLockGuard {
+            guard: ManuallyDrop::new(self.inner.lock()),
+            level: self.level,
+            interrupts_enabled,
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<IrqSpinLockGuard<'_, T>> {
+        let interrupts_enabled = interrupts::disable();
+        match self.inner.try_lock() {
+            Some(guard) => {
+                lock_order::acquire(self.level);
+                Some(IrqSpinLockGuard {
+                    guard: ManuallyDrop::new(guard),
+                    level: self.level,
+                    interrupts_enabled,
+                })
+            }
+            None => {
+                interrupts::restore(interrupts_enabled);
+                None
+            }
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.inner.is_locked()
+    }
+
+    /// Releases the lock without a guard, e.g. to print a panic raised while
+    /// it was held.
+    ///
+    /// # Safety
+    /// The holder must never touch the protected value again.
+    pub unsafe fn force_unlock(&self) {
+        unsafe { self.inner.force_unlock() };
+        lock_order::release(self.level);
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner()
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.inner.get_mut()
+    }
+}
+
+impl<T: Default> Default for IrqSpinLock<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for IrqSpinLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.try_lock() {
+            Some(guard) => f
+                .debug_struct("IrqSpinLock")
+                .field("data", &*guard)
+                .finish(),
+            None => f.write_str("IrqSpinLock { <locked> }"),
+        }
+    }
+}
+
+pub struct IrqSpinLockGuard<'a, T> {
+    guard: ManuallyDrop<spin::MutexGuard<'a, T>>,
+    level: Option<LockLevel>,
+    interrupts_enabled: bool,
+}
+
+impl<T> Deref for IrqSpinLockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T> DerefMut for IrqSpinLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T> Drop for IrqSpinLockGuard<'_, T> {
+    fn drop(&mut self) {
+        // unlock before interrupts can come in again
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+        lock_order::release(self.level);
+        interrupts::restore(self.interrupts_enabled);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lock() {
+        let lock = IrqSpinLock::new(1);
+        {
+            let mut guard = lock.lock();
+            *guard += 1;
+            assert!(lock.is_locked());
+            assert!(lock.try_lock().is_none());
+        }
+        assert!(!lock.is_locked());
+        assert_eq!(2, *lock.try_lock().unwrap());
+        assert_eq!(2, lock.into_inner());
+    }
+
+    #[test]
+    fn test_force_unlock() {
+        let lock = IrqSpinLock::new(());
+        core::mem::forget(lock.lock());
+        assert!(lock.is_locked());
+        unsafe { lock.force_unlock() };
+        assert!(lock.try_lock().is_some());
+    }
+}
@@ -0,0 +1,17 @@
+#![no_std]
+
+//! Spin locks which disable interrupts while held, so they can be shared
+//! between threads and interrupt handlers on the same CPU without
+//! deadlocking, and a lock-free queue for handing values out of interrupt
+//! handlers.
+
+mod array_queue;
+mod interrupts;
+mod irq_spin_lock;
+mod lock_order;
+mod rw_lock;
+
+pub use array_queue::ArrayQueue;
+pub use irq_spin_lock::{IrqSpinLock, IrqSpinLockGuard};
+pub use lock_order::{LockLevel, set_held_levels};
+pub use rw_lock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
@@ -0,0 +1,135 @@
+use core::sync::atomic::{AtomicPtr, AtomicU64, Ordering};
+
+/// Position of a lock in the lock order.
+///
+/// In debug builds a lock with a level may only be acquired while all held
+/// locks with a level have a lower one, so inverted nesting and recursive
+/// locking panic instead of deadlocking. Locks without a level aren't
+/// checked. The held levels are tracked per CPU once [`set_held_levels`] is
+/// called.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct LockLevel(u8);
+
+impl LockLevel {
+    pub const MAX: u8 = 63;
+
+    /// # Panics
+    /// Panics if `level` is greater than [`LockLevel::MAX`].
+    pub const fn new(level: u8) -> Self {
+        assert!(level <= Self::MAX, "lock level out of range");
+        Self(level)
+    }
+
+    pub const fn level(&self) -> u8 {
+        self.0
+    }
+
+    const fn bit(&self) -> u64 {
+        1 << self.0
+    }
+}
+
+/// Bit `n` is set while a lock of level `n` is held, used until
+/// [`set_held_levels`] is called
+static HELD: AtomicU64 = AtomicU64::new(0);
+/// `fn() -> &'static AtomicU64` returning the held levels of the running CPU
+static HELD_LEVELS: AtomicPtr<()> = AtomicPtr::new(core::ptr::null_mut());
+
+/// Tracks the held lock levels in the mask returned by `held_levels`, which
+/// must belong to the running CPU. Must be called while no leveled lock is
+/// held.
+pub fn set_held_levels(held_levels: fn() -> &'static AtomicU64) {
+    HELD_LEVELS.store(held_levels as *mut (), Ordering::Release);
+}
+
+fn held() -> &'static AtomicU64 {
+    let held_levels = HELD_LEVELS.load(Ordering::Acquire);
+    if held_levels.is_null() {
+        &HELD
+    } else {
+        let held_levels =
+            unsafe { core::mem::transmute::<*mut (), fn() -> &'static AtomicU64>(held_levels) };
+        held_levels()
+    }
+}
+
+/// Returns the highest held level which forbids acquiring `level`.
+fn check(held: u64, level: LockLevel) -> Result<(), LockLevel> {
+    let conflicting = held & !(level.bit() - 1);
+    if conflicting == 0 {
+        Ok(())
+    } else {
+        Err(LockLevel(
+            (u64::BITS - 1 - conflicting.leading_zeros()) as u8,
+        ))
+    }
+}
+
+/// Called with interrupts disabled before spinning on the lock.
+pub(crate) fn acquire(level: Option<LockLevel>) {
+    let Some(level) = level else {
+        return;
+    };
+    if cfg!(debug_assertions) {
+        let held = held().load(Ordering::Relaxed);
+        if let Err(held) = check(held, level) {
+            panic!(
+                "lock level {} acquired while holding level {}",
+                level.0, held.0
+            );
+        }
+    }
+    held().fetch_or(level.bit(), Ordering::Relaxed);
+}
+
+/// Like [`acquire`] for a shared lock, which the running CPU may already
+/// hold at `level` through another reader. Returns the level to pass to
+/// [`release`], `None` when an earlier holder marked it. The level is then
+/// cleared when that holder releases, so the checks may miss an inversion
+/// but never report a false one.
+pub(crate) fn acquire_shared(level: Option<LockLevel>) -> Option<LockLevel> {
+    let level = level?;
+    if cfg!(debug_assertions) {
+        let held = held().load(Ordering::Relaxed) & !level.bit();
+        if let Err(held) = check(held, level) {
+            panic!(
+                "lock level {} acquired while holding level {}",
+                level.0, held.0
+            );
+        }
+    }
+    let held = held().fetch_or(level.bit(), Ordering::Relaxed);
+    (held & level.bit() == 0).then_some(level)
+}
+
+pub(crate) fn release(level: Option<LockLevel>) {
+    if let Some(level) = level {
+        held().fetch_and(!level.bit(), Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_increasing_levels() {
+        let held = LockLevel::new(1).bit() | LockLevel::new(5).bit();
+        assert_eq!(Ok(()), check(0, LockLevel::new(0)));
+        assert_eq!(Ok(()), check(held, LockLevel::new(6)));
+        assert_eq!(Ok(()), check(held, LockLevel::new(63)));
+    }
+
+    #[test]
+    fn test_inverted_levels() {
+        let held = LockLevel::new(1).bit() | LockLevel::new(5).bit();
+        assert_eq!(Err(LockLevel::new(5)), check(held, LockLevel::new(2)));
+        assert_eq!(Err(LockLevel::new(5)), check(held, LockLevel::new(0)));
+    }
+
+    #[test]
+    fn test_recursive_level() {
+        let held = LockLevel::new(63).bit();
+        assert_eq!(Err(LockLevel::new(63)), check(held, LockLevel::new(63)));
+    }
+}
@@ -0,0 +1,203 @@
+use core::{
+    fmt,
+    mem::ManuallyDrop,
+    ops::{Deref, DerefMut},
+};
+
+use crate::{interrupts, lock_order, lock_order::LockLevel};
+
+/// Reader-writer spin lock which keeps interrupts disabled while it is held,
+/// see [`crate::IrqSpinLock`].
+pub struct RwLock<T> {
+    level: Option<LockLevel>,
+    inner: spin::RwLock<T>,
+}
+
+impl<T> RwLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            level: None,
+            inner: spin::RwLock::new(value),
+        }
+    }
+
+    /// Creates a lock which takes part in the lock order checks, see
+    /// [`LockLevel`].
+    pub const fn with_level(value: T, level: LockLevel) -> Self {
+        Self {
+            level: Some(level),
+            inner: spin::RwLock::new(value),
+        }
+    }
+
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        let interrupts_enabled = interrupts::disable();
+        let level = lock_order::acquire_shared(self.level);
+        RwLockReadGuard {
+            guard: ManuallyDrop::new(self.inner.read()),
+            level,
+            interrupts_enabled,
+        }
+    }
+
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        let interrupts_enabled = interrupts::disable();
+        match self.inner.try_read() {
+            Some(guard) => {
+                let level = lock_order::acquire_shared(self.level);
+                Some(RwLockReadGuard {
+                    guard: ManuallyDrop::new(guard),
+                    level,
+                    interrupts_enabled,
+                })
+            }
+            None => {
+                interrupts::restore(interrupts_enabled);
+                None
+            }
+        }
+    }
+
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        let interrupts_enabled = interrupts::disable();
+        lock_order::acquire(self.level);
+        RwLockWriteGuard {
+            guard: ManuallyDrop::new(self.inner.write()),
+            level: self.level,
+            interrupts_enabled,
+        }
+    }
+
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        let interrupts_enabled = interrupts::disable();
+        match self.inner.try_write() {
+            Some(guard) => {
+                lock_order::acquire(self.level);
+                Some(RwLockWriteGuard {
+                    guard: ManuallyDrop::new(guard),
+                    level: self.level,
+                    interrupts_enabled,
+                })
+            }
+            None => {
+                interrupts::restore(interrupts_enabled);
+                None
+            }
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner()
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.inner.get_mut()
+    }
+}
+
+impl<T: Default> Default for RwLock<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for RwLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.try_read() {
+            Some(guard) => f.debug_struct("RwLock").field("data", &*guard).finish(),
+            None => f.write_str("RwLock { <locked> }"),
+        }
+    }
+}
+
+pub struct RwLockReadGuard<'a, T> {
+    guard: ManuallyDrop<spin::RwLockReadGuard<'a, T>>,
+    /// `None` if another reader on this CPU marked the level as held
+    level: Option<LockLevel>,
+    interrupts_enabled: bool,
+}
+
+impl<T> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+        lock_order::release(self.level);
+        interrupts::restore(self.interrupts_enabled);
+    }
+}
+
+pub struct RwLockWriteGuard<'a, T> {
+    guard: ManuallyDrop<spin::RwLockWriteGuard<'a, T>>,
+    level: Option<LockLevel>,
+    interrupts_enabled: bool,
+}
+
+impl<T> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+        lock_order::release(self.level);
+        interrupts::restore(self.interrupts_enabled);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_readers_share() {
+        let lock = RwLock::new(1);
+        let first = lock.read();
+        let second = lock.read();
+        assert_eq!(2, *first + *second);
+        assert!(lock.try_write().is_none());
+    }
+
+    #[test]
+    fn test_writer_excludes() {
+        let lock = RwLock::new(1);
+        {
+            let mut guard = lock.write();
+            *guard = 5;
+            assert!(lock.try_read().is_none());
+            assert!(lock.try_write().is_none());
+        }
+        assert_eq!(5, *lock.read());
+    }
+
+    #[test]
+    fn test_leveled_readers_share() {
+        let lock = RwLock::with_level(1, LockLevel::new(LockLevel::MAX));
+        let first = lock.read();
+        let second = lock.try_read().unwrap();
+        drop(first);
+        let third = lock.read();
+        assert_eq!(3, *second + *third + *lock.read());
+        drop(second);
+        drop(third);
+        // the level is no longer held, so this isn't reported as recursive
+        *lock.write() += 1;
+        assert_eq!(2, *lock.read());
+    }
+}
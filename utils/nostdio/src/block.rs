@@ -0,0 +1,375 @@
+use crate::{Cursor, NoStdIoError, OffsetRead, OffsetWrite, Result};
+
+/// Largest block size supported by [`BlockDevice::read_bytes`] and
+/// [`BlockDevice::write_bytes`] for unaligned accesses.
+pub const MAX_BLOCK_SIZE: usize = 4096;
+
+/// A device addressed in fixed size blocks (sectors), such as a disk, a ram disk
+/// or a partition on one of those.
+///
+/// Buffers passed to [`BlockDevice::read_blocks`] and
+/// [`BlockDevice::write_blocks`] must be a multiple of the block size in length.
+pub trait BlockDevice {
+    /// Size of a block in bytes, typically 512 or 4096.
+    fn block_size(&self) -> usize;
+
+    /// Number of blocks in the device.
+    fn block_count(&self) -> u64;
+
+    /// Read `buf.len() / block_size()` blocks starting at `start_block`.
+    fn read_blocks(&self, start_block: u64, buf: &mut [u8]) -> Result<()>;
+
+    /// Write `buf.len() / block_size()` blocks starting at `start_block`.
+    fn write_blocks(&mut self, start_block: u64, buf: &[u8]) -> Result<()>;
+
+    /// Make sure all previous writes have reached the underlying storage.
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Hint that the given blocks are no longer in use. Devices which don't
+    /// support discarding (TRIM) ignore it, so the contents of discarded blocks
+    /// are unspecified afterwards.
+    fn discard(&mut self, _start_block: u64, _block_count: u64) -> Result<()> {
+        Ok(())
+    }
+
+    /// Size of the device in bytes.
+    fn size(&self) -> u64 {
+        self.block_count() * self.block_size() as u64
+    }
+
+    /// Read bytes starting at an arbitrary byte offset, reading whole blocks
+    /// from the device as needed.
+    fn read_bytes(&self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        let block_size = self.block_size();
+        let mut block = [0; MAX_BLOCK_SIZE];
+        let block = block
+            .get_mut(..block_size)
+            .ok_or(NoStdIoError::InvalidInput)?;
+
+        let mut offset = offset;
+        let mut buf = buf;
+        while !buf.is_empty() {
+            let block_idx = offset / block_size as u64;
+            let block_offset = usize::try_from(offset % block_size as u64)
+                .map_err(|_| NoStdIoError::InvalidInput)?;
+            let len = if block_offset == 0 && buf.len() >= block_size {
+                // aligned, read as many whole blocks as possible directly into buf
+                let len = buf.len() - buf.len() % block_size;
+                self.read_blocks(block_idx, buf.get_mut(..len).ok_or(NoStdIoError::Other)?)?;
+                len
+            } else {
+                let len = (block_size - block_offset).min(buf.len());
+                self.read_blocks(block_idx, block)?;
+                buf.get_mut(..len)
+                    .ok_or(NoStdIoError::Other)?
+                    .copy_from_slice(
+                        block
+                            .get(block_offset..block_offset + len)
+                            .ok_or(NoStdIoError::Other)?,
+                    );
+                len
+            };
+            offset += len as u64;
+            buf = buf.get_mut(len..).ok_or(NoStdIoError::Other)?;
+        }
+        Ok(())
+    }
+
+    /// Write bytes starting at an arbitrary byte offset. Partially written
+    /// blocks are read, modified and written back.
+    fn write_bytes(&mut self, offset: u64, buf: &[u8]) -> Result<()> {
+        let block_size = self.block_size();
+        let mut block = [0; MAX_BLOCK_SIZE];
+        let block = block
+            .get_mut(..block_size)
+            .ok_or(NoStdIoError::InvalidInput)?;
+
+        let mut offset = offset;
+        let mut buf = buf;
+        while !buf.is_empty() {
+            let block_idx = offset / block_size as u64;
+            let block_offset = usize::try_from(offset % block_size as u64)
+                .map_err(|_| NoStdIoError::InvalidInput)?;
+            let len = if block_offset == 0 && buf.len() >= block_size {
+                let len = buf.len() - buf.len() % block_size;
+                self.write_blocks(block_idx, buf.get(..len).ok_or(NoStdIoError::Other)?)?;
+                len
+            } else {
+                let len = (block_size - block_offset).min(buf.len());
+                self.read_blocks(block_idx, block)?;
+                block
+                    .get_mut(block_offset..block_offset + len)
+                    .ok_or(NoStdIoError::Other)?
+                    .copy_from_slice(buf.get(..len).ok_or(NoStdIoError::Other)?);
+                self.write_blocks(block_idx, block)?;
+                len
+            };
+            offset += len as u64;
+            buf = buf.get(len..).ok_or(NoStdIoError::Other)?;
+        }
+        Ok(())
+    }
+}
+
+impl<D: BlockDevice + ?Sized> BlockDevice for &mut D {
+    fn block_size(&self) -> usize {
+        (**self).block_size()
+    }
+
+    fn block_count(&self) -> u64 {
+        (**self).block_count()
+    }
+
+    fn read_blocks(&self, start_block: u64, buf: &mut [u8]) -> Result<()> {
+        (**self).read_blocks(start_block, buf)
+    }
+
+    fn write_blocks(&mut self, start_block: u64, buf: &[u8]) -> Result<()> {
+        (**self).write_blocks(start_block, buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        (**self).flush()
+    }
+
+    fn discard(&mut self, start_block: u64, block_count: u64) -> Result<()> {
+        (**self).discard(start_block, block_count)
+    }
+}
+
+/// Returns the number of blocks covered by `len` bytes, checking that `len` is a
+/// multiple of `block_size` and that the blocks are inside the device.
+fn check_range(block_size: usize, block_count: u64, start_block: u64, len: usize) -> Result<u64> {
+    if block_size == 0 || !len.is_multiple_of(block_size) {
+        return Err(NoStdIoError::InvalidInput);
+    }
+    let count = (len / block_size) as u64;
+    match start_block.checked_add(count) {
+        Some(end) if end <= block_count => Ok(count),
+        _ => Err(NoStdIoError::UnexpectedEof),
+    }
+}
+
+/// Adapts any byte addressable [`OffsetRead`]/[`OffsetWrite`] source, such as a
+/// [`Cursor`] over a ram disk or a `std::fs::File` image, into a [`BlockDevice`].
+pub struct OffsetBlockDevice<T> {
+    inner: T,
+    block_size: usize,
+    block_count: u64,
+}
+
+impl<T> OffsetBlockDevice<T> {
+    pub fn new(inner: T, block_size: usize, block_count: u64) -> Self {
+        Self {
+            inner,
+            block_size,
+            block_count,
+        }
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<'a> OffsetBlockDevice<Cursor<'a>> {
+    /// Creates a block device over the cursor's data. A trailing partial block
+    /// is not accessible.
+    pub fn from_cursor(cursor: Cursor<'a>, block_size: usize) -> Result<Self> {
+        if block_size == 0 {
+            return Err(NoStdIoError::InvalidInput);
+        }
+        let block_count = (cursor.len() / block_size) as u64;
+        Ok(Self::new(cursor, block_size, block_count))
+    }
+}
+
+#[cfg(feature = "std")]
+impl OffsetBlockDevice<std::fs::File> {
+    /// Creates a block device over a disk image file. A trailing partial block
+    /// is not accessible.
+    pub fn from_file(file: std::fs::File, block_size: usize) -> Result<Self> {
+        if block_size == 0 {
+            return Err(NoStdIoError::InvalidInput);
+        }
+        let block_count = file.metadata()?.len() / block_size as u64;
+        Ok(Self::new(file, block_size, block_count))
+    }
+}
+
+impl<T: OffsetRead + OffsetWrite> BlockDevice for OffsetBlockDevice<T> {
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn block_count(&self) -> u64 {
+        self.block_count
+    }
+
+    fn read_blocks(&self, start_block: u64, buf: &mut [u8]) -> Result<()> {
+        check_range(self.block_size, self.block_count, start_block, buf.len())?;
+        let read = self
+            .inner
+            .read_at_offset(start_block * self.block_size as u64, buf)?;
+        if read != buf.len() {
+            return Err(NoStdIoError::UnexpectedEof);
+        }
+        Ok(())
+    }
+
+    fn write_blocks(&mut self, start_block: u64, buf: &[u8]) -> Result<()> {
+        check_range(self.block_size, self.block_count, start_block, buf.len())?;
+        let written = self
+            .inner
+            .write_at_offset(start_block * self.block_size as u64, buf)?;
+        if written != buf.len() {
+            return Err(NoStdIoError::WriteZero);
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        OffsetWrite::flush(&mut self.inner)
+    }
+}
+
+/// A window of `block_count` blocks starting at `start_block` of another device,
+/// e.g. a partition. Block indices are relative to the start of the window.
+pub struct PartitionView<D: BlockDevice> {
+    device: D,
+    start_block: u64,
+    block_count: u64,
+}
+
+impl<D: BlockDevice> PartitionView<D> {
+    pub fn new(device: D, start_block: u64, block_count: u64) -> Result<Self> {
+        match start_block.checked_add(block_count) {
+            Some(end) if end <= device.block_count() => Ok(Self {
+                device,
+                start_block,
+                block_count,
+            }),
+            _ => Err(NoStdIoError::InvalidInput),
+        }
+    }
+
+    /// First block of the view on the underlying device.
+    pub fn start_block(&self) -> u64 {
+        self.start_block
+    }
+
+    pub fn into_inner(self) -> D {
+        self.device
+    }
+}
+
+impl<D: BlockDevice> BlockDevice for PartitionView<D> {
+    fn block_size(&self) -> usize {
+        self.device.block_size()
+    }
+
+    fn block_count(&self) -> u64 {
+        self.block_count
+    }
+
+    fn read_blocks(&self, start_block: u64, buf: &mut [u8]) -> Result<()> {
+        check_range(self.block_size(), self.block_count, start_block, buf.len())?;
+        self.device.read_blocks(self.start_block + start_block, buf)
+    }
+
+    fn write_blocks(&mut self, start_block: u64, buf: &[u8]) -> Result<()> {
+        check_range(self.block_size(), self.block_count, start_block, buf.len())?;
+        self.device
+            .write_blocks(self.start_block + start_block, buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.device.flush()
+    }
+
+    fn discard(&mut self, start_block: u64, block_count: u64) -> Result<()> {
+        match start_block.checked_add(block_count) {
+            Some(end) if end <= self.block_count => self
+                .device
+                .discard(self.start_block + start_block, block_count),
+            _ => Err(NoStdIoError::UnexpectedEof),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_offset_block_device() {
+        let mut data = [0; 2048];
+        let mut device = OffsetBlockDevice::from_cursor(Cursor::new(&mut data), 512).unwrap();
+        assert_eq!(4, device.block_count());
+
+        device.write_blocks(1, &[1; 1024]).unwrap();
+        let mut buf = [0; 512];
+        device.read_blocks(2, &mut buf).unwrap();
+        assert_eq!([1; 512], buf);
+        device.read_blocks(3, &mut buf).unwrap();
+        assert_eq!([0; 512], buf);
+
+        assert_eq!(
+            NoStdIoError::InvalidInput,
+            device.read_blocks(0, &mut [0; 100]).unwrap_err()
+        );
+        assert_eq!(
+            NoStdIoError::UnexpectedEof,
+            device.read_blocks(3, &mut [0; 1024]).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn test_unaligned_bytes() {
+        let mut data = [0; 2048];
+        {
+            let mut device = OffsetBlockDevice::from_cursor(Cursor::new(&mut data), 512).unwrap();
+            let buf: [u8; 1200] = core::array::from_fn(|i| i as u8);
+            device.write_bytes(300, &buf).unwrap();
+
+            let mut read = [0; 1200];
+            device.read_bytes(300, &mut read).unwrap();
+            assert_eq!(buf, read);
+
+            let mut read = [0; 3];
+            device.read_bytes(511, &mut read).unwrap();
+            assert_eq!([211, 212, 213], read);
+        }
+        assert_eq!(0, data[299]);
+        assert_eq!(0, data[300]);
+        assert_eq!(1, data[301]);
+        assert_eq!(0, data[1500]);
+    }
+
+    #[test]
+    fn test_partition_view() {
+        let mut data = [0; 2048];
+        {
+            let mut device = OffsetBlockDevice::from_cursor(Cursor::new(&mut data), 512).unwrap();
+            assert!(PartitionView::new(&mut device, 3, 2).is_err());
+        }
+        {
+            let mut device = OffsetBlockDevice::from_cursor(Cursor::new(&mut data), 512).unwrap();
+            let mut partition = PartitionView::new(&mut device, 1, 2).unwrap();
+            assert_eq!(2, partition.block_count());
+            assert_eq!(1024, partition.size());
+            partition.write_blocks(0, &[7; 512]).unwrap();
+            assert!(partition.write_blocks(2, &[7; 512]).is_err());
+        }
+        assert_eq!(0, data[511]);
+        assert_eq!(7, data[512]);
+        assert_eq!(7, data[1023]);
+        assert_eq!(0, data[1024]);
+    }
+}
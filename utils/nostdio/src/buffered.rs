@@ -0,0 +1,349 @@
+use core::mem::ManuallyDrop;
+
+use crate::{BufRead, NoStdIoError, Read, Result, Seek, SeekFrom, Write};
+
+pub const DEFAULT_BUF_SIZE: usize = 512;
+
+/// Adds buffering to any reader.
+///
+/// The buffer is stored inline, its size is given by `N`. It is based on the
+/// `std::io::BufReader` struct.
+pub struct BufReader<R, const N: usize = DEFAULT_BUF_SIZE> {
+    inner: R,
+    buf: [u8; N],
+    pos: usize,
+    filled: usize,
+}
+
+impl<R: Read> BufReader<R, DEFAULT_BUF_SIZE> {
+    pub fn new(inner: R) -> Self {
+        Self::with_buffer(inner)
+    }
+}
+
+impl<R: Read, const N: usize> BufReader<R, N> {
+    /// Creates a new `BufReader` with a buffer of `N` bytes.
+    pub fn with_buffer(inner: R) -> Self {
+        Self {
+            inner,
+            buf: [0; N],
+            pos: 0,
+            filled: 0,
+        }
+    }
+
+    /// Returns the currently buffered data without reading more.
+    pub fn buffer(&self) -> &[u8] {
+        self.buf.get(self.pos..self.filled).unwrap_or(&[])
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Gets a mutable reference to the underlying reader. Reading directly from
+    /// it bypasses any data left in the buffer.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// Unwraps this `BufReader`, returning the underlying reader. Any buffered
+    /// data is lost.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    fn discard_buffer(&mut self) {
+        self.pos = 0;
+        self.filled = 0;
+    }
+}
+
+impl<R: Read, const N: usize> Read for BufReader<R, N> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        // bypass our buffer entirely for large reads when it is empty
+        if self.pos == self.filled && buf.len() >= N {
+            self.discard_buffer();
+            return self.inner.read(buf);
+        }
+        let available = self.fill_buf()?;
+        let len = available.len().min(buf.len());
+        buf.get_mut(..len)
+            .ok_or(NoStdIoError::Other)?
+            .copy_from_slice(available.get(..len).ok_or(NoStdIoError::Other)?);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl<R: Read, const N: usize> BufRead for BufReader<R, N> {
+    fn fill_buf(&mut self) -> Result<&[u8]> {
+        if self.pos >= self.filled {
+            self.filled = self.inner.read(&mut self.buf)?;
+            self.pos = 0;
+        }
+        self.buf
+            .get(self.pos..self.filled)
+            .ok_or(NoStdIoError::Other)
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.filled);
+    }
+}
+
+impl<R: Read + Seek, const N: usize> Seek for BufReader<R, N> {
+    /// Seeking always discards the internal buffer. `SeekFrom::Current` is
+    /// relative to the position of the data returned by `read`, not the
+    /// position of the underlying reader.
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        let result = if let SeekFrom::Current(n) = pos {
+            let remainder =
+                i64::try_from(self.filled - self.pos).map_err(|_| NoStdIoError::InvalidInput)?;
+            let n = n.checked_sub(remainder).ok_or(NoStdIoError::InvalidInput)?;
+            self.inner.seek(SeekFrom::Current(n))?
+        } else {
+            self.inner.seek(pos)?
+        };
+        self.discard_buffer();
+        Ok(result)
+    }
+}
+
+/// Wraps a writer and buffers its output.
+///
+/// The buffer is stored inline, its size is given by `N`. Buffered data is
+/// written out when the buffer is full, on [`Write::flush`], on
+/// [`BufWriter::into_inner`] and, ignoring any error, when dropped. It is based
+/// on the `std::io::BufWriter` struct.
+pub struct BufWriter<W: Write, const N: usize = DEFAULT_BUF_SIZE> {
+    inner: W,
+    buf: [u8; N],
+    len: usize,
+}
+
+impl<W: Write> BufWriter<W, DEFAULT_BUF_SIZE> {
+    pub fn new(inner: W) -> Self {
+        Self::with_buffer(inner)
+    }
+}
+
+impl<W: Write, const N: usize> BufWriter<W, N> {
+    /// Creates a new `BufWriter` with a buffer of `N` bytes.
+    pub fn with_buffer(inner: W) -> Self {
+        Self {
+            inner,
+            buf: [0; N],
+            len: 0,
+        }
+    }
+
+    /// Returns the data waiting to be written.
+    pub fn buffer(&self) -> &[u8] {
+        self.buf.get(..self.len).unwrap_or(&[])
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Gets a mutable reference to the underlying writer. Writing directly to it
+    /// may reorder data relative to what is still buffered.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Writes out the buffer and returns the underlying writer.
+    pub fn into_inner(mut self) -> Result<W> {
+        self.flush_buf()?;
+        let this = ManuallyDrop::new(self);
+        // SAFETY: `this` is never dropped, so `inner` is moved out exactly once
+        Ok(unsafe { core::ptr::read(&this.inner) })
+    }
+
+    /// Writes the buffered data to the inner writer. On error, the bytes that
+    /// were not written stay in the buffer.
+    fn flush_buf(&mut self) -> Result<()> {
+        let mut written = 0;
+        let mut result = Ok(());
+        while written < self.len {
+            let data = self.buf.get(written..self.len).ok_or(NoStdIoError::Other)?;
+            match self.inner.write(data) {
+                Ok(0) => {
+                    result = Err(NoStdIoError::WriteZero);
+                    break;
+                }
+                Ok(n) => written += n,
+                Err(NoStdIoError::Interrupted) => {}
+                Err(err) => {
+                    result = Err(err);
+                    break;
+                }
+            }
+        }
+        if written > 0 {
+            self.buf.copy_within(written..self.len, 0);
+            self.len -= written;
+        }
+        result
+    }
+}
+
+impl<W: Write, const N: usize> Write for BufWriter<W, N> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if self.len + buf.len() > N {
+            self.flush_buf()?;
+        }
+        if buf.len() >= N {
+            return self.inner.write(buf);
+        }
+        self.buf
+            .get_mut(self.len..self.len + buf.len())
+            .ok_or(NoStdIoError::Other)?
+            .copy_from_slice(buf);
+        self.len += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.flush_buf()?;
+        self.inner.flush()
+    }
+}
+
+impl<W: Write + Seek, const N: usize> Seek for BufWriter<W, N> {
+    /// Writes out the buffer before seeking.
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        self.flush_buf()?;
+        self.inner.seek(pos)
+    }
+}
+
+impl<W: Write, const N: usize> Drop for BufWriter<W, N> {
+    fn drop(&mut self) {
+        let _ = self.flush_buf();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{string::String, vec::Vec};
+
+    use crate::Cursor;
+
+    use super::*;
+
+    struct CountingWriter {
+        data: Vec<u8>,
+        writes: usize,
+    }
+
+    impl Write for CountingWriter {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            self.writes += 1;
+            self.data.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+    }
+
+    #[test]
+    fn test_read_line() {
+        let mut data = *b"first line\nsecond\n\nlast";
+        let mut reader = BufReader::<_, 4>::with_buffer(Cursor::new(&mut data));
+
+        let mut line = String::new();
+        assert_eq!(11, reader.read_line(&mut line).unwrap());
+        assert_eq!("first line\n", line);
+
+        line.clear();
+        assert_eq!(7, reader.read_line(&mut line).unwrap());
+        assert_eq!("second\n", line);
+
+        line.clear();
+        assert_eq!(1, reader.read_line(&mut line).unwrap());
+        assert_eq!("\n", line);
+
+        line.clear();
+        assert_eq!(4, reader.read_line(&mut line).unwrap());
+        assert_eq!("last", line);
+
+        line.clear();
+        assert_eq!(0, reader.read_line(&mut line).unwrap());
+    }
+
+    #[test]
+    fn test_read_exact() {
+        let mut data = [0; 100];
+        for i in 0..data.len() {
+            data[i] = i as u8;
+        }
+        let mut reader = BufReader::<_, 16>::with_buffer(Cursor::new(&mut data));
+
+        let mut buf = [0; 30];
+        reader.read_exact(&mut buf).unwrap();
+        for i in 0..buf.len() {
+            assert_eq!(i as u8, buf[i]);
+        }
+
+        let mut buf = [0; 80];
+        assert_eq!(
+            NoStdIoError::UnexpectedEof,
+            reader.read_exact(&mut buf).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn test_seek() {
+        let mut data = [0; 100];
+        for i in 0..data.len() {
+            data[i] = i as u8;
+        }
+        let mut reader = BufReader::<_, 16>::with_buffer(Cursor::new(&mut data));
+
+        let mut buf = [0; 4];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(12, reader.buffer().len());
+        assert_eq!(6, reader.seek(SeekFrom::Current(2)).unwrap());
+        assert_eq!(0, reader.buffer().len());
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!([6, 7, 8, 9], buf);
+    }
+
+    #[test]
+    fn test_buf_writer() {
+        let mut writer = BufWriter::<_, 8>::with_buffer(CountingWriter {
+            data: Vec::new(),
+            writes: 0,
+        });
+
+        writer.write_all(b"abc").unwrap();
+        writer.write_all(b"def").unwrap();
+        assert_eq!(0, writer.get_ref().writes);
+        assert_eq!(b"abcdef", writer.buffer());
+
+        // doesn't fit, flushes then buffers
+        writer.write_all(b"ghi").unwrap();
+        assert_eq!(1, writer.get_ref().writes);
+        assert_eq!(b"ghi", writer.buffer());
+
+        // larger than the buffer, flushes then bypasses
+        writer.write_all(b"0123456789").unwrap();
+        assert_eq!(3, writer.get_ref().writes);
+
+        writer.write_all(b"xyz").unwrap();
+        let inner = writer.into_inner().unwrap();
+        assert_eq!(4, inner.writes);
+        assert_eq!(b"abcdefghi0123456789xyz", inner.data.as_slice());
+    }
+
+    #[test]
+    fn test_write_all_storage_full() {
+        let mut data = [0; 4];
+        let mut cursor = Cursor::new(&mut data);
+        assert_eq!(
+            NoStdIoError::WriteZero,
+            cursor.write_all(b"hello").unwrap_err()
+        );
+        assert_eq!(*b"hell", data);
+    }
+}
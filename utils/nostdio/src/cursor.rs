@@ -1,4 +1,6 @@
-use crate::{NoStdIoError, OffsetWrite, Read, Result, Seek, SeekFrom, Write, offset::OffsetRead};
+use crate::{
+    BufRead, NoStdIoError, OffsetWrite, Read, Result, Seek, SeekFrom, Write, offset::OffsetRead,
+};
 
 pub struct Cursor<'a> {
     data: &'a mut [u8],
@@ -9,6 +11,15 @@ impl<'a> Cursor<'a> {
     pub fn new(data: &'a mut [u8]) -> Self {
         Self { data, pos: 0 }
     }
+
+    /// Length of the underlying data.
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
 }
 
 impl<'a> Read for Cursor<'a> {
@@ -31,6 +42,20 @@ impl<'a> Read for Cursor<'a> {
     }
 }
 
+impl<'a> BufRead for Cursor<'a> {
+    fn fill_buf(&mut self) -> Result<&[u8]> {
+        Ok(self.data.get(self.pos..).unwrap_or(&[]))
+    }
+
+    fn consume(&mut self, amt: usize) {
+        // never past the end, unless a seek already moved it there
+        self.pos = self
+            .pos
+            .saturating_add(amt)
+            .min(self.data.len().max(self.pos));
+    }
+}
+
 impl<'a> Write for Cursor<'a> {
     fn write(&mut self, buf: &[u8]) -> Result<usize> {
         let start = self.pos;
@@ -159,6 +184,18 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_consume_past_end() {
+        let mut data = [0; 100];
+        let mut cursor = Cursor::new(&mut data);
+
+        cursor.consume(90);
+        assert_eq!(10, cursor.fill_buf().unwrap().len());
+        cursor.consume(usize::MAX);
+        assert_eq!(100, cursor.seek(SeekFrom::Current(0)).unwrap());
+        assert!(cursor.fill_buf().unwrap().is_empty());
+    }
+
     #[test]
     fn test_write_past_end() {
         let mut data = [0; 100];
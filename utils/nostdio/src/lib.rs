@@ -10,20 +10,51 @@
     clippy::cast_possible_truncation
 )]
 
+#[cfg(any(test, feature = "alloc"))]
+extern crate alloc;
+
+mod block;
+mod buffered;
 mod cursor;
 mod offset;
+pub mod partition;
+mod take;
+
+#[cfg(any(test, feature = "alloc"))]
+use alloc::{string::String, vec::Vec};
 
+pub use block::{BlockDevice, MAX_BLOCK_SIZE, OffsetBlockDevice, PartitionView};
+pub use buffered::{BufReader, BufWriter, DEFAULT_BUF_SIZE};
 pub use cursor::Cursor;
 pub use offset::{OffsetRead, OffsetWrite};
+pub use take::Take;
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum NoStdIoError {
     InvalidInput,
+    InvalidData,
     StorageFull,
     UnexpectedEof,
+    WriteZero,
+    Interrupted,
     Other,
 }
 
+#[cfg(feature = "std")]
+impl From<std::io::Error> for NoStdIoError {
+    fn from(err: std::io::Error) -> Self {
+        match err.kind() {
+            std::io::ErrorKind::InvalidInput => NoStdIoError::InvalidInput,
+            std::io::ErrorKind::InvalidData => NoStdIoError::InvalidData,
+            std::io::ErrorKind::StorageFull => NoStdIoError::StorageFull,
+            std::io::ErrorKind::UnexpectedEof => NoStdIoError::UnexpectedEof,
+            std::io::ErrorKind::WriteZero => NoStdIoError::WriteZero,
+            std::io::ErrorKind::Interrupted => NoStdIoError::Interrupted,
+            _ => NoStdIoError::Other,
+        }
+    }
+}
+
 pub type Result<T> = core::result::Result<T, NoStdIoError>;
 
 /// Enumeration of possible methods to seek within an I/O object.
@@ -116,6 +147,146 @@ pub trait Read {
     /// An error of the [`ErrorKind::Interrupted`] kind is non-fatal and the read
     /// operation should be retried if there is nothing else to do.
     fn read(&mut self, buf: &mut [u8]) -> Result<usize>;
+
+    /// Read the exact number of bytes required to fill `buf`.
+    ///
+    /// Calls [`Read::read`] until the buffer is full, retrying on
+    /// [`NoStdIoError::Interrupted`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`NoStdIoError::UnexpectedEof`] if the reader reaches its end before
+    /// `buf` is filled. The contents of `buf` are unspecified in that case.
+    fn read_exact(&mut self, mut buf: &mut [u8]) -> Result<()> {
+        while !buf.is_empty() {
+            match self.read(buf) {
+                Ok(0) => return Err(NoStdIoError::UnexpectedEof),
+                Ok(n) => {
+                    buf = buf.get_mut(n..).ok_or(NoStdIoError::Other)?;
+                }
+                Err(NoStdIoError::Interrupted) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    /// Read all bytes until the end of the source, appending them to `buf`.
+    ///
+    /// Returns the number of bytes appended.
+    #[cfg(any(test, feature = "alloc"))]
+    fn read_to_end(&mut self, buf: &mut Vec<u8>) -> Result<usize> {
+        let start_len = buf.len();
+        let mut chunk = [0; DEFAULT_BUF_SIZE];
+        loop {
+            match self.read(&mut chunk) {
+                Ok(0) => return Ok(buf.len() - start_len),
+                Ok(n) => buf.extend_from_slice(chunk.get(..n).ok_or(NoStdIoError::Other)?),
+                Err(NoStdIoError::Interrupted) => {}
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Creates an adapter which will read at most `limit` bytes from it.
+    fn take(self, limit: u64) -> Take<Self>
+    where
+        Self: Sized,
+    {
+        Take::new(self, limit)
+    }
+
+    /// Creates a "by reference" adapter for this instance of `Read`.
+    fn by_ref(&mut self) -> &mut Self
+    where
+        Self: Sized,
+    {
+        self
+    }
+}
+
+impl<R: Read + ?Sized> Read for &mut R {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        (**self).read(buf)
+    }
+}
+
+/// A `BufRead` is a type of [`Read`]er which has an internal buffer, allowing it
+/// to perform extra ways of reading, such as reading a line at a time.
+///
+/// It is based on the `std::io::BufRead` trait.
+pub trait BufRead: Read {
+    /// Returns the contents of the internal buffer, filling it with more data
+    /// from the inner reader if it is empty.
+    ///
+    /// An empty slice returned indicates that the end of the stream was reached.
+    /// The bytes are not consumed until [`BufRead::consume`] is called.
+    fn fill_buf(&mut self) -> Result<&[u8]>;
+
+    /// Tells this buffer that `amt` bytes have been consumed from the buffer, so
+    /// they should no longer be returned in calls to `read`.
+    fn consume(&mut self, amt: usize);
+
+    /// Read all bytes into `buf` until the delimiter `byte` or EOF is reached.
+    ///
+    /// The delimiter, if found, is appended to `buf`. Returns the number of
+    /// bytes read.
+    #[cfg(any(test, feature = "alloc"))]
+    fn read_until(&mut self, byte: u8, buf: &mut Vec<u8>) -> Result<usize> {
+        let mut read = 0;
+        loop {
+            let (done, used) = {
+                let available = match self.fill_buf() {
+                    Ok(available) => available,
+                    Err(NoStdIoError::Interrupted) => continue,
+                    Err(err) => return Err(err),
+                };
+                match available.iter().position(|b| *b == byte) {
+                    Some(i) => {
+                        buf.extend_from_slice(available.get(..=i).ok_or(NoStdIoError::Other)?);
+                        (true, i + 1)
+                    }
+                    None => {
+                        buf.extend_from_slice(available);
+                        (available.is_empty(), available.len())
+                    }
+                }
+            };
+            self.consume(used);
+            read += used;
+            if done {
+                return Ok(read);
+            }
+        }
+    }
+
+    /// Read all bytes until a newline (the `0xA` byte) is reached, and append
+    /// them to the provided `String` buffer, including the newline.
+    ///
+    /// Returns the number of bytes read, `0` meaning EOF was reached.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`NoStdIoError::InvalidData`] if the line is not valid UTF-8, in
+    /// which case `buf` is left unchanged.
+    #[cfg(any(test, feature = "alloc"))]
+    fn read_line(&mut self, buf: &mut String) -> Result<usize> {
+        let mut bytes = Vec::new();
+        let read = self.read_until(b'\n', &mut bytes)?;
+        let line = core::str::from_utf8(&bytes).map_err(|_| NoStdIoError::InvalidData)?;
+        buf.push_str(line);
+        Ok(read)
+    }
+}
+
+impl<B: BufRead + ?Sized> BufRead for &mut B {
+    fn fill_buf(&mut self) -> Result<&[u8]> {
+        (**self).fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        (**self).consume(amt)
+    }
 }
 
 pub trait Write {
@@ -148,6 +319,53 @@ pub trait Write {
     /// An error of the [`ErrorKind::Interrupted`] kind is non-fatal and the
     /// write operation should be retried if there is nothing else to do.
     fn write(&mut self, buf: &[u8]) -> Result<usize>;
+
+    /// Flush this output stream, ensuring that all intermediately buffered
+    /// contents reach their destination.
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Attempts to write an entire buffer into this writer.
+    ///
+    /// Calls [`Write::write`] until the whole buffer has been written, retrying
+    /// on [`NoStdIoError::Interrupted`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`NoStdIoError::WriteZero`] if the writer stops accepting bytes
+    /// before all of `buf` was written.
+    fn write_all(&mut self, mut buf: &[u8]) -> Result<()> {
+        while !buf.is_empty() {
+            match self.write(buf) {
+                Ok(0) => return Err(NoStdIoError::WriteZero),
+                Ok(n) => {
+                    buf = buf.get(n..).ok_or(NoStdIoError::Other)?;
+                }
+                Err(NoStdIoError::Interrupted) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    /// Creates a "by reference" adapter for this instance of `Write`.
+    fn by_ref(&mut self) -> &mut Self
+    where
+        Self: Sized,
+    {
+        self
+    }
+}
+
+impl<W: Write + ?Sized> Write for &mut W {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        (**self).write(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        (**self).flush()
+    }
 }
 
 pub trait Seek {
@@ -166,33 +384,41 @@ pub trait Seek {
     ///
     /// Seeking to a negative offset is considered an error.
     fn seek(&mut self, pos: SeekFrom) -> Result<u64>;
+
+    /// Returns the current seek position from the start of the stream.
+    fn stream_position(&mut self) -> Result<u64> {
+        self.seek(SeekFrom::Current(0))
+    }
+}
+
+impl<S: Seek + ?Sized> Seek for &mut S {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        (**self).seek(pos)
+    }
 }
 
 #[cfg(feature = "std")]
 impl Read for std::fs::File {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
-        (self as &mut dyn std::io::Read)
-            .read(buf)
-            .map_err(IoError::StdIoError)
+        Ok(std::io::Read::read(self, buf)?)
     }
 }
 
 #[cfg(feature = "std")]
 impl Write for std::fs::File {
     fn write(&mut self, buf: &[u8]) -> Result<usize> {
-        (self as &mut dyn std::io::Write)
-            .write(buf)
-            .map_err(IoError::StdIoError)
+        Ok(std::io::Write::write(self, buf)?)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(std::io::Write::flush(self)?)
     }
 }
 
 #[cfg(feature = "std")]
 impl Seek for std::fs::File {
     fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
-        let new_offset = (self as &mut dyn std::io::Seek)
-            .seek(pos.into())
-            .map_err(IoError::StdIoError)?;
-        Ok(new_offset)
+        Ok(std::io::Seek::seek(self, pos.into())?)
     }
 }
 
@@ -10,4 +10,45 @@ pub trait OffsetWrite {
     /// Writes data from the given buffer into self starting at the given offset.
     /// Returns the number of bytes written to self.
     fn write_at_offset(&mut self, offset: u64, buf: &[u8]) -> Result<usize>;
+
+    /// Make sure all previous writes have reached the underlying storage.
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl OffsetRead for std::fs::File {
+    fn read_at_offset(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        use std::io::{Read, Seek, SeekFrom};
+
+        let mut file = self;
+        file.seek(SeekFrom::Start(offset))?;
+        let mut read = 0;
+        while read < buf.len() {
+            let remaining = buf.get_mut(read..).ok_or(crate::NoStdIoError::Other)?;
+            match file.read(remaining) {
+                Ok(0) => break,
+                Ok(n) => read += n,
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(read)
+    }
+}
+
+#[cfg(feature = "std")]
+impl OffsetWrite for std::fs::File {
+    fn write_at_offset(&mut self, offset: u64, buf: &[u8]) -> Result<usize> {
+        use std::io::{Seek, SeekFrom, Write};
+
+        self.seek(SeekFrom::Start(offset))?;
+        self.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(self.sync_data()?)
+    }
 }
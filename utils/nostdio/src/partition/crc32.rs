@@ -0,0 +1,63 @@
+// CRC-32 (IEEE 802.3) as used by GPT headers and partition entry arrays.
+
+const POLYNOMIAL: u32 = 0xedb8_8320;
+
+const TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i: u32 = 0;
+    while i < 256 {
+        let mut crc = i;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ POLYNOMIAL
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        #[allow(clippy::indexing_slicing)]
+        {
+            table[i as usize] = crc;
+        }
+        i += 1;
+    }
+    table
+};
+
+pub(crate) struct Crc32(u32);
+
+impl Crc32 {
+    pub(crate) fn new() -> Self {
+        Self(0xffff_ffff)
+    }
+
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        for b in data {
+            let idx = ((self.0 ^ *b as u32) & 0xff) as usize;
+            self.0 = TABLE.get(idx).copied().unwrap_or(0) ^ (self.0 >> 8);
+        }
+    }
+
+    pub(crate) fn finish(&self) -> u32 {
+        !self.0
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(data);
+    crc.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(0, crc32(b""));
+        assert_eq!(0xcbf4_3926, crc32(b"123456789"));
+    }
+}
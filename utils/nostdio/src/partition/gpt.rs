@@ -0,0 +1,558 @@
+use core::fmt::Debug;
+
+use uuid::Uuid;
+use zerocopy::{
+    FromBytes, Immutable, IntoBytes, KnownLayout,
+    little_endian::{U16, U32, U64},
+};
+
+use crate::{
+    BlockDevice, NoStdIoError, Result,
+    partition::{Partition, crc32::Crc32},
+};
+
+const GPT_HEADER_SIZE: usize = core::mem::size_of::<GptHeaderRaw>();
+const GPT_ENTRY_SIZE: usize = core::mem::size_of::<GptEntryRaw>();
+const GPT_SIGNATURE: [u8; 8] = *b"EFI PART";
+const GPT_NAME_LEN: usize = 36;
+/// Upper bound on the entry count, the spec requires at least 128 entries so
+/// anything far past that is a corrupted header.
+const GPT_MAX_ENTRIES: u32 = 1024;
+/// Upper bound on the entry size, entries are 128 bytes times a power of two
+/// and nothing uses more than 128.
+const GPT_MAX_ENTRY_SIZE: usize = 4096;
+
+/// Well known GPT partition type GUIDs.
+pub mod gpt_type {
+    use uuid::{Uuid, uuid};
+
+    pub const UNUSED: Uuid = Uuid::nil();
+    pub const EFI_SYSTEM: Uuid = uuid!("c12a7328-f81f-11d2-ba4b-00a0c93ec93b");
+    pub const BIOS_BOOT: Uuid = uuid!("21686148-6449-6e6f-744e-656564454649");
+    pub const MICROSOFT_BASIC_DATA: Uuid = uuid!("ebd0a0a2-b9e5-4433-87c0-68b6b72699c7");
+    pub const LINUX_FILESYSTEM: Uuid = uuid!("0fc63daf-8483-4772-8e79-3d69d8477de4");
+    pub const LINUX_SWAP: Uuid = uuid!("0657fd6d-a4ab-43c4-84e5-0933c84b4f4f");
+}
+
+#[repr(C, packed)]
+#[derive(Clone, IntoBytes, FromBytes, Immutable, KnownLayout)]
+struct GptHeaderRaw {
+    /// "EFI PART"
+    signature: [u8; 8],
+    /// 0x00010000 for version 1.0
+    revision: U32,
+    /// Size of this header in bytes, at least 92
+    header_size: U32,
+    /// CRC32 of the header with this field zeroed
+    header_crc32: U32,
+    reserved: U32,
+    /// LBA containing this header
+    my_lba: U64,
+    /// LBA of the other (backup or primary) header
+    alternate_lba: U64,
+    /// First LBA usable by partitions
+    first_usable_lba: U64,
+    /// Last LBA usable by partitions
+    last_usable_lba: U64,
+    /// Mixed endian GUID of the disk
+    disk_guid: [u8; 16],
+    /// Start of the partition entry array
+    partition_entry_lba: U64,
+    /// Number of entries in the partition entry array
+    number_of_partition_entries: U32,
+    /// Size of a single entry, 128 * 2^n
+    size_of_partition_entry: U32,
+    /// CRC32 of the partition entry array
+    partition_entry_array_crc32: U32,
+}
+
+#[repr(C, packed)]
+#[derive(Clone, IntoBytes, FromBytes, Immutable, KnownLayout)]
+struct GptEntryRaw {
+    /// Mixed endian partition type GUID, nil if unused
+    partition_type_guid: [u8; 16],
+    /// Mixed endian GUID unique to this partition
+    unique_partition_guid: [u8; 16],
+    starting_lba: U64,
+    /// Inclusive
+    ending_lba: U64,
+    attributes: U64,
+    /// UTF-16LE, nul padded
+    partition_name: [U16; GPT_NAME_LEN],
+}
+
+/// A GUID partition table header.
+#[derive(Debug, Clone)]
+pub struct Gpt {
+    disk_guid: Uuid,
+    header_lba: u64,
+    first_usable_lba: u64,
+    last_usable_lba: u64,
+    partition_entry_lba: u64,
+    number_of_partition_entries: u32,
+    size_of_partition_entry: u32,
+}
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct GptPartition {
+    /// Index in the partition entry array
+    pub index: u32,
+    pub type_guid: Uuid,
+    pub unique_guid: Uuid,
+    pub first_lba: u64,
+    /// Inclusive
+    pub last_lba: u64,
+    pub attributes: u64,
+    name: [u16; GPT_NAME_LEN],
+}
+
+impl Gpt {
+    /// Reads the primary GPT header at LBA 1, falling back to the backup header
+    /// in the last block if the primary one is damaged. Both the header and the
+    /// partition entry array checksums are verified.
+    pub fn read<D: BlockDevice>(device: &D) -> Result<Self> {
+        match Self::read_header(device, 1) {
+            Ok(gpt) => Ok(gpt),
+            Err(err) => {
+                let last_lba = device.block_count().checked_sub(1).ok_or(err)?;
+                Self::read_header(device, last_lba)
+            }
+        }
+    }
+
+    fn read_header<D: BlockDevice>(device: &D, lba: u64) -> Result<Self> {
+        let block_size = device.block_size();
+        let mut buf = [0; GPT_HEADER_SIZE];
+        device.read_bytes(lba * block_size as u64, &mut buf)?;
+        let header = GptHeaderRaw::read_from_bytes(&buf).map_err(|_| NoStdIoError::InvalidData)?;
+
+        if header.signature != GPT_SIGNATURE || header.my_lba.get() != lba {
+            return Err(NoStdIoError::InvalidData);
+        }
+        let header_size =
+            usize::try_from(header.header_size.get()).map_err(|_| NoStdIoError::InvalidData)?;
+        if header_size < GPT_HEADER_SIZE || header_size > block_size {
+            return Err(NoStdIoError::InvalidData);
+        }
+
+        // the crc covers header_size bytes, which may be more than the fields we know
+        let mut crc = Crc32::new();
+        let mut zeroed = header.clone();
+        zeroed.header_crc32 = U32::new(0);
+        crc.update(zeroed.as_bytes());
+        let mut remaining = header_size - GPT_HEADER_SIZE;
+        let mut offset = lba * block_size as u64 + GPT_HEADER_SIZE as u64;
+        let mut chunk = [0; 64];
+        while remaining > 0 {
+            let len = remaining.min(chunk.len());
+            let chunk = chunk.get_mut(..len).ok_or(NoStdIoError::Other)?;
+            device.read_bytes(offset, chunk)?;
+            crc.update(chunk);
+            remaining -= len;
+            offset += len as u64;
+        }
+        if crc.finish() != header.header_crc32.get() {
+            return Err(NoStdIoError::InvalidData);
+        }
+
+        let entry_size = header.size_of_partition_entry.get();
+        if !(GPT_ENTRY_SIZE..=GPT_MAX_ENTRY_SIZE).contains(&(entry_size as usize))
+            || entry_size as usize % GPT_ENTRY_SIZE != 0
+            || !entry_size.is_power_of_two()
+            || header.number_of_partition_entries.get() > GPT_MAX_ENTRIES
+        {
+            return Err(NoStdIoError::InvalidData);
+        }
+
+        let gpt = Self {
+            disk_guid: Uuid::from_bytes_le(header.disk_guid),
+            header_lba: lba,
+            first_usable_lba: header.first_usable_lba.get(),
+            last_usable_lba: header.last_usable_lba.get(),
+            partition_entry_lba: header.partition_entry_lba.get(),
+            number_of_partition_entries: header.number_of_partition_entries.get(),
+            size_of_partition_entry: entry_size,
+        };
+
+        if gpt.entries_crc32(device)? != header.partition_entry_array_crc32.get() {
+            return Err(NoStdIoError::InvalidData);
+        }
+
+        Ok(gpt)
+    }
+
+    fn entries_crc32<D: BlockDevice>(&self, device: &D) -> Result<u32> {
+        let mut crc = Crc32::new();
+        let mut chunk = [0; GPT_ENTRY_SIZE];
+        let mut remaining =
+            self.number_of_partition_entries as u64 * self.size_of_partition_entry as u64;
+        let mut offset = self.entries_offset(device);
+        while remaining > 0 {
+            let len = usize::try_from(remaining)
+                .unwrap_or(usize::MAX)
+                .min(chunk.len());
+            let chunk = chunk.get_mut(..len).ok_or(NoStdIoError::Other)?;
+            device.read_bytes(offset, chunk)?;
+            crc.update(chunk);
+            remaining -= len as u64;
+            offset += len as u64;
+        }
+        Ok(crc.finish())
+    }
+
+    fn entries_offset<D: BlockDevice>(&self, device: &D) -> u64 {
+        self.partition_entry_lba * device.block_size() as u64
+    }
+
+    pub fn disk_guid(&self) -> Uuid {
+        self.disk_guid
+    }
+
+    /// LBA the header was read from, 1 unless the backup header was used.
+    pub fn header_lba(&self) -> u64 {
+        self.header_lba
+    }
+
+    pub fn first_usable_lba(&self) -> u64 {
+        self.first_usable_lba
+    }
+
+    pub fn last_usable_lba(&self) -> u64 {
+        self.last_usable_lba
+    }
+
+    /// Iterates over the used partition entries, reading them from `device`.
+    pub fn partitions<'a, D: BlockDevice>(&self, device: &'a D) -> GptPartitionIterator<'a, D> {
+        GptPartitionIterator {
+            device,
+            entries_offset: self.entries_offset(device),
+            entry_size: self.size_of_partition_entry,
+            entry_count: self.number_of_partition_entries,
+            next_index: 0,
+        }
+    }
+}
+
+pub struct GptPartitionIterator<'a, D: BlockDevice> {
+    device: &'a D,
+    entries_offset: u64,
+    entry_size: u32,
+    entry_count: u32,
+    next_index: u32,
+}
+
+impl<'a, D: BlockDevice> Iterator for GptPartitionIterator<'a, D> {
+    type Item = Result<GptPartition>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.next_index < self.entry_count {
+            let index = self.next_index;
+            self.next_index += 1;
+
+            let mut buf = [0; GPT_ENTRY_SIZE];
+            let offset = self.entries_offset + index as u64 * self.entry_size as u64;
+            if let Err(err) = self.device.read_bytes(offset, &mut buf) {
+                return Some(Err(err));
+            }
+            let entry = match GptEntryRaw::read_from_bytes(&buf) {
+                Ok(entry) => entry,
+                Err(_) => return Some(Err(NoStdIoError::InvalidData)),
+            };
+
+            let type_guid = Uuid::from_bytes_le(entry.partition_type_guid);
+            if type_guid == gpt_type::UNUSED {
+                continue;
+            }
+            if entry.ending_lba.get() < entry.starting_lba.get() {
+                return Some(Err(NoStdIoError::InvalidData));
+            }
+
+            return Some(Ok(GptPartition {
+                index,
+                type_guid,
+                unique_guid: Uuid::from_bytes_le(entry.unique_partition_guid),
+                first_lba: entry.starting_lba.get(),
+                last_lba: entry.ending_lba.get(),
+                attributes: entry.attributes.get(),
+                name: entry.partition_name.map(|c| c.get()),
+            }));
+        }
+        None
+    }
+}
+
+impl GptPartition {
+    /// The partition name, invalid UTF-16 is replaced with U+FFFD.
+    pub fn name(&self) -> impl Iterator<Item = char> + '_ {
+        let len = self
+            .name
+            .iter()
+            .position(|c| *c == 0)
+            .unwrap_or(GPT_NAME_LEN);
+        char::decode_utf16(self.name.iter().take(len).copied())
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+    }
+
+    /// Compares the partition name with `name`.
+    pub fn name_eq(&self, name: &str) -> bool {
+        self.name().eq(name.chars())
+    }
+}
+
+impl Partition for GptPartition {
+    fn start_lba(&self) -> u64 {
+        self.first_lba
+    }
+
+    fn block_count(&self) -> u64 {
+        self.last_lba
+            .saturating_add(1)
+            .saturating_sub(self.first_lba)
+    }
+}
+
+impl Debug for GptPartition {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        struct Name<'a>(&'a GptPartition);
+        impl Debug for Name<'_> {
+            fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                use core::fmt::Write;
+                f.write_char('"')?;
+                for c in self.0.name() {
+                    f.write_char(c)?;
+                }
+                f.write_char('"')
+            }
+        }
+
+        f.debug_struct("GptPartition")
+            .field("index", &self.index)
+            .field("type_guid", &self.type_guid)
+            .field("unique_guid", &self.unique_guid)
+            .field("first_lba", &self.first_lba)
+            .field("last_lba", &self.last_lba)
+            .field("attributes", &format_args!("0x{:016x}", self.attributes))
+            .field("name", &Name(self))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use uuid::uuid;
+
+    use crate::{
+        Cursor, OffsetBlockDevice,
+        partition::{
+            PartitionTable, crc32::crc32, mbr::tests::write_mbr, mbr_type, read_partition_table,
+        },
+    };
+
+    use super::*;
+
+    const BLOCK_SIZE: usize = 512;
+    const BLOCK_COUNT: usize = 128;
+    const ENTRY_COUNT: usize = 128;
+    const ENTRIES_BLOCKS: usize = ENTRY_COUNT * GPT_ENTRY_SIZE / BLOCK_SIZE;
+
+    fn write_entry(
+        entries: &mut [u8],
+        idx: usize,
+        type_guid: Uuid,
+        first: u64,
+        last: u64,
+        name: &str,
+    ) {
+        let entry = &mut entries[idx * GPT_ENTRY_SIZE..(idx + 1) * GPT_ENTRY_SIZE];
+        entry[0..16].copy_from_slice(&type_guid.to_bytes_le());
+        entry[16..32].copy_from_slice(&Uuid::from_u128(idx as u128 + 1).to_bytes_le());
+        entry[32..40].copy_from_slice(&first.to_le_bytes());
+        entry[40..48].copy_from_slice(&last.to_le_bytes());
+        for (i, c) in name.encode_utf16().enumerate() {
+            entry[56 + i * 2..58 + i * 2].copy_from_slice(&c.to_le_bytes());
+        }
+    }
+
+    fn write_header(
+        data: &mut [u8],
+        lba: u64,
+        alternate_lba: u64,
+        entries_lba: u64,
+        entries_crc: u32,
+    ) {
+        let offset = lba as usize * BLOCK_SIZE;
+        let header = &mut data[offset..offset + GPT_HEADER_SIZE];
+        header[0..8].copy_from_slice(&GPT_SIGNATURE);
+        header[8..12].copy_from_slice(&0x0001_0000u32.to_le_bytes());
+        header[12..16].copy_from_slice(&(GPT_HEADER_SIZE as u32).to_le_bytes());
+        header[24..32].copy_from_slice(&lba.to_le_bytes());
+        header[32..40].copy_from_slice(&alternate_lba.to_le_bytes());
+        header[40..48].copy_from_slice(&(2 + ENTRIES_BLOCKS as u64).to_le_bytes());
+        header[48..56].copy_from_slice(&((BLOCK_COUNT - 2 - ENTRIES_BLOCKS) as u64).to_le_bytes());
+        header[56..72]
+            .copy_from_slice(&uuid!("12345678-1234-5678-9abc-def012345678").to_bytes_le());
+        header[72..80].copy_from_slice(&entries_lba.to_le_bytes());
+        header[80..84].copy_from_slice(&(ENTRY_COUNT as u32).to_le_bytes());
+        header[84..88].copy_from_slice(&(GPT_ENTRY_SIZE as u32).to_le_bytes());
+        header[88..92].copy_from_slice(&entries_crc.to_le_bytes());
+        let crc = crc32(header);
+        header[16..20].copy_from_slice(&crc.to_le_bytes());
+    }
+
+    fn create_disk() -> Vec<u8> {
+        create_disk_with(|_| {})
+    }
+
+    /// Creates the test disk, `edit` may change the entries before their
+    /// checksum is calculated.
+    fn create_disk_with(edit: impl FnOnce(&mut [u8])) -> Vec<u8> {
+        let mut data = alloc::vec![0; BLOCK_SIZE * BLOCK_COUNT];
+        write_mbr(
+            &mut data,
+            &[(0, mbr_type::GPT_PROTECTIVE, 1, BLOCK_COUNT as u32 - 1)],
+        );
+
+        let mut entries = [0; ENTRY_COUNT * GPT_ENTRY_SIZE];
+        write_entry(&mut entries, 0, gpt_type::EFI_SYSTEM, 34, 39, "EFI system");
+        write_entry(
+            &mut entries,
+            2,
+            gpt_type::LINUX_FILESYSTEM,
+            40,
+            59,
+            "rootfs",
+        );
+        edit(&mut entries);
+        let entries_crc = crc32(&entries);
+
+        let backup_entries_lba = BLOCK_COUNT - 1 - ENTRIES_BLOCKS;
+        data[2 * BLOCK_SIZE..(2 + ENTRIES_BLOCKS) * BLOCK_SIZE].copy_from_slice(&entries);
+        data[backup_entries_lba * BLOCK_SIZE..(BLOCK_COUNT - 1) * BLOCK_SIZE]
+            .copy_from_slice(&entries);
+        write_header(&mut data, 1, BLOCK_COUNT as u64 - 1, 2, entries_crc);
+        write_header(
+            &mut data,
+            BLOCK_COUNT as u64 - 1,
+            1,
+            backup_entries_lba as u64,
+            entries_crc,
+        );
+
+        data[40 * BLOCK_SIZE] = 0xab;
+        data
+    }
+
+    fn assert_partitions<D: BlockDevice>(gpt: &Gpt, device: &D) {
+        let partitions: Vec<GptPartition> = gpt.partitions(device).map(|p| p.unwrap()).collect();
+        assert_eq!(2, partitions.len());
+
+        assert_eq!(0, partitions[0].index);
+        assert_eq!(gpt_type::EFI_SYSTEM, partitions[0].type_guid);
+        assert!(partitions[0].name_eq("EFI system"));
+
+        assert_eq!(2, partitions[1].index);
+        assert_eq!(gpt_type::LINUX_FILESYSTEM, partitions[1].type_guid);
+        assert_eq!(Uuid::from_u128(3), partitions[1].unique_guid);
+        assert_eq!(40, partitions[1].first_lba);
+        assert_eq!(59, partitions[1].last_lba);
+        assert_eq!(20, partitions[1].block_count());
+        assert!(partitions[1].name_eq("rootfs"));
+    }
+
+    #[test]
+    fn test_read_gpt() {
+        let mut data = create_disk();
+        let mut device =
+            OffsetBlockDevice::from_cursor(Cursor::new(&mut data), BLOCK_SIZE).unwrap();
+
+        let gpt = match read_partition_table(&device).unwrap() {
+            Some(PartitionTable::Gpt(gpt)) => gpt,
+            _ => panic!("expected gpt"),
+        };
+        assert_eq!(1, gpt.header_lba());
+        assert_eq!(
+            uuid!("12345678-1234-5678-9abc-def012345678"),
+            gpt.disk_guid()
+        );
+        assert_partitions(&gpt, &device);
+
+        let rootfs = gpt
+            .partitions(&device)
+            .map(|p| p.unwrap())
+            .find(|p| p.type_guid == gpt_type::LINUX_FILESYSTEM)
+            .unwrap();
+        let view = rootfs.open(&mut device).unwrap();
+        let mut buf = [0; BLOCK_SIZE];
+        view.read_blocks(0, &mut buf).unwrap();
+        assert_eq!(0xab, buf[0]);
+    }
+
+    #[test]
+    fn test_backup_header() {
+        let mut data = create_disk();
+        // corrupt the primary header
+        data[BLOCK_SIZE + 30] ^= 0xff;
+        let device = OffsetBlockDevice::from_cursor(Cursor::new(&mut data), BLOCK_SIZE).unwrap();
+
+        let gpt = Gpt::read(&device).unwrap();
+        assert_eq!(BLOCK_COUNT as u64 - 1, gpt.header_lba());
+        assert_partitions(&gpt, &device);
+    }
+
+    #[test]
+    fn test_bad_entries_crc() {
+        let mut data = create_disk();
+        // corrupt both entry arrays
+        data[2 * BLOCK_SIZE + 60] ^= 0xff;
+        data[(BLOCK_COUNT - 1 - ENTRIES_BLOCKS) * BLOCK_SIZE + 60] ^= 0xff;
+        let device = OffsetBlockDevice::from_cursor(Cursor::new(&mut data), BLOCK_SIZE).unwrap();
+
+        assert_eq!(NoStdIoError::InvalidData, Gpt::read(&device).unwrap_err());
+    }
+
+    #[test]
+    fn test_bad_entry_size() {
+        for entry_size in [0, 64, 192, 8192, u32::MAX] {
+            let mut data = create_disk();
+            // a single entry with a matching checksum, so only the size is wrong
+            let entries_lbas = [(1, 2), (BLOCK_COUNT - 1, BLOCK_COUNT - 1 - ENTRIES_BLOCKS)];
+            for (lba, entries_lba) in entries_lbas {
+                let entries_start = entries_lba * BLOCK_SIZE;
+                let entries_end = (entries_start + entry_size as usize).min(data.len());
+                let entries_crc = crc32(&data[entries_start..entries_end]);
+                let header = &mut data[lba * BLOCK_SIZE..lba * BLOCK_SIZE + GPT_HEADER_SIZE];
+                header[80..84].copy_from_slice(&1u32.to_le_bytes());
+                header[84..88].copy_from_slice(&entry_size.to_le_bytes());
+                header[88..92].copy_from_slice(&entries_crc.to_le_bytes());
+                header[16..20].fill(0);
+                let crc = crc32(header);
+                header[16..20].copy_from_slice(&crc.to_le_bytes());
+            }
+            let device =
+                OffsetBlockDevice::from_cursor(Cursor::new(&mut data), BLOCK_SIZE).unwrap();
+            assert_eq!(NoStdIoError::InvalidData, Gpt::read(&device).unwrap_err());
+        }
+    }
+
+    #[test]
+    fn test_corrupt_entry_range() {
+        let mut data = create_disk_with(|entries| {
+            write_entry(entries, 1, gpt_type::LINUX_FILESYSTEM, 60, 50, "backwards");
+        });
+        let device = OffsetBlockDevice::from_cursor(Cursor::new(&mut data), BLOCK_SIZE).unwrap();
+        let gpt = Gpt::read(&device).unwrap();
+        let partitions: Vec<Result<GptPartition>> = gpt.partitions(&device).collect();
+        assert_eq!(Some(&Err(NoStdIoError::InvalidData)), partitions.get(1));
+
+        let mut data = create_disk_with(|entries| {
+            write_entry(entries, 1, gpt_type::LINUX_FILESYSTEM, 60, u64::MAX, "huge");
+        });
+        let mut device =
+            OffsetBlockDevice::from_cursor(Cursor::new(&mut data), BLOCK_SIZE).unwrap();
+        let gpt = Gpt::read(&device).unwrap();
+        let huge = gpt.partitions(&device).nth(1).unwrap().unwrap();
+        assert_eq!(u64::MAX - 60, huge.block_count());
+        assert!(huge.open(&mut device).is_err());
+    }
+}
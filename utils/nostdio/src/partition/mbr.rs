@@ -0,0 +1,213 @@
+use core::fmt::Debug;
+
+use zerocopy::{FromBytes, Immutable, IntoBytes, KnownLayout, little_endian::U32};
+
+use crate::{BlockDevice, NoStdIoError, Result, partition::Partition};
+
+const MBR_SIZE: usize = core::mem::size_of::<MbrSector>();
+const MBR_SIGNATURE: [u8; 2] = [0x55, 0xaa];
+const MBR_PARTITION_COUNT: usize = 4;
+
+/// Well known MBR partition type ids.
+pub mod mbr_type {
+    pub const EMPTY: u8 = 0x00;
+    pub const FAT32_LBA: u8 = 0x0c;
+    pub const EXTENDED: u8 = 0x05;
+    pub const EXTENDED_LBA: u8 = 0x0f;
+    pub const LINUX: u8 = 0x83;
+    /// Marks a protective MBR in front of a GPT.
+    pub const GPT_PROTECTIVE: u8 = 0xee;
+    pub const EFI_SYSTEM: u8 = 0xef;
+}
+
+#[repr(C, packed)]
+#[derive(IntoBytes, FromBytes, Immutable, KnownLayout)]
+struct MbrSector {
+    /// Boot code
+    bootstrap: [u8; 440],
+    /// Optional disk signature
+    disk_signature: U32,
+    reserved: [u8; 2],
+    /// Primary partition entries
+    partitions: [MbrPartitionEntry; MBR_PARTITION_COUNT],
+    /// 0x55 0xAA
+    signature: [u8; 2],
+}
+
+#[repr(C, packed)]
+#[derive(Clone, IntoBytes, FromBytes, Immutable, KnownLayout)]
+struct MbrPartitionEntry {
+    /// 0x80 = bootable
+    status: u8,
+    /// CHS address of the first sector
+    chs_first: [u8; 3],
+    /// Partition type, see [`mbr_type`]
+    partition_type: u8,
+    /// CHS address of the last sector
+    chs_last: [u8; 3],
+    /// LBA of the first sector
+    first_lba: U32,
+    /// Number of sectors
+    sector_count: U32,
+}
+
+/// A master boot record read from LBA 0.
+///
+/// Only the four primary partitions are parsed, logical partitions inside an
+/// extended partition are not followed.
+#[derive(Debug, Clone)]
+pub struct Mbr {
+    disk_signature: u32,
+    partitions: [Option<MbrPartition>; MBR_PARTITION_COUNT],
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MbrPartition {
+    /// Slot in the partition table, 0 to 3
+    pub index: usize,
+    pub bootable: bool,
+    pub partition_type: u8,
+    pub first_lba: u64,
+    pub sector_count: u64,
+}
+
+impl Mbr {
+    /// Returns `None` if LBA 0 does not carry the MBR boot signature.
+    pub fn read<D: BlockDevice>(device: &D) -> Result<Option<Self>> {
+        let mut buf = [0; MBR_SIZE];
+        device.read_bytes(0, &mut buf)?;
+        let sector = MbrSector::read_from_bytes(&buf).map_err(|_| NoStdIoError::InvalidData)?;
+        if sector.signature != MBR_SIGNATURE {
+            return Ok(None);
+        }
+
+        let mut partitions = [const { None }; MBR_PARTITION_COUNT];
+        for (index, (entry, partition)) in sector
+            .partitions
+            .iter()
+            .zip(partitions.iter_mut())
+            .enumerate()
+        {
+            if entry.partition_type == mbr_type::EMPTY || entry.sector_count.get() == 0 {
+                continue;
+            }
+            *partition = Some(MbrPartition {
+                index,
+                bootable: entry.status & 0x80 != 0,
+                partition_type: entry.partition_type,
+                first_lba: entry.first_lba.get() as u64,
+                sector_count: entry.sector_count.get() as u64,
+            });
+        }
+
+        Ok(Some(Self {
+            disk_signature: sector.disk_signature.get(),
+            partitions,
+        }))
+    }
+
+    pub fn disk_signature(&self) -> u32 {
+        self.disk_signature
+    }
+
+    /// Returns the non-empty primary partitions.
+    pub fn partitions(&self) -> impl Iterator<Item = &MbrPartition> {
+        self.partitions.iter().flatten()
+    }
+
+    /// A protective MBR covers the disk with a single partition of type
+    /// [`mbr_type::GPT_PROTECTIVE`], so legacy tools don't treat a GPT disk as
+    /// empty. The real partition table is the GPT.
+    pub fn is_protective(&self) -> bool {
+        self.partitions()
+            .any(|p| p.partition_type == mbr_type::GPT_PROTECTIVE)
+    }
+}
+
+impl Partition for MbrPartition {
+    fn start_lba(&self) -> u64 {
+        self.first_lba
+    }
+
+    fn block_count(&self) -> u64 {
+        self.sector_count
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use crate::{Cursor, OffsetBlockDevice};
+
+    use super::*;
+
+    pub(crate) fn write_mbr(data: &mut [u8], entries: &[(u8, u8, u32, u32)]) {
+        for (i, (status, partition_type, first_lba, sector_count)) in entries.iter().enumerate() {
+            let offset = 446 + i * 16;
+            data[offset] = *status;
+            data[offset + 4] = *partition_type;
+            data[offset + 8..offset + 12].copy_from_slice(&first_lba.to_le_bytes());
+            data[offset + 12..offset + 16].copy_from_slice(&sector_count.to_le_bytes());
+        }
+        data[510] = 0x55;
+        data[511] = 0xaa;
+    }
+
+    #[test]
+    fn test_no_signature() {
+        let mut data = [0; 4096];
+        let device = OffsetBlockDevice::from_cursor(Cursor::new(&mut data), 512).unwrap();
+        assert!(Mbr::read(&device).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_read_mbr() {
+        let mut data = [0; 8192];
+        write_mbr(
+            &mut data,
+            &[
+                (0x80, mbr_type::FAT32_LBA, 1, 4),
+                (0, mbr_type::EMPTY, 0, 0),
+                (0, mbr_type::LINUX, 5, 10),
+            ],
+        );
+        data[5 * 512] = 42;
+        let mut device = OffsetBlockDevice::from_cursor(Cursor::new(&mut data), 512).unwrap();
+
+        let mbr = Mbr::read(&device).unwrap().unwrap();
+        assert!(!mbr.is_protective());
+        let partitions: alloc::vec::Vec<_> = mbr.partitions().cloned().collect();
+        assert_eq!(
+            alloc::vec![
+                MbrPartition {
+                    index: 0,
+                    bootable: true,
+                    partition_type: mbr_type::FAT32_LBA,
+                    first_lba: 1,
+                    sector_count: 4
+                },
+                MbrPartition {
+                    index: 2,
+                    bootable: false,
+                    partition_type: mbr_type::LINUX,
+                    first_lba: 5,
+                    sector_count: 10
+                }
+            ],
+            partitions
+        );
+
+        let view = partitions[1].open(&mut device).unwrap();
+        assert_eq!(10, view.block_count());
+        let mut buf = [0; 512];
+        view.read_blocks(0, &mut buf).unwrap();
+        assert_eq!(42, buf[0]);
+
+        assert!(partitions[0].open(&mut device).is_ok());
+        // extends past the end of the device
+        let too_big = MbrPartition {
+            sector_count: 100,
+            ..partitions[1].clone()
+        };
+        assert!(too_big.open(&mut device).is_err());
+    }
+}
@@ -0,0 +1,48 @@
+//! Partition table parsing for [`BlockDevice`]s.
+//!
+//! see https://uefi.org/specs/UEFI/2.10/05_GUID_Partition_Table_Format.html
+
+mod crc32;
+mod gpt;
+mod mbr;
+
+pub use gpt::{Gpt, GptPartition, GptPartitionIterator, gpt_type};
+pub use mbr::{Mbr, MbrPartition, mbr_type};
+
+use crate::{BlockDevice, PartitionView, Result};
+
+/// A partition on a block device, from either a GPT or an MBR table.
+pub trait Partition {
+    /// First block of the partition.
+    fn start_lba(&self) -> u64;
+
+    /// Number of blocks in the partition.
+    fn block_count(&self) -> u64;
+
+    /// Returns a view of the partition over `device`, with block 0 being the
+    /// first block of the partition.
+    fn open<D: BlockDevice>(&self, device: D) -> Result<PartitionView<D>> {
+        PartitionView::new(device, self.start_lba(), self.block_count())
+    }
+}
+
+pub enum PartitionTable {
+    Gpt(Gpt),
+    /// A classic MBR, which is not a protective MBR.
+    Mbr(Mbr),
+}
+
+/// Reads the partition table of a device, preferring GPT.
+///
+/// Returns `None` if the device has no MBR signature, e.g. a raw filesystem
+/// image. A protective MBR with an unreadable GPT is an error.
+pub fn read_partition_table<D: BlockDevice>(device: &D) -> Result<Option<PartitionTable>> {
+    let mbr = match Mbr::read(device)? {
+        Some(mbr) => mbr,
+        None => return Ok(None),
+    };
+    if mbr.is_protective() {
+        return Ok(Some(PartitionTable::Gpt(Gpt::read(device)?)));
+    }
+    Ok(Some(PartitionTable::Mbr(mbr)))
+}
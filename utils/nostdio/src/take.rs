@@ -0,0 +1,109 @@
+use crate::{BufRead, NoStdIoError, Read, Result};
+
+/// Reader adapter which limits the bytes read from an underlying reader.
+///
+/// This struct is generally created by calling [`Read::take`].
+pub struct Take<T> {
+    inner: T,
+    limit: u64,
+}
+
+impl<T> Take<T> {
+    pub fn new(inner: T, limit: u64) -> Self {
+        Self { inner, limit }
+    }
+
+    /// Returns the number of bytes that can be read before this instance will
+    /// return EOF.
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    pub fn set_limit(&mut self, limit: u64) {
+        self.limit = limit;
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    fn max_len(&self, len: usize) -> usize {
+        usize::try_from(self.limit).map_or(len, |limit| limit.min(len))
+    }
+}
+
+impl<T: Read> Read for Take<T> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if self.limit == 0 {
+            return Ok(0);
+        }
+        let max = self.max_len(buf.len());
+        let buf = buf.get_mut(..max).ok_or(NoStdIoError::Other)?;
+        let read = self.inner.read(buf)?;
+        self.limit -= read as u64;
+        Ok(read)
+    }
+}
+
+impl<T: BufRead> BufRead for Take<T> {
+    fn fill_buf(&mut self) -> Result<&[u8]> {
+        if self.limit == 0 {
+            return Ok(&[]);
+        }
+        let max_len = usize::try_from(self.limit).unwrap_or(usize::MAX);
+        let buf = self.inner.fill_buf()?;
+        let max = buf.len().min(max_len);
+        buf.get(..max).ok_or(NoStdIoError::Other)
+    }
+
+    fn consume(&mut self, amt: usize) {
+        let amt = self.max_len(amt);
+        self.limit -= amt as u64;
+        self.inner.consume(amt);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn test_take() {
+        let mut data = [0; 100];
+        for i in 0..data.len() {
+            data[i] = i as u8;
+        }
+        let mut cursor = Cursor::new(&mut data);
+
+        let mut take = cursor.by_ref().take(10);
+        let mut buf = [0; 20];
+        assert_eq!(10, take.read(&mut buf).unwrap());
+        assert_eq!(0, take.read(&mut buf).unwrap());
+        assert_eq!(0, take.limit());
+        for i in 0..10 {
+            assert_eq!(i as u8, buf[i]);
+        }
+
+        assert_eq!(20, cursor.read(&mut buf).unwrap());
+        assert_eq!(10, buf[0]);
+    }
+
+    #[test]
+    fn test_take_read_to_end() {
+        let mut data = *b"hello world";
+        let mut take = Cursor::new(&mut data).take(5);
+        let mut buf = alloc::vec::Vec::new();
+        assert_eq!(5, take.read_to_end(&mut buf).unwrap());
+        assert_eq!(b"hello", buf.as_slice());
+    }
+}
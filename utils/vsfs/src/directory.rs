@@ -0,0 +1,324 @@
+use core::fmt::Debug;
+
+use myos_api::{
+    Uid,
+    filesystem::{FileIoError, FilePos, Mode, Result},
+    time::Timestamp,
+};
+use nostdio::BlockDevice;
+use zerocopy::{FromBytes, IntoBytes};
+
+use crate::{
+    INodeBlockIndex, Vsfs,
+    file::File,
+    inode::INode,
+    physical::{BASE_PHYSICAL_DIRECTORY_ENTRY_SIZE, MAX_FILE_NAME_LEN, PhysicalDirectoryEntry},
+};
+
+pub struct Directory {
+    inode_idx: INodeBlockIndex,
+    inode: INode,
+}
+
+impl Directory {
+    pub(crate) fn new(inode_idx: INodeBlockIndex, inode: INode) -> Self {
+        Self { inode_idx, inode }
+    }
+
+    pub fn uid(&self) -> Uid {
+        self.inode.uid
+    }
+
+    pub fn gid(&self) -> Uid {
+        self.inode.gid
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.inode.mode.permissions()
+    }
+
+    pub fn ctime(&self) -> Timestamp {
+        self.inode.ctime
+    }
+
+    pub(crate) fn inode_idx(&self) -> INodeBlockIndex {
+        self.inode_idx
+    }
+
+    pub fn iter<'a, T: BlockDevice>(&self, fs: &'a Vsfs<T>) -> Result<DirectoryIterator<'a, T>> {
+        // re-read the inode, entries may have been added through another handle
+        let inode = fs.read_inode(self.inode_idx)?;
+        Ok(DirectoryIterator::new(fs, inode))
+    }
+
+    /// Looks up an entry by name.
+    pub fn find<T: BlockDevice>(
+        &self,
+        fs: &Vsfs<T>,
+        file_name: &str,
+    ) -> Result<Option<DirectoryEntry>> {
+        for entry in self.iter(fs)? {
+            let entry = entry?;
+            if entry.file_name()? == file_name {
+                return Ok(Some(entry));
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn exists<T: BlockDevice>(&self, fs: &Vsfs<T>, file_name: &str) -> Result<bool> {
+        Ok(self.find(fs, file_name)?.is_some())
+    }
+
+    pub fn create_file<'a, T: BlockDevice>(
+        &mut self,
+        fs: &'a mut Vsfs<T>,
+        options: CreateFileOptions,
+    ) -> Result<File<'a, T>> {
+        let (inode_idx, inode) = self.create_entry(fs, &options, Mode::regular_file())?;
+        Ok(File::new(fs, inode_idx, inode))
+    }
+
+    /// Creates a sub directory containing the `.` and `..` entries.
+    pub fn create_dir<T: BlockDevice>(
+        &mut self,
+        fs: &mut Vsfs<T>,
+        options: CreateFileOptions,
+    ) -> Result<Directory> {
+        let (inode_idx, mut inode) = self.create_entry(fs, &options, Mode::directory())?;
+        fs.write_dir_entry(inode_idx, &mut inode, inode_idx, ".")?;
+        fs.write_dir_entry(inode_idx, &mut inode, self.inode_idx(), "..")?;
+        Ok(Directory::new(inode_idx, inode))
+    }
+
+    fn create_entry<T: BlockDevice>(
+        &mut self,
+        fs: &mut Vsfs<T>,
+        options: &CreateFileOptions,
+        file_type: Mode,
+    ) -> Result<(INodeBlockIndex, INode)> {
+        validate_file_name(options.file_name)?;
+        if self.exists(fs, options.file_name)? {
+            return Err(FileIoError::FileAlreadyExists);
+        }
+
+        let mut inode = INode::new(options.mode.permissions() | file_type, options.time);
+        inode.uid = options.uid;
+        inode.gid = options.gid;
+        let inode_idx = fs.create_inode(&inode)?;
+
+        let mut dir_inode = fs.read_inode(self.inode_idx)?;
+        if let Err(err) =
+            fs.write_dir_entry(self.inode_idx, &mut dir_inode, inode_idx, options.file_name)
+        {
+            fs.delete_inode(inode_idx)?;
+            return Err(err);
+        }
+        self.inode = dir_inode;
+
+        Ok((inode_idx, inode))
+    }
+
+    pub fn open_file<'a, T: BlockDevice>(
+        &self,
+        fs: &'a mut Vsfs<T>,
+        file_name: &str,
+    ) -> Result<File<'a, T>> {
+        let entry = self.find(fs, file_name)?.ok_or(FileIoError::NotFound)?;
+        if entry.is_dir() {
+            return Err(FileIoError::IsADirectory);
+        }
+        Ok(File::new(fs, entry.inode_idx, entry.inode))
+    }
+
+    pub fn open_dir<T: BlockDevice>(&self, fs: &Vsfs<T>, file_name: &str) -> Result<Directory> {
+        let entry = self.find(fs, file_name)?.ok_or(FileIoError::NotFound)?;
+        entry.to_dir().ok_or(FileIoError::NotADirectory)
+    }
+
+    /// Removes an entry from this directory and frees its inode and data.
+    /// Directories must be empty.
+    pub fn unlink<T: BlockDevice>(&mut self, fs: &mut Vsfs<T>, file_name: &str) -> Result<()> {
+        if file_name == "." || file_name == ".." {
+            return Err(FileIoError::Other("cannot unlink . or .."));
+        }
+        let entry = self.find(fs, file_name)?.ok_or(FileIoError::NotFound)?;
+
+        if let Some(dir) = entry.to_dir() {
+            for child in dir.iter(fs)? {
+                let child = child?;
+                let name = child.file_name()?;
+                if name != "." && name != ".." {
+                    return Err(FileIoError::DirectoryNotEmpty);
+                }
+            }
+        }
+
+        // mark the entry as deleted, iteration skips entries without an inode
+        let mut dir_inode = fs.read_inode(self.inode_idx)?;
+        let deleted = PhysicalDirectoryEntry {
+            inode_idx: 0,
+            name_len: entry.physical_directory_entry.name_len,
+        };
+        fs.write(
+            self.inode_idx,
+            &mut dir_inode,
+            entry.offset.0,
+            deleted.as_bytes(),
+        )?;
+        self.inode = dir_inode;
+
+        fs.delete_inode(entry.inode_idx)
+    }
+}
+
+fn validate_file_name(file_name: &str) -> Result<()> {
+    if file_name.len() > MAX_FILE_NAME_LEN {
+        return Err(FileIoError::FilenameTooLong);
+    }
+    if file_name.is_empty() || file_name.contains(['/', '\0']) {
+        return Err(FileIoError::Other("invalid file name"));
+    }
+    Ok(())
+}
+
+pub struct CreateFileOptions<'a> {
+    pub uid: Uid,
+    pub gid: Uid,
+    /// Permission bits, the file type is set by the create function
+    pub mode: Mode,
+    pub file_name: &'a str,
+    pub time: Timestamp,
+}
+
+pub struct DirectoryIterator<'a, T: BlockDevice> {
+    fs: &'a Vsfs<T>,
+    inode: INode,
+    offset: FilePos,
+}
+
+impl<'a, T: BlockDevice> DirectoryIterator<'a, T> {
+    pub(crate) fn new(fs: &'a Vsfs<T>, inode: INode) -> Self {
+        Self {
+            fs,
+            inode,
+            offset: FilePos(0),
+        }
+    }
+
+    fn read_next(&mut self) -> Result<Option<DirectoryEntry>> {
+        let mut dir_entry_buf = [0; BASE_PHYSICAL_DIRECTORY_ENTRY_SIZE];
+        loop {
+            let entry_offset = self.offset;
+            let read = self
+                .fs
+                .read(&self.inode, self.offset.0, &mut dir_entry_buf)?;
+            if read != dir_entry_buf.len() {
+                return Ok(None);
+            }
+            self.offset += read;
+
+            let dir_entry = PhysicalDirectoryEntry::read_from_bytes(&dir_entry_buf)
+                .map_err(|_| FileIoError::BufferTooSmall)?;
+            let name_len = dir_entry.name_len;
+            if dir_entry.inode_idx == 0 {
+                self.offset += name_len;
+                continue;
+            }
+
+            let mut file_name = [0; MAX_FILE_NAME_LEN];
+            let file_name_buf = file_name
+                .get_mut(0..name_len as usize)
+                .ok_or(FileIoError::BufferTooSmall)?;
+            let read = self.fs.read(&self.inode, self.offset.0, file_name_buf)?;
+            if read < name_len as usize {
+                return Err(FileIoError::Other("truncated directory entry"));
+            }
+            self.offset += name_len;
+
+            let inode_idx = INodeBlockIndex(dir_entry.inode_idx);
+            let entry_inode = self.fs.read_inode(inode_idx)?;
+            return Ok(Some(DirectoryEntry {
+                physical_directory_entry: dir_entry,
+                offset: entry_offset,
+                inode_idx,
+                file_name,
+                inode: entry_inode,
+            }));
+        }
+    }
+}
+
+impl<'a, T: BlockDevice> Iterator for DirectoryIterator<'a, T> {
+    type Item = Result<DirectoryEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let result = self.read_next();
+        match result {
+            Ok(entry) => entry.map(Ok),
+            Err(err) => Some(Err(err)),
+        }
+    }
+}
+
+pub struct DirectoryEntry {
+    physical_directory_entry: PhysicalDirectoryEntry,
+    /// Position of the entry within the directory data
+    offset: FilePos,
+    inode_idx: INodeBlockIndex,
+    file_name: [u8; MAX_FILE_NAME_LEN],
+    inode: INode,
+}
+
+impl DirectoryEntry {
+    pub fn is_dir(&self) -> bool {
+        self.inode.mode.is_directory()
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.inode.mode.is_regular_file()
+    }
+
+    pub fn size(&self) -> u64 {
+        self.inode.size.0
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.inode.mode.permissions()
+    }
+
+    pub fn uid(&self) -> Uid {
+        self.inode.uid
+    }
+
+    pub fn gid(&self) -> Uid {
+        self.inode.gid
+    }
+
+    pub fn to_dir(&self) -> Option<Directory> {
+        if self.is_dir() {
+            Some(Directory::new(self.inode_idx, self.inode.clone()))
+        } else {
+            None
+        }
+    }
+
+    pub fn file_name(&self) -> Result<&str> {
+        let file_name = self
+            .file_name
+            .get(0..self.physical_directory_entry.name_len as usize)
+            .ok_or(FileIoError::BufferTooSmall)?;
+        str::from_utf8(file_name).map_err(|_| FileIoError::Other("failed to decode utf8"))
+    }
+}
+
+impl Debug for DirectoryEntry {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("DirectoryEntry")
+            .field("file_name", &self.file_name().unwrap_or("<invalid>"))
+            .field("inode_idx", &self.inode_idx.0)
+            .field("mode", &self.inode.mode)
+            .field("size", &self.inode.size.0)
+            .finish()
+    }
+}
@@ -0,0 +1,134 @@
+use myos_api::{
+    Uid,
+    filesystem::{FileIoError, Mode, Result},
+    time::Timestamp,
+};
+use nostdio::{BlockDevice, NoStdIoError, SeekFrom};
+
+use crate::{INodeBlockIndex, Vsfs, inode::INode};
+
+/// An open file. Reads and writes start at the current position, which is
+/// moved with [`File::seek`].
+pub struct File<'a, T: BlockDevice> {
+    fs: &'a mut Vsfs<T>,
+    inode_idx: INodeBlockIndex,
+    inode: INode,
+    pos: u64,
+}
+
+impl<'a, T: BlockDevice> File<'a, T> {
+    pub(crate) fn new(fs: &'a mut Vsfs<T>, inode_idx: INodeBlockIndex, inode: INode) -> Self {
+        Self {
+            fs,
+            inode_idx,
+            inode,
+            pos: 0,
+        }
+    }
+
+    pub fn size(&self) -> u64 {
+        self.inode.size.0
+    }
+
+    pub fn uid(&self) -> Uid {
+        self.inode.uid
+    }
+
+    pub fn gid(&self) -> Uid {
+        self.inode.gid
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.inode.mode.permissions()
+    }
+
+    pub fn mtime(&self) -> Timestamp {
+        self.inode.mtime
+    }
+
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let read = self.fs.read(&self.inode, self.pos, buf)?;
+        self.pos += read as u64;
+        Ok(read)
+    }
+
+    pub fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let written = self
+            .fs
+            .write(self.inode_idx, &mut self.inode, self.pos, buf)?;
+        self.pos += written as u64;
+        Ok(written)
+    }
+
+    pub fn write_all(&mut self, mut buf: &[u8]) -> Result<()> {
+        while !buf.is_empty() {
+            match self.write(buf)? {
+                0 => return Err(FileIoError::IoError(NoStdIoError::WriteZero)),
+                n => buf = buf.get(n..).ok_or(FileIoError::BufferTooSmall)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Seeking past the end of the file is allowed, the gap reads as zeros once
+    /// something is written after it.
+    pub fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        let invalid = FileIoError::IoError(NoStdIoError::InvalidInput);
+        self.pos = match pos {
+            SeekFrom::Start(v) => v,
+            SeekFrom::End(v) => self.size().checked_add_signed(v).ok_or(invalid)?,
+            SeekFrom::Current(v) => self.pos.checked_add_signed(v).ok_or(invalid)?,
+        };
+        Ok(self.pos)
+    }
+
+    /// Sets the modification time, vsfs has no clock of its own.
+    pub fn set_mtime(&mut self, mtime: Timestamp) -> Result<()> {
+        self.inode.mtime = mtime;
+        self.fs.write_inode(self.inode_idx, &self.inode)
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.fs.flush()
+    }
+}
+
+impl<T: BlockDevice> nostdio::Read for File<'_, T> {
+    fn read(&mut self, buf: &mut [u8]) -> nostdio::Result<usize> {
+        Ok(File::read(self, buf)?)
+    }
+}
+
+impl<T: BlockDevice> nostdio::Write for File<'_, T> {
+    fn write(&mut self, buf: &[u8]) -> nostdio::Result<usize> {
+        Ok(File::write(self, buf)?)
+    }
+
+    fn flush(&mut self) -> nostdio::Result<()> {
+        Ok(File::flush(self)?)
+    }
+}
+
+impl<T: BlockDevice> nostdio::Seek for File<'_, T> {
+    fn seek(&mut self, pos: SeekFrom) -> nostdio::Result<u64> {
+        Ok(File::seek(self, pos)?)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T: BlockDevice> std::io::Read for File<'_, T> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        File::read(self, buf).map_err(|err| std::io::Error::other(format!("{err:?}")))
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T: BlockDevice> std::io::Write for File<'_, T> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        File::write(self, buf).map_err(|err| std::io::Error::other(format!("{err:?}")))
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        File::flush(self).map_err(|err| std::io::Error::other(format!("{err:?}")))
+    }
+}
@@ -0,0 +1,112 @@
+use myos_api::{
+    filesystem::{FileIoError, Result},
+    time::Timestamp,
+};
+use nostdio::BlockDevice;
+use zerocopy::IntoBytes;
+
+use crate::{
+    FsOptions, Vsfs,
+    layout::Layout,
+    physical::{BLOCK_SIZE, MAGIC, PhysicalSuperBlock},
+};
+
+pub struct FormatVolumeOptions {
+    pub inode_count: u32,
+    pub data_block_count: u32,
+    /// Creation time of the root directory
+    pub time: Timestamp,
+}
+
+impl FormatVolumeOptions {
+    pub fn new(inode_count: u32, data_block_count: u32) -> Self {
+        Self {
+            inode_count,
+            data_block_count,
+            time: Timestamp::UNIX_EPOCH,
+        }
+    }
+
+    /// Size in bytes the device must have to hold the volume.
+    pub fn volume_size(&self) -> u64 {
+        Layout::new(self.inode_count, self.data_block_count)
+            .size()
+            .0
+    }
+}
+
+pub fn format_volume<T: BlockDevice>(
+    mut device: T,
+    options: FormatVolumeOptions,
+) -> Result<Vsfs<T>> {
+    let layout = Layout::new(options.inode_count, options.data_block_count);
+    if layout.size().0 > device.size() {
+        return Err(FileIoError::Other("device is too small for the volume"));
+    }
+
+    let mut block = [0; BLOCK_SIZE];
+
+    // write super block
+    let super_block = PhysicalSuperBlock {
+        magic: MAGIC,
+        inode_count: options.inode_count,
+        data_block_count: options.data_block_count,
+    };
+    super_block
+        .write_to_prefix(&mut block)
+        .map_err(|_| FileIoError::BufferTooSmall)?;
+    device.write_bytes(0, &block)?;
+
+    // clear the bitmaps and inodes, data blocks are cleared when allocated
+    block.fill(0);
+    let metadata_block_count =
+        layout.inode_bitmap_block_count + layout.data_bitmap_block_count + layout.inode_block_count;
+    for i in 0..metadata_block_count {
+        let addr = layout.inode_bitmap_offset.0 + i as u64 * BLOCK_SIZE as u64;
+        device.write_bytes(addr, &block)?;
+    }
+
+    // reserve inode 0 and data block 0, 0 marks unset entries
+    device.write_bytes(layout.inode_bitmap_offset.0, &[1])?;
+    device.write_bytes(layout.data_bitmap_offset.0, &[1])?;
+
+    let mut fs_options = FsOptions::new();
+    fs_options.init_root_inode = true;
+    fs_options.init_root_inode_time = options.time;
+    let fs = Vsfs::new(device, fs_options)?;
+
+    Ok(fs)
+}
+
+#[cfg(test)]
+mod tests {
+    use nostdio::{Cursor, OffsetBlockDevice};
+
+    use super::*;
+
+    #[test]
+    fn test_minimums() {
+        let mut data = [0; 100 * BLOCK_SIZE];
+        let device = OffsetBlockDevice::from_cursor(Cursor::new(&mut data), 512).unwrap();
+        let options = FormatVolumeOptions::new(10, 10);
+        let volume_size = options.volume_size();
+        let fs = format_volume(device, options).unwrap();
+        assert_eq!(
+            (1 /* super block */ + 1 /* inode bitmap */ + 1 /* data bitmap */ + 1 /* inode data */ + 10/* data */)
+                * BLOCK_SIZE as u64,
+            fs.size().0
+        );
+        assert_eq!(volume_size, fs.size().0);
+        // reserved inode and root directory
+        assert_eq!(8, fs.free_inode_count().unwrap());
+        // reserved block and root directory data
+        assert_eq!(8, fs.free_data_block_count().unwrap());
+    }
+
+    #[test]
+    fn test_device_too_small() {
+        let mut data = [0; 10 * BLOCK_SIZE];
+        let device = OffsetBlockDevice::from_cursor(Cursor::new(&mut data), 512).unwrap();
+        assert!(format_volume(device, FormatVolumeOptions::new(10, 10)).is_err());
+    }
+}
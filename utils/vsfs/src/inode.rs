@@ -1,5 +1,8 @@
-use file_io::{FilePos, Mode, TimeSeconds};
-use myos_api::Uid;
+use myos_api::{
+    Uid,
+    filesystem::{FilePos, Mode},
+    time::Timestamp,
+};
 
 use crate::{
     DataBlockIndex,
@@ -14,20 +17,19 @@ pub(crate) struct INode {
     /// size of the file
     pub size: FilePos,
     /// what time was this file last accessed?
-    pub time: TimeSeconds,
+    pub time: Timestamp,
     /// what time was this file created?
-    pub ctime: TimeSeconds,
+    pub ctime: Timestamp,
     /// what time was this file last modified?
-    pub mtime: TimeSeconds,
-    /// index into the blocks where the first x blocks of data can be found, 0 indicates unused block
+    pub mtime: Timestamp,
+    /// index into the blocks where the first x blocks of data can be found
     pub blocks: [Option<DataBlockIndex>; IMMEDIATE_BLOCK_COUNT],
-    /// if not 0, indicates an index into the block table where you will find more block addresses
+    /// if set, indicates an index into the block table where you will find more block addresses
     pub indirect_block_idx: Option<DataBlockIndex>,
 }
 
 impl INode {
-    pub(crate) fn new(mode: Mode, time: TimeSeconds) -> Self {
-        const BLOCK_NONE: Option<DataBlockIndex> = None;
+    pub(crate) fn new(mode: Mode, time: Timestamp) -> Self {
         Self {
             uid: Uid::root(),
             gid: Uid::root(),
@@ -36,7 +38,7 @@ impl INode {
             time,
             ctime: time,
             mtime: time,
-            blocks: [BLOCK_NONE; IMMEDIATE_BLOCK_COUNT],
+            blocks: [None; IMMEDIATE_BLOCK_COUNT],
             indirect_block_idx: None,
         }
     }
@@ -44,20 +46,16 @@ impl INode {
 
 impl From<PhysicalINode> for INode {
     fn from(value: PhysicalINode) -> Self {
-        let mut blocks = [None; IMMEDIATE_BLOCK_COUNT];
-        for i in 0..IMMEDIATE_BLOCK_COUNT {
-            blocks[i] = DataBlockIndex::from_u32(value.blocks[i]);
-        }
-
+        let blocks = value.blocks;
         Self {
             uid: Uid(value.uid),
             gid: Uid(value.gid),
             mode: Mode(value.mode),
             size: FilePos(value.size),
-            time: TimeSeconds(value.time),
-            ctime: TimeSeconds(value.ctime),
-            mtime: TimeSeconds(value.mtime),
-            blocks,
+            time: Timestamp::from_seconds(value.time),
+            ctime: Timestamp::from_seconds(value.ctime),
+            mtime: Timestamp::from_seconds(value.mtime),
+            blocks: blocks.map(DataBlockIndex::from_u32),
             indirect_block_idx: DataBlockIndex::from_u32(value.indirect_block_idx),
         }
     }
@@ -1,8 +1,8 @@
-use file_io::{FileIoError, FilePos, Result};
+use myos_api::filesystem::{FileIoError, FilePos, Result};
 
 use crate::{
     DataBlockIndex, INodeBlockIndex,
-    physical::{BLOCK_SIZE, PHYSICAL_INODE_SIZE, PHYSICAL_INODES_PER_BLOCK},
+    physical::{BITS_PER_BLOCK, BLOCK_SIZE, PHYSICAL_INODE_SIZE, PHYSICAL_INODES_PER_BLOCK},
 };
 
 pub(crate) struct Layout {
@@ -20,8 +20,8 @@ pub(crate) struct Layout {
 
 impl Layout {
     pub(crate) fn new(inode_count: u32, data_block_count: u32) -> Self {
-        let inode_bitmap_block_count = inode_count.div_ceil(8).div_ceil(BLOCK_SIZE as u32);
-        let data_bitmap_block_count = data_block_count.div_ceil(8).div_ceil(BLOCK_SIZE as u32);
+        let inode_bitmap_block_count = inode_count.div_ceil(BITS_PER_BLOCK);
+        let data_bitmap_block_count = data_block_count.div_ceil(BITS_PER_BLOCK);
         let inode_block_count = inode_count.div_ceil(PHYSICAL_INODES_PER_BLOCK);
 
         let inode_bitmap_offset = FilePos(BLOCK_SIZE as u64);
@@ -302,7 +302,10 @@ mod tests {
         let data_block_count = BLOCK_SIZE as u32 * 8 + 100;
         let layout = Layout::new(1, data_block_count);
 
-        assert_eq!(layout.data_offset, layout.calc_data_addr(DataBlockIndex(0)).unwrap());
+        assert_eq!(
+            layout.data_offset,
+            layout.calc_data_addr(DataBlockIndex(0)).unwrap()
+        );
 
         assert_eq!(
             FilePos(layout.data_offset.0 + BLOCK_SIZE as u64),
@@ -0,0 +1,810 @@
+#![cfg_attr(all(not(feature = "std"), not(test)), no_std)]
+#![allow(clippy::new_without_default)]
+#![deny(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::unimplemented,
+    clippy::unreachable,
+    clippy::indexing_slicing,
+    clippy::cast_possible_truncation
+)]
+
+//! The very simple file system from "Operating Systems: Three Easy Pieces".
+//!
+//! The volume is made of 4KiB blocks laid out as a super block, the inode
+//! bitmap, the data block bitmap, the inode table and finally the data blocks.
+//! Each inode has [`IMMEDIATE_BLOCK_COUNT`](physical::IMMEDIATE_BLOCK_COUNT)
+//! block pointers plus a single indirect block. Directories are files holding a
+//! list of `(inode, name)` entries.
+
+mod directory;
+mod file;
+mod format;
+mod inode;
+mod layout;
+mod physical;
+
+pub use directory::{CreateFileOptions, Directory, DirectoryEntry, DirectoryIterator};
+pub use file::File;
+pub use format::{FormatVolumeOptions, format_volume};
+use myos_api::{
+    filesystem::{FileIoError, FilePos, Mode, Result},
+    time::Timestamp,
+};
+use nostdio::BlockDevice;
+pub use physical::{BLOCK_SIZE, MAX_FILE_NAME_LEN, MAX_FILE_SIZE};
+use zerocopy::{FromBytes, IntoBytes};
+
+use crate::{
+    inode::INode,
+    layout::Layout,
+    physical::{
+        BITS_PER_BLOCK, BLOCK_NOT_SET, IMMEDIATE_BLOCK_COUNT, INDIRECT_BLOCK_COUNT, MAGIC,
+        PHYSICAL_INODE_SIZE, PhysicalDirectoryEntry, PhysicalINode, PhysicalSuperBlock,
+    },
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct INodeBlockIndex(pub u32);
+
+impl INodeBlockIndex {
+    pub(crate) fn root() -> Self {
+        // inode 0 is reserved
+        Self(1)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct DataBlockIndex(pub u32);
+
+impl DataBlockIndex {
+    pub(crate) fn from_u32(v: u32) -> Option<Self> {
+        if v == BLOCK_NOT_SET {
+            None
+        } else {
+            Some(Self(v))
+        }
+    }
+
+    pub(crate) fn to_u32(v: Option<Self>) -> u32 {
+        v.map_or(BLOCK_NOT_SET, |v| v.0)
+    }
+}
+
+pub struct FsOptions {
+    pub(crate) init_root_inode: bool,
+    pub(crate) init_root_inode_time: Timestamp,
+}
+
+impl FsOptions {
+    pub fn new() -> Self {
+        Self {
+            init_root_inode: false,
+            init_root_inode_time: Timestamp::UNIX_EPOCH,
+        }
+    }
+}
+
+pub struct Vsfs<T: BlockDevice> {
+    device: T,
+    layout: Layout,
+}
+
+impl<T: BlockDevice> Vsfs<T> {
+    pub fn new(device: T, options: FsOptions) -> Result<Self> {
+        let mut buf = [0; core::mem::size_of::<PhysicalSuperBlock>()];
+        device.read_bytes(0, &mut buf)?;
+        let super_block =
+            PhysicalSuperBlock::read_from_bytes(&buf).map_err(|_| FileIoError::BufferTooSmall)?;
+        if super_block.magic != MAGIC {
+            return Err(FileIoError::Other("invalid magic"));
+        }
+
+        let layout = Layout::new(super_block.inode_count, super_block.data_block_count);
+        if layout.size().0 > device.size() {
+            return Err(FileIoError::Other("volume is larger than the device"));
+        }
+
+        let mut fs = Self { device, layout };
+
+        if options.init_root_inode {
+            let mut root_inode = INode::new(
+                Mode(0o755) | Mode::directory(),
+                options.init_root_inode_time,
+            );
+            let root_idx = INodeBlockIndex::root();
+            fs.write_inode(root_idx, &root_inode)?;
+            fs.set_inode_bitmap(root_idx, true)?;
+            fs.write_dir_entry(root_idx, &mut root_inode, root_idx, ".")?;
+            fs.write_dir_entry(root_idx, &mut root_inode, root_idx, "..")?;
+        }
+
+        Ok(fs)
+    }
+
+    pub fn size(&self) -> FilePos {
+        self.layout.size()
+    }
+
+    pub fn root_dir(&self) -> Result<Directory> {
+        let inode = self.read_inode(INodeBlockIndex::root())?;
+        Ok(Directory::new(INodeBlockIndex::root(), inode))
+    }
+
+    /// Number of unused inodes.
+    pub fn free_inode_count(&self) -> Result<u32> {
+        self.count_free(self.layout.inode_bitmap_offset, self.layout.inode_count)
+    }
+
+    /// Number of unused data blocks.
+    pub fn free_data_block_count(&self) -> Result<u32> {
+        self.count_free(self.layout.data_bitmap_offset, self.layout.data_block_count)
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        Ok(self.device.flush()?)
+    }
+
+    pub fn into_inner(self) -> T {
+        self.device
+    }
+
+    /// Reads an inode.
+    ///
+    /// This function will validate that the given index has data.
+    pub(crate) fn read_inode(&self, inode_idx: INodeBlockIndex) -> Result<INode> {
+        if !self.is_inode_idx_readable(inode_idx)? {
+            return Err(FileIoError::Other("cannot read from empty inode"));
+        }
+
+        let (block_addr, inode_offset) = self.layout.calc_inode_block_addr(inode_idx)?;
+        let mut buf = [0; PHYSICAL_INODE_SIZE];
+        self.device
+            .read_bytes(block_addr.0 + inode_offset as u64, &mut buf)?;
+        let inode =
+            PhysicalINode::read_from_bytes(&buf).map_err(|_| FileIoError::BufferTooSmall)?;
+        Ok(inode.into())
+    }
+
+    /// Checks the inode bitmap to see if the given inode has data
+    fn is_inode_idx_readable(&self, inode_idx: INodeBlockIndex) -> Result<bool> {
+        let (addr, offset, bit) = self.layout.calc_inode_bitmap_addr(inode_idx)?;
+        self.read_bit(addr, offset, bit)
+    }
+
+    /// Writes an inode at the given index, overwriting any existing inode data.
+    pub(crate) fn write_inode(&mut self, inode_idx: INodeBlockIndex, inode: &INode) -> Result<()> {
+        let (addr, offset) = self.layout.calc_inode_block_addr(inode_idx)?;
+        let physical_inode: PhysicalINode = inode.into();
+        self.device
+            .write_bytes(addr.0 + offset as u64, physical_inode.as_bytes())?;
+        Ok(())
+    }
+
+    fn set_inode_bitmap(&mut self, inode_idx: INodeBlockIndex, value: bool) -> Result<()> {
+        let (addr, offset, bit) = self.layout.calc_inode_bitmap_addr(inode_idx)?;
+        self.write_bit(addr, offset, bit, value)
+    }
+
+    /// Finds an unused inode, marks it as used and writes `inode` to it.
+    pub(crate) fn create_inode(&mut self, inode: &INode) -> Result<INodeBlockIndex> {
+        let inode_idx = self
+            .find_free(self.layout.inode_bitmap_offset, self.layout.inode_count)?
+            .map(INodeBlockIndex)
+            .ok_or(FileIoError::Other("out of inodes"))?;
+        self.write_inode(inode_idx, inode)?;
+        self.set_inode_bitmap(inode_idx, true)?;
+        Ok(inode_idx)
+    }
+
+    /// Releases the data blocks of an inode and marks the inode as unused.
+    pub(crate) fn delete_inode(&mut self, inode_idx: INodeBlockIndex) -> Result<()> {
+        let mut inode = self.read_inode(inode_idx)?;
+        self.free_inode_data(&mut inode)?;
+        self.set_inode_bitmap(inode_idx, false)
+    }
+
+    /// Reads data from the given inode data starting at `pos`. Returns the
+    /// amount of data read, which is less than `buf.len()` only at the end of
+    /// the file.
+    pub(crate) fn read(&self, inode: &INode, pos: u64, buf: &mut [u8]) -> Result<usize> {
+        let remaining = inode.size.0.saturating_sub(pos);
+        let len = usize::try_from(remaining).map_or(buf.len(), |r| r.min(buf.len()));
+
+        let mut done = 0;
+        while done < len {
+            let file_pos = pos + done as u64;
+            let block_offset = block_offset(file_pos)?;
+            let chunk_len = (BLOCK_SIZE - block_offset).min(len - done);
+            let chunk = buf
+                .get_mut(done..done + chunk_len)
+                .ok_or(FileIoError::BufferTooSmall)?;
+            match self.data_block(inode, file_pos / BLOCK_SIZE as u64)? {
+                Some(data_block_idx) => {
+                    let addr = self.layout.calc_data_addr(data_block_idx)?;
+                    self.device
+                        .read_bytes(addr.0 + block_offset as u64, chunk)?;
+                }
+                // sparse, never written
+                None => chunk.fill(0),
+            }
+            done += chunk_len;
+        }
+        Ok(len)
+    }
+
+    /// Writes data to the given inode starting at `pos`, allocating data
+    /// blocks as needed, and saves the inode. Returns the amount of data
+    /// written, which is less than `buf.len()` only if the disk fills up.
+    pub(crate) fn write(
+        &mut self,
+        inode_idx: INodeBlockIndex,
+        inode: &mut INode,
+        pos: u64,
+        buf: &[u8],
+    ) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if pos >= MAX_FILE_SIZE {
+            return Err(FileIoError::Other("file too large"));
+        }
+        let len = usize::try_from(MAX_FILE_SIZE - pos).map_or(buf.len(), |r| r.min(buf.len()));
+
+        let mut done = 0;
+        let mut result = Ok(());
+        while done < len {
+            let file_pos = pos + done as u64;
+            let block_offset = block_offset(file_pos)?;
+            let chunk_len = (BLOCK_SIZE - block_offset).min(len - done);
+            let chunk = buf
+                .get(done..done + chunk_len)
+                .ok_or(FileIoError::BufferTooSmall)?;
+            let data_block_idx =
+                match self.data_block_or_allocate(inode, file_pos / BLOCK_SIZE as u64) {
+                    Ok(data_block_idx) => data_block_idx,
+                    Err(err) => {
+                        result = Err(err);
+                        break;
+                    }
+                };
+            let addr = self.layout.calc_data_addr(data_block_idx)?;
+            self.device
+                .write_bytes(addr.0 + block_offset as u64, chunk)?;
+            done += chunk_len;
+        }
+
+        let end = pos + done as u64;
+        if end > inode.size.0 {
+            inode.size = FilePos(end);
+        }
+        self.write_inode(inode_idx, inode)?;
+
+        match result {
+            Err(err) if done == 0 => Err(err),
+            _ => Ok(done),
+        }
+    }
+
+    /// Appends a directory entry to the directory `dir_inode`.
+    pub(crate) fn write_dir_entry(
+        &mut self,
+        dir_inode_idx: INodeBlockIndex,
+        dir_inode: &mut INode,
+        entry_inode_idx: INodeBlockIndex,
+        name: &str,
+    ) -> Result<()> {
+        let mut buf = [0; physical::BASE_PHYSICAL_DIRECTORY_ENTRY_SIZE + MAX_FILE_NAME_LEN];
+        let entry = PhysicalDirectoryEntry::write(entry_inode_idx, name, &mut buf)?;
+        let pos = dir_inode.size.0;
+        if self.write(dir_inode_idx, dir_inode, pos, entry)? != entry.len() {
+            return Err(FileIoError::OutOfDiskSpaceError);
+        }
+        Ok(())
+    }
+
+    /// Finds the data block holding block `file_block` of a file, `None` if
+    /// that part of the file has not been written.
+    fn data_block(&self, inode: &INode, file_block: u64) -> Result<Option<DataBlockIndex>> {
+        let file_block =
+            usize::try_from(file_block).map_err(|_| FileIoError::Other("file too large"))?;
+        if let Some(block) = inode.blocks.get(file_block) {
+            return Ok(*block);
+        }
+
+        let indirect_idx = file_block - IMMEDIATE_BLOCK_COUNT;
+        if indirect_idx >= INDIRECT_BLOCK_COUNT {
+            return Err(FileIoError::Other("file too large"));
+        }
+        match inode.indirect_block_idx {
+            Some(indirect_block_idx) => self.read_indirect(indirect_block_idx, indirect_idx),
+            None => Ok(None),
+        }
+    }
+
+    /// Same as [`Vsfs::data_block`] but allocates the data block, and the
+    /// indirect block if needed, when they don't exist yet.
+    fn data_block_or_allocate(
+        &mut self,
+        inode: &mut INode,
+        file_block: u64,
+    ) -> Result<DataBlockIndex> {
+        let file_block =
+            usize::try_from(file_block).map_err(|_| FileIoError::Other("file too large"))?;
+        if let Some(block) = inode.blocks.get_mut(file_block) {
+            if let Some(data_block_idx) = block {
+                return Ok(*data_block_idx);
+            }
+            let data_block_idx = self.allocate_data_block()?;
+            *block = Some(data_block_idx);
+            return Ok(data_block_idx);
+        }
+
+        let indirect_idx = file_block - IMMEDIATE_BLOCK_COUNT;
+        if indirect_idx >= INDIRECT_BLOCK_COUNT {
+            return Err(FileIoError::Other("file too large"));
+        }
+        let indirect_block_idx = match inode.indirect_block_idx {
+            Some(indirect_block_idx) => indirect_block_idx,
+            None => {
+                let indirect_block_idx = self.allocate_data_block()?;
+                inode.indirect_block_idx = Some(indirect_block_idx);
+                indirect_block_idx
+            }
+        };
+        if let Some(data_block_idx) = self.read_indirect(indirect_block_idx, indirect_idx)? {
+            return Ok(data_block_idx);
+        }
+        let data_block_idx = self.allocate_data_block()?;
+        let addr = self.layout.calc_data_addr(indirect_block_idx)?;
+        self.device.write_bytes(
+            addr.0 + (indirect_idx * size_of::<u32>()) as u64,
+            &data_block_idx.0.to_le_bytes(),
+        )?;
+        Ok(data_block_idx)
+    }
+
+    fn read_indirect(
+        &self,
+        indirect_block_idx: DataBlockIndex,
+        indirect_idx: usize,
+    ) -> Result<Option<DataBlockIndex>> {
+        let addr = self.layout.calc_data_addr(indirect_block_idx)?;
+        let mut buf = [0; size_of::<u32>()];
+        self.device
+            .read_bytes(addr.0 + (indirect_idx * size_of::<u32>()) as u64, &mut buf)?;
+        Ok(DataBlockIndex::from_u32(u32::from_le_bytes(buf)))
+    }
+
+    /// Releases all data blocks used by an inode and truncates it to 0 bytes.
+    fn free_inode_data(&mut self, inode: &mut INode) -> Result<()> {
+        for block in inode.blocks.iter_mut() {
+            if let Some(data_block_idx) = block.take() {
+                self.free_data_block(data_block_idx)?;
+            }
+        }
+
+        if let Some(indirect_block_idx) = inode.indirect_block_idx.take() {
+            let mut buf = [0; BLOCK_SIZE];
+            let addr = self.layout.calc_data_addr(indirect_block_idx)?;
+            self.device.read_bytes(addr.0, &mut buf)?;
+            let (ptrs, _) = buf.as_chunks::<{ size_of::<u32>() }>();
+            for ptr in ptrs {
+                if let Some(data_block_idx) = DataBlockIndex::from_u32(u32::from_le_bytes(*ptr)) {
+                    self.free_data_block(data_block_idx)?;
+                }
+            }
+            self.free_data_block(indirect_block_idx)?;
+        }
+
+        inode.size = FilePos(0);
+        Ok(())
+    }
+
+    /// Finds an unused data block, marks it as used and zeros it.
+    fn allocate_data_block(&mut self) -> Result<DataBlockIndex> {
+        let data_block_idx = self
+            .find_free(self.layout.data_bitmap_offset, self.layout.data_block_count)?
+            .map(DataBlockIndex)
+            .ok_or(FileIoError::OutOfDiskSpaceError)?;
+
+        let (addr, offset, bit) = self.layout.calc_data_bitmap_addr(data_block_idx)?;
+        self.write_bit(addr, offset, bit, true)?;
+
+        let addr = self.layout.calc_data_addr(data_block_idx)?;
+        self.device.write_bytes(addr.0, &[0; BLOCK_SIZE])?;
+
+        Ok(data_block_idx)
+    }
+
+    fn free_data_block(&mut self, data_block_idx: DataBlockIndex) -> Result<()> {
+        let (addr, offset, bit) = self.layout.calc_data_bitmap_addr(data_block_idx)?;
+        self.write_bit(addr, offset, bit, false)
+    }
+
+    fn read_bit(&self, addr: FilePos, offset: usize, bit: u8) -> Result<bool> {
+        let mut byte = [0];
+        self.device.read_bytes(addr.0 + offset as u64, &mut byte)?;
+        let [byte] = byte;
+        Ok((byte >> bit) & 1 == 1)
+    }
+
+    fn write_bit(&mut self, addr: FilePos, offset: usize, bit: u8, value: bool) -> Result<()> {
+        let mut byte = [0];
+        self.device.read_bytes(addr.0 + offset as u64, &mut byte)?;
+        if value {
+            byte = byte.map(|b| b | (1 << bit));
+        } else {
+            byte = byte.map(|b| b & !(1 << bit));
+        }
+        self.device.write_bytes(addr.0 + offset as u64, &byte)?;
+        Ok(())
+    }
+
+    /// Returns the index of the first clear bit in a bitmap.
+    fn find_free(&self, bitmap_offset: FilePos, count: u32) -> Result<Option<u32>> {
+        let mut found = None;
+        self.scan_bitmap(bitmap_offset, count, |idx, used| {
+            if !used {
+                found = Some(idx);
+            }
+            found.is_none()
+        })?;
+        Ok(found)
+    }
+
+    fn count_free(&self, bitmap_offset: FilePos, count: u32) -> Result<u32> {
+        let mut free = 0;
+        self.scan_bitmap(bitmap_offset, count, |_, used| {
+            if !used {
+                free += 1;
+            }
+            true
+        })?;
+        Ok(free)
+    }
+
+    /// Calls `f` with the index and state of each bit in a bitmap, one block
+    /// read at a time, until `f` returns false.
+    fn scan_bitmap<F: FnMut(u32, bool) -> bool>(
+        &self,
+        bitmap_offset: FilePos,
+        count: u32,
+        mut f: F,
+    ) -> Result<()> {
+        let mut block = [0; BLOCK_SIZE];
+        for idx in 0..count {
+            let bit = idx % BITS_PER_BLOCK;
+            if bit == 0 {
+                let addr = bitmap_offset.0 + (idx / BITS_PER_BLOCK) as u64 * BLOCK_SIZE as u64;
+                self.device.read_bytes(addr, &mut block)?;
+            }
+            let byte = block
+                .get((bit / 8) as usize)
+                .ok_or(FileIoError::BufferTooSmall)?;
+            if !f(idx, (byte >> (bit % 8)) & 1 == 1) {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn block_offset(file_pos: u64) -> Result<usize> {
+    usize::try_from(file_pos % BLOCK_SIZE as u64).map_err(|_| FileIoError::BufferTooSmall)
+}
+
+#[cfg(test)]
+mod tests {
+    use myos_api::Uid;
+    use nostdio::{Cursor, OffsetBlockDevice, Read, SeekFrom, Write};
+
+    use super::*;
+
+    type Device<'a> = OffsetBlockDevice<Cursor<'a>>;
+
+    fn format(data: &mut [u8], inode_count: u32, data_block_count: u32) -> Vsfs<Device<'_>> {
+        let device = OffsetBlockDevice::from_cursor(Cursor::new(data), 512).unwrap();
+        let mut options = FormatVolumeOptions::new(inode_count, data_block_count);
+        options.time = Timestamp::from_seconds(123);
+        format_volume(device, options).unwrap()
+    }
+
+    fn reopen(data: &mut [u8]) -> Vsfs<Device<'_>> {
+        let device = OffsetBlockDevice::from_cursor(Cursor::new(data), 512).unwrap();
+        Vsfs::new(device, FsOptions::new()).unwrap()
+    }
+
+    fn options(file_name: &str) -> CreateFileOptions<'_> {
+        CreateFileOptions {
+            file_name,
+            uid: Uid(1000),
+            gid: Uid(100),
+            mode: Mode(0o644),
+            time: Timestamp::from_seconds(456),
+        }
+    }
+
+    fn names<T: BlockDevice>(fs: &Vsfs<T>, dir: &Directory) -> Vec<String> {
+        dir.iter(fs)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().unwrap().to_string())
+            .collect()
+    }
+
+    fn pattern(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn test_root_dir() {
+        let mut data = vec![0; 100 * BLOCK_SIZE];
+        let fs = format(&mut data, 10, 10);
+
+        let root = fs.root_dir().unwrap();
+        assert_eq!(Uid::root(), root.uid());
+        assert_eq!(Uid::root(), root.gid());
+        assert_eq!(Mode(0o755), root.mode());
+        assert_eq!(Timestamp::from_seconds(123), root.ctime());
+
+        let mut count = 0;
+        for entry in root.iter(&fs).unwrap() {
+            let entry = entry.unwrap();
+            assert!(entry.is_dir());
+            let dir = entry.to_dir().unwrap();
+            assert_eq!(Uid::root(), dir.uid());
+            assert_eq!(Uid::root(), dir.gid());
+            assert_eq!(Mode(0o755), dir.mode());
+            assert_eq!(INodeBlockIndex::root(), dir.inode_idx());
+
+            if count == 0 {
+                assert_eq!(".", entry.file_name().unwrap());
+            } else if count == 1 {
+                assert_eq!("..", entry.file_name().unwrap());
+            }
+            count += 1;
+        }
+        assert_eq!(2, count);
+    }
+
+    #[test]
+    fn test_create_file() {
+        let mut data = vec![0; 100 * BLOCK_SIZE];
+        {
+            let mut fs = format(&mut data, 10, 10);
+            let mut root_dir = fs.root_dir().unwrap();
+            let mut file = root_dir.create_file(&mut fs, options("hello.txt")).unwrap();
+            file.write_all(b"Hello World!").unwrap();
+            assert_eq!(12, file.size());
+
+            assert_eq!(
+                FileIoError::FileAlreadyExists,
+                root_dir
+                    .create_file(&mut fs, options("hello.txt"))
+                    .err()
+                    .unwrap()
+            );
+        }
+
+        let mut fs = reopen(&mut data);
+        let root_dir = fs.root_dir().unwrap();
+        assert_eq!(vec![".", "..", "hello.txt"], names(&fs, &root_dir));
+
+        let entry = root_dir.find(&fs, "hello.txt").unwrap().unwrap();
+        assert!(entry.is_file());
+        assert_eq!(12, entry.size());
+        assert_eq!(Uid(1000), entry.uid());
+        assert_eq!(Uid(100), entry.gid());
+        assert_eq!(Mode(0o644), entry.mode());
+
+        let mut file = root_dir.open_file(&mut fs, "hello.txt").unwrap();
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf).unwrap();
+        assert_eq!(b"Hello World!", buf.as_slice());
+    }
+
+    #[test]
+    fn test_large_file() {
+        // spans the immediate blocks and the indirect block
+        let len = (IMMEDIATE_BLOCK_COUNT + 5) * BLOCK_SIZE + 123;
+        let expected = pattern(len);
+
+        let mut data = vec![0; 100 * BLOCK_SIZE];
+        {
+            let mut fs = format(&mut data, 10, 50);
+            let mut root_dir = fs.root_dir().unwrap();
+            let mut file = root_dir.create_file(&mut fs, options("large")).unwrap();
+            // odd sized writes to cross block boundaries
+            for chunk in expected.chunks(1000) {
+                file.write_all(chunk).unwrap();
+            }
+            assert_eq!(len as u64, file.size());
+        }
+
+        let mut fs = reopen(&mut data);
+        let root_dir = fs.root_dir().unwrap();
+        let mut file = root_dir.open_file(&mut fs, "large").unwrap();
+        let mut actual = vec![0; len];
+        file.read_exact(&mut actual).unwrap();
+        assert!(expected == actual);
+        assert_eq!(0, file.read(&mut [0; 10]).unwrap());
+
+        // read across the immediate/indirect boundary
+        let pos = (IMMEDIATE_BLOCK_COUNT * BLOCK_SIZE - 10) as u64;
+        file.seek(SeekFrom::Start(pos)).unwrap();
+        let mut buf = [0; 20];
+        file.read_exact(&mut buf).unwrap();
+        assert_eq!(&expected[pos as usize..pos as usize + 20], &buf);
+    }
+
+    #[test]
+    fn test_overwrite_and_sparse() {
+        let mut data = vec![0; 100 * BLOCK_SIZE];
+        let mut fs = format(&mut data, 10, 20);
+        let mut root_dir = fs.root_dir().unwrap();
+        let mut file = root_dir.create_file(&mut fs, options("file")).unwrap();
+
+        file.write_all(b"0123456789").unwrap();
+        file.seek(SeekFrom::Start(2)).unwrap();
+        file.write_all(b"ab").unwrap();
+        assert_eq!(10, file.size());
+
+        // leave a hole of 2 blocks
+        file.seek(SeekFrom::Start(3 * BLOCK_SIZE as u64)).unwrap();
+        file.write_all(b"end").unwrap();
+        assert_eq!(3 * BLOCK_SIZE as u64 + 3, file.size());
+
+        file.seek(SeekFrom::Start(0)).unwrap();
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf).unwrap();
+        assert_eq!(b"01ab456789", &buf[..10]);
+        assert!(buf[10..3 * BLOCK_SIZE].iter().all(|b| *b == 0));
+        assert_eq!(b"end", &buf[3 * BLOCK_SIZE..]);
+
+        // the hole doesn't use data blocks, root dir + 2 file blocks
+        drop(file);
+        assert_eq!(20 - 1 - 3, fs.free_data_block_count().unwrap());
+    }
+
+    #[test]
+    fn test_directories() {
+        let mut data = vec![0; 100 * BLOCK_SIZE];
+        {
+            let mut fs = format(&mut data, 10, 20);
+            let mut root_dir = fs.root_dir().unwrap();
+            let mut sub_dir = root_dir.create_dir(&mut fs, options("sub")).unwrap();
+            assert_eq!(vec![".", ".."], names(&fs, &sub_dir));
+            let mut file = sub_dir.create_file(&mut fs, options("nested.txt")).unwrap();
+            file.write_all(b"nested").unwrap();
+        }
+
+        let mut fs = reopen(&mut data);
+        let root_dir = fs.root_dir().unwrap();
+        assert_eq!(vec![".", "..", "sub"], names(&fs, &root_dir));
+        let sub_dir = root_dir.open_dir(&fs, "sub").unwrap();
+        assert_eq!(Mode(0o644), sub_dir.mode());
+        assert_eq!(vec![".", "..", "nested.txt"], names(&fs, &sub_dir));
+
+        let parent = sub_dir.open_dir(&fs, "..").unwrap();
+        assert_eq!(INodeBlockIndex::root(), parent.inode_idx());
+
+        assert_eq!(
+            FileIoError::NotADirectory,
+            sub_dir.open_dir(&fs, "nested.txt").err().unwrap()
+        );
+        assert_eq!(
+            FileIoError::IsADirectory,
+            root_dir.open_file(&mut fs, "sub").err().unwrap()
+        );
+        assert_eq!(
+            FileIoError::NotFound,
+            root_dir.open_file(&mut fs, "missing").err().unwrap()
+        );
+
+        let mut file = sub_dir.open_file(&mut fs, "nested.txt").unwrap();
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf).unwrap();
+        assert_eq!(b"nested", buf.as_slice());
+    }
+
+    #[test]
+    fn test_unlink() {
+        let mut data = vec![0; 100 * BLOCK_SIZE];
+        let mut fs = format(&mut data, 10, 20);
+        let free_inodes = fs.free_inode_count().unwrap();
+        let free_blocks = fs.free_data_block_count().unwrap();
+
+        let mut root_dir = fs.root_dir().unwrap();
+        let mut file = root_dir.create_file(&mut fs, options("a")).unwrap();
+        file.write_all(&pattern(15 * BLOCK_SIZE)).unwrap();
+        root_dir.create_file(&mut fs, options("b")).unwrap();
+        let mut sub_dir = root_dir.create_dir(&mut fs, options("sub")).unwrap();
+        sub_dir.create_file(&mut fs, options("c")).unwrap();
+
+        assert_eq!(
+            FileIoError::DirectoryNotEmpty,
+            root_dir.unlink(&mut fs, "sub").err().unwrap()
+        );
+        assert_eq!(
+            FileIoError::NotFound,
+            root_dir.unlink(&mut fs, "missing").err().unwrap()
+        );
+
+        root_dir.unlink(&mut fs, "a").unwrap();
+        sub_dir.unlink(&mut fs, "c").unwrap();
+        root_dir.unlink(&mut fs, "sub").unwrap();
+        assert_eq!(vec![".", "..", "b"], names(&fs, &root_dir));
+        assert!(!root_dir.exists(&fs, "a").unwrap());
+        assert_eq!(free_inodes - 1, fs.free_inode_count().unwrap());
+        // only the data blocks of the root directory remain in use
+        assert_eq!(free_blocks, fs.free_data_block_count().unwrap());
+
+        // a name can be reused after unlink
+        let mut file = root_dir.create_file(&mut fs, options("a")).unwrap();
+        file.write_all(b"again").unwrap();
+        drop(file);
+        assert_eq!(vec![".", "..", "b", "a"], names(&fs, &root_dir));
+    }
+
+    #[test]
+    fn test_out_of_space() {
+        let mut data = vec![0; 100 * BLOCK_SIZE];
+        let mut fs = format(&mut data, 3, 4);
+        let mut root_dir = fs.root_dir().unwrap();
+
+        let mut file = root_dir.create_file(&mut fs, options("a")).unwrap();
+        // 2 data blocks are left
+        let written = file.write(&pattern(3 * BLOCK_SIZE)).unwrap();
+        assert_eq!(2 * BLOCK_SIZE, written);
+        assert_eq!(
+            FileIoError::OutOfDiskSpaceError,
+            file.write(b"more").err().unwrap()
+        );
+        assert!(file.write_all(b"more").is_err());
+
+        // inode 0 is reserved, 1 is the root directory and 2 is "a"
+        assert_eq!(
+            FileIoError::Other("out of inodes"),
+            root_dir.create_file(&mut fs, options("b")).err().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_file_names() {
+        let mut data = vec![0; 100 * BLOCK_SIZE];
+        let mut fs = format(&mut data, 10, 10);
+        let mut root_dir = fs.root_dir().unwrap();
+
+        let long_name = "x".repeat(MAX_FILE_NAME_LEN + 1);
+        assert_eq!(
+            FileIoError::FilenameTooLong,
+            root_dir
+                .create_file(&mut fs, options(&long_name))
+                .err()
+                .unwrap()
+        );
+        assert!(root_dir.create_file(&mut fs, options("a/b")).is_err());
+        assert!(root_dir.create_file(&mut fs, options("")).is_err());
+        assert!(root_dir.create_file(&mut fs, options(".")).is_err());
+
+        let max_name = "y".repeat(MAX_FILE_NAME_LEN);
+        root_dir.create_file(&mut fs, options(&max_name)).unwrap();
+        assert!(root_dir.exists(&fs, &max_name).unwrap());
+    }
+
+    #[test]
+    fn test_nostdio_write() {
+        let mut data = vec![0; 100 * BLOCK_SIZE];
+        let mut fs = format(&mut data, 10, 10);
+        let mut root_dir = fs.root_dir().unwrap();
+        let file = root_dir.create_file(&mut fs, options("buffered")).unwrap();
+
+        let mut writer = nostdio::BufWriter::<_, 16>::with_buffer(file);
+        for _ in 0..10 {
+            writer.write_all(b"line\n").unwrap();
+        }
+        let file = writer.into_inner().unwrap();
+        assert_eq!(50, file.size());
+    }
+}
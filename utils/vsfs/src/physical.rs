@@ -1,4 +1,4 @@
-use file_io::{FileIoError, Result};
+use myos_api::filesystem::{FileIoError, Result};
 use zerocopy::{FromBytes, Immutable, IntoBytes, KnownLayout};
 
 use crate::{DataBlockIndex, INodeBlockIndex, inode::INode};
@@ -6,11 +6,21 @@ use crate::{DataBlockIndex, INodeBlockIndex, inode::INode};
 pub const MAGIC: [u8; 4] = *b"vsfs";
 pub const BLOCK_SIZE: usize = 4 * 1024;
 pub(crate) const PHYSICAL_INODE_SIZE: usize = core::mem::size_of::<PhysicalINode>();
+#[allow(clippy::cast_possible_truncation)]
 pub(crate) const PHYSICAL_INODES_PER_BLOCK: u32 = (BLOCK_SIZE / PHYSICAL_INODE_SIZE) as u32;
+/// Number of inodes or data blocks tracked by one bitmap block
+#[allow(clippy::cast_possible_truncation)]
+pub(crate) const BITS_PER_BLOCK: u32 = BLOCK_SIZE as u32 * 8;
 /// Number of block offsets stored in the inode itself, if the number of
 /// blocks exceeds this amount additional blocks will be stored in
 /// the indirect_block data
 pub(crate) const IMMEDIATE_BLOCK_COUNT: usize = 12;
+/// Number of block offsets stored in the indirect block
+pub(crate) const INDIRECT_BLOCK_COUNT: usize = BLOCK_SIZE / core::mem::size_of::<u32>();
+/// Largest file size supported by the immediate and indirect blocks
+pub const MAX_FILE_SIZE: u64 = ((IMMEDIATE_BLOCK_COUNT + INDIRECT_BLOCK_COUNT) * BLOCK_SIZE) as u64;
+/// Inode 0 and data block 0 are reserved at format time so that 0 can be used
+/// to mark unused block pointers and deleted directory entries.
 pub(crate) const BLOCK_NOT_SET: u32 = 0;
 
 #[repr(C, packed)]
@@ -21,35 +31,30 @@ pub(crate) struct PhysicalINode {
     pub mode: u16,
     /// size of the file
     pub size: u64,
-    /// what time was this file last accessed?
-    pub time: u64,
-    /// what time was this file created?
-    pub ctime: u64,
-    /// what time was this file last modified?
-    pub mtime: u64,
+    /// what time was this file last accessed? (seconds since the unix epoch)
+    pub time: i64,
+    /// what time was this file created? (seconds since the unix epoch)
+    pub ctime: i64,
+    /// what time was this file last modified? (seconds since the unix epoch)
+    pub mtime: i64,
     /// index into the blocks where the first x blocks of data can be found, 0 indicates unused block
     pub blocks: [u32; IMMEDIATE_BLOCK_COUNT],
     /// if not 0, indicates an index into the block table where you will find more block addresses
     pub indirect_block_idx: u32,
 }
 
-impl From<INode> for PhysicalINode {
-    fn from(value: INode) -> Self {
-        let mut blocks = [0; IMMEDIATE_BLOCK_COUNT];
-        for i in 0..IMMEDIATE_BLOCK_COUNT {
-            blocks[i] = value.blocks[i].unwrap_or(DataBlockIndex(BLOCK_NOT_SET)).0;
-        }
-
+impl From<&INode> for PhysicalINode {
+    fn from(value: &INode) -> Self {
         Self {
             uid: value.uid.0,
             gid: value.gid.0,
             mode: value.mode.0,
             size: value.size.0,
-            time: value.time.0,
-            ctime: value.ctime.0,
-            mtime: value.mtime.0,
-            blocks,
-            indirect_block_idx: value.indirect_block_idx.unwrap_or(DataBlockIndex(BLOCK_NOT_SET)).0,
+            time: value.time.seconds(),
+            ctime: value.ctime.seconds(),
+            mtime: value.mtime.seconds(),
+            blocks: value.blocks.map(DataBlockIndex::to_u32),
+            indirect_block_idx: DataBlockIndex::to_u32(value.indirect_block_idx),
         }
     }
 }
@@ -66,14 +71,15 @@ pub(crate) struct PhysicalSuperBlock {
 #[repr(C, packed)]
 #[derive(Debug, Clone, IntoBytes, FromBytes, Immutable, KnownLayout)]
 pub(crate) struct PhysicalDirectoryEntry {
+    /// 0 if the entry has been unlinked
     pub inode_idx: u32,
     pub name_len: u16,
-    // name: [u8; < MAX_FILE_NAME_LEN]
+    // name: [u8; name_len]
 }
 
 pub(crate) const BASE_PHYSICAL_DIRECTORY_ENTRY_SIZE: usize =
     core::mem::size_of::<PhysicalDirectoryEntry>();
-pub(crate) const MAX_FILE_NAME_LEN: usize = BLOCK_SIZE - BASE_PHYSICAL_DIRECTORY_ENTRY_SIZE;
+pub const MAX_FILE_NAME_LEN: usize = 255;
 
 impl PhysicalDirectoryEntry {
     pub(crate) fn write<'a>(
@@ -96,19 +102,18 @@ impl PhysicalDirectoryEntry {
         };
         let entry_bytes = entry.as_bytes();
         let total_len = entry_bytes.len() + name_bytes.len();
-        if total_len > dest_buf.len() {
-            return Err(FileIoError::BufferTooSmall);
-        }
 
         dest_buf
             .get_mut(0..entry_bytes.len())
             .ok_or(FileIoError::BufferTooSmall)?
             .copy_from_slice(entry_bytes);
         dest_buf
-            .get_mut(entry_bytes.len()..entry_bytes.len() + name_bytes.len())
+            .get_mut(entry_bytes.len()..total_len)
             .ok_or(FileIoError::BufferTooSmall)?
             .copy_from_slice(name_bytes);
 
-        Ok(&dest_buf[0..total_len])
+        dest_buf
+            .get(0..total_len)
+            .ok_or(FileIoError::BufferTooSmall)
     }
 }